@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use ethers::providers::Middleware;
+use ethers::types::{BlockNumber, H160, H256};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use sandooo::common::chain::CHAIN;
+use sandooo::common::constants::Env;
+use sandooo::common::evm::VictimTx;
+use sandooo::common::latency::now_ms;
+use sandooo::common::pools::{load_all_pools, load_curve_pools, CurvePool, Pool};
+use sandooo::common::provider::{connect_provider, RpcPreset};
+use sandooo::common::streams::{NewBlock, NewPendingTx};
+use sandooo::common::token_lists::TokenLists;
+use sandooo::common::utils::{is_weth, scaled_amount, setup_logger, MainCurrency};
+use sandooo::sandwich::simulation::{
+    extract_swap_info, BatchSandwich, Sandwich, SwapDirection, CURVE_VERSION,
+};
+use sandooo::sandwich::victims::VictimBudget;
+
+/*
+Finds the optimal frontrun amount for every pool a (pending) victim tx buys from,
+the same way the bot does: trace the victim's swaps, simulate a small sandwich, then optimize the amount in.
+
+    cargo run --example optimal_input -- <tx hash>
+
+Uses the RPC endpoints from .env (see .env.example). Syncing pools takes a while on the first run.
+*/
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    setup_logger().unwrap();
+
+    let tx_hash = match std::env::args().nth(1) {
+        Some(arg) => H256::from_str(&arg)?,
+        None => return Err(anyhow!("usage: optimal_input <tx hash>")),
+    };
+
+    let env = Env::new();
+    let provider = connect_provider(&env.rpc_url()).await?;
+
+    let (pools, _) = load_all_pools(
+        env.rpc_url(),
+        CHAIN.pool_sync_from_block,
+        RpcPreset::from_env(&env).log_chunk_size(),
+    )
+    .await?;
+    let pools_map: HashMap<H160, Pool> = pools.into_iter().map(|p| (p.address, p)).collect();
+    let curve_pools_map: HashMap<H160, CurvePool> = load_curve_pools(&provider)
+        .await?
+        .into_iter()
+        .map(|p| (p.address, p))
+        .collect();
+
+    let block = provider
+        .get_block(BlockNumber::Latest)
+        .await?
+        .ok_or(anyhow!("no latest block"))?;
+    let new_block = NewBlock::from_block(&block).ok_or(anyhow!("latest block has no number"))?;
+
+    let tx = provider
+        .get_transaction(tx_hash)
+        .await?
+        .ok_or(anyhow!("transaction {:?} not found", tx_hash))?;
+    let pending_tx = NewPendingTx {
+        added_block: None,
+        tx: tx.clone(),
+        hint: None,
+        first_seen_ms: now_ms(),
+    };
+
+    let swap_info = extract_swap_info(
+        &provider,
+        &new_block,
+        &pending_tx,
+        &pools_map,
+        &curve_pools_map,
+        &VictimBudget::new(&env),
+        &TokenLists::default(),
+    )
+    .await?;
+    info!("{:?} touches {:?} pools", tx_hash, swap_info.len());
+
+    let victim_tx = VictimTx {
+        tx_hash,
+        from: tx.from,
+        to: tx.to.unwrap_or_default(),
+        data: tx.input.0.clone().into(),
+        value: tx.value,
+        gas_price: tx.gas_price.unwrap_or_default(),
+        gas_limit: Some(tx.gas.as_u64()),
+    };
+    let base_fee = new_block.next_base_fee;
+    let max_fee = base_fee;
+
+    for info in swap_info {
+        // only buys are sandwiched, Curve pools use a coarse sweep instead of the optimizer
+        if matches!(info.direction, SwapDirection::Sell) || info.version == CURVE_VERSION {
+            info!("Skipping {:?} ({:?})", info.target_pair, info.direction);
+            continue;
+        }
+
+        let decimals = MainCurrency::new(info.main_currency).decimals();
+        let (small_amount_in, ceiling_amount_in) = if is_weth(info.main_currency) {
+            (
+                scaled_amount(1, decimals, -2),
+                scaled_amount(100, decimals, 0),
+            )
+        } else {
+            (
+                scaled_amount(10, decimals, 0),
+                scaled_amount(300000, decimals, 0),
+            )
+        };
+
+        let mut sandwich = Sandwich {
+            amount_in: small_amount_in,
+            swap_info: info.clone(),
+            victim_tx: victim_tx.clone(),
+            optimized_sandwich: None,
+        };
+        let batch_sandwich = BatchSandwich {
+            sandwiches: vec![sandwich.clone()],
+        };
+        let simulated = match batch_sandwich
+            .simulate(
+                provider.clone(),
+                None,
+                new_block.block_number,
+                base_fee,
+                max_fee,
+                None,
+                None,
+                None,
+            )
+            .await
+        {
+            Ok(simulated) => simulated,
+            Err(e) => {
+                warn!(
+                    "{:?} / BatchSandwich.simulate error: {e:?}",
+                    info.target_pair
+                );
+                continue;
+            }
+        };
+        if simulated.profit <= 0 {
+            info!(
+                "{:?} / Not profitable at {:?}: {:?}",
+                info.target_pair, small_amount_in, simulated.profit
+            );
+            continue;
+        }
+
+        match sandwich
+            .optimize(
+                provider.clone(),
+                new_block.block_number,
+                ceiling_amount_in,
+                base_fee,
+                max_fee,
+                simulated.front_access_list,
+                simulated.back_access_list,
+                None,
+            )
+            .await
+        {
+            Ok(optimized) => info!(
+                "🥪 {:?} / Optimal amount in: {:?} / Max revenue: {:?} / Gas used: {:?}",
+                info.target_pair,
+                optimized.amount_in,
+                optimized.max_revenue,
+                optimized.front_gas_used + optimized.back_gas_used
+            ),
+            Err(e) => warn!("{:?} / Sandwich.optimize error: {e:?}", info.target_pair),
+        }
+    }
+
+    Ok(())
+}
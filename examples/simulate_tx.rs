@@ -0,0 +1,51 @@
+use anyhow::{anyhow, Result};
+use ethers::types::H256;
+use log::info;
+use std::str::FromStr;
+
+use sandooo::common::constants::Env;
+use sandooo::common::evm::replay_tx;
+use sandooo::common::provider::connect_provider;
+use sandooo::common::utils::setup_logger;
+
+/*
+Replays a transaction on a fork of the chain and prints what happened.
+Pending txs run on top of the latest block, mined txs on top of their parent block.
+
+    cargo run --example simulate_tx -- <tx hash>
+
+Uses the RPC endpoints from .env (see .env.example).
+*/
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    setup_logger().unwrap();
+
+    let tx_hash = match std::env::args().nth(1) {
+        Some(arg) => H256::from_str(&arg)?,
+        None => return Err(anyhow!("usage: simulate_tx <tx hash>")),
+    };
+
+    let env = Env::new();
+    let provider = connect_provider(&env.rpc_url()).await?;
+
+    let (block_number, result) = replay_tx(provider, tx_hash).await?;
+    info!(
+        "Simulated {:?} on top of block #{:?}",
+        tx_hash, block_number
+    );
+    match result {
+        Ok(result) => {
+            info!(
+                "✅ Success / Gas used: {:?} / Gas refunded: {:?} / Logs: {:?}",
+                result.gas_used,
+                result.gas_refunded,
+                result.logs.map(|logs| logs.len()).unwrap_or(0)
+            );
+            info!("Output: {:?}", result.output);
+        }
+        Err(e) => info!("❌ Reverted: {e:?}"),
+    }
+
+    Ok(())
+}
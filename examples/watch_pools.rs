@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Result};
+use ethers::types::{H160, U256};
+use log::info;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use sandooo::common::constants::Env;
+use sandooo::common::evm::EvmSimulator;
+use sandooo::common::provider::connect_provider;
+use sandooo::common::streams::{stream_new_blocks, Event};
+use sandooo::common::utils::setup_logger;
+
+/*
+Prints the reserves of Uniswap V2 style pairs on every new block, and how much they moved.
+
+    cargo run --example watch_pools -- <pair address> [<pair address> ...]
+
+Blocks are streamed from the websocket endpoint in .env (see .env.example).
+*/
+fn change(prev: U256, current: U256) -> String {
+    if current >= prev {
+        format!("+{}", current - prev)
+    } else {
+        format!("-{}", prev - current)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    setup_logger().unwrap();
+
+    let pairs = std::env::args()
+        .skip(1)
+        .map(|arg| H160::from_str(&arg))
+        .collect::<Result<Vec<H160>, _>>()?;
+    if pairs.is_empty() {
+        return Err(anyhow!(
+            "usage: watch_pools <pair address> [<pair address> ...]"
+        ));
+    }
+
+    let env = Env::new();
+    let wss_url = env
+        .wss_url
+        .split(',')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_string();
+    let ws_provider = connect_provider(&wss_url).await?;
+    let provider = connect_provider(&env.rpc_url()).await?;
+
+    let (event_sender, mut event_receiver) = broadcast::channel(512);
+    tokio::spawn(stream_new_blocks(
+        ws_provider,
+        event_sender,
+        Duration::from_secs(env.ws_stall_timeout_secs),
+    ));
+
+    let mut last_reserves: HashMap<H160, (U256, U256)> = HashMap::new();
+    loop {
+        let new_block = match event_receiver.recv().await {
+            Ok(Event::Block(block)) => block,
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        info!("▶️ Block #{:?}", new_block.block_number);
+
+        let mut simulator = EvmSimulator::new(provider.clone(), None, new_block.block_number);
+        for pair in &pairs {
+            match simulator.get_pair_reserves(*pair) {
+                Ok((reserve0, reserve1)) => {
+                    let (prev0, prev1) = last_reserves
+                        .get(pair)
+                        .cloned()
+                        .unwrap_or((reserve0, reserve1));
+                    info!(
+                        "{:?} / Reserve0: {:?} ({}) / Reserve1: {:?} ({})",
+                        pair,
+                        reserve0,
+                        change(prev0, reserve0),
+                        reserve1,
+                        change(prev1, reserve1),
+                    );
+                    last_reserves.insert(*pair, (reserve0, reserve1));
+                }
+                Err(e) => info!("{:?} / get_pair_reserves error: {e:?}", pair),
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+
+use sandooo::common::journal::{TradeJournal, TRADE_JOURNAL_DB_FILE};
+
+// Prints the daily PnL from the trade journal: cargo run --bin pnl -- [days, default 30]
+fn main() -> Result<()> {
+    let days = std::env::args()
+        .nth(1)
+        .and_then(|arg| arg.parse::<u64>().ok())
+        .unwrap_or(30);
+
+    let journal = TradeJournal::open(TRADE_JOURNAL_DB_FILE)?;
+    let daily = journal.daily_pnl(days)?;
+
+    println!(
+        "{:<12} {:>9} {:>7} {:>24} {:>24} {:>24} {:>24}",
+        "day", "attempted", "landed", "simulated", "realized", "gas", "bribes"
+    );
+    let mut realized_total = 0;
+    for pnl in &daily {
+        println!(
+            "{:<12} {:>9} {:>7} {:>24} {:>24} {:>24} {:>24}",
+            pnl.day,
+            pnl.attempted,
+            pnl.landed,
+            pnl.simulated_profit,
+            pnl.realized_profit,
+            pnl.gas_cost,
+            pnl.bribes
+        );
+        realized_total += pnl.realized_profit;
+    }
+    println!("Realized over the last {} days: {}", days, realized_total);
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::Result;
+use ethers::providers::Middleware;
+use ethers::types::H160;
+use log::info;
+
+use sandooo::common::chain::CHAIN;
+use sandooo::common::constants::Env;
+use sandooo::common::pools::load_all_pools;
+use sandooo::common::provider::{connect_provider, RpcPreset};
+use sandooo::common::token_safety::{screen_tokens, TokenSafetyStore};
+use sandooo::common::tokens::load_all_tokens;
+use sandooo::common::utils::{is_main_currency, setup_logger};
+
+// Screens every cached token offline and writes the results to the cache store
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    setup_logger().unwrap();
+
+    let env = Env::new();
+    let provider = connect_provider(&env.rpc_url()).await?;
+
+    let (pools, prev_pool_id) = load_all_pools(
+        env.rpc_url(),
+        CHAIN.pool_sync_from_block,
+        RpcPreset::from_env(&env).log_chunk_size(),
+    )
+    .await?;
+    let block_number = provider.get_block_number().await?;
+    let tokens_map = load_all_tokens(&provider, block_number, &pools, prev_pool_id).await?;
+
+    let tokens: Vec<H160> = tokens_map
+        .keys()
+        .filter(|token| !is_main_currency(**token))
+        .cloned()
+        .collect();
+    info!(
+        "Screening {:?} tokens at block #{:?}",
+        tokens.len(),
+        block_number
+    );
+
+    let screened = screen_tokens(
+        provider.clone(),
+        block_number,
+        tokens,
+        &pools,
+        env.token_screen_concurrency,
+    )
+    .await?;
+
+    let mut safety_store = TokenSafetyStore::open(&env)?;
+    let saved = safety_store.upsert(&screened, block_number.as_u64())?;
+    let unsafe_tokens = screened.values().filter(|m| !m.is_sandwichable()).count();
+    info!(
+        "Saved {:?} tokens to the {} cache ({:?} honeypots or taxed)",
+        saved,
+        safety_store.store.backend(),
+        unsafe_tokens
+    );
+
+    Ok(())
+}
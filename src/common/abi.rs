@@ -7,6 +7,8 @@ pub struct Abi {
     pub pair: BaseContract,
     pub token: BaseContract,
     pub sando_bot: BaseContract,
+    pub curve_pool: BaseContract,
+    pub wrapped_native: BaseContract,
 }
 
 impl Abi {
@@ -21,6 +23,7 @@ impl Abi {
                 "function token0() external view returns (address)",
                 "function token1() external view returns (address)",
                 "function getReserves() external view returns (uint112,uint112,uint32)",
+                "function swap(uint256,uint256,address,bytes) external",
             ])
             .unwrap(),
         );
@@ -44,11 +47,31 @@ impl Abi {
             parse_abi(&["function recoverToken(address,uint256) public"]).unwrap(),
         );
 
+        let curve_pool = BaseContract::from(
+            parse_abi(&[
+                "function coins(uint256) external view returns (address)",
+                "function get_dy(int128,int128,uint256) external view returns (uint256)",
+                "function exchange(int128,int128,uint256,uint256) external",
+            ])
+            .unwrap(),
+        );
+
+        // WETH9 style wrapper of the chain's native token (WETH, WBNB)
+        let wrapped_native = BaseContract::from(
+            parse_abi(&[
+                "function deposit() external payable",
+                "function withdraw(uint256) external",
+            ])
+            .unwrap(),
+        );
+
         Self {
             factory,
             pair,
             token,
             sando_bot,
+            curve_pool,
+            wrapped_native,
         }
     }
 }
@@ -1,13 +1,72 @@
 use anyhow::Result;
-use ethers::types::{H256, U64};
+use ethers::types::{H160, H256, U256, U64};
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
 use teloxide::prelude::*;
 use teloxide::types::ChatId;
 
 use crate::common::constants::Env;
+use crate::common::latency::now_ms;
+use crate::common::redact::{redact, redact_json};
+
+/*
+Structured alerts for operators' own tooling (PagerDuty, Slack, automation), next to the Telegram messages.
+With ALERT_WEBHOOK_URL set, every event is POSTed as JSON, secrets redacted:
+    {"event": "bundle_sent", "chain_id": 1, "timestamp_ms": 1700000000000, "bundle_id": "...", ...}
+- bundle_sent: a bundle went out to the builders (or the sequencer)
+- bundle_included: its frontrun landed in the target block
+- simulation_error: failures counted since the last report, every FAILURE_REPORT_INTERVAL_SECS
+- circuit_breaker: a breaker tripped and bundle submission is paused
+Webhooks are fire and forget: a slow or failing endpoint is logged, never holds up the bot.
+*/
+pub static WEBHOOK_TIMEOUT_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AlertEvent {
+    BundleSent {
+        bundle_id: String,
+        context: String,
+        target_block: U64,
+        victim_tx_hashes: Vec<H256>,
+        pairs: Vec<H160>,
+        expected_profit: i128,
+        bribe: U256,
+        builders: Vec<String>,
+    },
+    BundleIncluded {
+        bundle_id: String,
+        context: String,
+        target_block: U64,
+        included_block: Option<U64>,
+        expected_profit: i128,
+    },
+    SimulationError {
+        interval_secs: u64,
+        counts: BTreeMap<String, u64>, // Failure --> count
+    },
+    CircuitBreaker {
+        breaker: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct WebhookPayload<'a> {
+    chain_id: u64,
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    event: &'a AlertEvent,
+}
 
 pub struct Alert {
     pub bot: Option<Bot>,
     pub chat_id: Option<ChatId>,
+    pub webhook_url: String, // empty: no webhook
+    pub chain_id: u64,
+    pub client: reqwest::Client,
 }
 
 impl Alert {
@@ -19,19 +78,57 @@ impl Alert {
             Self {
                 bot: Some(bot),
                 chat_id: Some(chat_id),
+                webhook_url: env.alert_webhook_url,
+                chain_id: env.chain_id,
+                client: reqwest::Client::new(),
             }
         } else {
             Self {
                 bot: None,
                 chat_id: None,
+                webhook_url: env.alert_webhook_url,
+                chain_id: env.chain_id,
+                client: reqwest::Client::new(),
             }
         }
     }
 
+    // POSTs the event to ALERT_WEBHOOK_URL in the background
+    pub fn event(&self, event: AlertEvent) {
+        if self.webhook_url.is_empty() {
+            return;
+        }
+        let payload = WebhookPayload {
+            chain_id: self.chain_id,
+            timestamp_ms: now_ms(),
+            event: &event,
+        };
+        let mut body = match serde_json::to_value(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("AlertEvent serialization error: {e:?}");
+                return;
+            }
+        };
+        redact_json(&mut body);
+        let request = self
+            .client
+            .post(&self.webhook_url)
+            .timeout(Duration::from_millis(WEBHOOK_TIMEOUT_MS))
+            .json(&body);
+        tokio::spawn(async move {
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Err(e) => warn!("Alert webhook error: {e:?}"),
+                _ => {}
+            }
+        });
+    }
+
     pub async fn send(&self, message: &str) -> Result<()> {
         match &self.bot {
             Some(bot) => {
-                bot.send_message(self.chat_id.unwrap(), message).await?;
+                bot.send_message(self.chat_id.unwrap(), redact(message))
+                    .await?;
             }
             _ => {}
         }
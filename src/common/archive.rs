@@ -0,0 +1,105 @@
+use anyhow::Result;
+use ethers::types::U64;
+use ethers_flashbots::BundleRequest;
+use flate2::{write::GzEncoder, Compression};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read_dir, remove_file, File},
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::common::constants::Env;
+use crate::common::execution::SendBundleResponse;
+
+/*
+Keeps the exact eth_sendBundle payload (signed RLP txs, target block, ...) for every bundle we submit,
+so when a builder does something unexpected or the on-chain result doesn't match the simulation,
+we can replay/verify exactly what was sent.
+
+Each bundle is written as a gzipped JSON file: {archive_dir}/{target_block}-{bundle_id}.json.gz
+(read it back with: zcat cache/bundles/<file>.json.gz)
+Files older than BUNDLE_ARCHIVE_RETENTION_DAYS are deleted, 0 disables the archive.
+*/
+#[derive(Debug, Clone)]
+pub struct BundleArchive {
+    pub dir: PathBuf,
+    pub retention: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchivedBundle<'a> {
+    pub bundle_id: &'a str,
+    pub block_number: U64,
+    pub submitted_at: u64,
+    pub request: &'a BundleRequest,
+    pub responses: &'a HashMap<String, SendBundleResponse>,
+}
+
+impl BundleArchive {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            dir: PathBuf::from(&env.bundle_archive_dir),
+            retention: Duration::from_secs(env.bundle_archive_retention_days * 24 * 60 * 60),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.retention.is_zero()
+    }
+
+    pub fn save(
+        &self,
+        bundle_id: &str,
+        block_number: U64,
+        request: &BundleRequest,
+        responses: &HashMap<String, SendBundleResponse>,
+    ) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        create_dir_all(&self.dir)?;
+
+        let archived = ArchivedBundle {
+            bundle_id,
+            block_number,
+            submitted_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            request,
+            responses,
+        };
+        let path = self
+            .dir
+            .join(format!("{}-{}.json.gz", block_number, bundle_id));
+        let mut encoder = GzEncoder::new(File::create(&path)?, Compression::default());
+        encoder.write_all(&serde_json::to_vec(&archived)?)?;
+        encoder.finish()?;
+
+        match self.prune() {
+            Err(e) => warn!("BundleArchive.prune error: {e:?}"),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn prune(&self) -> Result<usize> {
+        let mut removed = 0;
+        let now = SystemTime::now();
+        for entry in read_dir(&self.dir)? {
+            let entry = entry?;
+            let modified = entry.metadata()?.modified()?;
+            let age = now.duration_since(modified).unwrap_or_default();
+            if age > self.retention {
+                remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+        if removed > 0 {
+            info!("Pruned {} archived bundles", removed);
+        }
+        Ok(removed)
+    }
+}
@@ -0,0 +1,50 @@
+use ethers::types::{Transaction, U256};
+
+use crate::common::constants::Env;
+
+/*
+When blocks are full, builders won't fit a huge multi-sandwich bundle no matter the bribe.
+We estimate how much gas is left in the target block from the mempool pressure:
+the gas of every pending tx seen since the last block that can pay the next base fee.
+Those txs compete for the same block, so whatever they don't take is what's left for us.
+
+Gas limits of pending txs are an overestimate of what they'll use,
+so the estimate errs on the side of smaller bundles.
+*/
+#[derive(Debug, Clone)]
+pub struct BlockSpaceEstimator {
+    pub pending_gas: U256, // gas of competing pending txs since the last block
+    pub max_bundle_gas_pct: u64, // a bundle never takes more than this % of the block
+    pub min_bundle_gas_pct: u64, // ... and is always allowed at least this %
+}
+
+impl BlockSpaceEstimator {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            pending_gas: U256::zero(),
+            max_bundle_gas_pct: env.max_bundle_gas_pct,
+            min_bundle_gas_pct: env.min_bundle_gas_pct,
+        }
+    }
+
+    pub fn on_block(&mut self) {
+        // most of the txs we counted were just included
+        self.pending_gas = U256::zero();
+    }
+
+    pub fn record_pending_tx(&mut self, tx: &Transaction, next_base_fee: U256) {
+        let max_fee = tx.max_fee_per_gas.or(tx.gas_price).unwrap_or_default();
+        if max_fee < next_base_fee {
+            return;
+        }
+        self.pending_gas = self.pending_gas.saturating_add(tx.gas);
+    }
+
+    // Max combined gas (victims + frontruns + backruns) for a bundle in the next block
+    pub fn bundle_gas_cap(&self, block_gas_limit: U256) -> u64 {
+        let max_gas = block_gas_limit * self.max_bundle_gas_pct / 100;
+        let min_gas = block_gas_limit * self.min_bundle_gas_pct / 100;
+        let available = block_gas_limit.saturating_sub(self.pending_gas);
+        std::cmp::max(std::cmp::min(available, max_gas), min_gas).as_u64()
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::Result;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use url::Url;
+
+use crate::common::chain::ChainProfile;
+
+/*
+Builders we send bundles to, loaded from BUILDERS_CONFIG (JSON) when the file exists,
+so builders can be added or removed without recompiling. Otherwise the chain profile's defaults are used.
+[
+  { "name": "beaverbuild", "url": "https://rpc.beaverbuild.org", "supports_refunds": true },
+  { "name": "eden", "url": "https://api.edennetwork.io/v1/rpc", "max_body_size": 1000000 }
+]
+- supports_refunds: bundles carry refundRecipient (our owner), for builders that refund part of the bundle value
+- requires_signature: send the X-Flashbots-Signature header signed by IDENTITY_KEY (default: true)
+- max_body_size: requests larger than this (in bytes) aren't sent to the builder (default: 0, no limit)
+- enabled: set to false to keep the entry but stop sending to it (default: true)
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuilderConfig {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub supports_refunds: bool,
+    #[serde(default = "default_true")]
+    pub requires_signature: bool,
+    #[serde(default)]
+    pub max_body_size: usize,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+pub struct Builder {
+    pub name: String,
+    pub url: Url,
+    pub supports_refunds: bool,
+    pub requires_signature: bool,
+    pub max_body_size: usize,
+}
+
+impl Builder {
+    pub fn new(name: &str, url: Url) -> Self {
+        Self {
+            name: name.to_string(),
+            url,
+            supports_refunds: false,
+            requires_signature: true,
+            max_body_size: 0,
+        }
+    }
+
+    pub fn accepts_body_size(&self, size: usize) -> bool {
+        self.max_body_size == 0 || size <= self.max_body_size
+    }
+}
+
+pub fn read_builders_config(path: &str) -> Result<Vec<Builder>> {
+    let configs: Vec<BuilderConfig> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut builders = Vec::new();
+    for config in configs {
+        if !config.enabled {
+            continue;
+        }
+        builders.push(Builder {
+            url: Url::parse(&config.url)?,
+            name: config.name,
+            supports_refunds: config.supports_refunds,
+            requires_signature: config.requires_signature,
+            max_body_size: config.max_body_size,
+        });
+    }
+    Ok(builders)
+}
+
+pub fn load_builders(path: &str, chain: &ChainProfile) -> Vec<Builder> {
+    let defaults = || {
+        let mut builders: Vec<Builder> = chain
+            .builder_urls
+            .iter()
+            .map(|(name, url)| Builder::new(name, url.clone()))
+            .collect();
+        builders.sort_by(|a, b| a.name.cmp(&b.name));
+        builders
+    };
+    if path.is_empty() || !Path::new(path).exists() {
+        return defaults();
+    }
+    match read_builders_config(path) {
+        Ok(builders) => {
+            info!("Loaded {} builders from {}", builders.len(), path);
+            builders
+        }
+        Err(e) => {
+            warn!(
+                "Invalid builders config {}, using the defaults: {e:?}",
+                path
+            );
+            defaults()
+        }
+    }
+}
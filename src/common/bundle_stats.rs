@@ -0,0 +1,115 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::execution::BundleInclusion;
+use crate::common::tracker::TrackedBundle;
+
+/*
+Outcome of every bundle we've sent, kept across restarts:
+whether it landed in the target block, and for misses, how far it got
+according to flashbots_getBundleStatsV2 (simulated / considered / sealed by builders),
+along with its latency timeline (see latency::BundleTimeline).
+*/
+pub const BUNDLE_STATS_DB_FILE: &str = "cache/.bundle-stats.db";
+
+pub struct BundleStatsStore {
+    pub conn: Connection,
+}
+
+impl BundleStatsStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS bundle_outcomes (
+                 bundle_id        TEXT PRIMARY KEY,
+                 bundle_hash      TEXT,
+                 frontrun_tx_hash TEXT NOT NULL,
+                 target_block     INTEGER NOT NULL,
+                 landed           INTEGER NOT NULL,
+                 high_priority    INTEGER,
+                 simulated        INTEGER,
+                 considered_by    INTEGER,
+                 sealed_by        INTEGER,
+                 expected_profit  TEXT NOT NULL,
+                 recorded_at      INTEGER NOT NULL,
+                 timeline         TEXT
+             );",
+        )?;
+        // databases created before the timeline column was added
+        let has_timeline = conn
+            .prepare("SELECT timeline FROM bundle_outcomes LIMIT 0")
+            .is_ok();
+        if !has_timeline {
+            conn.execute("ALTER TABLE bundle_outcomes ADD COLUMN timeline TEXT", [])?;
+        }
+        Ok(Self { conn })
+    }
+
+    pub fn record(&mut self, bundle: &TrackedBundle, inclusion: &BundleInclusion) -> Result<()> {
+        let stats = inclusion.stats.as_ref();
+        let recorded_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO bundle_outcomes
+             (bundle_id, bundle_hash, frontrun_tx_hash, target_block, landed, high_priority,
+              simulated, considered_by, sealed_by, expected_profit, recorded_at, timeline)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                bundle.bundle_id,
+                bundle.bundle_hash.map(|h| format!("{:?}", h)),
+                format!("{:?}", bundle.frontrun_tx_hash),
+                bundle.target_block.as_u64() as i64,
+                inclusion.landed,
+                stats.map(|s| s.is_high_priority),
+                stats.map(|s| s.is_simulated),
+                stats.map(|s| s.considered_by_builders_at.len() as i64),
+                stats.map(|s| s.sealed_by_builders_at.len() as i64),
+                bundle.expected_profit.to_string(),
+                recorded_at as i64,
+                serde_json::to_string(&bundle.timeline)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    // (sent, landed) over all recorded bundles
+    pub fn totals(&self) -> Result<(u64, u64)> {
+        let (sent, landed): (i64, i64) = self.conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(landed), 0) FROM bundle_outcomes",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((sent as u64, landed as u64))
+    }
+}
+
+// One line summary of why a bundle did or didn't land, for alerts
+pub fn describe_inclusion(bundle: &TrackedBundle, inclusion: &BundleInclusion) -> String {
+    if inclusion.landed {
+        return format!(
+            "[{}] Bundle landed in block #{:?}",
+            bundle.bundle_id, bundle.target_block
+        );
+    }
+    let reason = match &inclusion.stats {
+        None => String::from("no stats from the relay"),
+        Some(stats) if !stats.is_simulated => String::from("never simulated by the relay"),
+        Some(stats) if stats.considered_by_builders_at.is_empty() => {
+            String::from("simulated, but not considered by any builder")
+        }
+        Some(stats) if stats.sealed_by_builders_at.is_empty() => format!(
+            "considered by {} builder(s), but never sealed (outbid)",
+            stats.considered_by_builders_at.len()
+        ),
+        Some(stats) => format!(
+            "sealed by {} builder(s), but their blocks lost",
+            stats.sealed_by_builders_at.len()
+        ),
+    };
+    format!(
+        "[{}] Bundle missed block #{:?}: {}",
+        bundle.bundle_id, bundle.target_block, reason
+    )
+}
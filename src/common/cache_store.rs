@@ -0,0 +1,441 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use redis::Commands;
+use rusqlite::{params, Connection};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::common::constants::Env;
+
+/*
+Where caches live: discovered pools, token info, token safety results, and the tracker's in-flight bundles.
+Records are JSON values grouped in collections ("pools", "tokens", "token_safety", "tracker"),
+keyed by address/id, and the backend is picked with CACHE_BACKEND / CACHE_URL:
+- csv: one append-only file per collection in the CACHE_URL directory (default: cache),
+  the last row written for a key wins. Each collection is read once into memory,
+  then only the rows appended since (by us or another store) are read. Good enough for a single bot on one machine
+- sqlite: a single database file at CACHE_URL (default: cache/.cache.db),
+  with indices on the JSON fields asked for with create_index
+- redis: one hash per collection on the CACHE_URL server (default: redis://127.0.0.1/),
+  so several bots (and screen_tokens) can share state
+*/
+pub trait CacheStore: Send {
+    fn backend(&self) -> &'static str;
+
+    // Inserts or overwrites (key, value) records, returns how many were written
+    fn put_many(&mut self, collection: &str, records: &[(String, String)]) -> Result<usize>;
+
+    fn get(&mut self, collection: &str, key: &str) -> Result<Option<String>>;
+
+    fn load_all(&mut self, collection: &str) -> Result<Vec<(String, String)>>;
+
+    fn count(&mut self, collection: &str) -> Result<usize>;
+
+    fn remove(&mut self, collection: &str, key: &str) -> Result<()>;
+
+    // Lets find look records up by a top level field of their JSON value without a scan, where the backend can
+    fn create_index(&mut self, _collection: &str, _field: &str) -> Result<()> {
+        Ok(())
+    }
+
+    // Records whose JSON value has the string field set to value
+    fn find(
+        &mut self,
+        collection: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let mut found = Vec::new();
+        for (key, record) in self.load_all(collection)? {
+            let json: serde_json::Value = serde_json::from_str(&record)?;
+            if json.get(field).and_then(|v| v.as_str()) == Some(value) {
+                found.push((key, record));
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl dyn CacheStore + '_ {
+    pub fn put_json<T: Serialize>(
+        &mut self,
+        collection: &str,
+        items: &[(String, T)],
+    ) -> Result<usize> {
+        let mut records = Vec::new();
+        for (key, item) in items {
+            records.push((key.clone(), serde_json::to_string(item)?));
+        }
+        self.put_many(collection, &records)
+    }
+
+    pub fn get_json<T: DeserializeOwned>(
+        &mut self,
+        collection: &str,
+        key: &str,
+    ) -> Result<Option<T>> {
+        match self.get(collection, key)? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_json<T: DeserializeOwned>(&mut self, collection: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        for (_, value) in self.load_all(collection)? {
+            items.push(serde_json::from_str(&value)?);
+        }
+        Ok(items)
+    }
+
+    pub fn find_json<T: DeserializeOwned>(
+        &mut self,
+        collection: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        for (_, record) in self.find(collection, field, value)? {
+            items.push(serde_json::from_str(&record)?);
+        }
+        Ok(items)
+    }
+}
+
+pub fn open_cache_store(env: &Env) -> Result<Box<dyn CacheStore>> {
+    let url = env.cache_url.as_str();
+    let store: Box<dyn CacheStore> = match env.cache_backend.trim().to_lowercase().as_str() {
+        "csv" => Box::new(CsvCacheStore::open(if url.is_empty() {
+            "cache"
+        } else {
+            url
+        })?),
+        "redis" => Box::new(RedisCacheStore::open(if url.is_empty() {
+            "redis://127.0.0.1/"
+        } else {
+            url
+        })?),
+        "" | "sqlite" => Box::new(SqliteCacheStore::open(if url.is_empty() {
+            "cache/.cache.db"
+        } else {
+            url
+        })?),
+        backend => return Err(anyhow!("Unknown CACHE_BACKEND: {}", backend)),
+    };
+    Ok(store)
+}
+
+pub static CSV_COMPACTION_MIN_ROWS: usize = 1000;
+
+// A collection as read so far: the live records, and where to pick the file up from
+#[derive(Debug, Default)]
+struct CsvCollection {
+    records: BTreeMap<String, String>,
+    rows: usize, // rows read, overwritten and removed ones included
+    offset: u64, // bytes of the file read into records
+}
+
+pub struct CsvCacheStore {
+    pub dir: String,
+    collections: HashMap<String, CsvCollection>,
+}
+
+impl CsvCacheStore {
+    pub fn open(dir: &str) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_string(),
+            collections: HashMap::new(),
+        })
+    }
+
+    fn path(&self, collection: &str) -> String {
+        format!("{}/{}.csv", self.dir, collection)
+    }
+
+    fn append(&self, collection: &str, records: &[(String, Option<&str>)]) -> Result<()> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.path(collection))?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(file);
+        for (key, value) in records {
+            // an empty value marks a removed key
+            writer.write_record([key.as_str(), value.unwrap_or("")])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // The collection's records, after reading the rows appended since the last call
+    fn read(&mut self, name: &str) -> Result<&CsvCollection> {
+        let path = self.path(name);
+        let first_read = !self.collections.contains_key(name);
+        let collection = self.collections.entry(name.to_string()).or_default();
+        if !Path::new(&path).exists() {
+            *collection = CsvCollection::default();
+            return Ok(collection);
+        }
+        // shorter than what we've read: compacted by another store, start over
+        if fs::metadata(&path)?.len() < collection.offset {
+            *collection = CsvCollection::default();
+        }
+
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(collection.offset))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        // a row still being written is picked up next time
+        let complete = tail
+            .iter()
+            .rposition(|byte| *byte == b'\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(&tail[..complete]);
+        for row in reader.records() {
+            let row = row?;
+            collection.rows += 1;
+            let key = row.get(0).unwrap_or_default().to_string();
+            match row.get(1) {
+                Some(value) if !value.is_empty() => {
+                    collection.records.insert(key, value.to_string())
+                }
+                _ => collection.records.remove(&key),
+            };
+        }
+        collection.offset += complete as u64;
+
+        // rewrite the file on open once overwritten rows outnumber live ones, so it doesn't grow forever
+        if first_read && collection.rows > 2 * collection.records.len() + CSV_COMPACTION_MIN_ROWS {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_path(&path)?;
+            for (key, value) in &collection.records {
+                writer.write_record([key.as_str(), value.as_str()])?;
+            }
+            writer.flush()?;
+            collection.rows = collection.records.len();
+            collection.offset = fs::metadata(&path)?.len();
+        }
+        Ok(collection)
+    }
+}
+
+impl CacheStore for CsvCacheStore {
+    fn backend(&self) -> &'static str {
+        "csv"
+    }
+
+    fn put_many(&mut self, collection: &str, records: &[(String, String)]) -> Result<usize> {
+        let records: Vec<(String, Option<&str>)> = records
+            .iter()
+            .map(|(key, value)| (key.clone(), Some(value.as_str())))
+            .collect();
+        self.append(collection, &records)?;
+        Ok(records.len())
+    }
+
+    fn get(&mut self, collection: &str, key: &str) -> Result<Option<String>> {
+        Ok(self.read(collection)?.records.get(key).cloned())
+    }
+
+    fn load_all(&mut self, collection: &str) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .read(collection)?
+            .records
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn count(&mut self, collection: &str) -> Result<usize> {
+        Ok(self.read(collection)?.records.len())
+    }
+
+    fn remove(&mut self, collection: &str, key: &str) -> Result<()> {
+        self.append(collection, &[(key.to_string(), None)])
+    }
+}
+
+pub struct SqliteCacheStore {
+    pub conn: Connection,
+}
+
+impl SqliteCacheStore {
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS cache (
+                 collection TEXT NOT NULL,
+                 key        TEXT NOT NULL,
+                 value      TEXT NOT NULL,
+                 PRIMARY KEY (collection, key)
+             );",
+        )?;
+        Ok(Self { conn })
+    }
+}
+
+impl CacheStore for SqliteCacheStore {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn put_many(&mut self, collection: &str, records: &[(String, String)]) -> Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut upserted = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO cache (collection, key, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(collection, key) DO UPDATE SET value = excluded.value",
+            )?;
+            for (key, value) in records {
+                upserted += stmt.execute(params![collection, key, value])?;
+            }
+        }
+        tx.commit()?;
+        Ok(upserted)
+    }
+
+    fn get(&mut self, collection: &str, key: &str) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT value FROM cache WHERE collection = ?1 AND key = ?2")?;
+        let mut rows = stmt.query_map(params![collection, key], |row| row.get(0))?;
+        Ok(rows.next().transpose()?)
+    }
+
+    fn load_all(&mut self, collection: &str) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT key, value FROM cache WHERE collection = ?1 ORDER BY key")?;
+        let records = stmt
+            .query_map(params![collection], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        Ok(records)
+    }
+
+    fn count(&mut self, collection: &str) -> Result<usize> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM cache WHERE collection = ?1",
+            params![collection],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    fn remove(&mut self, collection: &str, key: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM cache WHERE collection = ?1 AND key = ?2",
+            params![collection, key],
+        )?;
+        Ok(())
+    }
+
+    fn create_index(&mut self, collection: &str, field: &str) -> Result<()> {
+        let (collection, field) = (sql_identifier(collection)?, sql_identifier(field)?);
+        self.conn.execute_batch(&format!(
+            "CREATE INDEX IF NOT EXISTS cache_{collection}_{field}
+             ON cache (collection, json_extract(value, '$.{field}'));"
+        ))?;
+        Ok(())
+    }
+
+    // the expression has to be spelled like the index's for SQLite to use it
+    fn find(
+        &mut self,
+        collection: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let field = sql_identifier(field)?;
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "SELECT key, value FROM cache
+             WHERE collection = ?1 AND json_extract(value, '$.{field}') = ?2 ORDER BY key"
+        ))?;
+        let records = stmt
+            .query_map(params![collection, value], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()?;
+        Ok(records)
+    }
+}
+
+// Collection and field names go into index names and JSON paths, not as bound parameters
+fn sql_identifier(name: &str) -> Result<&str> {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Ok(name)
+    } else {
+        Err(anyhow!("Invalid cache field name: {:?}", name))
+    }
+}
+
+pub struct RedisCacheStore {
+    pub conn: redis::Connection,
+}
+
+// Namespaces our hashes on a shared Redis
+pub static REDIS_KEY_PREFIX: &str = "sandooo";
+
+fn redis_key(collection: &str) -> String {
+    format!("{}:{}", REDIS_KEY_PREFIX, collection)
+}
+
+impl RedisCacheStore {
+    pub fn open(url: &str) -> Result<Self> {
+        let conn = redis::Client::open(url)?.get_connection()?;
+        info!("Connected to Redis cache at {}", url);
+        Ok(Self { conn })
+    }
+}
+
+impl CacheStore for RedisCacheStore {
+    fn backend(&self) -> &'static str {
+        "redis"
+    }
+
+    fn put_many(&mut self, collection: &str, records: &[(String, String)]) -> Result<usize> {
+        let key = redis_key(collection);
+        let mut pipe = redis::pipe();
+        for (field, value) in records {
+            pipe.hset(&key, field, value).ignore();
+        }
+        pipe.query::<()>(&mut self.conn)?;
+        Ok(records.len())
+    }
+
+    fn get(&mut self, collection: &str, key: &str) -> Result<Option<String>> {
+        Ok(self.conn.hget(redis_key(collection), key)?)
+    }
+
+    fn load_all(&mut self, collection: &str) -> Result<Vec<(String, String)>> {
+        let records: HashMap<String, String> = self.conn.hgetall(redis_key(collection))?;
+        let mut records: Vec<(String, String)> = records.into_iter().collect();
+        records.sort();
+        Ok(records)
+    }
+
+    fn count(&mut self, collection: &str) -> Result<usize> {
+        Ok(self.conn.hlen(redis_key(collection))?)
+    }
+
+    fn remove(&mut self, collection: &str, key: &str) -> Result<()> {
+        self.conn.hdel::<_, _, ()>(redis_key(collection), key)?;
+        Ok(())
+    }
+}
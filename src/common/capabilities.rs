@@ -0,0 +1,114 @@
+use ethers::prelude::*;
+use ethers::providers::{Middleware, Provider};
+use log::{info, warn};
+use std::sync::Arc;
+
+use crate::common::provider::RpcClient;
+
+/*
+Different users run Geth, Erigon, Reth, Alchemy, Infura, ...
+and each of them exposes a different set of APIs.
+We probe the node once at startup and degrade features explicitly
+instead of silently seeing zero opportunities.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ProviderCapabilities {
+    pub client_version: String,
+    pub debug_trace_call: bool, // Geth style debug_traceCall (used to detect victims)
+    pub debug_trace_call_many: bool, // Erigon/Reth debug_traceCallMany (concurrent victim traces)
+    pub trace_call: bool,       // Parity/Erigon style trace_call
+    pub pending_tx_subscription: bool,
+    pub get_logs: bool,
+}
+
+fn probe_tx() -> TransactionRequest {
+    TransactionRequest::new()
+        .from(H160::zero())
+        .to(H160::zero())
+        .value(U256::zero())
+}
+
+pub async fn detect_capabilities(provider: &Arc<Provider<RpcClient>>) -> ProviderCapabilities {
+    let client_version = provider.client_version().await.unwrap_or_default();
+
+    let mut opts = GethDebugTracingCallOptions::default();
+    opts.tracing_options.tracer = Some(GethDebugTracerType::BuiltInTracer(
+        GethDebugBuiltInTracerType::CallTracer,
+    ));
+    let debug_trace_call = provider
+        .debug_trace_call(probe_tx(), Some(BlockNumber::Latest.into()), opts)
+        .await
+        .is_ok();
+
+    let debug_trace_call_many = provider
+        .request::<_, serde_json::Value>(
+            "debug_traceCallMany",
+            (
+                serde_json::json!([{ "transactions": [probe_tx()] }]),
+                serde_json::json!({ "blockNumber": "latest", "transactionIndex": -1 }),
+                serde_json::json!({ "tracer": "callTracer" }),
+            ),
+        )
+        .await
+        .is_ok();
+
+    let trace_call = provider
+        .trace_call(
+            probe_tx(),
+            vec![TraceType::Trace],
+            Some(BlockNumber::Latest),
+        )
+        .await
+        .is_ok();
+
+    // the subscription is dropped (and unsubscribed) right away
+    let pending_tx_subscription = provider.subscribe_pending_txs().await.is_ok();
+
+    let get_logs = provider
+        .get_logs(
+            &Filter::new()
+                .from_block(BlockNumber::Latest)
+                .to_block(BlockNumber::Latest),
+        )
+        .await
+        .is_ok();
+
+    ProviderCapabilities {
+        client_version,
+        debug_trace_call,
+        debug_trace_call_many,
+        trace_call,
+        pending_tx_subscription,
+        get_logs,
+    }
+}
+
+impl ProviderCapabilities {
+    pub fn pretty_print(&self) {
+        let mark = |supported: bool| if supported { "✅" } else { "❌" };
+        info!("Provider capabilities ({})", self.client_version);
+        info!("- debug_traceCall: {}", mark(self.debug_trace_call));
+        info!(
+            "- debug_traceCallMany: {}",
+            mark(self.debug_trace_call_many)
+        );
+        info!("- trace_call: {}", mark(self.trace_call));
+        info!(
+            "- newPendingTransactions: {}",
+            mark(self.pending_tx_subscription)
+        );
+        info!("- eth_getLogs: {}", mark(self.get_logs));
+    }
+
+    pub fn warn_degraded(&self) {
+        if !self.debug_trace_call {
+            warn!("debug_traceCall unavailable: victims are detected from router calldata and eth_call, opaque aggregator routes are missed");
+        }
+        if !self.pending_tx_subscription {
+            warn!("newPendingTransactions unavailable: mempool stream is disabled");
+        }
+        if !self.get_logs {
+            warn!("eth_getLogs unavailable: pool sync will fail, use a cached pool file");
+        }
+    }
+}
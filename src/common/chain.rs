@@ -0,0 +1,465 @@
+use ethers::prelude::Lazy;
+use ethers::types::{H160, H256, U256, U64};
+use ethers::utils::{get_create2_address_from_hash, keccak256};
+use revm::primitives::SpecId;
+use std::collections::HashMap;
+use std::str::FromStr;
+use url::Url;
+
+use crate::common::constants::*;
+use crate::common::hardforks::{known_hardforks, load_hardforks, Hardfork};
+use crate::common::utils::calculate_next_block_base_fee_with;
+
+pub static MAINNET: u64 = 1;
+pub static GOERLI: u64 = 5;
+pub static SEPOLIA: u64 = 11155111;
+pub static OPTIMISM: u64 = 10;
+pub static BASE: u64 = 8453;
+pub static ARBITRUM: u64 = 42161;
+pub static BSC: u64 = 56;
+
+// The chain profile of the chain we're running on (CHAIN_ID)
+pub static CHAIN: Lazy<ChainProfile> = Lazy::new(|| {
+    let env = Env::new();
+    ChainProfile::new(env.chain_id).with_hardforks(&env.hardforks)
+});
+
+#[derive(Debug, Clone)]
+pub struct CurrencyInfo {
+    pub address: H160,
+    pub symbol: String,
+    pub decimals: u8,
+    pub balance_slot: i32,
+}
+
+impl CurrencyInfo {
+    pub fn new(address: &str, symbol: &str, decimals: u8, balance_slot: i32) -> Self {
+        Self {
+            address: H160::from_str(address).unwrap(),
+            symbol: symbol.to_string(),
+            decimals,
+            balance_slot,
+        }
+    }
+}
+
+/*
+The token gas is paid in (ETH, BNB). We trade its wrapped ERC20 (the weth main currency),
+which converts 1:1 through deposit()/withdraw(), so gas costs and profits are in the same unit.
+*/
+#[derive(Debug, Clone)]
+pub struct NativeCurrency {
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl NativeCurrency {
+    // amount whole tokens in wei
+    pub fn units(&self, amount: u64) -> U256 {
+        U256::from(amount) * U256::from(10).pow(U256::from(self.decimals))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Factory {
+    pub address: H160,
+    pub fee: u32,
+    pub deployed_block: u64, // no pools before this, the pool sync starts here
+}
+
+// Uniswap V2 factory deployment, the first PairCreated on mainnet
+pub static UNISWAP_V2_DEPLOYED_BLOCK: u64 = 10000835;
+
+// Hash of the Uniswap V2 pair creation code, the same on every chain the factory is deployed on
+pub static UNISWAP_V2_INIT_CODE_HASH: &str =
+    "0x96e8ac4277198ff8b6f785478aa9a39f403cb768dd02cbee326c3e7da348845f";
+
+// (pair address, stable is token0) used to convert stables to the wrapped native token
+pub type ConversionPair = (H160, bool);
+
+// The stable / wrapped native pair of a Uniswap V2 factory, at its CREATE2 address
+pub fn v2_conversion_pair(factory: H160, stable: H160, weth: H160) -> ConversionPair {
+    let stable_is_token0 = stable < weth;
+    let (token0, token1) = if stable_is_token0 {
+        (stable, weth)
+    } else {
+        (weth, stable)
+    };
+    let salt = keccak256([token0.as_bytes(), token1.as_bytes()].concat());
+    let init_code_hash = H256::from_str(UNISWAP_V2_INIT_CODE_HASH).unwrap();
+    let pair = get_create2_address_from_hash(factory, salt, init_code_hash);
+    (pair, stable_is_token0)
+}
+
+/*
+Chain specific settings.
+Only mainnet has a healthy builder market, testnets are served by the Flashbots relay only.
+
+L2s (Optimism, Base, Arbitrum) have no relay at all: transactions go straight to the sequencer.
+BSC has no Flashbots relay either, txs are sent to the public mempool and ordered by gas price.
+Arbitrum's sequencer is first come first served: priority fees don't order txs,
+so a frontrun can't be placed before a victim and sandwich mode refuses to run there (is_fcfs).
+
+Main currencies keep their mainnet names:
+- weth: the wrapped native token (WETH, WBNB), see native for the token itself
+- usdt: the primary stablecoin (USDT)
+- usdc: the secondary stablecoin (USDC, BUSD on BSC)
+- dai: DAI, what a lot of mid-cap tokens are paired with
+*/
+#[derive(Debug, Clone)]
+pub struct ChainProfile {
+    pub chain_id: u64,
+    pub name: String,
+    pub relay_url: Url,
+    pub builder_urls: HashMap<String, Url>,
+    pub is_l2: bool,
+    pub is_fcfs: bool, // the sequencer orders txs by arrival, not by fee
+    pub has_bundle_relay: bool,
+    pub block_time_secs: u64,
+    pub native: NativeCurrency,
+    pub weth: CurrencyInfo,
+    pub usdt: CurrencyInfo,
+    pub usdc: CurrencyInfo,
+    pub dai: CurrencyInfo,
+    // known stable -> wrapped native pairs, candidates of pricing.rs along with the loaded pools
+    pub usdt_weth_pair: ConversionPair,
+    pub usdc_weth_pair: ConversionPair,
+    pub dai_weth_pair: ConversionPair,
+    // V2 factories to discover pools from. Empty means any PairCreated event is accepted
+    pub factories: Vec<Factory>,
+    // the usual swap routers / aggregators, where victims come from (see load_shedding.rs)
+    pub routers: Vec<H160>,
+    pub default_fee: u32,
+    pub pool_sync_from_block: u64,
+    // EIP-1559 parameters. base_fee_max_change_denominator = 0 means a flat base fee (Arbitrum)
+    pub elasticity_multiplier: u64,
+    pub base_fee_max_change_denominator: u64,
+    // oldest first, empty means the latest spec (see hardforks.rs)
+    pub hardforks: Vec<Hardfork>,
+}
+
+impl ChainProfile {
+    pub fn new(chain_id: u64) -> Self {
+        let (name, relay_url) = if chain_id == GOERLI {
+            ("goerli", "https://relay-goerli.flashbots.net")
+        } else if chain_id == SEPOLIA {
+            ("sepolia", "https://relay-sepolia.flashbots.net")
+        } else if chain_id == OPTIMISM {
+            ("optimism", "https://relay.flashbots.net")
+        } else if chain_id == BASE {
+            ("base", "https://relay.flashbots.net")
+        } else if chain_id == ARBITRUM {
+            ("arbitrum", "https://relay.flashbots.net")
+        } else if chain_id == BSC {
+            ("bsc", "https://relay.flashbots.net")
+        } else {
+            ("mainnet", "https://relay.flashbots.net")
+        };
+        let is_l2 = chain_id == OPTIMISM || chain_id == BASE || chain_id == ARBITRUM;
+        let is_fcfs = chain_id == ARBITRUM;
+        let has_bundle_relay = !is_l2 && chain_id != BSC;
+        let (elasticity_multiplier, base_fee_max_change_denominator) =
+            if chain_id == OPTIMISM || chain_id == BASE {
+                (6, 250)
+            } else if chain_id == ARBITRUM || chain_id == BSC {
+                (1, 0)
+            } else {
+                (2, 8)
+            };
+        let relay_url = Url::parse(relay_url).unwrap();
+
+        // The endpoints here will gracefully fail if it doesn't work
+        let mut builder_urls = HashMap::new();
+        if has_bundle_relay {
+            builder_urls.insert("flashbots".to_string(), relay_url.clone());
+        }
+
+        if chain_id == MAINNET {
+            let builders = vec![
+                ("beaverbuild", "https://rpc.beaverbuild.org"),
+                ("rsync", "https://rsync-builder.xyz"),
+                ("titanbuilder", "https://rpc.titanbuilder.xyz"),
+                ("builder0x69", "https://builder0x69.io"),
+                ("f1b", "https://rpc.f1b.io"),
+                ("lokibuilder", "https://rpc.lokibuilder.xyz"),
+                ("eden", "https://api.edennetwork.io/v1/rpc"),
+                ("penguinbuild", "https://rpc.penguinbuild.org"),
+                ("gambit", "https://builder.gmbit.co/rpc"),
+                ("idcmev", "https://rpc.idcmev.xyz"),
+            ];
+            for (builder, url) in builders {
+                builder_urls.insert(builder.to_string(), Url::parse(url).unwrap());
+            }
+        }
+
+        let bsc = chain_id == BSC;
+
+        let block_time_secs = if bsc {
+            3
+        } else if chain_id == OPTIMISM || chain_id == BASE {
+            2
+        } else if chain_id == ARBITRUM {
+            1 // 250ms
+        } else {
+            12
+        };
+
+        let native = NativeCurrency {
+            symbol: String::from(if bsc { "BNB" } else { "ETH" }),
+            decimals: 18,
+        };
+
+        let (weth, usdt, usdc, dai) = if bsc {
+            (
+                CurrencyInfo::new("0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c", "WBNB", 18, 3),
+                CurrencyInfo::new("0x55d398326f99059fF775485246999027B3197955", "USDT", 18, 1),
+                CurrencyInfo::new("0xe9e7CEA3DedcA5984780Bafc599bD69ADd087D56", "BUSD", 18, 1),
+                CurrencyInfo::new("0x1AF3F329e8BE154074D8769D1FFa4eE058B1DBc3", "DAI", 18, 1),
+            )
+        } else if chain_id == OPTIMISM {
+            (
+                CurrencyInfo::new("0x4200000000000000000000000000000000000006", "WETH", 18, 3),
+                CurrencyInfo::new("0x94b008aA00579c1307B0EF2c499aD98a8ce58e58", "USDT", 6, 0),
+                CurrencyInfo::new("0x0b2C639c533813f4Aa9D7837CAf62653d097Ff85", "USDC", 6, 9),
+                CurrencyInfo::new("0xDA10009cBd5D07dd0CeCc66161FC93D7c9000da1", "DAI", 18, 2),
+            )
+        } else if chain_id == BASE {
+            (
+                CurrencyInfo::new("0x4200000000000000000000000000000000000006", "WETH", 18, 3),
+                CurrencyInfo::new("0xfde4C96c8593536E31F229EA8f37b2ADa2699bb2", "USDT", 6, 0),
+                CurrencyInfo::new("0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913", "USDC", 6, 9),
+                CurrencyInfo::new("0x50c5725949A6F0c72E6C4a641F24049A917DB0Cb", "DAI", 18, 0),
+            )
+        } else if chain_id == ARBITRUM {
+            (
+                CurrencyInfo::new("0x82aF49447D8a07e3bd95BD0d56f35241523fBab1", "WETH", 18, 51),
+                CurrencyInfo::new("0xFd086bC7CD5C481DCC9C85ebE478A1C0b69FCbb9", "USDT", 6, 51),
+                CurrencyInfo::new("0xaf88d065e77c8cC2239327C5EDb3A432268e5831", "USDC", 6, 9),
+                CurrencyInfo::new("0xDA10009cBd5D07dd0CeCc66161FC93D7c9000da1", "DAI", 18, 2),
+            )
+        } else {
+            (
+                CurrencyInfo::new(WETH, "WETH", WETH_DECIMALS, WETH_BALANCE_SLOT),
+                CurrencyInfo::new(USDT, "USDT", USDT_DECIMALS, USDT_BALANCE_SLOT),
+                CurrencyInfo::new(USDC, "USDC", USDC_DECIMALS, USDC_BALANCE_SLOT),
+                CurrencyInfo::new(DAI, "DAI", DAI_DECIMALS, DAI_BALANCE_SLOT),
+            )
+        };
+
+        // Uniswap V2 on the L2s, deployed in late 2023: the sync start only has to be before that
+        let l2_v2_factory = if chain_id == OPTIMISM {
+            Some(Factory {
+                address: H160::from_str("0x0c3c1c532F1e39EdF36BE9Fe0bE1410313E074Bf").unwrap(),
+                fee: 300,
+                deployed_block: 105235063, // Bedrock
+            })
+        } else if chain_id == BASE {
+            Some(Factory {
+                address: H160::from_str("0x8909Dc15e40173Ff4699343b6eB8132c65e18eC6").unwrap(),
+                fee: 300,
+                deployed_block: 0,
+            })
+        } else if chain_id == ARBITRUM {
+            Some(Factory {
+                address: H160::from_str("0xf1D7CC64Fb4452F05c498126312eBE29f30Fbcf9").unwrap(),
+                fee: 300,
+                deployed_block: 100000000,
+            })
+        } else {
+            None
+        };
+
+        let (usdt_weth_pair, usdc_weth_pair, dai_weth_pair) = if let Some(factory) = &l2_v2_factory
+        {
+            (
+                v2_conversion_pair(factory.address, usdt.address, weth.address),
+                v2_conversion_pair(factory.address, usdc.address, weth.address),
+                v2_conversion_pair(factory.address, dai.address, weth.address),
+            )
+        } else if bsc {
+            (
+                // token0: USDT / token1: WBNB
+                (
+                    H160::from_str("0x16b9a82891338f9bA80E2D6970FddA79D1eb0daE").unwrap(),
+                    true,
+                ),
+                // token0: WBNB / token1: BUSD
+                (
+                    H160::from_str("0x58F876857a02D6762E0101bb5C46A8c1ED44Dc16").unwrap(),
+                    false,
+                ),
+                // token0: DAI / token1: WBNB
+                (
+                    H160::from_str("0xc7c3cCCE4FA25700fD5574DA7E200ae28BBd36A3").unwrap(),
+                    true,
+                ),
+            )
+        } else {
+            (
+                // token0: WETH / token1: USDT
+                (
+                    H160::from_str("0x0d4a11d5EEaaC28EC3F61d100daF4d40471f1852").unwrap(),
+                    false,
+                ),
+                // token0: USDC / token1: WETH
+                (
+                    H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap(),
+                    true,
+                ),
+                // token0: DAI / token1: WETH
+                (
+                    H160::from_str("0xA478c2975Ab1Ea89e8196811F51A7B7Ade33eB11").unwrap(),
+                    true,
+                ),
+            )
+        };
+
+        let (factories, default_fee) = if bsc {
+            (
+                vec![Factory {
+                    // PancakeSwap V2: 0.25%
+                    address: H160::from_str("0xcA143Ce32Fe78f1f7019d7d551a6402fC5350c73").unwrap(),
+                    fee: 250,
+                    deployed_block: 6809737,
+                }],
+                250,
+            )
+        } else if let Some(factory) = l2_v2_factory {
+            (vec![factory], 300)
+        } else {
+            (Vec::new(), 300)
+        };
+        let routers: Vec<&str> = if chain_id == MAINNET {
+            vec![
+                "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D", // Uniswap V2: Router02
+                "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F", // Sushiswap: Router
+                "0xEf1c6E67703c7BD7107eed8303Fbe6EC2554BF6B", // Uniswap: Universal Router (old)
+                "0x3fC91A3afd70395Cd496C647d5a8CC8B4B7F1C9b", // Uniswap: Universal Router
+                "0x1111111254EEB25477B68fb85Ed929f73A960582", // 1inch v5: Aggregation Router
+                "0xDef1C0ded9bec7F1a1670819833240f027b25EfF", // 0x: Exchange Proxy
+                "0x881D40237659C251811CEC9c364ef91dC08D300C", // Metamask: Swap Router
+            ]
+        } else if bsc {
+            vec![
+                "0x10ED43C718714eb63d5aA57B78B54704E256024E", // Pancakeswap: Router v2
+                "0x1111111254EEB25477B68fb85Ed929f73A960582", // 1inch v5: Aggregation Router
+                "0xDef1C0ded9bec7F1a1670819833240f027b25EfF", // 0x: Exchange Proxy
+            ]
+        } else if chain_id == OPTIMISM {
+            vec![
+                "0x4A7b5Da61326A6379179b40d00F57E5bbDC962c2", // Uniswap V2: Router02
+                "0xCb1355ff08Ab38bBCE60111F1bb2B784bE25D7e8", // Uniswap: Universal Router
+                "0x1111111254EEB25477B68fb85Ed929f73A960582", // 1inch v5: Aggregation Router
+                "0xDEF1ABE32c034e558Cdd535791643C58a13aCC10", // 0x: Exchange Proxy
+            ]
+        } else if chain_id == BASE {
+            vec![
+                "0x4752ba5DBc23f44D87826276BF6Fd6b1C372aD24", // Uniswap V2: Router02
+                "0x3fC91A3afd70395Cd496C647d5a8CC8B4B7F1C9b", // Uniswap: Universal Router
+                "0x1111111254EEB25477B68fb85Ed929f73A960582", // 1inch v5: Aggregation Router
+                "0xDef1C0ded9bec7F1a1670819833240f027b25EfF", // 0x: Exchange Proxy
+            ]
+        } else if chain_id == ARBITRUM {
+            vec![
+                "0x4752ba5DBc23f44D87826276BF6Fd6b1C372aD24", // Uniswap V2: Router02
+                "0x5E325eDA8064b456f4781070C0738d849c824258", // Uniswap: Universal Router
+                "0x1111111254EEB25477B68fb85Ed929f73A960582", // 1inch v5: Aggregation Router
+                "0xDef1C0ded9bec7F1a1670819833240f027b25EfF", // 0x: Exchange Proxy
+            ]
+        } else {
+            Vec::new()
+        };
+        let routers = routers
+            .into_iter()
+            .map(|router| H160::from_str(router).unwrap())
+            .collect();
+
+        let pool_sync_from_block = factories
+            .iter()
+            .map(|f| f.deployed_block)
+            .min()
+            .unwrap_or(UNISWAP_V2_DEPLOYED_BLOCK);
+
+        Self {
+            chain_id,
+            name: name.to_string(),
+            relay_url,
+            builder_urls,
+            is_l2,
+            is_fcfs,
+            has_bundle_relay,
+            block_time_secs,
+            native,
+            weth,
+            usdt,
+            usdc,
+            dai,
+            usdt_weth_pair,
+            usdc_weth_pair,
+            dai_weth_pair,
+            factories,
+            routers,
+            default_fee,
+            pool_sync_from_block,
+            elasticity_multiplier,
+            base_fee_max_change_denominator,
+            hardforks: known_hardforks(chain_id),
+        }
+    }
+
+    // The known forks, plus / moved by the ones in HARDFORKS
+    pub fn with_hardforks(mut self, raw: &str) -> Self {
+        self.hardforks = load_hardforks(self.chain_id, raw);
+        self
+    }
+
+    // Spec of the last fork active at this block (blocks before the first one we know of get that one)
+    pub fn spec_at(&self, block_number: U64) -> SpecId {
+        match self
+            .hardforks
+            .iter()
+            .rev()
+            .find(|fork| fork.block <= block_number.as_u64())
+            .or(self.hardforks.first())
+        {
+            Some(fork) => fork.spec_id,
+            None => SpecId::LATEST,
+        }
+    }
+
+    pub fn next_hardfork(&self, block_number: U64) -> Option<&Hardfork> {
+        self.hardforks
+            .iter()
+            .find(|fork| fork.block > block_number.as_u64())
+    }
+
+    pub fn wrapped_native(&self) -> &CurrencyInfo {
+        &self.weth
+    }
+
+    pub fn main_currencies(&self) -> Vec<&CurrencyInfo> {
+        vec![&self.weth, &self.usdt, &self.usdc, &self.dai]
+    }
+
+    pub fn factory_fee(&self, factory: H160) -> u32 {
+        match self.factories.iter().find(|f| f.address == factory) {
+            Some(f) => f.fee,
+            None => self.default_fee,
+        }
+    }
+
+    // Number of blocks spanning roughly the given number of seconds (at least 1)
+    pub fn blocks_for_secs(&self, secs: u64) -> u64 {
+        std::cmp::max(secs / self.block_time_secs, 1)
+    }
+
+    pub fn next_block_base_fee(&self, gas_used: U256, gas_limit: U256, base_fee: U256) -> U256 {
+        calculate_next_block_base_fee_with(
+            gas_used,
+            gas_limit,
+            base_fee,
+            self.elasticity_multiplier,
+            self.base_fee_max_change_denominator,
+        )
+    }
+}
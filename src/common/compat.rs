@@ -0,0 +1,159 @@
+use alloy_primitives::{Address, B256, U256 as AU256};
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::providers::{Middleware, Provider};
+use ethers::types::{BlockNumber, Filter, H160, H256, U256, U64};
+
+use crate::common::provider::RpcClient;
+
+/*
+ethers-rs is deprecated, and we're moving to alloy one module at a time.
+This layer is what migrated modules talk to instead of ethers directly:
+1. ToAlloy / ToEthers convert the primitive types at the boundary with modules that still use ethers
+2. ChainReader is the read-only provider interface, with alloy-native types.
+   It's implemented for the ethers provider today, an alloy provider only has to implement the same trait.
+Migrated so far: streams.rs (block polling), pools.rs (pool discovery logs).
+*/
+pub trait ToAlloy {
+    type Alloy;
+    fn to_alloy(self) -> Self::Alloy;
+}
+
+pub trait ToEthers {
+    type Ethers;
+    fn to_ethers(self) -> Self::Ethers;
+}
+
+impl ToAlloy for H160 {
+    type Alloy = Address;
+    fn to_alloy(self) -> Address {
+        Address::from_slice(self.as_bytes())
+    }
+}
+
+impl ToEthers for Address {
+    type Ethers = H160;
+    fn to_ethers(self) -> H160 {
+        H160::from_slice(self.as_slice())
+    }
+}
+
+impl ToAlloy for H256 {
+    type Alloy = B256;
+    fn to_alloy(self) -> B256 {
+        B256::from_slice(self.as_bytes())
+    }
+}
+
+impl ToEthers for B256 {
+    type Ethers = H256;
+    fn to_ethers(self) -> H256 {
+        H256::from_slice(self.as_slice())
+    }
+}
+
+impl ToAlloy for U256 {
+    type Alloy = AU256;
+    fn to_alloy(self) -> AU256 {
+        let mut bytes = [0u8; 32];
+        self.to_big_endian(&mut bytes);
+        AU256::from_be_bytes(bytes)
+    }
+}
+
+impl ToEthers for AU256 {
+    type Ethers = U256;
+    fn to_ethers(self) -> U256 {
+        U256::from_big_endian(&self.to_be_bytes::<32>())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: B256,
+    pub parent_hash: B256,
+    pub timestamp: u64,
+    pub base_fee: AU256,
+    pub gas_used: AU256,
+    pub gas_limit: AU256,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub addresses: Vec<Address>, // empty: any address
+    pub topic0: Vec<B256>,       // empty: any event
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RawLog {
+    pub address: Address,
+    pub topics: Vec<B256>,
+    pub data: Vec<u8>,
+    pub block_number: Option<u64>,
+}
+
+#[async_trait]
+pub trait ChainReader: Send + Sync {
+    async fn block_number(&self) -> Result<u64>;
+
+    // None: latest block
+    async fn block_header(&self, number: Option<u64>) -> Result<Option<BlockHeader>>;
+
+    async fn logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>>;
+}
+
+#[async_trait]
+impl ChainReader for Provider<RpcClient> {
+    async fn block_number(&self) -> Result<u64> {
+        Ok(self.get_block_number().await?.as_u64())
+    }
+
+    async fn block_header(&self, number: Option<u64>) -> Result<Option<BlockHeader>> {
+        let id = match number {
+            Some(number) => BlockNumber::Number(U64::from(number)),
+            None => BlockNumber::Latest,
+        };
+        let block = match self.get_block(id).await? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        Ok(Some(BlockHeader {
+            number: block.number.unwrap_or_default().as_u64(),
+            hash: block.hash.unwrap_or_default().to_alloy(),
+            parent_hash: block.parent_hash.to_alloy(),
+            timestamp: block.timestamp.as_u64(),
+            base_fee: block.base_fee_per_gas.unwrap_or_default().to_alloy(),
+            gas_used: block.gas_used.to_alloy(),
+            gas_limit: block.gas_limit.to_alloy(),
+        }))
+    }
+
+    async fn logs(&self, filter: &LogFilter) -> Result<Vec<RawLog>> {
+        let mut ethers_filter = Filter::new()
+            .from_block(U64::from(filter.from_block))
+            .to_block(U64::from(filter.to_block));
+        if !filter.addresses.is_empty() {
+            let addresses: Vec<H160> = filter.addresses.iter().map(|a| a.to_ethers()).collect();
+            ethers_filter = ethers_filter.address(addresses);
+        }
+        if !filter.topic0.is_empty() {
+            let topic0: Vec<H256> = filter.topic0.iter().map(|t| t.to_ethers()).collect();
+            ethers_filter = ethers_filter.topic0(topic0);
+        }
+        let logs = self
+            .get_logs(&ethers_filter)
+            .await?
+            .into_iter()
+            .map(|log| RawLog {
+                address: log.address.to_alloy(),
+                topics: log.topics.into_iter().map(|t| t.to_alloy()).collect(),
+                data: log.data.to_vec(),
+                block_number: log.block_number.map(|n| n.as_u64()),
+            })
+            .collect();
+        Ok(logs)
+    }
+}
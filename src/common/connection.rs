@@ -0,0 +1,71 @@
+use ethers::providers::Provider;
+use log::{info, warn};
+use std::{sync::Arc, time::Duration};
+
+use crate::common::constants::Env;
+use crate::common::provider::{connect_provider, RpcClient};
+
+pub static WS_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/*
+WSS_URL can be a comma-separated list of endpoints.
+The connection manager hands out a connection to the current endpoint,
+and when a stream ends or stalls (no message within WS_STALL_TIMEOUT_SECS)
+the caller rotates to the next endpoint and reconnects.
+Each stream owns its own manager (and connection), so one stalled subscription doesn't take the others down.
+*/
+#[derive(Debug, Clone)]
+pub struct ConnectionManager {
+    pub urls: Vec<String>,
+    pub current: usize,
+    pub stall_timeout: Duration,
+}
+
+impl ConnectionManager {
+    pub fn new(env: &Env) -> Self {
+        let urls = env
+            .wss_url
+            .split(',')
+            .map(|url| url.trim())
+            .filter(|url| !url.is_empty())
+            .map(String::from)
+            .collect();
+        Self {
+            urls,
+            current: 0,
+            stall_timeout: Duration::from_secs(env.ws_stall_timeout_secs),
+        }
+    }
+
+    pub fn current_url(&self) -> &str {
+        &self.urls[self.current]
+    }
+
+    pub fn rotate(&mut self) {
+        if !self.urls.is_empty() {
+            self.current = (self.current + 1) % self.urls.len();
+        }
+    }
+
+    // Tries every endpoint once, starting from the current one
+    pub async fn connect(&mut self) -> Option<Arc<Provider<RpcClient>>> {
+        for _ in 0..self.urls.len() {
+            let url = self.current_url().to_string();
+            let connection = tokio::time::timeout(
+                Duration::from_secs(WS_CONNECT_TIMEOUT_SECS),
+                connect_provider(&url),
+            )
+            .await;
+            match connection {
+                Ok(Ok(provider)) => {
+                    info!("Connected to websocket endpoint #{}", self.current);
+                    return Some(provider);
+                }
+                Ok(Err(e)) => warn!("Websocket endpoint #{} error: {e:?}", self.current),
+                Err(_) => warn!("Websocket endpoint #{} connection timed out", self.current),
+            }
+            self.rotate();
+        }
+        None
+    }
+}
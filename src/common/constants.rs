@@ -1,11 +1,87 @@
+use ethers::prelude::Lazy;
+use log::warn;
+use std::{collections::HashMap, fs, path::Path, sync::RwLock};
+
 pub static PROJECT_NAME: &str = "sandooo";
 
+/*
+Layered configuration: a setting comes from the environment (.env) when it's set there,
+otherwise from the TOML file at CONFIG_FILE (default: config.toml, optional), otherwise its default.
+The file uses the same names as the env, in any case, and tables are only there to group settings:
+    [sandwich]
+    max_amount_in_weth = 100
+    max_token_exposure = ["WETH:40", "USDT:120000"]   # arrays are joined with commas
+See config.example.toml. The file is read again on a config reload (see reload.rs).
+*/
+pub static DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+static CONFIG_FILE_VALUES: Lazy<RwLock<HashMap<String, String>>> =
+    Lazy::new(|| RwLock::new(read_config_file()));
+
+fn toml_to_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Array(items) => items
+            .iter()
+            .map(toml_to_string)
+            .collect::<Vec<String>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+fn flatten_config(table: &toml::Table, values: &mut HashMap<String, String>) {
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(table) => flatten_config(table, values),
+            value => {
+                values.insert(key.to_uppercase(), toml_to_string(value));
+            }
+        }
+    }
+}
+
+pub fn read_config_file() -> HashMap<String, String> {
+    let path = match std::env::var("CONFIG_FILE") {
+        Ok(path) if !path.is_empty() => path,
+        _ => String::from(DEFAULT_CONFIG_FILE),
+    };
+    let mut values = HashMap::new();
+    if !Path::new(&path).exists() {
+        return values;
+    }
+    match fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|raw| Ok(raw.parse::<toml::Table>()?))
+    {
+        Ok(table) => flatten_config(&table, &mut values),
+        Err(e) => warn!("Invalid config file {}: {e:?}", path),
+    }
+    values
+}
+
+pub fn reload_config_file() -> usize {
+    let values = read_config_file();
+    let count = values.len();
+    *CONFIG_FILE_VALUES.write().unwrap() = values;
+    count
+}
+
 pub fn get_env(key: &str) -> String {
-    std::env::var(key).unwrap_or(String::from(""))
+    match std::env::var(key) {
+        Ok(value) if !value.is_empty() => value,
+        _ => CONFIG_FILE_VALUES
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .unwrap_or_default(),
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Env {
+    pub chain_id: u64,
     pub https_url: String,
     pub wss_url: String,
     pub bot_address: String,
@@ -15,11 +91,96 @@ pub struct Env {
     pub telegram_chat_id: String,
     pub use_alert: bool,
     pub debug: bool,
+    pub dry_run: bool,
+    pub simulation_cache: bool,
+    pub simulation_workers: usize, // 0: one per core
+    pub backrun_arbitrage: bool,   // backrun the victims we can't sandwich
+    pub reserve_tracking: bool,    // V2 reserves from Sync events instead of getReserves
+    pub confirmations: u64,
+    pub beacon_genesis_time: u64,
+    pub seconds_per_slot: u64,
+    pub slot_submit_earliest_ms: u64,
+    pub slot_submit_latest_ms: u64,
+    pub max_victims_per_bundle: usize,
+    pub max_inflight_bundles_per_pool: usize,
+    pub max_inflight_exposure: String,
+    pub max_sandwich_amount: String,
+    pub max_token_exposure: String,
+    pub max_block_notional: String,
+    pub telemetry: bool,
+    pub telemetry_url: String,
+    pub experiment_name: String,
+    pub experiment_arms: String,
+    pub pool_sync_concurrency: usize,
+    pub control_api_addr: String,
+    pub control_api_token: String,
+    pub dashboard: bool,
+    pub hardforks: String,
+    pub alert_webhook_url: String,
+    pub max_amount_in_weth: u64,         // optimizer ceiling, whole WETH
+    pub max_amount_in_stables: u64,      // optimizer ceiling, whole USDT/USDC/DAI
+    pub optimize_tolerance_weth: f64,    // optimizer stops at this step size, in WETH
+    pub optimize_tolerance_stables: f64, // optimizer stops at this step size, in USDT/USDC/DAI
+    pub analytical_sizing: bool,         // size V2 buys from the reserves before any grid search
+    pub min_pool_liquidity_weth: f64,    // skip pools with less, both sides in WETH (0: off)
+    pub pending_tx_expiry_blocks: u64,   // 0: ~36 seconds worth of blocks
+    pub gas_limit_pct: u64,              // gas limit, in % of the simulated gas used
+    pub bribe_gas_pct: u64, // gas the bribe (priority fee) is spread over, in % of the backrun's gas used
+    pub bribe_pct_aggressive: u64, // bps of the revenue, aggressive profile
+    pub bribe_pct_conservative: u64, // bps of the revenue, conservative profile
+    pub rpc_compute_units_per_second: u64,
+    pub rpc_max_retries: u32,
+    pub rpc_provider: String,
+    pub max_drawdown_eth: f64,
+    pub drawdown_window_hours: u64,
+    pub bundle_archive_dir: String,
+    pub bundle_archive_retention_days: u64,
+    pub ws_stall_timeout_secs: u64,
+    pub event_channel_capacity: usize,
+    pub extra_routers: String, // comma separated, on top of the chain profile's routers
+    pub max_promising_sandwiches: usize,
+    pub dual_simulation: bool,
+    pub competing_prestate: bool, // estimate revenue behind the best-paying pending swaps on our pools
+    pub competing_prestate_txs: usize, // at most this many of them
+    pub max_victim_state_fetches: usize,
+    pub max_victim_trace_depth: usize,
+    pub token_allowlist_file: String,
+    pub token_denylist_file: String,
+    pub mev_share: bool,
+    pub mev_share_url: String,
+    pub mev_share_relay_url: String,
+    pub token_screen_concurrency: usize,
+    pub bloxroute_ws_url: String,
+    pub bloxroute_auth_header: String,
+    pub external_feed_addr: String,
+    pub external_feed_signer: String,
+    pub max_bundle_gas_pct: u64,
+    pub min_bundle_gas_pct: u64,
+    pub operating_mode: String,
+    pub competitor_policy: String,
+    pub sandwich_bots_path: String,
+    pub competitor_bribe_bump_bps: u64, // bps of the revenue added to the bribe on pools a bot is after (outbid)
+    pub bundle_target_blocks: u64,
+    pub builders_config: String,
+    pub execution_contexts_config: String,
+    pub bundle_bribe_decay_bps: u64,
+    pub reputation_check_interval_secs: u64,
+    pub bribe_policy: String,
+    pub bribe_floor_bps: u64,
+    pub bribe_ceiling_bps: u64,
+    pub bribe_history_path: String,
+    pub kill_switch_file: String,
+    pub cache_backend: String,
+    pub cache_url: String,
+    pub store_flush_interval_ms: u64,
+    pub store_write_batch_size: usize,
+    pub loss_streak_limit: u64,
 }
 
 impl Env {
     pub fn new() -> Self {
         Env {
+            chain_id: get_env("CHAIN_ID").parse::<u64>().unwrap_or(1),
             https_url: get_env("HTTPS_URL"),
             wss_url: get_env("WSS_URL"),
             bot_address: get_env("BOT_ADDRESS"),
@@ -29,15 +190,184 @@ impl Env {
             telegram_chat_id: get_env("TELEGRAM_CHAT_ID"),
             use_alert: get_env("USE_ALERT").parse::<bool>().unwrap(),
             debug: get_env("DEBUG").parse::<bool>().unwrap(),
+            dry_run: get_env("DRY_RUN").parse::<bool>().unwrap_or(false),
+            simulation_cache: get_env("SIMULATION_CACHE").parse::<bool>().unwrap_or(true),
+            simulation_workers: get_env("SIMULATION_WORKERS").parse::<usize>().unwrap_or(0),
+            confirmations: get_env("CONFIRMATIONS").parse::<u64>().unwrap_or(3),
+            beacon_genesis_time: get_env("BEACON_GENESIS_TIME")
+                .parse::<u64>()
+                .unwrap_or(MAINNET_BEACON_GENESIS_TIME),
+            seconds_per_slot: get_env("SECONDS_PER_SLOT").parse::<u64>().unwrap_or(12),
+            slot_submit_earliest_ms: get_env("SLOT_SUBMIT_EARLIEST_MS")
+                .parse::<u64>()
+                .unwrap_or(12000),
+            slot_submit_latest_ms: get_env("SLOT_SUBMIT_LATEST_MS").parse::<u64>().unwrap_or(0),
+            max_victims_per_bundle: get_env("MAX_VICTIMS_PER_BUNDLE")
+                .parse::<usize>()
+                .unwrap_or(10),
+            max_inflight_bundles_per_pool: get_env("MAX_INFLIGHT_BUNDLES_PER_POOL")
+                .parse::<usize>()
+                .unwrap_or(3),
+            max_inflight_exposure: get_env("MAX_INFLIGHT_EXPOSURE"),
+            max_sandwich_amount: get_env("MAX_SANDWICH_AMOUNT"),
+            max_token_exposure: get_env("MAX_TOKEN_EXPOSURE"),
+            max_block_notional: get_env("MAX_BLOCK_NOTIONAL"),
+            telemetry: get_env("TELEMETRY").parse::<bool>().unwrap_or(false),
+            telemetry_url: get_env("TELEMETRY_URL"),
+            experiment_name: match get_env("EXPERIMENT_NAME").as_str() {
+                "" => String::from("bribe_pct"),
+                name => String::from(name),
+            },
+            experiment_arms: get_env("EXPERIMENT_ARMS"),
+            pool_sync_concurrency: get_env("POOL_SYNC_CONCURRENCY")
+                .parse::<usize>()
+                .unwrap_or(8),
+            control_api_addr: get_env("CONTROL_API_ADDR"),
+            control_api_token: get_env("CONTROL_API_TOKEN"),
+            dashboard: get_env("DASHBOARD").parse::<bool>().unwrap_or(false),
+            hardforks: get_env("HARDFORKS"),
+            alert_webhook_url: get_env("ALERT_WEBHOOK_URL"),
+            max_amount_in_weth: get_env("MAX_AMOUNT_IN_WETH").parse::<u64>().unwrap_or(100),
+            max_amount_in_stables: get_env("MAX_AMOUNT_IN_STABLES")
+                .parse::<u64>()
+                .unwrap_or(300000),
+            optimize_tolerance_weth: get_env("OPTIMIZE_TOLERANCE_WETH")
+                .parse::<f64>()
+                .unwrap_or(0.0001),
+            optimize_tolerance_stables: get_env("OPTIMIZE_TOLERANCE_STABLES")
+                .parse::<f64>()
+                .unwrap_or(0.001),
+            analytical_sizing: get_env("ANALYTICAL_SIZING").parse::<bool>().unwrap_or(true),
+            min_pool_liquidity_weth: get_env("MIN_POOL_LIQUIDITY_WETH")
+                .parse::<f64>()
+                .unwrap_or(1.0),
+            pending_tx_expiry_blocks: get_env("PENDING_TX_EXPIRY_BLOCKS")
+                .parse::<u64>()
+                .unwrap_or(0),
+            backrun_arbitrage: get_env("BACKRUN_ARBITRAGE").parse::<bool>().unwrap_or(true),
+            reserve_tracking: get_env("RESERVE_TRACKING").parse::<bool>().unwrap_or(true),
+            gas_limit_pct: get_env("GAS_LIMIT_PCT").parse::<u64>().unwrap_or(130),
+            bribe_gas_pct: get_env("BRIBE_GAS_PCT").parse::<u64>().unwrap_or(105),
+            bribe_pct_aggressive: get_env("BRIBE_PCT_AGGRESSIVE")
+                .parse::<u64>()
+                .unwrap_or(9900),
+            bribe_pct_conservative: get_env("BRIBE_PCT_CONSERVATIVE")
+                .parse::<u64>()
+                .unwrap_or(9000),
+            rpc_compute_units_per_second: get_env("RPC_COMPUTE_UNITS_PER_SECOND")
+                .parse::<u64>()
+                .unwrap_or(0),
+            rpc_max_retries: get_env("RPC_MAX_RETRIES").parse::<u32>().unwrap_or(5),
+            rpc_provider: get_env("RPC_PROVIDER"),
+            max_drawdown_eth: get_env("MAX_DRAWDOWN_ETH").parse::<f64>().unwrap_or(0.0),
+            drawdown_window_hours: get_env("DRAWDOWN_WINDOW_HOURS").parse::<u64>().unwrap_or(6),
+            bundle_archive_dir: match get_env("BUNDLE_ARCHIVE_DIR").as_str() {
+                "" => String::from("cache/bundles"),
+                dir => String::from(dir),
+            },
+            bundle_archive_retention_days: get_env("BUNDLE_ARCHIVE_RETENTION_DAYS")
+                .parse::<u64>()
+                .unwrap_or(7),
+            ws_stall_timeout_secs: get_env("WS_STALL_TIMEOUT_SECS")
+                .parse::<u64>()
+                .unwrap_or(30),
+            event_channel_capacity: get_env("EVENT_CHANNEL_CAPACITY")
+                .parse::<usize>()
+                .unwrap_or(4096),
+            extra_routers: get_env("EXTRA_ROUTERS"),
+            max_promising_sandwiches: get_env("MAX_PROMISING_SANDWICHES")
+                .parse::<usize>()
+                .unwrap_or(1000),
+            dual_simulation: get_env("DUAL_SIMULATION").parse::<bool>().unwrap_or(false),
+            competing_prestate: get_env("COMPETING_PRESTATE")
+                .parse::<bool>()
+                .unwrap_or(false),
+            competing_prestate_txs: get_env("COMPETING_PRESTATE_TXS")
+                .parse::<usize>()
+                .unwrap_or(3),
+            max_victim_state_fetches: get_env("MAX_VICTIM_STATE_FETCHES")
+                .parse::<usize>()
+                .unwrap_or(500),
+            max_victim_trace_depth: get_env("MAX_VICTIM_TRACE_DEPTH")
+                .parse::<usize>()
+                .unwrap_or(16),
+            token_allowlist_file: get_env("TOKEN_ALLOWLIST_FILE"),
+            token_denylist_file: get_env("TOKEN_DENYLIST_FILE"),
+            mev_share: get_env("MEV_SHARE").parse::<bool>().unwrap_or(false),
+            mev_share_url: match get_env("MEV_SHARE_URL").as_str() {
+                "" => String::from("https://mev-share.flashbots.net"),
+                url => String::from(url),
+            },
+            mev_share_relay_url: match get_env("MEV_SHARE_RELAY_URL").as_str() {
+                "" => String::from("https://relay.flashbots.net"),
+                url => String::from(url),
+            },
+            token_screen_concurrency: get_env("TOKEN_SCREEN_CONCURRENCY")
+                .parse::<usize>()
+                .unwrap_or(8),
+            bloxroute_ws_url: get_env("BLOXROUTE_WS_URL"),
+            bloxroute_auth_header: get_env("BLOXROUTE_AUTH_HEADER"),
+            external_feed_addr: get_env("EXTERNAL_FEED_ADDR"),
+            external_feed_signer: get_env("EXTERNAL_FEED_SIGNER"),
+            max_bundle_gas_pct: get_env("MAX_BUNDLE_GAS_PCT").parse::<u64>().unwrap_or(30),
+            min_bundle_gas_pct: get_env("MIN_BUNDLE_GAS_PCT").parse::<u64>().unwrap_or(3),
+            operating_mode: get_env("OPERATING_MODE"),
+            competitor_policy: get_env("COMPETITOR_POLICY"),
+            sandwich_bots_path: match get_env("SANDWICH_BOTS_PATH").as_str() {
+                "" => String::from("cache/sandwich_bots.json"),
+                path => String::from(path),
+            },
+            competitor_bribe_bump_bps: get_env("COMPETITOR_BRIBE_BUMP_BPS")
+                .parse::<u64>()
+                .unwrap_or(300),
+            bundle_target_blocks: get_env("BUNDLE_TARGET_BLOCKS").parse::<u64>().unwrap_or(1),
+            builders_config: get_env("BUILDERS_CONFIG"),
+            execution_contexts_config: get_env("EXECUTION_CONTEXTS_CONFIG"),
+            bundle_bribe_decay_bps: get_env("BUNDLE_BRIBE_DECAY_BPS")
+                .parse::<u64>()
+                .unwrap_or(2000),
+            reputation_check_interval_secs: get_env("REPUTATION_CHECK_INTERVAL_SECS")
+                .parse::<u64>()
+                .unwrap_or(600),
+            bribe_policy: get_env("BRIBE_POLICY"),
+            bribe_floor_bps: get_env("BRIBE_FLOOR_BPS").parse::<u64>().unwrap_or(5000),
+            bribe_ceiling_bps: get_env("BRIBE_CEILING_BPS").parse::<u64>().unwrap_or(9900),
+            bribe_history_path: match get_env("BRIBE_HISTORY_PATH").as_str() {
+                "" => String::from("cache/bribes.json"),
+                path => String::from(path),
+            },
+            kill_switch_file: get_env("KILL_SWITCH_FILE"),
+            cache_backend: get_env("CACHE_BACKEND"),
+            cache_url: get_env("CACHE_URL"),
+            store_flush_interval_ms: get_env("STORE_FLUSH_INTERVAL_MS")
+                .parse::<u64>()
+                .unwrap_or(1000),
+            store_write_batch_size: get_env("STORE_WRITE_BATCH_SIZE")
+                .parse::<usize>()
+                .unwrap_or(500),
+            loss_streak_limit: get_env("LOSS_STREAK_LIMIT").parse::<u64>().unwrap_or(3),
+        }
+    }
+
+    // HTTPS_URL for heavy calls, falls back to the (first) websocket when it's not set
+    pub fn rpc_url(&self) -> String {
+        match self.https_url.as_str() {
+            "" => String::from(self.wss_url.split(',').next().unwrap_or("").trim()),
+            url => String::from(url),
         }
     }
 }
 
+pub static MAINNET_BEACON_GENESIS_TIME: u64 = 1606824023;
+
 pub static COINBASE: &str = "0xDAFEA492D9c6733ae3d56b7Ed1ADB60692c98Bc5"; // Flashbots Builder
 
+pub static CURVE_REGISTRY: &str = "0x90E00ACe148ca3b23Ac1bC8C240C2a7Dd9c2d7f5"; // Curve main registry
+
 pub static WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
 pub static USDT: &str = "0xdAC17F958D2ee523a2206206994597C13D831ec7";
 pub static USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+pub static DAI: &str = "0x6B175474E89094C44Da98b954EedeAC495271d0F";
 
 /*
 Can figure out the balance slot of ERC-20 tokens using the:
@@ -49,7 +379,9 @@ Especially tokens that are using proxy patterns.
 pub static WETH_BALANCE_SLOT: i32 = 3;
 pub static USDT_BALANCE_SLOT: i32 = 2;
 pub static USDC_BALANCE_SLOT: i32 = 9;
+pub static DAI_BALANCE_SLOT: i32 = 2;
 
 pub static WETH_DECIMALS: u8 = 18;
 pub static USDT_DECIMALS: u8 = 6;
 pub static USDC_DECIMALS: u8 = 6;
+pub static DAI_DECIMALS: u8 = 18;
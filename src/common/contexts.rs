@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Result};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::H160;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path, str::FromStr};
+
+use crate::common::chain::CHAIN;
+use crate::common::constants::{get_env, Env};
+use crate::common::keys::load_wallet;
+use crate::common::nonce::NonceManager;
+
+/*
+Bot contracts we execute through, each with its own owner key and inventory scope, loaded from
+EXECUTION_CONTEXTS_CONFIG (JSON) when the file exists. Otherwise BOT_ADDRESS / PRIVATE_KEY is the only
+("default") context and trades every main currency.
+[
+  { "name": "weth", "bot_address": "0x...", "private_key_env": "WETH_BOT_PRIVATE_KEY", "main_currencies": ["WETH"] },
+  { "name": "stables", "bot_address": "0x...", "private_key_env": "STABLES_BOT_PRIVATE_KEY", "main_currencies": ["USDT", "USDC", "DAI"] }
+]
+- private_key_env: the env var holding the owner's private key (or keystore, see keys.rs), so keys stay out of the config file
+- main_currencies: symbols this context holds inventory in (default: empty, every main currency)
+A main currency is routed to the first context whose scope includes it, and a bundle goes through
+a single bot contract, so sandwiches routed to different contexts are never bundled together.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextConfig {
+    pub name: String,
+    pub bot_address: String,
+    pub private_key_env: String,
+    #[serde(default)]
+    pub main_currencies: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecutionContext {
+    pub name: String,
+    pub owner: LocalWallet,
+    pub bot_address: H160,
+    pub main_currencies: Vec<H160>, // empty: every main currency
+    pub nonces: NonceManager,       // per owner, shared by every executor using this context
+}
+
+impl ExecutionContext {
+    pub fn covers(&self, main_currency: H160) -> bool {
+        self.main_currencies.is_empty() || self.main_currencies.contains(&main_currency)
+    }
+}
+
+pub fn default_context(env: &Env) -> ExecutionContext {
+    ExecutionContext {
+        name: String::from("default"),
+        owner: load_wallet("PRIVATE_KEY", &env.private_key)
+            .unwrap()
+            .with_chain_id(env.chain_id),
+        bot_address: H160::from_str(&env.bot_address).unwrap(),
+        main_currencies: Vec::new(),
+        nonces: NonceManager::new(),
+    }
+}
+
+pub fn read_contexts_config(path: &str, chain_id: u64) -> Result<Vec<ExecutionContext>> {
+    let configs: Vec<ContextConfig> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut contexts = Vec::new();
+    for config in configs {
+        let owner = load_wallet(&config.private_key_env, &get_env(&config.private_key_env))
+            .map_err(|e| anyhow!("{}: {e:?}", config.name))?
+            .with_chain_id(chain_id);
+        let mut main_currencies = Vec::new();
+        for symbol in &config.main_currencies {
+            match CHAIN
+                .main_currencies()
+                .iter()
+                .find(|mc| mc.symbol.eq_ignore_ascii_case(symbol))
+            {
+                Some(mc) => main_currencies.push(mc.address),
+                None => return Err(anyhow!("{}: unknown main currency {}", config.name, symbol)),
+            }
+        }
+        contexts.push(ExecutionContext {
+            bot_address: H160::from_str(&config.bot_address)?,
+            name: config.name,
+            owner,
+            main_currencies,
+            nonces: NonceManager::new(),
+        });
+    }
+    if contexts.is_empty() {
+        return Err(anyhow!("No execution contexts"));
+    }
+    Ok(contexts)
+}
+
+pub fn load_contexts(env: &Env) -> Vec<ExecutionContext> {
+    let path = env.execution_contexts_config.as_str();
+    if path.is_empty() || !Path::new(path).exists() {
+        return vec![default_context(env)];
+    }
+    match read_contexts_config(path, env.chain_id) {
+        Ok(contexts) => {
+            info!("Loaded {} execution contexts from {}", contexts.len(), path);
+            contexts
+        }
+        Err(e) => {
+            warn!(
+                "Invalid execution contexts config {}, using BOT_ADDRESS / PRIVATE_KEY: {e:?}",
+                path
+            );
+            vec![default_context(env)]
+        }
+    }
+}
+
+// Index of the context that trades this main currency
+pub fn route(contexts: &[ExecutionContext], main_currency: H160) -> Option<usize> {
+    contexts.iter().position(|c| c.covers(main_currency))
+}
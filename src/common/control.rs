@@ -0,0 +1,431 @@
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Path, State},
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{Html, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use bounded_vec_deque::BoundedVecDeque;
+use ethers::types::{H160, H256, U256, U64};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use crate::common::constants::Env;
+use crate::common::dashboard::{DashboardState, DASHBOARD_HTML};
+use crate::common::execution::{parse_withdrawal_token, BuilderCounts, BuilderStats, Executor};
+use crate::common::kill_switch::KillSwitch;
+use crate::common::pools::Pool;
+use crate::common::profile::{switch_mode, OperatingMode, OperatingProfile};
+use crate::common::redact::RedactedJson;
+use crate::common::reload::ConfigReloader;
+use crate::common::token_lists::TokenLists;
+use crate::common::tokens::Token;
+use crate::sandwich::simulation::SkipReason;
+
+/*
+Control API (CONTROL_API_ADDR, disabled when empty).
+Read-through lookups on the bot's in-memory state, so operators can quickly
+investigate an address mentioned in an alert:
+- GET /token/{address}: cached metadata, safety check results, stats, recent sandwiches
+- GET /pool/{address}: pool info, both tokens, stats, recent sandwiches
+- GET /mode, POST /mode/{aggressive|conservative}: current operating profile, switch profiles
+- GET /builders: accepted / rejected / timed out bundle submissions per builder
+- GET /halt, POST /halt, POST /resume: kill switch state, stop / resume bundle submission
+- POST /bribe/{bps}: override the operating profile's bribe_pct (until the next mode switch)
+- POST /token-lists/reload: re-read TOKEN_DENYLIST_FILE / TOKEN_ALLOWLIST_FILE
+- POST /reload: reload .env into the running bot (see reload.rs)
+- POST /withdraw {"token": "WETH", "amount": "1000000000000000000", "context": "default"}:
+  send tokens from a bot contract to its owner. token is a main currency symbol, the native currency
+  symbol or an address, amount is in wei (default: the whole balance), context defaults to the first one
+- GET /dashboard, GET /dashboard/state: web dashboard and the state it shows (with DASHBOARD=true, see dashboard.rs)
+With CONTROL_API_TOKEN set, every request needs an "Authorization: Bearer <token>" header
+(or a ?token=<token> query, so the dashboard can be opened in a browser).
+Without it, the API only binds to a loopback address. Responses go through the secret redaction layer.
+*/
+pub static RECENT_SANDWICHES_PER_ADDRESS: usize = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SandwichRecord {
+    pub tx_hash: H256,
+    pub block_number: U64,
+    pub pair: H160,
+    pub main_currency: H160,
+    pub target_token: H160,
+    pub amount_in: U256,
+    pub max_revenue: U256,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AddressStats {
+    pub victim_swaps: u64,
+    pub promising_sandwiches: u64,
+    pub skips: HashMap<&'static str, u64>, // SkipReason --> count
+}
+
+pub struct ControlState {
+    pub pools: HashMap<H160, Pool>,
+    pub tokens: HashMap<H160, Token>,
+    pub stats: HashMap<H160, AddressStats>,
+    pub recent_sandwiches: HashMap<H160, BoundedVecDeque<SandwichRecord>>,
+    pub profile: OperatingProfile,
+    pub builder_stats: BuilderStats,
+    pub kill_switch: KillSwitch,
+    pub dashboard: DashboardState,
+    pub token_lists: Arc<TokenLists>, // swapped whole on reload, the strategy holds on to its copy per event
+    pub executor: Executor,
+    pub config_reloader: ConfigReloader,
+}
+
+pub type SharedControlState = Arc<RwLock<ControlState>>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenReport {
+    pub token: Token,
+    pub pool_count: usize,
+    pub stats: AddressStats,
+    pub recent_sandwiches: Vec<SandwichRecord>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolReport {
+    pub pool: Pool,
+    pub token0: Option<Token>,
+    pub token1: Option<Token>,
+    pub stats: AddressStats,
+    pub recent_sandwiches: Vec<SandwichRecord>,
+}
+
+impl ControlState {
+    pub fn new(
+        pools: HashMap<H160, Pool>,
+        tokens: HashMap<H160, Token>,
+        profile: OperatingProfile,
+        token_lists: TokenLists,
+        executor: Executor,
+    ) -> SharedControlState {
+        Arc::new(RwLock::new(Self {
+            pools,
+            tokens,
+            stats: HashMap::new(),
+            recent_sandwiches: HashMap::new(),
+            profile,
+            builder_stats: executor.builder_stats.clone(),
+            kill_switch: executor.kill_switch.clone(),
+            dashboard: DashboardState::default(),
+            token_lists: Arc::new(token_lists),
+            executor,
+            config_reloader: ConfigReloader::new(),
+        }))
+    }
+
+    // victim swaps are accounted on both the pair and the traded token
+    pub fn record_victim_swap(&mut self, pair: H160, target_token: H160) {
+        for address in [pair, target_token] {
+            self.stats.entry(address).or_default().victim_swaps += 1;
+        }
+    }
+
+    pub fn record_skip(&mut self, pair: H160, target_token: H160, reason: SkipReason) {
+        for address in [pair, target_token] {
+            *self
+                .stats
+                .entry(address)
+                .or_default()
+                .skips
+                .entry(reason.as_str())
+                .or_insert(0) += 1;
+        }
+    }
+
+    pub fn record_sandwich(&mut self, record: SandwichRecord) {
+        for address in [record.pair, record.target_token] {
+            self.stats.entry(address).or_default().promising_sandwiches += 1;
+            self.recent_sandwiches
+                .entry(address)
+                .or_insert_with(|| BoundedVecDeque::new(RECENT_SANDWICHES_PER_ADDRESS))
+                .push_back(record.clone());
+        }
+    }
+
+    fn stats_of(&self, address: H160) -> AddressStats {
+        self.stats.get(&address).cloned().unwrap_or_default()
+    }
+
+    fn recent_sandwiches_of(&self, address: H160) -> Vec<SandwichRecord> {
+        match self.recent_sandwiches.get(&address) {
+            Some(records) => records.iter().rev().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn token_report(&self, address: H160) -> Option<TokenReport> {
+        let token = self.tokens.get(&address)?.clone();
+        Some(TokenReport {
+            pool_count: token.pool_ids.len(),
+            token,
+            stats: self.stats_of(address),
+            recent_sandwiches: self.recent_sandwiches_of(address),
+        })
+    }
+
+    pub fn pool_report(&self, address: H160) -> Option<PoolReport> {
+        let pool = *self.pools.get(&address)?;
+        Some(PoolReport {
+            pool,
+            token0: self.tokens.get(&pool.token0).cloned(),
+            token1: self.tokens.get(&pool.token1).cloned(),
+            stats: self.stats_of(address),
+            recent_sandwiches: self.recent_sandwiches_of(address),
+        })
+    }
+}
+
+async fn get_token(
+    State(state): State<SharedControlState>,
+    Path(address): Path<String>,
+) -> Result<RedactedJson<TokenReport>, StatusCode> {
+    let address = H160::from_str(&address).map_err(|_| StatusCode::BAD_REQUEST)?;
+    match state.read().unwrap().token_report(address) {
+        Some(report) => Ok(RedactedJson(report)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_pool(
+    State(state): State<SharedControlState>,
+    Path(address): Path<String>,
+) -> Result<RedactedJson<PoolReport>, StatusCode> {
+    let address = H160::from_str(&address).map_err(|_| StatusCode::BAD_REQUEST)?;
+    match state.read().unwrap().pool_report(address) {
+        Some(report) => Ok(RedactedJson(report)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_mode(State(state): State<SharedControlState>) -> RedactedJson<OperatingProfile> {
+    RedactedJson(state.read().unwrap().profile.clone())
+}
+
+async fn set_mode(
+    State(state): State<SharedControlState>,
+    Path(mode): Path<String>,
+) -> Result<RedactedJson<OperatingProfile>, StatusCode> {
+    match OperatingMode::parse(&mode) {
+        Some(mode) => Ok(RedactedJson(switch_mode(&state, mode))),
+        None => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+async fn get_builders(
+    State(state): State<SharedControlState>,
+) -> RedactedJson<HashMap<String, BuilderCounts>> {
+    RedactedJson(state.read().unwrap().builder_stats.snapshot())
+}
+
+async fn get_halt(State(state): State<SharedControlState>) -> RedactedJson<bool> {
+    RedactedJson(state.read().unwrap().kill_switch.is_halted())
+}
+
+async fn halt(State(state): State<SharedControlState>) -> RedactedJson<bool> {
+    let kill_switch = state.read().unwrap().kill_switch.clone();
+    kill_switch.halt("control API");
+    RedactedJson(kill_switch.is_halted())
+}
+
+async fn resume(State(state): State<SharedControlState>) -> RedactedJson<bool> {
+    let kill_switch = state.read().unwrap().kill_switch.clone();
+    kill_switch.resume("control API");
+    RedactedJson(kill_switch.is_halted())
+}
+
+async fn set_bribe(
+    State(state): State<SharedControlState>,
+    Path(bps): Path<u64>,
+) -> Result<RedactedJson<OperatingProfile>, StatusCode> {
+    if bps > 10000 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut state = state.write().unwrap();
+    state.profile.bribe_pct = U256::from(bps);
+    info!("Bribe set to {} bps", bps);
+    Ok(RedactedJson(state.profile.clone()))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenListsReport {
+    pub allow: Option<usize>, // None: every token is allowed
+    pub deny: usize,
+}
+
+async fn reload_token_lists(
+    State(state): State<SharedControlState>,
+) -> RedactedJson<TokenListsReport> {
+    let env = Env::new();
+    let mut state = state.write().unwrap();
+    let token_lists = TokenLists::load(&env, &state.tokens);
+    let report = TokenListsReport {
+        allow: token_lists.allow.as_ref().map(|allow| allow.len()),
+        deny: token_lists.deny.len(),
+    };
+    state.token_lists = Arc::new(token_lists);
+    info!("Token lists reloaded: {:?}", report);
+    RedactedJson(report)
+}
+
+// The strategy reloads before its next event
+async fn reload_config(State(state): State<SharedControlState>) -> RedactedJson<bool> {
+    state.read().unwrap().config_reloader.request("control API");
+    RedactedJson(true)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WithdrawalRequest {
+    pub token: String,
+    pub amount: Option<String>,
+    pub context: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WithdrawalReport {
+    pub context: String,
+    pub token: H160,
+    pub amount: U256,
+    pub tx_hash: H256,
+}
+
+async fn withdraw(
+    State(state): State<SharedControlState>,
+    Json(request): Json<WithdrawalRequest>,
+) -> Result<RedactedJson<WithdrawalReport>, (StatusCode, String)> {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST, message);
+    let token = parse_withdrawal_token(&request.token)
+        .ok_or_else(|| bad_request(format!("Unknown token {}", request.token)))?;
+    let executor = {
+        let state = state.read().unwrap();
+        let context = match &request.context {
+            Some(name) => state
+                .executor
+                .contexts
+                .iter()
+                .find(|c| &c.name == name)
+                .ok_or_else(|| bad_request(format!("Unknown context {}", name)))?,
+            None => &state.executor.contexts[0],
+        };
+        state.executor.with_context(context)
+    };
+    let internal_error = |e: anyhow::Error| (StatusCode::INTERNAL_SERVER_ERROR, format!("{e:?}"));
+
+    let amount = match &request.amount {
+        Some(amount) => U256::from_dec_str(amount)
+            .map_err(|_| bad_request(format!("Invalid amount {}", amount)))?,
+        None => executor.bot_balance(token).await.map_err(internal_error)?,
+    };
+    if amount.is_zero() {
+        return Err(bad_request(String::from("Nothing to withdraw")));
+    }
+
+    let tx_hash = executor
+        .withdraw(token, amount)
+        .await
+        .map_err(internal_error)?;
+    info!(
+        "Withdrawal of {} {:?} from {} ({:?}) sent: {:?}",
+        amount, token, executor.context, executor.bot_address, tx_hash
+    );
+    Ok(RedactedJson(WithdrawalReport {
+        context: executor.context.clone(),
+        token,
+        amount,
+        tx_hash,
+    }))
+}
+
+async fn get_dashboard() -> Html<&'static str> {
+    Html(DASHBOARD_HTML)
+}
+
+async fn get_dashboard_state(
+    State(state): State<SharedControlState>,
+) -> RedactedJson<DashboardState> {
+    RedactedJson(state.read().unwrap().dashboard.clone())
+}
+
+// Compares every byte, so the time taken doesn't tell how much of the token was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn require_token<B>(
+    State(token): State<String>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            request
+                .uri()
+                .query()
+                .and_then(|query| query.split('&').find_map(|kv| kv.strip_prefix("token=")))
+        })
+        .unwrap_or_default();
+    if !constant_time_eq(provided.trim().as_bytes(), token.as_bytes()) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(next.run(request).await)
+}
+
+pub async fn serve_control_api(
+    addr: String,
+    token: String,
+    dashboard: bool,
+    state: SharedControlState,
+) -> Result<()> {
+    let addr = SocketAddr::from_str(&addr)?;
+    if token.is_empty() && !addr.ip().is_loopback() {
+        return Err(anyhow!(
+            "Control API on {:?} needs CONTROL_API_TOKEN outside localhost",
+            addr
+        ));
+    }
+
+    let mut router = Router::new()
+        .route("/token/:address", get(get_token))
+        .route("/pool/:address", get(get_pool))
+        .route("/mode", get(get_mode))
+        .route("/mode/:mode", post(set_mode))
+        .route("/builders", get(get_builders))
+        .route("/halt", get(get_halt).post(halt))
+        .route("/resume", post(resume))
+        .route("/bribe/:bps", post(set_bribe))
+        .route("/token-lists/reload", post(reload_token_lists))
+        .route("/reload", post(reload_config))
+        .route("/withdraw", post(withdraw));
+    if dashboard {
+        router = router
+            .route("/dashboard", get(get_dashboard))
+            .route("/dashboard/state", get(get_dashboard_state));
+    }
+    let mut app = router.with_state(state);
+    if !token.is_empty() {
+        app = app.route_layer(middleware::from_fn_with_state(token, require_token));
+    }
+
+    info!("Control API listening on {:?}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+
+    Ok(())
+}
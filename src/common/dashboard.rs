@@ -0,0 +1,270 @@
+use anyhow::Result;
+use ethers::providers::{Middleware, Provider};
+use ethers::signers::Signer;
+use ethers::types::{H160, H256, U256, U64};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::common::chain::CHAIN;
+use crate::common::contexts::ExecutionContext;
+use crate::common::latency::now_ms;
+use crate::common::multicall::get_token_balances_multicall;
+use crate::common::provider::RpcClient;
+use crate::common::tracker::{BundleStatus, TrackedBundle};
+use crate::sandwich::simulation::{PendingTxInfo, Sandwich};
+
+/*
+Web dashboard (DASHBOARD=true, served by the control API so it needs CONTROL_API_ADDR).
+GET /dashboard is a single page polling GET /dashboard/state, a snapshot of the strategy's
+in-memory state it refreshes as it goes:
+- pending sandwich candidates: victim txs we're tracking and the pairs they touch
+- promising sandwiches: optimized, waiting to be bundled in main_dish
+- the last DASHBOARD_RECENT_BUNDLES submitted bundles and their status
+- owner / bot contract balances of every execution context, every DASHBOARD_BALANCE_BLOCKS blocks
+With CONTROL_API_TOKEN set, open it as /dashboard?token=<token>.
+*/
+pub static DASHBOARD_RECENT_BUNDLES: usize = 50;
+pub static DASHBOARD_BALANCE_BLOCKS: u64 = 5;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CandidateView {
+    pub tx_hash: H256,
+    pub from: H160,
+    pub pairs: Vec<H160>,
+    pub added_block: Option<U64>,
+    pub traced_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PromisingView {
+    pub tx_hash: H256,
+    pub pair: H160,
+    pub main_currency: H160,
+    pub target_token: H160,
+    pub amount_in: U256,
+    pub max_revenue: U256,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleView {
+    pub bundle_id: String,
+    pub context: String,
+    pub target_block: U64,
+    pub included_block: Option<U64>,
+    pub status: BundleStatus,
+    pub expected_profit: i128,
+    pub pairs: Vec<H160>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContextBalances {
+    pub name: String,
+    pub owner: H160,
+    pub owner_balance: U256, // native currency
+    pub bot_address: H160,
+    pub bot_balances: BTreeMap<String, U256>, // main currency symbol --> balance
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DashboardState {
+    pub block_number: U64,
+    pub updated_ms: u64,
+    pub candidates: Vec<CandidateView>,
+    pub promising: Vec<PromisingView>,
+    pub bundles: VecDeque<BundleView>, // newest first
+    pub balances: Vec<ContextBalances>,
+    pub balances_block: U64,
+}
+
+impl BundleView {
+    pub fn from_bundle(bundle: &TrackedBundle) -> Self {
+        Self {
+            bundle_id: bundle.bundle_id.clone(),
+            context: bundle.context.clone(),
+            target_block: bundle.target_block,
+            included_block: bundle.included_block.map(|(number, _)| number),
+            status: bundle.status.clone(),
+            expected_profit: bundle.expected_profit,
+            pairs: bundle.pairs.clone(),
+        }
+    }
+}
+
+impl DashboardState {
+    pub fn update_candidates(
+        &mut self,
+        block_number: U64,
+        pending_txs: &HashMap<H256, PendingTxInfo>,
+        promising_sandwiches: &HashMap<H256, Vec<Sandwich>>,
+    ) {
+        self.block_number = block_number;
+        self.updated_ms = now_ms();
+
+        self.candidates = pending_txs
+            .iter()
+            .map(|(tx_hash, info)| CandidateView {
+                tx_hash: *tx_hash,
+                from: info.pending_tx.tx.from,
+                pairs: info.touched_pairs.iter().map(|s| s.target_pair).collect(),
+                added_block: info.pending_tx.added_block,
+                traced_ms: info.traced_ms,
+            })
+            .collect();
+        self.candidates
+            .sort_by(|a, b| b.traced_ms.cmp(&a.traced_ms));
+
+        self.promising = Vec::new();
+        for (tx_hash, sandwiches) in promising_sandwiches {
+            for sandwich in sandwiches {
+                if let Some(optimized) = &sandwich.optimized_sandwich {
+                    self.promising.push(PromisingView {
+                        tx_hash: *tx_hash,
+                        pair: sandwich.swap_info.target_pair,
+                        main_currency: sandwich.swap_info.main_currency,
+                        target_token: sandwich.swap_info.target_token,
+                        amount_in: optimized.amount_in,
+                        max_revenue: optimized.max_revenue,
+                    });
+                }
+            }
+        }
+        self.promising
+            .sort_by(|a, b| b.max_revenue.cmp(&a.max_revenue));
+    }
+
+    // a bundle already shown is updated in place, a new one goes on top
+    pub fn record_bundle(&mut self, bundle: &TrackedBundle) {
+        let view = BundleView::from_bundle(bundle);
+        match self
+            .bundles
+            .iter_mut()
+            .find(|b| b.bundle_id == view.bundle_id)
+        {
+            Some(existing) => *existing = view,
+            None => {
+                self.bundles.push_front(view);
+                self.bundles.truncate(DASHBOARD_RECENT_BUNDLES);
+            }
+        }
+    }
+
+    pub fn balances_due(&self, block_number: U64) -> bool {
+        self.balances.is_empty()
+            || block_number >= self.balances_block + U64::from(DASHBOARD_BALANCE_BLOCKS)
+    }
+
+    pub fn set_balances(&mut self, block_number: U64, balances: Vec<ContextBalances>) {
+        self.balances = balances;
+        self.balances_block = block_number;
+    }
+}
+
+pub async fn get_context_balances(
+    provider: &Arc<Provider<RpcClient>>,
+    contexts: &[ExecutionContext],
+    block_number: U64,
+) -> Result<Vec<ContextBalances>> {
+    let mut balances = Vec::new();
+    for context in contexts {
+        let owner = context.owner.address();
+        let owner_balance = provider
+            .get_balance(owner, Some(block_number.into()))
+            .await?;
+        let main_currencies: Vec<_> = CHAIN
+            .main_currencies()
+            .into_iter()
+            .filter(|mc| context.covers(mc.address))
+            .collect();
+        let tokens = main_currencies.iter().map(|mc| mc.address).collect();
+        let token_balances =
+            get_token_balances_multicall(provider, context.bot_address, &tokens, block_number)
+                .await?;
+        balances.push(ContextBalances {
+            name: context.name.clone(),
+            owner,
+            owner_balance,
+            bot_address: context.bot_address,
+            bot_balances: main_currencies
+                .iter()
+                .map(|mc| {
+                    (
+                        mc.symbol.clone(),
+                        token_balances.get(&mc.address).cloned().unwrap_or_default(),
+                    )
+                })
+                .collect(),
+        });
+    }
+    Ok(balances)
+}
+
+pub static DASHBOARD_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>sandooo</title>
+<style>
+  body { font-family: monospace; font-size: 12px; margin: 16px; background: #111; color: #ddd; }
+  h2 { font-size: 14px; margin: 20px 0 6px; }
+  table { border-collapse: collapse; }
+  th, td { padding: 2px 10px 2px 0; text-align: left; }
+  th { color: #888; }
+  .confirmed, .included { color: #6c6; }
+  .missed, .reorged { color: #c66; }
+</style>
+</head>
+<body>
+<div id="status">loading...</div>
+<h2>Balances</h2><table id="balances"></table>
+<h2>Promising sandwiches</h2><table id="promising"></table>
+<h2>Pending candidates</h2><table id="candidates"></table>
+<h2>Recent bundles</h2><table id="bundles"></table>
+<script>
+const token = new URLSearchParams(location.search).get("token");
+const headers = token ? { Authorization: "Bearer " + token } : {};
+
+function table(id, columns, rows, rowClass) {
+  const head = "<tr>" + columns.map(c => "<th>" + c[0] + "</th>").join("") + "</tr>";
+  const body = rows.map(r =>
+    "<tr class='" + (rowClass ? rowClass(r) : "") + "'>" +
+    columns.map(c => "<td>" + c[1](r) + "</td>").join("") + "</tr>").join("");
+  document.getElementById(id).innerHTML = head + body;
+}
+
+async function refresh() {
+  try {
+    const response = await fetch("/dashboard/state", { headers });
+    if (!response.ok) throw new Error(response.status);
+    const s = await response.json();
+    document.getElementById("status").textContent =
+      "Block #" + parseInt(s.block_number) + " / updated " + new Date(s.updated_ms).toLocaleTimeString();
+    table("balances", [
+      ["context", r => r.name], ["owner", r => r.owner], ["owner balance", r => BigInt(r.owner_balance)],
+      ["bot", r => r.bot_address],
+      ["bot balances", r => Object.entries(r.bot_balances).map(([k, v]) => k + ": " + BigInt(v)).join(", ")],
+    ], s.balances);
+    table("promising", [
+      ["victim", r => r.tx_hash], ["pair", r => r.pair], ["main currency", r => r.main_currency],
+      ["amount in", r => BigInt(r.amount_in)], ["max revenue", r => BigInt(r.max_revenue)],
+    ], s.promising);
+    table("candidates", [
+      ["victim", r => r.tx_hash], ["from", r => r.from], ["pairs", r => r.pairs.join(", ")],
+      ["added block", r => r.added_block ? parseInt(r.added_block) : ""],
+    ], s.candidates);
+    table("bundles", [
+      ["bundle", r => r.bundle_id], ["context", r => r.context], ["target block", r => parseInt(r.target_block)],
+      ["included block", r => r.included_block ? parseInt(r.included_block) : ""],
+      ["status", r => r.status], ["expected profit", r => r.expected_profit],
+    ], s.bundles, r => r.status.toLowerCase());
+  } catch (e) {
+    document.getElementById("status").textContent = "error: " + e.message;
+  }
+}
+
+refresh();
+setInterval(refresh, 2000);
+</script>
+</body>
+</html>
+"#;
@@ -0,0 +1,113 @@
+use ethers::types::H256;
+use log::info;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/*
+Every pending tx source (node mempool, bloXroute, the external feed) publishes into the same channel.
+TxDedup sits in front of the channel: a tx hash is only let through the first time
+any source sees it, so the strategy never traces the same victim twice.
+
+When a slower source sees the same tx later, we record how far behind it was,
+which is logged periodically per source to tell whether a source is worth paying for.
+*/
+// How many recent tx hashes we remember
+pub static DEDUP_CAPACITY: usize = 50000;
+pub static DEDUP_REPORT_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TxSource {
+    Node,
+    Bloxroute,
+    External,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceStats {
+    pub first: u64,         // txs this source delivered before any other
+    pub late: u64,          // txs another source already delivered
+    pub total_lag_ms: u128, // sum of how late this source was on those
+}
+
+#[derive(Debug)]
+struct DedupState {
+    first_seen: HashMap<H256, (TxSource, Instant)>,
+    order: VecDeque<H256>,
+    stats: HashMap<TxSource, SourceStats>,
+    last_report: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct TxDedup {
+    inner: Arc<Mutex<DedupState>>,
+}
+
+impl Default for TxDedup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TxDedup {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(DedupState {
+                first_seen: HashMap::new(),
+                order: VecDeque::new(),
+                stats: HashMap::new(),
+                last_report: Instant::now(),
+            })),
+        }
+    }
+
+    // true if no source has seen this tx yet, and it should be sent to the strategy
+    pub fn observe(&self, tx_hash: H256, source: TxSource) -> bool {
+        let mut guard = self.inner.lock().unwrap();
+        let state = &mut *guard;
+        let now = Instant::now();
+
+        let is_first = match state.first_seen.get(&tx_hash) {
+            Some(&(first_source, first_seen)) => {
+                if first_source != source {
+                    let lag = now.duration_since(first_seen).as_millis();
+                    let stats = state.stats.entry(source).or_default();
+                    stats.late += 1;
+                    stats.total_lag_ms += lag;
+                }
+                false
+            }
+            None => {
+                state.first_seen.insert(tx_hash, (source, now));
+                state.order.push_back(tx_hash);
+                if state.order.len() > DEDUP_CAPACITY {
+                    if let Some(oldest) = state.order.pop_front() {
+                        state.first_seen.remove(&oldest);
+                    }
+                }
+                state.stats.entry(source).or_default().first += 1;
+                true
+            }
+        };
+
+        if state.last_report.elapsed() >= Duration::from_secs(DEDUP_REPORT_INTERVAL_SECS) {
+            for (source, stats) in &state.stats {
+                let avg_lag_ms = if stats.late > 0 {
+                    stats.total_lag_ms / stats.late as u128
+                } else {
+                    0
+                };
+                info!(
+                    "[{:?}] First: {} / Late: {} (avg {}ms behind)",
+                    source, stats.first, stats.late, avg_lag_ms
+                );
+            }
+            state.stats.clear();
+            state.last_report = now;
+        }
+
+        is_first
+    }
+}
@@ -2,21 +2,24 @@ use anyhow::{anyhow, Result};
 use ethers::prelude::abi;
 use ethers::providers::Middleware;
 use ethers::types::{transaction::eip2930::AccessList, H160, H256, U256, U64};
-use foundry_evm_mini::evm::executor::fork::{BlockchainDb, BlockchainDbMeta, SharedBackend};
+use foundry_evm_mini::evm::executor::fork::SharedBackend;
 use foundry_evm_mini::evm::executor::inspector::{get_precompiles_for, AccessListTracer};
 use revm::primitives::bytes::Bytes as rBytes;
 use revm::primitives::{Bytes, Log, B160};
 use revm::{
     db::{CacheDB, Database},
+    interpreter::{opcode, InstructionResult, Interpreter},
     primitives::{
         keccak256, AccountInfo, Bytecode, ExecutionResult, Output, TransactTo, B256, U256 as rU256,
     },
-    EVM,
+    EVMData, Inspector, EVM,
 };
-use std::{collections::BTreeSet, default::Default, str::FromStr, sync::Arc};
+use std::{collections::HashSet, default::Default, str::FromStr, sync::Arc};
 
 use crate::common::abi::Abi;
+use crate::common::chain::CHAIN;
 use crate::common::constants::COINBASE;
+use crate::common::fork_db::fork_db;
 use crate::common::utils::{access_list_to_revm, create_new_wallet};
 
 #[derive(Debug, Clone, Default)]
@@ -65,6 +68,69 @@ pub struct TxResult {
     pub gas_refunded: u64,
 }
 
+/*
+Salmonella: tokens engineered to trap sandwich bots. They behave like a normal token when
+called by a regular user, but detect a bot (tx.origin isn't the caller, the tx is bundled and
+pays the builder) and silently send less, or nothing, back.
+The inspector watches the code of the given tokens while a tx runs and flags:
+- OriginBalanceCheck: the token reads tx.origin's balance (ORIGIN followed by BALANCE)
+- OriginRead: the token reads tx.origin at all, which transfers have no use for
+- CoinbaseRead: the token reads block.coinbase, e.g. to only misbehave in builder blocks
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SalmonellaFlag {
+    OriginBalanceCheck,
+    OriginRead,
+    CoinbaseRead,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SalmonellaInspector {
+    pub watched: HashSet<B160>,
+    pub flags: HashSet<(H160, SalmonellaFlag)>,
+    last_origin_read: Option<B160>, // the contract that ran ORIGIN as its previous opcode
+}
+
+impl SalmonellaInspector {
+    pub fn new(tokens: &[H160]) -> Self {
+        Self {
+            watched: tokens.iter().map(|token| (*token).into()).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for SalmonellaInspector {
+    fn step(&mut self, interp: &mut Interpreter, _data: &mut EVMData<'_, DB>) -> InstructionResult {
+        let address = interp.contract.address;
+        if !self.watched.contains(&address) {
+            return InstructionResult::Continue;
+        }
+        let op = interp.current_opcode();
+        match op {
+            opcode::ORIGIN => {
+                self.flags
+                    .insert((address.into(), SalmonellaFlag::OriginRead));
+            }
+            opcode::BALANCE if self.last_origin_read == Some(address) => {
+                self.flags
+                    .insert((address.into(), SalmonellaFlag::OriginBalanceCheck));
+            }
+            opcode::COINBASE => {
+                self.flags
+                    .insert((address.into(), SalmonellaFlag::CoinbaseRead));
+            }
+            _ => {}
+        }
+        self.last_origin_read = if op == opcode::ORIGIN {
+            Some(address)
+        } else {
+            None
+        };
+        InstructionResult::Continue
+    }
+}
+
 #[derive(Clone)]
 pub struct EvmSimulator<M> {
     pub provider: Arc<M>,
@@ -76,19 +142,8 @@ pub struct EvmSimulator<M> {
 
 impl<M: Middleware + 'static> EvmSimulator<M> {
     pub fn new(provider: Arc<M>, owner: Option<H160>, block_number: U64) -> Self {
-        let shared_backend = SharedBackend::spawn_backend_thread(
-            provider.clone(),
-            BlockchainDb::new(
-                BlockchainDbMeta {
-                    cfg_env: Default::default(),
-                    block_env: Default::default(),
-                    hosts: BTreeSet::from(["".to_string()]),
-                },
-                None,
-            ),
-            Some(block_number.into()),
-        );
-        let db = CacheDB::new(shared_backend);
+        // the block's shared fork, see fork_db.rs
+        let db = fork_db(provider.clone(), block_number);
         EvmSimulator::new_with_db(provider, owner, block_number, db)
     }
 
@@ -107,6 +162,7 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
         evm.database(db);
 
         evm.env.block.number = rU256::from(block_number.as_u64() + 1);
+        evm.env.cfg.spec_id = CHAIN.spec_at(block_number + U64::from(1));
         evm.env.block.coinbase = H160::from_str(COINBASE).unwrap().into();
 
         Self {
@@ -125,10 +181,17 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
     pub fn insert_db(&mut self, db: CacheDB<SharedBackend>) {
         let mut evm = EVM::new();
         evm.database(db);
+        evm.env.cfg.spec_id = self.evm.env.cfg.spec_id;
 
         self.evm = evm;
     }
 
+    // Simulates in a later block than the one after the fork's, on the same state
+    pub fn set_block_number(&mut self, block_number: U64) {
+        self.evm.env.block.number = rU256::from(block_number.as_u64());
+        self.evm.env.cfg.spec_id = CHAIN.spec_at(block_number);
+    }
+
     pub fn get_block_number(&mut self) -> U256 {
         self.evm.env.block.number.into()
     }
@@ -146,16 +209,12 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
     }
 
     pub fn get_access_list(&mut self, tx: Tx) -> Result<AccessList> {
-        self.evm.env.tx.caller = tx.caller.into();
-        self.evm.env.tx.transact_to = TransactTo::Call(tx.transact_to.into());
-        self.evm.env.tx.data = tx.data;
-        self.evm.env.tx.value = tx.value.into();
-        self.evm.env.tx.gas_price = tx.gas_price.into();
-        self.evm.env.tx.gas_limit = tx.gas_limit;
+        let (caller, transact_to) = (tx.caller, tx.transact_to);
+        self.set_tx_env(tx);
         let mut access_list_tracer = AccessListTracer::new(
             Default::default(),
-            tx.caller.into(),
-            tx.transact_to.into(),
+            caller.into(),
+            transact_to.into(),
             get_precompiles_for(self.evm.env.cfg.spec_id),
         );
         let access_list = match self.evm.inspect_ref(&mut access_list_tracer) {
@@ -177,13 +236,33 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
         self._call(tx, true)
     }
 
-    pub fn _call(&mut self, tx: Tx, commit: bool) -> Result<TxResult> {
+    // Commits the tx like call, flagging salmonella behavior of the given tokens along the way
+    pub fn call_with_salmonella_check(
+        &mut self,
+        tx: Tx,
+        tokens: &[H160],
+    ) -> Result<(TxResult, HashSet<(H160, SalmonellaFlag)>)> {
+        self.set_tx_env(tx);
+        let mut inspector = SalmonellaInspector::new(tokens);
+        let result = match self.evm.inspect_commit(&mut inspector) {
+            Ok(result) => result,
+            Err(e) => return Err(anyhow!("EVM call failed: {:?}", e)),
+        };
+        let output = Self::tx_result(result)?;
+        Ok((output, inspector.flags))
+    }
+
+    fn set_tx_env(&mut self, tx: Tx) {
         self.evm.env.tx.caller = tx.caller.into();
         self.evm.env.tx.transact_to = TransactTo::Call(tx.transact_to.into());
         self.evm.env.tx.data = tx.data;
         self.evm.env.tx.value = tx.value.into();
         self.evm.env.tx.gas_price = tx.gas_price.into();
         self.evm.env.tx.gas_limit = tx.gas_limit;
+    }
+
+    pub fn _call(&mut self, tx: Tx, commit: bool) -> Result<TxResult> {
+        self.set_tx_env(tx);
 
         let result;
 
@@ -200,6 +279,10 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
             result = ref_tx.result;
         }
 
+        Self::tx_result(result)
+    }
+
+    fn tx_result(result: ExecutionResult) -> Result<TxResult> {
         let output = match result {
             ExecutionResult::Success {
                 gas_used,
@@ -270,17 +353,49 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
         self.insert_account_info(target, contract_info);
     }
 
-    pub fn get_eth_balance_of(&mut self, target: H160) -> U256 {
+    pub fn get_native_balance_of(&mut self, target: H160) -> U256 {
         let acc = self.basic(target).unwrap().unwrap();
         acc.balance.into()
     }
 
-    pub fn set_eth_balance(&mut self, target: H160, amount: U256) {
+    pub fn set_native_balance(&mut self, target: H160, amount: U256) {
         let user_balance = amount.into();
         let user_info = AccountInfo::new(user_balance, 0, B256::zero(), Bytecode::default());
         self.insert_account_info(target.into(), user_info);
     }
 
+    /*
+    revm charges value + gas_limit * gas_price upfront, where a victim's gas price is its max fee.
+    The node checks the same thing before accepting the tx, so with the victim's own gas limit
+    a balance that can't cover it means the victim can't land: that's an error, not a top up.
+    Only when the gas limit is unknown (defaulted to 5M) can the victim fail here while being
+    valid on-chain: then the gas part is topped up, as long as the balance covers the value,
+    keeping the victim's nonce and code.
+    */
+    pub fn fund_caller(&mut self, victim_tx: &VictimTx) -> Result<()> {
+        let tx = Tx::from(victim_tx.clone());
+        let required = tx
+            .value
+            .saturating_add(U256::from(tx.gas_limit).saturating_mul(tx.gas_price));
+        let mut account_info = self.basic(tx.caller)?.unwrap_or_default();
+        let balance = U256::from(account_info.balance);
+        if balance >= required {
+            return Ok(());
+        }
+        if victim_tx.gas_limit.is_some() || balance < tx.value {
+            return Err(anyhow!(
+                "{:?} can't pay for {:?}: balance {:?} < {:?}",
+                tx.caller,
+                victim_tx.tx_hash,
+                balance,
+                required
+            ));
+        }
+        account_info.balance = required.into();
+        self.insert_account_info(tx.caller, account_info);
+        Ok(())
+    }
+
     pub fn get_token_balance(&mut self, token_address: H160, owner: H160) -> Result<U256> {
         let calldata = self.abi.token.encode("balanceOf", owner)?;
         let value = self.staticcall(Tx {
@@ -324,6 +439,133 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
         Ok((out.0, out.1))
     }
 
+    // paused() of Pausable tokens (OpenZeppelin, USDC), tokens without it revert --> not paused
+    pub fn is_paused(&mut self, token_address: H160) -> bool {
+        let value = self.staticcall(Tx {
+            caller: self.owner,
+            transact_to: token_address,
+            data: rBytes::from_static(&[0x5c, 0x97, 0x5a, 0xbb]),
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        });
+        match value {
+            Ok(value) if value.output.len() >= 32 => {
+                !U256::from_big_endian(&value.output[..32]).is_zero()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn approve(&mut self, token_address: H160, spender: H160, amount: U256) -> Result<()> {
+        let calldata = self.abi.token.encode("approve", (spender, amount))?;
+        self.call(Tx {
+            caller: self.owner,
+            transact_to: token_address,
+            data: calldata.0,
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        })?;
+        Ok(())
+    }
+
+    // Owner's native token --> wrapped native token
+    pub fn wrap_native(&mut self, amount: U256) -> Result<()> {
+        let calldata = self.abi.wrapped_native.encode("deposit", ())?;
+        self.call(Tx {
+            caller: self.owner,
+            transact_to: CHAIN.wrapped_native().address,
+            data: calldata.0,
+            value: amount,
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        })?;
+        Ok(())
+    }
+
+    // Owner's wrapped native token --> native token
+    pub fn unwrap_native(&mut self, amount: U256) -> Result<()> {
+        let calldata = self.abi.wrapped_native.encode("withdraw", amount)?;
+        self.call(Tx {
+            caller: self.owner,
+            transact_to: CHAIN.wrapped_native().address,
+            data: calldata.0,
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        })?;
+        Ok(())
+    }
+
+    pub fn transfer_token(&mut self, token_address: H160, to: H160, amount: U256) -> Result<()> {
+        let calldata = self.abi.token.encode("transfer", (to, amount))?;
+        self.call(Tx {
+            caller: self.owner,
+            transact_to: token_address,
+            data: calldata.0,
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        })?;
+        Ok(())
+    }
+
+    pub fn v2_swap(
+        &mut self,
+        pair_address: H160,
+        amount0_out: U256,
+        amount1_out: U256,
+        to: H160,
+    ) -> Result<TxResult> {
+        let calldata = self.abi.pair.encode(
+            "swap",
+            (amount0_out, amount1_out, to, ethers::types::Bytes::new()),
+        )?;
+        self.call(Tx {
+            caller: self.owner,
+            transact_to: pair_address,
+            data: calldata.0,
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        })
+    }
+
+    pub fn curve_get_dy(&mut self, pool: H160, i: i128, j: i128, dx: U256) -> Result<U256> {
+        let calldata = self.abi.curve_pool.encode("get_dy", (i, j, dx))?;
+        let value = self.staticcall(Tx {
+            caller: self.owner,
+            transact_to: pool,
+            data: calldata.0,
+            value: U256::zero(),
+            gas_price: U256::zero(),
+            gas_limit: 5000000,
+        })?;
+        let out = self.abi.curve_pool.decode_output("get_dy", value.output)?;
+        Ok(out)
+    }
+
+    pub fn curve_exchange(
+        &mut self,
+        pool: H160,
+        i: i128,
+        j: i128,
+        dx: U256,
+        min_dy: U256,
+        gas_price: U256,
+    ) -> Result<TxResult> {
+        let calldata = self.abi.curve_pool.encode("exchange", (i, j, dx, min_dy))?;
+        self.call(Tx {
+            caller: self.owner,
+            transact_to: pool,
+            data: calldata.0,
+            value: U256::zero(),
+            gas_price,
+            gas_limit: 5000000,
+        })
+    }
+
     pub fn get_balance_slot(&mut self, token_address: H160) -> Result<i32> {
         let calldata = self.abi.token.encode("balanceOf", token_address)?;
         self.evm.env.tx.caller = self.owner.into();
@@ -353,3 +595,31 @@ impl<M: Middleware + 'static> EvmSimulator<M> {
         Ok(-1)
     }
 }
+
+// Replays a tx on a fork: mined txs on top of their parent block, pending ones on top of the latest block
+pub async fn replay_tx<M: Middleware + 'static>(
+    provider: Arc<M>,
+    tx_hash: H256,
+) -> Result<(U64, Result<TxResult>)> {
+    let tx = provider
+        .get_transaction(tx_hash)
+        .await?
+        .ok_or(anyhow!("transaction {:?} not found", tx_hash))?;
+    let block_number = match tx.block_number {
+        Some(mined_at) => mined_at - 1,
+        None => provider.get_block_number().await?,
+    };
+
+    let victim_tx = VictimTx {
+        tx_hash,
+        from: tx.from,
+        to: tx.to.unwrap_or_default(),
+        data: tx.input.0.clone().into(),
+        value: tx.value,
+        gas_price: tx.gas_price.unwrap_or_default(),
+        gas_limit: Some(tx.gas.as_u64()),
+    };
+
+    let mut simulator = EvmSimulator::new(provider, None, block_number);
+    Ok((block_number, simulator.call(Tx::from(victim_tx))))
+}
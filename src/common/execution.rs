@@ -1,17 +1,31 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use ethers::prelude::*;
 use ethers::providers::{Middleware, Provider};
 use ethers::signers::{LocalWallet, Signer};
 use ethers::types::transaction::{eip2718::TypedTransaction, eip2930::AccessList};
 use ethers_flashbots::*;
-use serde::Deserialize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use url::Url;
 
 use crate::common::abi::Abi;
+use crate::common::archive::BundleArchive;
+use crate::common::builders::{load_builders, Builder};
+use crate::common::chain::{ChainProfile, CHAIN};
 use crate::common::constants::Env;
+use crate::common::contexts::{load_contexts, route, ExecutionContext};
+use crate::common::keys::load_wallet;
+use crate::common::kill_switch::KillSwitch;
+use crate::common::latency::{now_ms, BundleTimeline};
+use crate::common::nonce::NonceManager;
+use crate::common::provider::RpcClient;
+use crate::common::replacement::{variant_uuid, ScheduledBundles, ScheduledSend};
+use crate::common::slot::SlotClock;
+use crate::common::utils::get_token_balance_at;
 
 #[derive(Debug, Clone)]
 pub struct SandoBundle {
@@ -20,100 +34,388 @@ pub struct SandoBundle {
     pub backrun_tx: TypedTransaction,
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+// The highest the base fee can be blocks later: it rises by up to 12.5% per block
+pub fn max_base_fee_after(base_fee: U256, blocks: u64) -> U256 {
+    let mut base_fee = base_fee;
+    for _ in 0..blocks {
+        base_fee = base_fee * U256::from(9) / U256::from(8);
+    }
+    base_fee
+}
+
+impl SandoBundle {
+    /*
+    The same bundle for a target blocks_ahead blocks after the first one:
+    - its own calldata, the contract reverts outside the block the calldata names
+      (see BatchSandwich.simulate_for_block)
+    - the max fee is raised to keep up with the base fee (see max_base_fee_after)
+    - the bribe (the backrun's priority fee) decays by decay_bps per block,
+      as the victim is less and less likely to still be pending
+    The txs are signed again when the variant is turned into a bundle request.
+    */
+    pub fn variant(
+        &self,
+        blocks_ahead: u64,
+        decay_bps: u64,
+        front_calldata: Bytes,
+        back_calldata: Bytes,
+    ) -> SandoBundle {
+        let mut variant = self.clone();
+        variant.frontrun_tx.set_data(front_calldata);
+        variant.backrun_tx.set_data(back_calldata);
+        for (tx, decays) in [
+            (&mut variant.frontrun_tx, false),
+            (&mut variant.backrun_tx, true),
+        ] {
+            if let TypedTransaction::Eip1559(inner) = tx {
+                let mut priority_fee = inner.max_priority_fee_per_gas.unwrap_or_default();
+                let base_fee = inner
+                    .max_fee_per_gas
+                    .unwrap_or_default()
+                    .saturating_sub(priority_fee);
+                if decays {
+                    for _ in 0..blocks_ahead {
+                        priority_fee = priority_fee * U256::from(10000 - decay_bps.min(10000))
+                            / U256::from(10000);
+                    }
+                }
+                inner.max_priority_fee_per_gas = Some(priority_fee);
+                inner.max_fee_per_gas =
+                    Some(max_base_fee_after(base_fee, blocks_ahead) + priority_fee);
+            }
+        }
+        variant
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct SendBundleResponse {
     pub bundle_hash: BundleHash,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BuilderTimestamp {
+    pub pubkey: String,
+    pub timestamp: String,
+}
+
+// Response of flashbots_getBundleStatsV2
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BundleStatsV2 {
+    pub is_high_priority: bool,
+    pub is_simulated: bool,
+    pub simulated_at: Option<String>,
+    pub received_at: Option<String>,
+    pub considered_by_builders_at: Vec<BuilderTimestamp>,
+    pub sealed_by_builders_at: Vec<BuilderTimestamp>,
+}
+
+// Response of flashbots_getUserStatsV2, payments are in wei
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UserStatsV2 {
+    pub is_high_priority: bool,
+    pub all_time_validator_payments: String,
+    pub all_time_gas_simulated: String,
+    pub last_7d_validator_payments: String,
+    pub last_7d_gas_simulated: String,
+    pub last_1d_validator_payments: String,
+    pub last_1d_gas_simulated: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BundleInclusion {
+    pub landed: bool,                 // frontrun tx is in the target block
+    pub stats: Option<BundleStatsV2>, // why it didn't land: never simulated, not considered, not sealed...
+}
+
+// The token to withdraw: a main currency symbol, the native currency symbol or an address.
+// The native currency is recovered with the zero address (see Sandooo.recoverToken)
+pub fn parse_withdrawal_token(token: &str) -> Option<H160> {
+    if token.eq_ignore_ascii_case(&CHAIN.native.symbol) {
+        return Some(H160::zero());
+    }
+    match CHAIN
+        .main_currencies()
+        .iter()
+        .find(|mc| mc.symbol.eq_ignore_ascii_case(token))
+    {
+        Some(mc) => Some(mc.address),
+        None => H160::from_str(token).ok(),
+    }
+}
+
+// A builder that hasn't answered by then is counted as a timeout
+pub static BUILDER_REQUEST_TIMEOUT_MS: u64 = 2000;
+
+// A withdrawal that isn't mined by then releases its nonce
+pub static WITHDRAWAL_NONCE_BLOCKS: u64 = 25;
+
+#[derive(Debug, Clone)]
+pub enum BuilderResult {
+    Accepted(SendBundleResponse),
+    Rejected(String), // the builder's error (or a transport error)
+    Timeout,
+    Scheduled, // sent when the slot's submission window opens, its responses end up in ScheduledBundles
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BuilderCounts {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub timeout: u64,
+    pub last_error: Option<String>,
+}
+
+/*
+Per-builder submission counts since startup, shared with the control API (GET /builders),
+to see which builders actually accept our bundles.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct BuilderStats {
+    inner: Arc<Mutex<HashMap<String, BuilderCounts>>>,
+}
+
+impl BuilderStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, builder: &str, result: &BuilderResult) {
+        let mut stats = self.inner.lock().unwrap();
+        let counts = stats.entry(builder.to_string()).or_default();
+        match result {
+            BuilderResult::Accepted(_) => counts.accepted += 1,
+            BuilderResult::Rejected(reason) => {
+                counts.rejected += 1;
+                counts.last_error = Some(reason.clone());
+            }
+            BuilderResult::Timeout => counts.timeout += 1,
+            BuilderResult::Scheduled => {}
+        }
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, BuilderCounts> {
+        self.inner.lock().unwrap().clone()
+    }
+}
+
+// The builders that accepted the bundle, with their responses
+pub fn accepted_responses(
+    results: &HashMap<String, BuilderResult>,
+) -> HashMap<String, SendBundleResponse> {
+    results
+        .iter()
+        .filter_map(|(builder, result)| match result {
+            BuilderResult::Accepted(response) => Some((builder.clone(), response.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+// JSON-RPC request to a builder, honoring its signature and body size options
+pub async fn builder_request<R: serde::de::DeserializeOwned>(
+    builder: &Builder,
+    identity: &LocalWallet,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<Option<R>> {
+    let body_size = serde_json::to_vec(&params)?.len();
+    if !builder.accepts_body_size(body_size) {
+        return Err(anyhow!(
+            "{} bytes exceeds the max body size of {}",
+            body_size,
+            builder.max_body_size
+        ));
+    }
+    let signer = if builder.requires_signature {
+        Some(identity.clone())
+    } else {
+        None
+    };
+    let relay = Relay::new(builder.url.clone(), signer);
+    let result: Option<R> = relay.request(method, [params]).await?;
+    Ok(result)
+}
+
 pub async fn send_bundle(
-    builder: String,
-    relay_url: Url,
+    builder: Builder,
     identity: LocalWallet,
     bundle: BundleRequest,
+    replacement_uuid: Option<String>,
+    refund_recipient: H160,
 ) -> Result<(String, Option<SendBundleResponse>)> {
-    let relay = Relay::new(relay_url, Some(identity.clone()));
-    let result: Option<SendBundleResponse> = relay.request("eth_sendBundle", [bundle]).await?;
-    Ok((builder, result))
+    // BundleRequest has no replacementUuid/refundRecipient, so they're added to the serialized params
+    let mut params = serde_json::to_value(&bundle)?;
+    if let Some(uuid) = replacement_uuid {
+        params["replacementUuid"] = serde_json::json!(uuid);
+    }
+    if builder.supports_refunds {
+        params["refundRecipient"] = serde_json::json!(refund_recipient);
+    }
+    let result = builder_request(&builder, &identity, "eth_sendBundle", params).await?;
+    Ok((builder.name, result))
+}
+
+pub async fn cancel_bundle(
+    builder: Builder,
+    identity: LocalWallet,
+    replacement_uuid: String,
+) -> Result<String> {
+    let params = serde_json::json!({ "replacementUuid": replacement_uuid });
+    let _: Option<serde_json::Value> =
+        builder_request(&builder, &identity, "eth_cancelBundle", params).await?;
+    Ok(builder.name)
+}
+
+pub async fn send_private_transaction(
+    builder: Builder,
+    identity: LocalWallet,
+    params: serde_json::Value,
+) -> Result<(String, Option<serde_json::Value>)> {
+    let result = builder_request(&builder, &identity, "eth_sendPrivateTransaction", params).await?;
+    Ok((builder.name, result))
+}
+
+// Sends the bundle to every builder concurrently, returns each builder's result and when it acknowledged the bundle
+async fn send_to_builders(
+    builders: Vec<Builder>,
+    identity: LocalWallet,
+    bundle: BundleRequest,
+    replacement_uuid: Option<String>,
+    owner: H160,
+    builder_stats: BuilderStats,
+) -> (HashMap<String, BuilderResult>, Vec<(String, u64)>) {
+    let mut names = Vec::new();
+    let mut requests = Vec::new();
+    for builder in &builders {
+        names.push(builder.name.clone());
+        let request = tokio::time::timeout(
+            Duration::from_millis(BUILDER_REQUEST_TIMEOUT_MS),
+            send_bundle(
+                builder.clone(),
+                identity.clone(),
+                bundle.clone(),
+                replacement_uuid.clone(),
+                owner,
+            ),
+        );
+        // each builder's response time, for the bundle's latency timeline
+        requests.push(tokio::task::spawn(async move {
+            let result = request.await;
+            (result, now_ms())
+        }));
+    }
+    let results = futures::future::join_all(requests).await;
+    let mut result_map = HashMap::new();
+    let mut acknowledged_at = Vec::new();
+    for (builder, result) in names.into_iter().zip(results) {
+        let result = result.map(|(result, at)| {
+            if matches!(result, Ok(Ok(_))) {
+                acknowledged_at.push((builder.clone(), at));
+            }
+            result
+        });
+        let builder_result = match result {
+            Ok(Ok(Ok((_, response)))) => BuilderResult::Accepted(response.unwrap_or_default()),
+            Ok(Ok(Err(e))) => BuilderResult::Rejected(format!("{e:?}")),
+            Ok(Err(_)) => BuilderResult::Timeout,
+            Err(e) => BuilderResult::Rejected(format!("{e:?}")),
+        };
+        match &builder_result {
+            BuilderResult::Rejected(reason) => {
+                warn!("[{}] Bundle rejected: {}", builder, reason)
+            }
+            BuilderResult::Timeout => warn!("[{}] Bundle request timed out", builder),
+            _ => {}
+        }
+        builder_stats.record(&builder, &builder_result);
+        result_map.insert(builder, builder_result);
+    }
+    (result_map, acknowledged_at)
+}
+
+// Archives the responses of the builders that accepted the bundle, if any did
+fn archive_responses(
+    archive: &BundleArchive,
+    bundle_id: &str,
+    bundle: &BundleRequest,
+    results: &HashMap<String, BuilderResult>,
+) {
+    let responses = accepted_responses(results);
+    if responses.is_empty() {
+        return;
+    }
+    match archive.save(
+        bundle_id,
+        bundle.block().unwrap_or_default(),
+        bundle,
+        &responses,
+    ) {
+        Err(e) => warn!("BundleArchive.save error: {e:?}"),
+        _ => {}
+    }
 }
 
 pub struct Executor {
-    pub provider: Arc<Provider<Ws>>,
+    pub provider: Arc<Provider<RpcClient>>,
     pub abi: Abi,
     pub owner: LocalWallet,
     pub identity: LocalWallet,
     pub bot_address: H160,
-    pub builder_urls: HashMap<String, Url>,
-    pub client: SignerMiddleware<FlashbotsMiddleware<Arc<Provider<Ws>>, LocalWallet>, LocalWallet>,
+    pub chain_id: u64,
+    pub sequencer_mode: bool, // L2s/BSC: no bundle relay, send txs directly to the sequencer/mempool
+    pub builders: Vec<Builder>,
+    pub slot_clock: SlotClock,
+    pub archive: BundleArchive,
+    pub mev_share_relay_url: String,
+    pub relay_url: Url,
+    pub builder_stats: BuilderStats,
+    pub scheduled: ScheduledBundles,
+    pub nonces: NonceManager,
+    pub kill_switch: KillSwitch,
+    pub client:
+        SignerMiddleware<FlashbotsMiddleware<Arc<Provider<RpcClient>>, LocalWallet>, LocalWallet>,
+    pub contexts: Vec<ExecutionContext>, // owner, bot_address and nonces are the ones of contexts[0]
+    pub context: String,                 // name of the context this executor signs for
 }
 
 impl Executor {
-    pub fn new(provider: Arc<Provider<Ws>>) -> Self {
+    pub fn new(provider: Arc<Provider<RpcClient>>) -> Self {
         let env = Env::new();
         let abi = Abi::new();
-        let bot_address = H160::from_str(&env.bot_address).unwrap();
 
-        let owner = env
-            .private_key
-            .parse::<LocalWallet>()
-            .unwrap()
-            .with_chain_id(1 as u64);
+        let contexts = load_contexts(&env);
+        let bot_address = contexts[0].bot_address;
+        let owner = contexts[0].owner.clone();
+        let nonces = contexts[0].nonces.clone();
+        let context = contexts[0].name.clone();
 
-        let identity = env
-            .identity_key
-            .parse::<LocalWallet>()
+        let identity = load_wallet("IDENTITY_KEY", &env.identity_key)
             .unwrap()
-            .with_chain_id(1 as u64);
+            .with_chain_id(env.chain_id);
 
-        let relay_url = Url::parse("https://relay.flashbots.net").unwrap();
+        let chain = ChainProfile::new(env.chain_id);
+        let relay_url = chain.relay_url.clone();
 
         let client = SignerMiddleware::new(
             FlashbotsMiddleware::new(provider.clone(), relay_url.clone(), identity.clone()),
             owner.clone(),
         );
 
-        // The endpoints here will gracefully fail if it doesn't work
-        let mut builder_urls = HashMap::new();
-        builder_urls.insert(
-            "flashbots".to_string(),
-            Url::parse("https://relay.flashbots.net").unwrap(),
-        );
-        builder_urls.insert(
-            "beaverbuild".to_string(),
-            Url::parse("https://rpc.beaverbuild.org").unwrap(),
-        );
-        builder_urls.insert(
-            "rsync".to_string(),
-            Url::parse("https://rsync-builder.xyz").unwrap(),
-        );
-        builder_urls.insert(
-            "titanbuilder".to_string(),
-            Url::parse("https://rpc.titanbuilder.xyz").unwrap(),
-        );
-        builder_urls.insert(
-            "builder0x69".to_string(),
-            Url::parse("https://builder0x69.io").unwrap(),
-        );
-        builder_urls.insert("f1b".to_string(), Url::parse("https://rpc.f1b.io").unwrap());
-        builder_urls.insert(
-            "lokibuilder".to_string(),
-            Url::parse("https://rpc.lokibuilder.xyz").unwrap(),
-        );
-        builder_urls.insert(
-            "eden".to_string(),
-            Url::parse("https://api.edennetwork.io/v1/rpc").unwrap(),
-        );
-        builder_urls.insert(
-            "penguinbuild".to_string(),
-            Url::parse("https://rpc.penguinbuild.org").unwrap(),
-        );
-        builder_urls.insert(
-            "gambit".to_string(),
-            Url::parse("https://builder.gmbit.co/rpc").unwrap(),
-        );
-        builder_urls.insert(
-            "idcmev".to_string(),
-            Url::parse("https://rpc.idcmev.xyz").unwrap(),
+        let builders = if chain.has_bundle_relay {
+            load_builders(&env.builders_config, &chain)
+        } else {
+            Vec::new()
+        };
+
+        let slot_clock = SlotClock::new(
+            env.beacon_genesis_time,
+            env.seconds_per_slot,
+            env.slot_submit_earliest_ms,
+            env.slot_submit_latest_ms,
         );
 
         Self {
@@ -122,8 +424,73 @@ impl Executor {
             owner,
             identity,
             bot_address,
-            builder_urls,
+            chain_id: env.chain_id,
+            sequencer_mode: !chain.has_bundle_relay,
+            builders,
+            slot_clock,
+            archive: BundleArchive::new(&env),
+            mev_share_relay_url: env.mev_share_relay_url.clone(),
+            relay_url,
+            builder_stats: BuilderStats::new(),
+            scheduled: ScheduledBundles::new(),
+            nonces,
+            kill_switch: KillSwitch::new(&env.kill_switch_file),
+            client,
+            contexts,
+            context,
+        }
+    }
+
+    // Builder list from a reloaded env (BUILDERS_CONFIG)
+    pub fn reload_builders(&mut self, env: &Env) {
+        let chain = ChainProfile::new(self.chain_id);
+        if chain.has_bundle_relay {
+            self.builders = load_builders(&env.builders_config, &chain);
+        }
+    }
+
+    // The context that trades this main currency (see contexts.rs)
+    pub fn route(&self, main_currency: H160) -> Option<&ExecutionContext> {
+        route(&self.contexts, main_currency).map(|idx| &self.contexts[idx])
+    }
+
+    pub fn get_context(&self, name: &str) -> &ExecutionContext {
+        self.contexts
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap_or(&self.contexts[0])
+    }
+
+    // An executor signing with the context's owner for its bot contract, sharing everything else
+    pub fn with_context(&self, context: &ExecutionContext) -> Self {
+        let client = SignerMiddleware::new(
+            FlashbotsMiddleware::new(
+                self.provider.clone(),
+                self.relay_url.clone(),
+                self.identity.clone(),
+            ),
+            context.owner.clone(),
+        );
+        Self {
+            provider: self.provider.clone(),
+            abi: Abi::new(),
+            owner: context.owner.clone(),
+            identity: self.identity.clone(),
+            bot_address: context.bot_address,
+            chain_id: self.chain_id,
+            sequencer_mode: self.sequencer_mode,
+            builders: self.builders.clone(),
+            slot_clock: self.slot_clock.clone(),
+            archive: self.archive.clone(),
+            mev_share_relay_url: self.mev_share_relay_url.clone(),
+            relay_url: self.relay_url.clone(),
+            builder_stats: self.builder_stats.clone(),
+            scheduled: self.scheduled.clone(),
+            nonces: context.nonces.clone(),
+            kill_switch: self.kill_switch.clone(),
             client,
+            contexts: self.contexts.clone(),
+            context: context.name.clone(),
         }
     }
 
@@ -132,7 +499,42 @@ impl Executor {
             .provider
             .get_transaction_count(self.owner.address(), Some(BlockNumber::Latest.into()))
             .await?;
-        Ok((self.owner.address(), U256::from(nonce), U64::from(1)))
+        Ok((
+            self.owner.address(),
+            U256::from(nonce),
+            U64::from(self.chain_id),
+        ))
+    }
+
+    pub async fn resync_nonce(&self) -> Result<()> {
+        let nonce = self
+            .provider
+            .get_transaction_count(self.owner.address(), Some(BlockNumber::Latest.into()))
+            .await?;
+        self.nonces.resync(nonce);
+        Ok(())
+    }
+
+    // First nonce of a bundle, bundles competing for the same blocks share it (see nonce.rs)
+    pub async fn bundle_nonce(&self) -> Result<U256> {
+        if !self.nonces.is_synced() {
+            self.resync_nonce().await?;
+        }
+        match self.nonces.bundle_nonce() {
+            Some(nonce) => Ok(nonce),
+            None => Err(anyhow!("Nonces not synced")),
+        }
+    }
+
+    // First of count consecutive nonces for txs sent on their own, that can be mined until last_block
+    pub async fn commit_nonces(&self, count: u64, last_block: U64) -> Result<U256> {
+        if !self.nonces.is_synced() {
+            self.resync_nonce().await?;
+        }
+        match self.nonces.commit(count, last_block) {
+            Some(nonce) => Ok(nonce),
+            None => Err(anyhow!("Nonces not synced")),
+        }
     }
 
     pub async fn transfer_in_tx(&self, amount_in: U256) -> Result<TypedTransaction> {
@@ -172,6 +574,40 @@ impl Executor {
         }))
     }
 
+    // Balance of token (zero address: the native currency) held by the bot contract
+    pub async fn bot_balance(&self, token: H160) -> Result<U256> {
+        let block_number = self.provider.get_block_number().await?;
+        if token.is_zero() {
+            Ok(self
+                .provider
+                .get_balance(self.bot_address, Some(block_number.into()))
+                .await?)
+        } else {
+            get_token_balance_at(self.provider.clone(), self.bot_address, token, block_number).await
+        }
+    }
+
+    // Sends amount of token (zero address: the native currency) from the bot contract to the owner
+    pub async fn withdraw(&self, token: H160, amount: U256) -> Result<H256> {
+        let block_number = self.provider.get_block_number().await?;
+        let (max_fee_per_gas, max_priority_fee_per_gas) =
+            self.provider.estimate_eip1559_fees(None).await?;
+        let mut tx = self
+            .transfer_out_tx(token, amount, max_priority_fee_per_gas, max_fee_per_gas)
+            .await?;
+        // committed, so sandwiches sent meanwhile start after it
+        let nonce = self
+            .commit_nonces(1, block_number + U64::from(WITHDRAWAL_NONCE_BLOCKS))
+            .await?;
+        tx.set_nonce(nonce);
+        let signature = self.client.signer().sign_transaction(&tx).await?;
+        let pending_tx = self
+            .provider
+            .send_raw_transaction(tx.rlp_signed(&signature))
+            .await?;
+        Ok(*pending_tx)
+    }
+
     pub async fn to_typed_transaction(
         &self,
         calldata: Bytes,
@@ -207,31 +643,39 @@ impl Executor {
         front_gas_limit: u64,
         back_gas_limit: u64,
         base_fee: U256,
+        front_priority_fee_per_gas: U256,
         max_priority_fee_per_gas: U256,
         max_fee_per_gas: U256,
+        last_target_block: U64,
     ) -> Result<SandoBundle> {
-        let common = self._common_fields().await?;
+        let owner = self.owner.address();
+        let chain_id = U64::from(self.chain_id);
         let to = NameOrAddress::Address(self.bot_address);
-        let front_nonce = common.1;
+        // txs sent to the sequencer are mined whether the sandwich pays or not
+        let front_nonce = if self.sequencer_mode {
+            self.commit_nonces(2, last_target_block).await?
+        } else {
+            self.bundle_nonce().await?
+        };
         let back_nonce = front_nonce + U256::from(1); // should increase nonce by 1
         let frontrun_tx = TypedTransaction::Eip1559(Eip1559TransactionRequest {
             to: Some(to.clone()),
-            from: Some(common.0),
+            from: Some(owner),
             data: Some(front_calldata),
             value: Some(U256::zero()),
-            chain_id: Some(common.2),
-            max_priority_fee_per_gas: Some(U256::zero()),
-            max_fee_per_gas: Some(base_fee),
+            chain_id: Some(chain_id),
+            max_priority_fee_per_gas: Some(front_priority_fee_per_gas),
+            max_fee_per_gas: Some(base_fee + front_priority_fee_per_gas),
             gas: Some(U256::from(front_gas_limit)),
             nonce: Some(front_nonce),
             access_list: front_access_list,
         });
         let backrun_tx = TypedTransaction::Eip1559(Eip1559TransactionRequest {
             to: Some(to),
-            from: Some(common.0),
+            from: Some(owner),
             data: Some(back_calldata),
             value: Some(U256::zero()),
-            chain_id: Some(common.2),
+            chain_id: Some(chain_id),
             max_priority_fee_per_gas: Some(max_priority_fee_per_gas),
             max_fee_per_gas: Some(max_fee_per_gas),
             gas: Some(U256::from(back_gas_limit)),
@@ -260,6 +704,25 @@ impl Executor {
         Ok(bundle)
     }
 
+    // Other people's txs as they are, then our backrun
+    pub async fn to_backrun_bundle_request(
+        &self,
+        preceding_txs: Vec<Transaction>,
+        backrun_tx: TypedTransaction,
+        block_number: U64,
+    ) -> Result<BundleRequest> {
+        let signature = self.client.signer().sign_transaction(&backrun_tx).await?;
+        let mut bundle = BundleRequest::new()
+            .set_block(block_number)
+            .set_simulation_block(block_number - U64::from(1))
+            .set_simulation_timestamp(0);
+        for tx in &preceding_txs {
+            bundle = bundle.push_transaction(tx.rlp());
+        }
+        bundle = bundle.push_transaction(backrun_tx.rlp_signed(&signature));
+        Ok(bundle)
+    }
+
     pub async fn to_sando_bundle_request(
         &self,
         sando_bundle: SandoBundle,
@@ -295,46 +758,284 @@ impl Executor {
         Ok(bundle)
     }
 
-    pub async fn simulate_bundle(&self, bundle: &BundleRequest) {
-        match self.client.inner().simulate_bundle(bundle).await {
-            Ok(simulated) => {
-                println!("{:?}", simulated);
-            }
-            Err(e) => {
-                println!("Flashbots bundle simulation error: {e:?}");
+    /*
+    L2 execution: there is no bundle relay, so the frontrun/backrun are sent
+    as normal transactions to the sequencer. Ordering relies on priority fees,
+    which is why the frontrun should outbid the victims and the backrun shouldn't.
+    */
+    pub async fn send_to_sequencer(&self, sando_bundle: SandoBundle) -> Result<Vec<H256>> {
+        self.kill_switch.check()?;
+        let mut tx_hashes = Vec::new();
+        for tx in vec![sando_bundle.frontrun_tx, sando_bundle.backrun_tx] {
+            let signature = self.client.signer().sign_transaction(&tx).await?;
+            let pending_tx = self
+                .provider
+                .send_raw_transaction(tx.rlp_signed(&signature))
+                .await?;
+            tx_hashes.push(*pending_tx);
+        }
+        Ok(tx_hashes)
+    }
+
+    /*
+    MEV-Share bundles reference the user's tx by hash (we never see the signed tx),
+    and our backrun goes right after it. The matchmaker refunds part of the backrun's value to the user.
+    */
+    pub async fn send_mev_share_backrun(
+        &self,
+        hint_hash: H256,
+        backrun_tx: TypedTransaction,
+        block_number: U64,
+        max_block_number: U64,
+    ) -> Result<Option<serde_json::Value>> {
+        self.kill_switch.check()?;
+        let signature = self.client.signer().sign_transaction(&backrun_tx).await?;
+        let signed_backrun_tx = backrun_tx.rlp_signed(&signature);
+        let bundle = serde_json::json!({
+            "version": "v0.1",
+            "inclusion": {
+                "block": block_number,
+                "maxBlock": max_block_number,
+            },
+            "body": [
+                { "hash": hint_hash },
+                { "tx": signed_backrun_tx, "canRevert": false },
+            ],
+        });
+        let relay = Relay::new(
+            Url::parse(&self.mev_share_relay_url)?,
+            Some(self.identity.clone()),
+        );
+        let result: Option<serde_json::Value> = relay.request("mev_sendBundle", [bundle]).await?;
+        Ok(result)
+    }
+
+    /*
+    A single tx that doesn't need to go right after anyone else's (e.g. a backrun whose victim already landed)
+    doesn't need a bundle: eth_sendPrivateTransaction keeps it out of the public mempool,
+    and the relay/builders keep trying to include it until max_block_number.
+    Returns the tx hash and the builders that accepted it.
+    */
+    pub async fn send_private_tx(
+        &self,
+        tx: TypedTransaction,
+        max_block_number: U64,
+    ) -> Result<(H256, Vec<String>)> {
+        self.kill_switch.check()?;
+        let signature = self.client.signer().sign_transaction(&tx).await?;
+        let tx_hash = tx.hash(&signature);
+        let params = serde_json::json!({
+            "tx": tx.rlp_signed(&signature),
+            "maxBlockNumber": max_block_number,
+            "preferences": { "fast": true },
+        });
+
+        let mut builders = Vec::new();
+        let mut requests = Vec::new();
+        for builder in &self.builders {
+            builders.push(builder.name.clone());
+            requests.push(tokio::task::spawn(tokio::time::timeout(
+                Duration::from_millis(BUILDER_REQUEST_TIMEOUT_MS),
+                send_private_transaction(builder.clone(), self.identity.clone(), params.clone()),
+            )));
+        }
+        let results = futures::future::join_all(requests).await;
+        let mut accepted = Vec::new();
+        for (builder, result) in builders.into_iter().zip(results) {
+            match result {
+                Ok(Ok(Ok(_))) => accepted.push(builder),
+                Ok(Ok(Err(e))) => warn!("[{}] Private tx rejected: {e:?}", builder),
+                Ok(Err(_)) => warn!("[{}] Private tx request timed out", builder),
+                Err(e) => warn!("[{}] Private tx request error: {e:?}", builder),
             }
         }
+        Ok((tx_hash, accepted))
+    }
+
+    pub async fn get_bundle_stats(
+        &self,
+        bundle_hash: BundleHash,
+        block_number: U64,
+    ) -> Result<Option<BundleStatsV2>> {
+        let relay = Relay::new(self.relay_url.clone(), Some(self.identity.clone()));
+        let params = serde_json::json!({
+            "bundleHash": bundle_hash,
+            "blockNumber": block_number,
+        });
+        let stats: Option<BundleStatsV2> = relay
+            .request("flashbots_getBundleStatsV2", [params])
+            .await?;
+        Ok(stats)
+    }
+
+    // Reputation of our IDENTITY_KEY with the Flashbots relay
+    pub async fn get_user_stats(&self, block_number: U64) -> Result<Option<UserStatsV2>> {
+        let relay = Relay::new(self.relay_url.clone(), Some(self.identity.clone()));
+        let params = serde_json::json!({ "blockNumber": block_number });
+        let stats: Option<UserStatsV2> =
+            relay.request("flashbots_getUserStatsV2", [params]).await?;
+        Ok(stats)
+    }
+
+    /*
+    Checks whether a sent bundle landed: looks for our frontrun tx in the target block,
+    and asks the relay for the bundle's stats so a miss can be explained.
+    */
+    pub async fn check_inclusion(
+        &self,
+        bundle_hash: Option<BundleHash>,
+        frontrun_tx_hash: H256,
+        target_block: U64,
+    ) -> Result<BundleInclusion> {
+        let landed = match self.provider.get_block(target_block).await? {
+            Some(block) => block.transactions.contains(&frontrun_tx_hash),
+            None => false,
+        };
+        let stats = match bundle_hash {
+            Some(bundle_hash) if !self.sequencer_mode => {
+                match self.get_bundle_stats(bundle_hash, target_block).await {
+                    Ok(stats) => stats,
+                    Err(e) => {
+                        warn!("flashbots_getBundleStatsV2 error: {e:?}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        Ok(BundleInclusion { landed, stats })
+    }
+
+    // eth_callBundle on the Flashbots relay
+    pub async fn simulate_bundle(&self, bundle: &BundleRequest) -> Result<SimulatedBundle> {
+        let simulated = self.client.inner().simulate_bundle(bundle).await?;
+        Ok(simulated)
     }
 
+    /*
+    Sends the bundle to every builder in the slot's submission window.
+    Before the window opens, it's scheduled instead: the builders get it once the window opens,
+    and their responses go to self.scheduled (BuilderResult::Scheduled for now).
+    With a bundle_id, the accepted responses are archived once they're in.
+    */
     pub async fn broadcast_bundle(
         &self,
         bundle: BundleRequest,
-    ) -> Result<HashMap<String, SendBundleResponse>> {
-        let mut requests = Vec::new();
-        for (builder, url) in &self.builder_urls {
-            requests.push(tokio::task::spawn(send_bundle(
-                builder.clone(),
-                url.clone(),
+        replacement_uuid: Option<String>,
+        timeline: Option<&mut BundleTimeline>,
+        bundle_id: Option<&str>,
+    ) -> Result<HashMap<String, BuilderResult>> {
+        self.kill_switch.check()?;
+        let delay = self.slot_clock.submission_delay().unwrap_or_default();
+        if delay.is_zero() {
+            // replaces a bundle still waiting with the same uuid
+            if let Some(uuid) = &replacement_uuid {
+                self.scheduled.cancel(uuid);
+            }
+            let (result_map, acknowledged_at) = send_to_builders(
+                self.builders.clone(),
                 self.identity.clone(),
                 bundle.clone(),
+                replacement_uuid,
+                self.owner.address(),
+                self.builder_stats.clone(),
+            )
+            .await;
+            if let Some(timeline) = timeline {
+                timeline.broadcast.extend(acknowledged_at);
+            }
+            if let Some(bundle_id) = bundle_id {
+                archive_responses(&self.archive, bundle_id, &bundle, &result_map);
+            }
+            return Ok(result_map);
+        }
+
+        // too early for the slot: send once the window opens, without holding up the caller
+        let ticket = replacement_uuid
+            .as_ref()
+            .map(|uuid| self.scheduled.schedule(uuid));
+        let builders = self.builders.clone();
+        let identity = self.identity.clone();
+        let owner = self.owner.address();
+        let builder_stats = self.builder_stats.clone();
+        let kill_switch = self.kill_switch.clone();
+        let scheduled = self.scheduled.clone();
+        let archive = self.archive.clone();
+        let bundle_id = bundle_id.map(|id| id.to_string());
+        let result_map = builders
+            .iter()
+            .map(|builder| (builder.name.clone(), BuilderResult::Scheduled))
+            .collect();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            // we may have been halted, or the bundle cancelled or replaced, while waiting
+            match kill_switch.check() {
+                Err(e) => {
+                    warn!("Scheduled bundle dropped: {e:?}");
+                    return;
+                }
+                _ => {}
+            }
+            if let (Some(uuid), Some(ticket)) = (&replacement_uuid, ticket) {
+                if !scheduled.start(uuid, ticket) {
+                    info!("Scheduled bundle {} cancelled or replaced, not sent", uuid);
+                    return;
+                }
+            }
+            let (result_map, acknowledged_at) = send_to_builders(
+                builders,
+                identity,
+                bundle.clone(),
+                replacement_uuid.clone(),
+                owner,
+                builder_stats,
+            )
+            .await;
+            if let Some(bundle_id) = &bundle_id {
+                archive_responses(&archive, bundle_id, &bundle, &result_map);
+            }
+            if let Some(uuid) = &replacement_uuid {
+                scheduled.finish(
+                    uuid,
+                    ScheduledSend {
+                        target_block: bundle.block().unwrap_or_default(),
+                        responses: accepted_responses(&result_map),
+                        acknowledged_at,
+                    },
+                );
+            }
+        });
+        Ok(result_map)
+    }
+
+    // Cancels a bundle sent by send_sando_bundle_request (or any bundle with a replacementUuid) in every block it targets
+    pub async fn cancel_bundle_variants(&self, replacement_uuid: &str, target_blocks: u64) {
+        for blocks_ahead in 0..std::cmp::max(target_blocks, 1) {
+            self.cancel_bundle(&variant_uuid(replacement_uuid, blocks_ahead))
+                .await;
+        }
+    }
+
+    // Cancels our bundle with this replacementUuid on every builder that supports it (or before it's sent)
+    pub async fn cancel_bundle(&self, replacement_uuid: &str) {
+        self.scheduled.cancel(replacement_uuid);
+        let mut requests = Vec::new();
+        for builder in &self.builders {
+            requests.push(tokio::task::spawn(tokio::time::timeout(
+                Duration::from_millis(BUILDER_REQUEST_TIMEOUT_MS),
+                cancel_bundle(
+                    builder.clone(),
+                    self.identity.clone(),
+                    replacement_uuid.to_string(),
+                ),
             )));
         }
-        let results = futures::future::join_all(requests).await;
-        let mut response_map = HashMap::new();
-        for result in results {
+        for result in futures::future::join_all(requests).await {
             match result {
-                Ok(response) => match response {
-                    Ok(bundle_response) => {
-                        let builder = bundle_response.0;
-                        let send_bundle_response = bundle_response.1.unwrap_or_default();
-                        response_map.insert(builder.clone(), send_bundle_response);
-                    }
-                    Err(_) => {}
-                },
-                Err(_) => {}
+                Ok(Ok(Err(e))) => warn!("eth_cancelBundle error: {e:?}"),
+                Ok(Err(_)) => warn!("eth_cancelBundle timed out"),
+                _ => {}
             }
         }
-
-        Ok(response_map)
     }
 }
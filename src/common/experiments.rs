@@ -0,0 +1,147 @@
+use ethers::core::rand::{thread_rng, Rng};
+use ethers::types::U256;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::common::alert::Alert;
+use crate::common::constants::*;
+use crate::common::tracker::Tracker;
+
+/*
+A/B experiments on strategy parameters.
+Each bundle the planner (main_dish) builds is randomly assigned to an arm,
+the arm's parameters are used for that bundle, and the tracker accounts
+inclusions/realized profit per arm so we can see which configuration earns more.
+Currently arms differ by bribe_pct (basis points of revenue paid to the builder).
+*/
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub name: String,
+    pub bribe_pct: U256,
+}
+
+// Outcomes accounted by the tracker
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArmStats {
+    pub sent: u64,
+    pub included: u64,
+    pub reorged: u64,
+    pub realized_profit: i128,
+}
+
+pub struct Experiment {
+    pub name: String,
+    pub arms: Vec<Arm>,
+    pub assigned: HashMap<String, u64>,
+    pub report_interval: Duration,
+    pub last_report: Instant,
+}
+
+/*
+Parses: "control:9900,lowbribe:9000" (arm name : bribe_pct in basis points)
+*/
+pub fn parse_arms(raw: &str) -> Vec<Arm> {
+    let mut arms = Vec::new();
+    for item in raw.split(',') {
+        let mut parts = item.trim().split(':');
+        let (name, bribe_pct) = match (parts.next(), parts.next()) {
+            (Some(name), Some(bribe_pct)) => (name.trim().to_string(), bribe_pct.trim()),
+            _ => continue,
+        };
+        let bribe_pct = match bribe_pct.parse::<u64>() {
+            Ok(bribe_pct) if bribe_pct <= 10000 => bribe_pct,
+            _ => continue,
+        };
+        arms.push(Arm {
+            name,
+            bribe_pct: U256::from(bribe_pct),
+        });
+    }
+    arms
+}
+
+impl Experiment {
+    // Experiments need at least two arms, otherwise the strategy runs with its defaults
+    pub fn new(env: &Env) -> Option<Self> {
+        let arms = parse_arms(&env.experiment_arms);
+        if arms.len() < 2 {
+            return None;
+        }
+        info!(
+            "Experiment [{}] running with arms: {:?}",
+            env.experiment_name, arms
+        );
+        Some(Self {
+            name: env.experiment_name.clone(),
+            arms,
+            assigned: HashMap::new(),
+            report_interval: Duration::from_secs(3600),
+            last_report: Instant::now(),
+        })
+    }
+
+    pub fn assign(&mut self) -> Arm {
+        let idx = thread_rng().gen_range(0..self.arms.len());
+        let arm = self.arms[idx].clone();
+        *self.assigned.entry(arm.name.clone()).or_insert(0) += 1;
+        arm
+    }
+
+    // Realized profit per assigned opportunity, so arms that send fewer bundles aren't favored
+    pub fn profit_per_opportunity(&self, arm: &Arm, tracker: &Tracker) -> f64 {
+        let assigned = *self.assigned.get(&arm.name).unwrap_or(&0);
+        if assigned == 0 {
+            return 0.0;
+        }
+        let realized_profit = tracker
+            .arm_stats
+            .get(&arm.name)
+            .map(|s| s.realized_profit)
+            .unwrap_or(0);
+        (realized_profit as f64) / (assigned as f64)
+    }
+
+    pub async fn maybe_report(&mut self, tracker: &Tracker, alert: &Alert) {
+        if self.last_report.elapsed() < self.report_interval {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        let mut lines = vec![format!("Experiment [{}]", self.name)];
+        for arm in &self.arms {
+            let stats = tracker
+                .arm_stats
+                .get(&arm.name)
+                .cloned()
+                .unwrap_or_default();
+            lines.push(format!(
+                "> {} (bribe {:?}bp): assigned {} / sent {} / included {} / reorged {} / realized {:?} / per opportunity {:.0}",
+                arm.name,
+                arm.bribe_pct,
+                self.assigned.get(&arm.name).unwrap_or(&0),
+                stats.sent,
+                stats.included,
+                stats.reorged,
+                stats.realized_profit,
+                self.profit_per_opportunity(arm, tracker),
+            ));
+        }
+
+        let best = self.arms.iter().max_by(|x, y| {
+            self.profit_per_opportunity(x, tracker)
+                .partial_cmp(&self.profit_per_opportunity(y, tracker))
+                .unwrap()
+        });
+        if let Some(best) = best {
+            lines.push(format!("> Leading arm: {}", best.name));
+        }
+
+        let message = lines.join("\n");
+        info!("{}", message);
+        match alert.send(&message).await {
+            _ => {}
+        }
+    }
+}
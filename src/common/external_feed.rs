@@ -0,0 +1,151 @@
+use anyhow::{anyhow, Result};
+use ethers::types::{Bytes, Signature, Transaction, H160, H256, U64};
+use ethers::utils::{keccak256, rlp::Rlp};
+use log::{info, warn};
+use serde::Deserialize;
+use std::str::FromStr;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::broadcast::Sender;
+
+use crate::common::dedup::{TxDedup, TxSource};
+use crate::common::latency::now_ms;
+use crate::common::streams::{Event, NewPendingTx};
+
+/*
+External victim feed (EXTERNAL_FEED_ADDR, disabled when empty): lets a faster detection process
+(a colocated partner, a separate scanner) feed victims into the strategy.
+Listens on TCP ("127.0.0.1:9100") or a Unix socket ("unix:/tmp/sandooo.sock"),
+and reads newline delimited JSON messages:
+{ "raw_tx": "0x02f8...", "signature": "0x..." }
+- raw_tx: the victim's signed tx, RLP encoded. Its signature and chain id are checked
+- signature: EIP-191 signature of raw_tx by EXTERNAL_FEED_SIGNER, required when it's set
+Each message is answered with "ok" or "error: {reason}", and accepted txs go through the same
+dedup as the mempool sources before being published as Event::PendingTx.
+*/
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalVictim {
+    pub raw_tx: Bytes,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExternalFeedValidator {
+    pub chain_id: u64,
+    pub signer: Option<H160>, // None: messages don't need to be signed
+}
+
+impl ExternalFeedValidator {
+    pub fn validate(&self, line: &str) -> Result<Transaction> {
+        let message: ExternalVictim = serde_json::from_str(line)?;
+
+        if let Some(signer) = self.signer {
+            let signature = match &message.signature {
+                Some(signature) => Signature::from_str(signature)?,
+                None => return Err(anyhow!("missing signature")),
+            };
+            signature.verify(message.raw_tx.as_ref(), signer)?;
+        }
+
+        let (mut tx, _) = Transaction::decode_signed_rlp(&Rlp::new(message.raw_tx.as_ref()))?;
+        tx.hash = H256::from(keccak256(message.raw_tx.as_ref()));
+        tx.from = tx.recover_from()?;
+        if let Some(chain_id) = tx.chain_id {
+            if chain_id != self.chain_id.into() {
+                return Err(anyhow!("wrong chain id: {:?}", chain_id));
+            }
+        }
+        if tx.to.is_none() {
+            return Err(anyhow!("contract creations can't be victims"));
+        }
+        if tx.transaction_type.is_none() {
+            tx.transaction_type = Some(U64::zero());
+        }
+        Ok(tx)
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    validator: ExternalFeedValidator,
+    event_sender: Sender<Event>,
+    dedup: TxDedup,
+) {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("External feed read error: {e:?}");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match validator.validate(&line) {
+            Ok(tx) => {
+                if dedup.observe(tx.hash, TxSource::External) {
+                    match event_sender.send(Event::PendingTx(NewPendingTx {
+                        added_block: None,
+                        tx,
+                        hint: None,
+                        first_seen_ms: now_ms(),
+                    })) {
+                        Ok(_) => {}
+                        Err(_) => {}
+                    }
+                }
+                String::from("ok\n")
+            }
+            Err(e) => format!("error: {}\n", e),
+        };
+        match writer.write_all(reply.as_bytes()).await {
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+pub async fn serve_external_feed(
+    addr: String,
+    validator: ExternalFeedValidator,
+    event_sender: Sender<Event>,
+    dedup: TxDedup,
+) -> Result<()> {
+    match addr.strip_prefix("unix:") {
+        Some(path) => {
+            // a socket file left behind by the last run would make bind fail
+            let _ = std::fs::remove_file(path);
+            let listener = UnixListener::bind(path)?;
+            info!("External victim feed listening on {}", addr);
+            loop {
+                let (stream, _) = listener.accept().await?;
+                tokio::spawn(handle_connection(
+                    stream,
+                    validator.clone(),
+                    event_sender.clone(),
+                    dedup.clone(),
+                ));
+            }
+        }
+        None => {
+            let listener = TcpListener::bind(&addr).await?;
+            info!("External victim feed listening on {}", addr);
+            loop {
+                let (stream, peer) = listener.accept().await?;
+                info!("External feed connected: {:?}", peer);
+                tokio::spawn(handle_connection(
+                    stream,
+                    validator.clone(),
+                    event_sender.clone(),
+                    dedup.clone(),
+                ));
+            }
+        }
+    }
+}
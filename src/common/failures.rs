@@ -0,0 +1,127 @@
+use log::warn;
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::common::alert::{Alert, AlertEvent};
+
+/*
+Why an opportunity didn't turn into a bundle (or a bundle didn't go out).
+Failures are attached to errors as anyhow context where they happen
+(e.g. simulator.call(front_tx).context(Failure::SimRevert(TxStage::Front))),
+then classified with Failure::of wherever the error ends up, counted,
+and summarized in a periodic alert so it's clear what dominates.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TxStage {
+    Front,
+    Victim,
+    Back,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Failure {
+    TraceUnavailable,      // debug_traceCall(Many) failed
+    SimRevert(TxStage),    // a tx of the sandwich reverted in simulation
+    EncodingError,         // frontrun/backrun calldata or the bundle txs couldn't be built
+    BuilderRejected,       // no builder/sequencer accepted the bundle
+    InsufficientInventory, // no main currency balance (or exposure) left to frontrun with
+    Salmonella, // a target token behaves differently for sandwich bots (see SalmonellaInspector)
+    Unknown,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::SimRevert(stage) => write!(f, "SimRevert({:?})", stage),
+            failure => write!(f, "{:?}", failure),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+impl Failure {
+    pub fn of(e: &anyhow::Error) -> Self {
+        e.downcast_ref::<Failure>()
+            .copied()
+            .unwrap_or(Failure::Unknown)
+    }
+}
+
+pub static FAILURE_REPORT_INTERVAL_SECS: u64 = 3600;
+
+pub struct FailureStats {
+    pub counts: HashMap<Failure, u64>, // since the last report
+    pub totals: HashMap<Failure, u64>, // since startup
+    pub last_report: Instant,
+}
+
+impl FailureStats {
+    pub fn new() -> Self {
+        Self {
+            counts: HashMap::new(),
+            totals: HashMap::new(),
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, failure: Failure) {
+        *self.counts.entry(failure).or_insert(0) += 1;
+        *self.totals.entry(failure).or_insert(0) += 1;
+    }
+
+    pub fn record_error(&mut self, e: &anyhow::Error) -> Failure {
+        let failure = Failure::of(e);
+        self.record(failure);
+        failure
+    }
+
+    pub fn summary(&self) -> String {
+        let mut counts: Vec<(&Failure, &u64)> = self.counts.iter().collect();
+        counts.sort_by(|x, y| y.1.cmp(x.1));
+        counts
+            .iter()
+            .map(|(failure, count)| format!("- {}: {}", failure, count))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // Called from the block loop, alerts at most once per FAILURE_REPORT_INTERVAL_SECS
+    pub async fn maybe_report(&mut self, alert: &Alert) {
+        if self.last_report.elapsed() < Duration::from_secs(FAILURE_REPORT_INTERVAL_SECS) {
+            return;
+        }
+        self.last_report = Instant::now();
+        if self.counts.is_empty() {
+            return;
+        }
+        let message = format!(
+            "Failures in the last {}s:\n{}",
+            FAILURE_REPORT_INTERVAL_SECS,
+            self.summary()
+        );
+        warn!("{}", message);
+        match alert.send(&message).await {
+            Err(e) => warn!("Telegram error: {e:?}"),
+            _ => {}
+        }
+        alert.event(AlertEvent::SimulationError {
+            interval_secs: FAILURE_REPORT_INTERVAL_SECS,
+            counts: self
+                .counts
+                .iter()
+                .map(|(failure, count)| (failure.to_string(), *count))
+                .collect(),
+        });
+        self.counts.clear();
+    }
+}
+
+impl Default for FailureStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,188 @@
+use ethers::prelude::{abi, Lazy};
+use ethers::providers::Middleware;
+use ethers::types::{H160, U256, U64};
+use foundry_evm_mini::evm::executor::fork::{BlockchainDb, BlockchainDbMeta, SharedBackend};
+use log::{info, warn};
+use revm::db::{CacheDB, Database};
+use revm::primitives::{keccak256, U256 as rU256};
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::common::tokens::get_cached_balance_slot;
+
+/*
+One forked database per block, shared by every simulation on top of it.
+EvmSimulator::new used to spawn its own SharedBackend, so every simulation of the appetizer,
+the optimizer and main_dish fetched the same pools and tokens again over RPC.
+Now the first simulator of a block spawns the backend, and everyone after gets a clone of the same CacheDB:
+- the state fetched by any of them is cached in the shared backend for all the others
+- writes (balances set, txs committed) stay in each clone's own CacheDB layer
+- the strategy resets it on every new block (reorgs included) and prewarms it with
+  the pools and tokens the pending victims touch, so re-simulating them doesn't wait on RPC
+Simulations on another block (replays, backtests) get a one-off backend, unless it's a newer block.
+Every thread (the simulation pool's workers, see simulation_pool.rs) pins its own copy of the base CacheDB
+and only goes back to FORK_DB, and its lock, once FORK_DB_VERSION says it was replaced.
+*/
+pub struct ForkDb {
+    pub block_number: U64,
+    pub generation: u64, // tells a reorged block apart from the one it replaced
+    pub db: CacheDB<SharedBackend>,
+}
+
+pub static FORK_DB: Lazy<Mutex<Option<ForkDb>>> = Lazy::new(|| Mutex::new(None));
+
+// Bumped, under FORK_DB's lock, whenever its database is replaced (new block, reorg, prewarm)
+pub static FORK_DB_VERSION: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    // (block number, FORK_DB_VERSION, db) of the base database this thread last forked from
+    static PINNED_FORK_DB: RefCell<Option<(U64, u64, CacheDB<SharedBackend>)>> = RefCell::new(None);
+}
+
+fn pin(block_number: U64, version: u64, db: &CacheDB<SharedBackend>) {
+    PINNED_FORK_DB.with(|pinned| *pinned.borrow_mut() = Some((block_number, version, db.clone())));
+}
+
+// UniswapV2Pair storage: token0, token1, reserve0 / reserve1 / blockTimestampLast
+pub static V2_PAIR_SLOTS: [u64; 3] = [6, 7, 8];
+
+fn spawn_fork_db<M: Middleware + 'static>(
+    provider: Arc<M>,
+    block_number: U64,
+) -> CacheDB<SharedBackend> {
+    let shared_backend = SharedBackend::spawn_backend_thread(
+        provider,
+        BlockchainDb::new(
+            BlockchainDbMeta {
+                cfg_env: Default::default(),
+                block_env: Default::default(),
+                hosts: BTreeSet::from(["".to_string()]),
+            },
+            None,
+        ),
+        Some(block_number.into()),
+    );
+    CacheDB::new(shared_backend)
+}
+
+// A clone of the block's forked database, spawned on the first call for a block
+pub fn fork_db<M: Middleware + 'static>(
+    provider: Arc<M>,
+    block_number: U64,
+) -> CacheDB<SharedBackend> {
+    let version = FORK_DB_VERSION.load(Ordering::SeqCst);
+    let pinned = PINNED_FORK_DB.with(|pinned| match pinned.borrow().as_ref() {
+        Some((pinned_block, pinned_version, db))
+            if *pinned_block == block_number && *pinned_version == version =>
+        {
+            Some(db.clone())
+        }
+        _ => None,
+    });
+    if let Some(db) = pinned {
+        return db;
+    }
+
+    let mut fork = FORK_DB.lock().unwrap();
+    match fork.as_ref() {
+        Some(fork) if fork.block_number == block_number => {
+            pin(
+                block_number,
+                FORK_DB_VERSION.load(Ordering::SeqCst),
+                &fork.db,
+            );
+            return fork.db.clone();
+        }
+        Some(fork) if fork.block_number > block_number => {
+            return spawn_fork_db(provider, block_number)
+        }
+        _ => {}
+    }
+    let db = spawn_fork_db(provider, block_number);
+    let generation = fork.as_ref().map(|f| f.generation + 1).unwrap_or(0);
+    *fork = Some(ForkDb {
+        block_number,
+        generation,
+        db: db.clone(),
+    });
+    let version = FORK_DB_VERSION.fetch_add(1, Ordering::SeqCst) + 1;
+    pin(block_number, version, &db);
+    db
+}
+
+// Called on every new block: the next simulations fork from it, even at the same height after a reorg
+pub fn reset_fork_db<M: Middleware + 'static>(provider: Arc<M>, block_number: U64) {
+    let db = spawn_fork_db(provider, block_number);
+    let mut fork = FORK_DB.lock().unwrap();
+    let generation = fork.as_ref().map(|f| f.generation + 1).unwrap_or(0);
+    *fork = Some(ForkDb {
+        block_number,
+        generation,
+        db,
+    });
+    FORK_DB_VERSION.fetch_add(1, Ordering::SeqCst);
+}
+
+/*
+Loads the pairs' reserves and the tokens' code and pair balances into the block's database.
+Blocks on RPC, run it with spawn_blocking. The fetched state lands in the shared backend either way,
+and the warmed CacheDB replaces the base one if the block is still the same.
+*/
+pub fn prewarm_fork_db(block_number: U64, pairs: &[(H160, Vec<H160>)]) {
+    let (generation, mut db) = match FORK_DB.lock().unwrap().as_ref() {
+        Some(fork) if fork.block_number == block_number => (fork.generation, fork.db.clone()),
+        _ => return,
+    };
+    let started = Instant::now();
+    let mut loaded = 0;
+    for (pair, tokens) in pairs {
+        match db.basic((*pair).into()) {
+            Ok(_) => loaded += 1,
+            Err(e) => {
+                warn!("prewarm_fork_db basic error: {e:?}");
+                continue;
+            }
+        }
+        for slot in V2_PAIR_SLOTS {
+            match db.storage((*pair).into(), rU256::from(slot)) {
+                Ok(_) => loaded += 1,
+                Err(e) => warn!("prewarm_fork_db storage error: {e:?}"),
+            }
+        }
+        for token in tokens {
+            match db.basic((*token).into()) {
+                Ok(_) => loaded += 1,
+                Err(e) => warn!("prewarm_fork_db basic error: {e:?}"),
+            }
+            if let Some(slot) = get_cached_balance_slot(*token) {
+                let balance_slot = keccak256(&abi::encode(&[
+                    abi::Token::Address(*pair),
+                    abi::Token::Uint(U256::from(slot)),
+                ]));
+                match db.storage((*token).into(), balance_slot.into()) {
+                    Ok(_) => loaded += 1,
+                    Err(e) => warn!("prewarm_fork_db storage error: {e:?}"),
+                }
+            }
+        }
+    }
+    let mut fork = FORK_DB.lock().unwrap();
+    if let Some(fork) = fork.as_mut() {
+        if fork.generation == generation {
+            fork.db = db;
+            FORK_DB_VERSION.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    info!(
+        "Fork db of block #{:?} prewarmed: {} accounts / slots in {} ms",
+        block_number,
+        loaded,
+        started.elapsed().as_millis()
+    );
+}
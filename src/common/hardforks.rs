@@ -0,0 +1,160 @@
+use ethers::types::U64;
+use log::{info, warn};
+use revm::primitives::SpecId;
+use std::collections::HashSet;
+
+use crate::common::alert::Alert;
+use crate::common::chain::{CHAIN, GOERLI, MAINNET, SEPOLIA};
+
+/*
+Hardforks the simulator follows, so gas costs and opcodes match the rules of the block we simulate:
+- each chain profile has the activation blocks we know of, oldest first (see known_hardforks)
+- HARDFORKS adds or moves forks, e.g. "CANCUN:19426587", as soon as an activation block is announced
+- EvmSimulator runs with the spec of the last fork activated at the block it simulates.
+  Chains without a schedule (L2s, BSC) simulate with the latest spec, as before
+- HardforkWatch alerts HARDFORK_ALERT_SECS ahead of the next fork, and again once it's active
+*/
+pub static HARDFORK_ALERT_SECS: u64 = 86400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hardfork {
+    pub name: &'static str,
+    pub spec_id: SpecId,
+    pub block: u64, // first block with the new rules
+}
+
+fn spec_by_name(name: &str) -> Option<(&'static str, SpecId)> {
+    match name.trim().to_uppercase().as_str() {
+        "MERGE" | "PARIS" => Some(("MERGE", SpecId::MERGE)),
+        "SHANGHAI" => Some(("SHANGHAI", SpecId::SHANGHAI)),
+        "CANCUN" => Some(("CANCUN", SpecId::CANCUN)),
+        _ => None,
+    }
+}
+
+fn hardfork(name: &str, block: u64) -> Hardfork {
+    let (name, spec_id) = spec_by_name(name).unwrap();
+    Hardfork {
+        name,
+        spec_id,
+        block,
+    }
+}
+
+pub fn known_hardforks(chain_id: u64) -> Vec<Hardfork> {
+    if chain_id == MAINNET {
+        vec![
+            hardfork("MERGE", 15537394),
+            hardfork("SHANGHAI", 17034870),
+            hardfork("CANCUN", 19426587),
+        ]
+    } else if chain_id == GOERLI {
+        vec![
+            hardfork("MERGE", 7382819),
+            hardfork("SHANGHAI", 8656123),
+            hardfork("CANCUN", 10388176),
+        ]
+    } else if chain_id == SEPOLIA {
+        vec![
+            hardfork("MERGE", 1735371),
+            hardfork("SHANGHAI", 2990908),
+            hardfork("CANCUN", 5187023),
+        ]
+    } else {
+        Vec::new()
+    }
+}
+
+/*
+Parses: "SHANGHAI:17034870,CANCUN:19426587" on top of the chain's known forks,
+a fork that's already known gets the new activation block
+*/
+pub fn load_hardforks(chain_id: u64, raw: &str) -> Vec<Hardfork> {
+    let mut hardforks = known_hardforks(chain_id);
+    for item in raw.split(',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        let mut parts = item.split(':');
+        let parsed = match (parts.next(), parts.next()) {
+            (Some(name), Some(block)) => match (spec_by_name(name), block.trim().parse::<u64>()) {
+                (Some((name, spec_id)), Ok(block)) => Some(Hardfork {
+                    name,
+                    spec_id,
+                    block,
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+        match parsed {
+            Some(fork) => {
+                hardforks.retain(|known| known.name != fork.name);
+                hardforks.push(fork);
+            }
+            None => warn!(
+                "Invalid hardfork {}, expected <SHANGHAI|CANCUN|...>:<block>",
+                item
+            ),
+        }
+    }
+    hardforks.sort_by_key(|fork| fork.block);
+    hardforks
+}
+
+pub struct HardforkWatch {
+    pub alert_blocks: u64,
+    pub spec_id: Option<SpecId>, // spec of the next block, as of the last block seen
+    pub warned: HashSet<&'static str>,
+}
+
+impl HardforkWatch {
+    pub fn new() -> Self {
+        Self {
+            alert_blocks: CHAIN.blocks_for_secs(HARDFORK_ALERT_SECS),
+            spec_id: None,
+            warned: HashSet::new(),
+        }
+    }
+
+    pub async fn on_block(&mut self, block_number: U64, alert: &Alert) {
+        // we simulate against the next block
+        let next_block = block_number + U64::from(1);
+        let spec_id = CHAIN.spec_at(next_block);
+        match self.spec_id {
+            None => info!("Simulating with the {:?} spec", spec_id),
+            Some(previous) if previous != spec_id => {
+                let message = format!(
+                    "⛓️ Hardfork active from block #{}: simulating with {:?} (was {:?})",
+                    next_block, spec_id, previous
+                );
+                warn!("{}", message);
+                match alert.send(&message).await {
+                    Err(e) => warn!("Telegram error: {e:?}"),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        self.spec_id = Some(spec_id);
+
+        if let Some(fork) = CHAIN.next_hardfork(next_block) {
+            let blocks_left = fork.block - next_block.as_u64();
+            if blocks_left <= self.alert_blocks && self.warned.insert(fork.name) {
+                let message = format!(
+                    "⛓️ {} activates at block #{}, in {} blocks (~{} min). Check gas / simulation constants against the new rules",
+                    fork.name,
+                    fork.block,
+                    blocks_left,
+                    blocks_left * CHAIN.block_time_secs / 60
+                );
+                warn!("{}", message);
+                match alert.send(&message).await {
+                    Err(e) => warn!("Telegram error: {e:?}"),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use anyhow::Result;
+use chrono::{TimeZone, Utc};
+use ethers::types::{H160, H256, U256, U64};
+use rusqlite::{params, Connection};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::tracker::BundleStatus;
+
+/*
+Every sandwich we've attempted, and what became of it, kept across restarts so operators
+don't have to reconstruct results from logs:
+- attempt: victims, pairs, amounts in, simulated revenue/profit, gas, bribe and the builders that took it
+- outcome: updated by the tracker as the bundle goes Pending --> Included --> Confirmed (or Missed/Reorged).
+  realized_profit is set once the bundle is confirmed, and cleared again if it gets reorged out
+- DRY_RUN bundles are journaled with the dry_run status and left out of the PnL
+Profits are in wei of the main currency the bot accounts in (see SimulatedSandwich), stored as TEXT
+since they don't fit in SQLite integers.
+*/
+pub const TRADE_JOURNAL_DB_FILE: &str = "cache/.trade-journal.db";
+
+#[derive(Debug, Clone, Default)]
+pub struct TradeEntry {
+    pub bundle_id: String,
+    pub victim_tx_hashes: Vec<H256>,
+    pub pairs: Vec<H160>,
+    pub amounts_in: Vec<(H160, U256)>, // (main_currency, amount_in) per sandwich
+    pub simulated_revenue: i128,       // after gas, before the bribe
+    pub expected_profit: i128,         // simulated_revenue - bribe
+    pub gas_used: u64,                 // front + back
+    pub gas_cost: i128,                // front + back at the simulated base fee
+    pub bribe: U256,
+    pub builders: Vec<String>, // builders (or the sequencer) that acknowledged the bundle
+    pub target_block: U64,
+    pub dry_run: bool, // simulated by the relay, never broadcast
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DailyPnl {
+    pub day: String, // YYYY-MM-DD, UTC
+    pub attempted: u64,
+    pub landed: u64,
+    pub simulated_profit: i128, // expected profit of the bundles that landed
+    pub realized_profit: i128,
+    pub gas_cost: i128,
+    pub bribes: U256,
+}
+
+pub struct TradeJournal {
+    pub conn: Connection,
+}
+
+fn status_name(status: &BundleStatus) -> &'static str {
+    match status {
+        BundleStatus::Pending => "pending",
+        BundleStatus::Included => "included",
+        BundleStatus::Confirmed => "confirmed",
+        BundleStatus::Reorged => "reorged",
+        BundleStatus::Missed => "missed",
+    }
+}
+
+fn join<T: std::fmt::Debug>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|item| format!("{:?}", item))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn unix_secs() -> Result<i64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64)
+}
+
+impl TradeJournal {
+    pub fn open(path: &str) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS trades (
+                 bundle_id         TEXT PRIMARY KEY,
+                 victim_tx_hashes  TEXT NOT NULL,
+                 pairs             TEXT NOT NULL,
+                 amounts_in        TEXT NOT NULL,
+                 simulated_revenue TEXT NOT NULL,
+                 expected_profit   TEXT NOT NULL,
+                 realized_profit   TEXT,
+                 gas_used          INTEGER NOT NULL,
+                 gas_cost          TEXT NOT NULL,
+                 bribe             TEXT NOT NULL,
+                 builders          TEXT NOT NULL,
+                 target_block      INTEGER NOT NULL,
+                 included_block    INTEGER,
+                 status            TEXT NOT NULL,
+                 attempted_at      INTEGER NOT NULL,
+                 updated_at        INTEGER NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS trades_attempted_at ON trades (attempted_at);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    pub fn record_attempt(&mut self, trade: &TradeEntry) -> Result<()> {
+        let now = unix_secs()?;
+        let amounts_in = trade
+            .amounts_in
+            .iter()
+            .map(|(main_currency, amount_in)| format!("{:?}:{}", main_currency, amount_in))
+            .collect::<Vec<String>>()
+            .join(",");
+        self.conn.execute(
+            "INSERT OR REPLACE INTO trades
+             (bundle_id, victim_tx_hashes, pairs, amounts_in, simulated_revenue, expected_profit,
+              realized_profit, gas_used, gas_cost, bribe, builders, target_block, included_block,
+              status, attempted_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, NULL, ?7, ?8, ?9, ?10, ?11, NULL, ?12, ?13, ?13)",
+            params![
+                trade.bundle_id,
+                join(&trade.victim_tx_hashes),
+                join(&trade.pairs),
+                amounts_in,
+                trade.simulated_revenue.to_string(),
+                trade.expected_profit.to_string(),
+                trade.gas_used as i64,
+                trade.gas_cost.to_string(),
+                trade.bribe.to_string(),
+                trade.builders.join(","),
+                trade.target_block.as_u64() as i64,
+                if trade.dry_run {
+                    "dry_run"
+                } else {
+                    status_name(&BundleStatus::Pending)
+                },
+                now
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn record_status(
+        &mut self,
+        bundle_id: &str,
+        status: &BundleStatus,
+        included_block: Option<U64>,
+        realized_profit: Option<i128>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "UPDATE trades
+             SET status = ?2, included_block = ?3, realized_profit = ?4, updated_at = ?5
+             WHERE bundle_id = ?1",
+            params![
+                bundle_id,
+                status_name(status),
+                included_block.map(|b| b.as_u64() as i64),
+                realized_profit.map(|p| p.to_string()),
+                unix_secs()?
+            ],
+        )?;
+        Ok(())
+    }
+
+    // PnL per UTC day over the last `days` days, oldest first
+    pub fn daily_pnl(&self, days: u64) -> Result<Vec<DailyPnl>> {
+        let since = unix_secs()? - (days * 86400) as i64;
+        let mut stmt = self.conn.prepare(
+            "SELECT attempted_at, status, expected_profit, realized_profit, gas_cost, bribe
+             FROM trades WHERE attempted_at >= ?1 AND status != 'dry_run'",
+        )?;
+        let rows = stmt
+            .query_map(params![since], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut pnl: BTreeMap<String, DailyPnl> = BTreeMap::new();
+        for (attempted_at, status, expected_profit, realized_profit, gas_cost, bribe) in rows {
+            let day = match Utc.timestamp_opt(attempted_at, 0).single() {
+                Some(datetime) => datetime.format("%Y-%m-%d").to_string(),
+                None => continue,
+            };
+            let entry = pnl.entry(day.clone()).or_insert(DailyPnl {
+                day,
+                ..Default::default()
+            });
+            entry.attempted += 1;
+            if status == "included" || status == "confirmed" {
+                entry.landed += 1;
+                entry.simulated_profit += expected_profit.parse::<i128>().unwrap_or(0);
+                entry.gas_cost += gas_cost.parse::<i128>().unwrap_or(0);
+                entry.bribes = entry
+                    .bribes
+                    .saturating_add(U256::from_dec_str(&bribe).unwrap_or_default());
+            }
+            if let Some(realized_profit) = realized_profit {
+                entry.realized_profit += realized_profit.parse::<i128>().unwrap_or(0);
+            }
+        }
+        Ok(pnl.into_values().collect())
+    }
+}
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use ethers::signers::LocalWallet;
+use log::info;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::common::constants::get_env;
+
+/*
+Signing keys from the env: PRIVATE_KEY, IDENTITY_KEY and the execution contexts' private_key_env.
+Each holds either the raw hex key or the path of an encrypted JSON keystore (geth / foundry `cast wallet new`),
+so raw keys never have to sit in .env. Keystores are decrypted once at startup, in Executor::new,
+with the password from <VAR>_PASSWORD (e.g. PRIVATE_KEY_PASSWORD), else KEYSTORE_PASSWORD,
+else asked for on the terminal.
+*/
+pub fn load_wallet(var: &str, value: &str) -> Result<LocalWallet> {
+    let value = value.trim();
+    if !Path::new(value).is_file() {
+        return value
+            .parse::<LocalWallet>()
+            .map_err(|e| anyhow!("invalid {}: {e:?}", var));
+    }
+    let password = match get_env(&format!("{}_PASSWORD", var)).as_str() {
+        "" => match get_env("KEYSTORE_PASSWORD").as_str() {
+            "" => prompt_password(var, value)?,
+            password => password.to_string(),
+        },
+        password => password.to_string(),
+    };
+    let wallet = LocalWallet::decrypt_keystore(value, password)
+        .map_err(|e| anyhow!("{}: can't decrypt keystore {}: {e:?}", var, value))?;
+    info!("{}: decrypted keystore {}", var, value);
+    Ok(wallet)
+}
+
+fn prompt_password(var: &str, path: &str) -> Result<String> {
+    eprint!("Password of {} keystore {}: ", var, path);
+    std::io::stderr().flush()?;
+    // keep it off the screen when there's a terminal to turn echo off on
+    let echo_off = Command::new("stty")
+        .arg("-echo")
+        .stdin(Stdio::inherit())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false);
+    let mut password = String::new();
+    let read = std::io::stdin().read_line(&mut password);
+    if echo_off {
+        let _ = Command::new("stty")
+            .arg("echo")
+            .stdin(Stdio::inherit())
+            .status();
+        eprintln!();
+    }
+    read?;
+    Ok(password.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
@@ -0,0 +1,72 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+/*
+Stops every bundle/tx submission right away, while streams, pool syncs and caches keep running,
+so the bot can be resumed during an incident without a cold restart. Halted when any of:
+- KILL_SWITCH_FILE exists (touch it to halt, remove it to resume)
+- SIGUSR1 was received (SIGUSR2 resumes)
+- /halt was sent from Telegram or POST /halt to the control API (/resume, POST /resume resume)
+*/
+#[derive(Debug, Clone, Default)]
+pub struct KillSwitch {
+    halted: Arc<AtomicBool>,
+    flag_path: String, // empty: no file flag
+}
+
+impl KillSwitch {
+    pub fn new(flag_path: &str) -> Self {
+        Self {
+            halted: Arc::new(AtomicBool::new(false)),
+            flag_path: flag_path.to_string(),
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted.load(Ordering::SeqCst)
+            || (!self.flag_path.is_empty() && Path::new(&self.flag_path).exists())
+    }
+
+    // Err while halted, for submission paths to bail out with
+    pub fn check(&self) -> Result<()> {
+        if self.is_halted() {
+            return Err(anyhow!("Kill switch is on, not submitting"));
+        }
+        Ok(())
+    }
+
+    pub fn halt(&self, by: &str) {
+        self.halted.store(true, Ordering::SeqCst);
+        warn!("Kill switch on ({}): bundle submission halted", by);
+    }
+
+    // Doesn't remove the file flag, whoever created it has to
+    pub fn resume(&self, by: &str) {
+        self.halted.store(false, Ordering::SeqCst);
+        if !self.flag_path.is_empty() && Path::new(&self.flag_path).exists() {
+            warn!(
+                "Kill switch released ({}), but {} still exists",
+                by, self.flag_path
+            );
+        } else {
+            info!("Kill switch off ({}): bundle submission resumed", by);
+        }
+    }
+}
+
+pub async fn listen_kill_signals(kill_switch: KillSwitch) -> Result<()> {
+    let mut halt = signal(SignalKind::user_defined1())?;
+    let mut resume = signal(SignalKind::user_defined2())?;
+    loop {
+        tokio::select! {
+            _ = halt.recv() => kill_switch.halt("SIGUSR1"),
+            _ = resume.recv() => kill_switch.resume("SIGUSR2"),
+        }
+    }
+}
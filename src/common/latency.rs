@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/*
+Where the time went for a bundle, from the moment we saw the victim to the block it targeted.
+All timestamps are unix milliseconds, 0 when the step didn't happen:
+- first_seen: the earliest victim tx arrived from a pending tx source
+- traced: the last victim's swaps were traced (touched pairs known)
+- optimized: the last sandwich in the bundle had its amount in optimized
+- signed: the frontrun/backrun txs of the first variant were signed
+- broadcast: when each builder acknowledged the first variant
+- block_timestamp: timestamp of the including block, or of the target block for misses
+  (block timestamps only have second precision)
+Stored with the bundle's outcome in bundle_outcomes.timeline as JSON.
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BundleTimeline {
+    pub first_seen: u64,
+    pub traced: u64,
+    pub optimized: u64,
+    pub signed: u64,
+    pub broadcast: BTreeMap<String, u64>, // builder -> acknowledged at
+    pub block_timestamp: u64,
+}
+
+fn elapsed(from: u64, to: u64) -> String {
+    if from == 0 || to == 0 {
+        return String::from("-");
+    }
+    format!("{}ms", to as i64 - from as i64)
+}
+
+impl BundleTimeline {
+    pub fn first_broadcast(&self) -> u64 {
+        self.broadcast.values().cloned().min().unwrap_or(0)
+    }
+
+    pub fn last_broadcast(&self) -> u64 {
+        self.broadcast.values().cloned().max().unwrap_or(0)
+    }
+
+    // Time spent in each step, e.g. "seen→traced 12ms, traced→optimized 40ms, ..."
+    pub fn describe(&self) -> String {
+        let mut steps = vec![
+            format!("seen→traced {}", elapsed(self.first_seen, self.traced)),
+            format!("traced→optimized {}", elapsed(self.traced, self.optimized)),
+            format!("optimized→signed {}", elapsed(self.optimized, self.signed)),
+        ];
+        for (builder, at) in &self.broadcast {
+            steps.push(format!("signed→{} {}", builder, elapsed(self.signed, *at)));
+        }
+        steps.push(format!(
+            "broadcast→block {}",
+            elapsed(self.last_broadcast(), self.block_timestamp)
+        ));
+        steps.push(format!(
+            "total {}",
+            elapsed(self.first_seen, self.block_timestamp)
+        ));
+        steps.join(", ")
+    }
+}
@@ -0,0 +1,108 @@
+use ethers::types::{Transaction, H160};
+use log::warn;
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::common::chain::CHAIN;
+use crate::common::constants::Env;
+
+/*
+During mempool storms (NFT mints, airdrops) pending txs arrive faster than we can trace them,
+and once the broadcast channel is full the oldest events are silently dropped.
+Instead, we look at the channel backlog and shed the work least likely to be a sandwich victim first:
+1. backlog >= 25% of capacity: skip plain transfers and contract creations
+2. backlog >= 50% of capacity: also skip txs to unknown contracts, only trace the known routers
+The known routers are the chain profile's (see chain.rs), plus the ones in EXTRA_ROUTERS.
+Dropped (lagged) and shed events are counted and logged periodically.
+*/
+pub static LOAD_SHED_REPORT_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TxPriority {
+    Low,    // plain transfers, contract creations
+    Normal, // unknown contracts
+    High,   // known routers
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadShedder {
+    pub capacity: usize,
+    pub routers: HashSet<H160>,
+    pub lagged: u64, // events the channel dropped before we could receive them
+    pub shed: u64,   // events we skipped on purpose
+    pub last_report: Instant,
+}
+
+impl LoadShedder {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            capacity: env.event_channel_capacity,
+            routers: CHAIN
+                .routers
+                .iter()
+                .copied()
+                .chain(
+                    env.extra_routers
+                        .split(',')
+                        .filter_map(|router| H160::from_str(router.trim()).ok()),
+                )
+                .collect(),
+            lagged: 0,
+            shed: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn priority(&self, tx: &Transaction) -> TxPriority {
+        match tx.to {
+            Some(to) if self.routers.contains(&to) => TxPriority::High,
+            Some(_) if !tx.input.is_empty() => TxPriority::Normal,
+            _ => TxPriority::Low,
+        }
+    }
+
+    // The lowest priority we still process with the current backlog
+    pub fn min_priority(&self, backlog: usize) -> TxPriority {
+        if backlog >= self.capacity / 2 {
+            TxPriority::High
+        } else if backlog >= self.capacity / 4 {
+            TxPriority::Normal
+        } else {
+            TxPriority::Low
+        }
+    }
+
+    pub fn is_shed(&self, tx: &Transaction, backlog: usize) -> bool {
+        self.priority(tx) < self.min_priority(backlog)
+    }
+
+    pub fn should_process(&mut self, tx: &Transaction, backlog: usize) -> bool {
+        if self.is_shed(tx, backlog) {
+            self.shed += 1;
+            return false;
+        }
+        true
+    }
+
+    pub fn record_lagged(&mut self, dropped: u64) {
+        self.lagged += dropped;
+    }
+
+    pub fn maybe_report(&mut self) {
+        if self.last_report.elapsed() < Duration::from_secs(LOAD_SHED_REPORT_INTERVAL_SECS) {
+            return;
+        }
+        if self.lagged > 0 || self.shed > 0 {
+            warn!(
+                "Load shedding: {} events dropped by the channel, {} pending txs shed in the last {}s",
+                self.lagged, self.shed, LOAD_SHED_REPORT_INTERVAL_SECS
+            );
+        }
+        self.lagged = 0;
+        self.shed = 0;
+        self.last_report = Instant::now();
+    }
+}
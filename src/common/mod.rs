@@ -1,10 +1,54 @@
 pub mod abi;
 pub mod alert;
+pub mod archive;
+pub mod block_space;
+pub mod builders;
+pub mod bundle_stats;
 pub mod bytecode;
+pub mod cache_store;
+pub mod capabilities;
+pub mod chain;
+pub mod compat;
+pub mod connection;
 pub mod constants;
+pub mod contexts;
+pub mod control;
+pub mod dashboard;
+pub mod dedup;
 pub mod evm;
 pub mod execution;
+pub mod experiments;
+pub mod external_feed;
+pub mod failures;
+pub mod fork_db;
+pub mod hardforks;
+pub mod journal;
+pub mod keys;
+pub mod kill_switch;
+pub mod latency;
+pub mod load_shedding;
+pub mod multicall;
+pub mod nonce;
+pub mod pool_store;
 pub mod pools;
+pub mod pricing;
+pub mod profile;
+pub mod provider;
+pub mod redact;
+pub mod reload;
+pub mod reorg;
+pub mod replacement;
+pub mod reputation;
+pub mod reserves;
+pub mod risk;
+pub mod simulation_pool;
+pub mod slot;
+pub mod store_writer;
 pub mod streams;
+pub mod telemetry;
+pub mod token_lists;
+pub mod token_safety;
 pub mod tokens;
+pub mod tracker;
 pub mod utils;
+pub mod victim_lock;
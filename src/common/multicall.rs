@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Result};
+use ethers::prelude::*;
+use ethers::providers::Provider;
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use crate::common::provider::RpcClient;
+use crate::common::tokens::TokenInfo;
+
+/*
+Batches view calls through Multicall3 (deployed at the same address on all major chains),
+so we can read name/symbol/decimals/balances of hundreds of tokens in a single eth_call.
+Calls are sent with allowFailure, so a single broken token doesn't fail the whole batch.
+*/
+pub static MULTICALL3: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+// Max number of calls aggregated in one request
+pub static MULTICALL_BATCH_SIZE: usize = 300;
+
+abigen!(
+    IERC20Metadata,
+    r#"[
+        function name() external view returns (string)
+        function symbol() external view returns (string)
+        function decimals() external view returns (uint8)
+        function balanceOf(address) external view returns (uint256)
+    ]"#,
+);
+
+pub async fn new_multicall(
+    provider: &Arc<Provider<RpcClient>>,
+    block_number: U64,
+) -> Result<Multicall<Provider<RpcClient>>> {
+    let multicall = Multicall::new(provider.clone(), Some(H160::from_str(MULTICALL3).unwrap()))
+        .await?
+        .version(MulticallVersion::Multicall3)
+        .block(block_number);
+    Ok(multicall)
+}
+
+// Tokens that fail (e.g. bytes32 name/symbol) are left out of the result
+pub async fn get_token_info_multicall(
+    provider: &Arc<Provider<RpcClient>>,
+    block_number: U64,
+    tokens: &Vec<H160>,
+) -> Result<HashMap<H160, TokenInfo>> {
+    let mut token_info = HashMap::new();
+    let mut multicall = new_multicall(provider, block_number).await?;
+
+    for chunk in tokens.chunks(MULTICALL_BATCH_SIZE / 3) {
+        multicall.clear_calls();
+        for token in chunk {
+            let contract = IERC20Metadata::new(*token, provider.clone());
+            multicall
+                .add_call(contract.name(), true)
+                .add_call(contract.symbol(), true)
+                .add_call(contract.decimals(), true);
+        }
+
+        let results = multicall.call_raw().await?;
+        for (i, token) in chunk.iter().enumerate() {
+            let (name, symbol, decimals) =
+                match (&results[3 * i], &results[3 * i + 1], &results[3 * i + 2]) {
+                    (Ok(name), Ok(symbol), Ok(decimals)) => (
+                        name.clone().into_string(),
+                        symbol.clone().into_string(),
+                        decimals.clone().into_uint(),
+                    ),
+                    _ => continue,
+                };
+            match (name, symbol, decimals) {
+                (Some(name), Some(symbol), Some(decimals)) if decimals <= U256::from(255) => {
+                    token_info.insert(
+                        *token,
+                        TokenInfo {
+                            address: *token,
+                            name,
+                            symbol,
+                            decimals: decimals.as_u32() as u8,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(token_info)
+}
+
+pub async fn get_token_balances_multicall(
+    provider: &Arc<Provider<RpcClient>>,
+    owner: H160,
+    tokens: &Vec<H160>,
+    block_number: U64,
+) -> Result<HashMap<H160, U256>> {
+    let mut token_balances = HashMap::new();
+    let mut multicall = new_multicall(provider, block_number).await?;
+
+    for chunk in tokens.chunks(MULTICALL_BATCH_SIZE) {
+        multicall.clear_calls();
+        for token in chunk {
+            let contract = IERC20Metadata::new(*token, provider.clone());
+            multicall.add_call(contract.balance_of(owner), true);
+        }
+
+        let results = multicall.call_raw().await?;
+        for (token, result) in chunk.iter().zip(results) {
+            let balance = match result {
+                Ok(balance) => balance.into_uint(),
+                Err(_) => None,
+            };
+            match balance {
+                Some(balance) => token_balances.insert(*token, balance),
+                None => return Err(anyhow!("balanceOf failed for {:?}", token)),
+            };
+        }
+    }
+
+    Ok(token_balances)
+}
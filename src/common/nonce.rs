@@ -0,0 +1,85 @@
+use ethers::types::{U256, U64};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/*
+Nonces of the owner wallet, tracked locally instead of asking the node for every bundle.
+Bundles built for the same blocks are alternatives (other victims, other pools, replacements),
+at most one of them lands before the others are rebuilt, so they all start from the same base nonce:
+the node's nonce, past the txs we sent on our own that may still be mined (withdrawals, sequencer txs).
+Only the txs inside one bundle get consecutive nonces.
+- a tx sent on its own commits its nonces until the last block it can be mined in
+- on every new block the node's nonce is fetched again before the next bundle, and expired commitments are dropped
+- when a bundle resolves (included, missed, reorged), we resync with the node's nonce right away
+*/
+#[derive(Debug, Default)]
+struct NonceState {
+    onchain: Option<U256>, // None: fetch from the node before the next bundle
+    committed: BTreeMap<U256, U64>, // nonce -> last block the tx using it can be mined in
+}
+
+impl NonceState {
+    fn base(&self) -> Option<U256> {
+        let onchain = self.onchain?;
+        let after_committed = match self.committed.keys().next_back() {
+            Some(highest) => *highest + U256::from(1),
+            None => onchain,
+        };
+        Some(std::cmp::max(onchain, after_committed))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NonceManager {
+    inner: Arc<Mutex<NonceState>>,
+}
+
+impl NonceManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.inner.lock().unwrap().onchain.is_some()
+    }
+
+    // First nonce of a bundle, the same for every bundle until a tx we sent lands. None if we have to sync with the node first
+    pub fn bundle_nonce(&self) -> Option<U256> {
+        self.inner.lock().unwrap().base()
+    }
+
+    // Commits count consecutive nonces for txs sent on their own, returns the first one. None if we have to sync first
+    pub fn commit(&self, count: u64, last_block: U64) -> Option<U256> {
+        let mut state = self.inner.lock().unwrap();
+        let first = state.base()?;
+        for i in 0..count {
+            state.committed.insert(first + U256::from(i), last_block);
+        }
+        Some(first)
+    }
+
+    // Gives back nonces committed for txs that were never sent (DRY_RUN)
+    pub fn release(&self, first: U256, count: u64) {
+        let mut state = self.inner.lock().unwrap();
+        for i in 0..count {
+            state.committed.remove(&(first + U256::from(i)));
+        }
+    }
+
+    // The node's nonce (at the latest block): nonces below it are used onchain
+    pub fn resync(&self, onchain_nonce: U256) {
+        let mut state = self.inner.lock().unwrap();
+        state.committed.retain(|nonce, _| *nonce >= onchain_nonce);
+        state.onchain = Some(onchain_nonce);
+    }
+
+    pub fn expire(&self, block_number: U64) {
+        let mut state = self.inner.lock().unwrap();
+        state
+            .committed
+            .retain(|_, last_block| *last_block >= block_number);
+        state.onchain = None;
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use ethers::types::H160;
+use log::info;
+use rusqlite::{Connection, Row};
+use std::{fs, path::Path, str::FromStr};
+
+use crate::common::cache_store::{open_cache_store, CacheStore};
+use crate::common::constants::Env;
+use crate::common::pools::{DexVariant, Pool};
+
+/*
+Discovered pools, kept in the cache store (see cache_store.rs) under "pools", keyed by address.
+Replaces the old caches, which are migrated once on first open:
+- the CSV cache (cache/.cached-pools.csv)
+- the SQLite pools table (cache/.cached-pools.db)
+Pools are upserted by address, so restarts and incremental syncs don't have to re-read the whole history,
+and indexed by token0/token1 and block_number where the backend supports it (SQLite).
+*/
+pub const POOLS_COLLECTION: &str = "pools";
+pub const LEGACY_POOL_DB_FILE: &str = "cache/.cached-pools.db";
+pub const LEGACY_POOL_CSV_FILE: &str = "cache/.cached-pools.csv";
+
+pub struct PoolStore {
+    pub store: Box<dyn CacheStore>,
+}
+
+fn pool_from_row(row: &Row) -> rusqlite::Result<Pool> {
+    let address: String = row.get(1)?;
+    let version: i32 = row.get(2)?;
+    let token0: String = row.get(3)?;
+    let token1: String = row.get(4)?;
+    let block_number: i64 = row.get(6)?;
+    let timestamp: i64 = row.get(7)?;
+    let version = match version {
+        2 => DexVariant::UniswapV2,
+        _ => DexVariant::UniswapV2,
+    };
+    Ok(Pool {
+        id: row.get(0)?,
+        address: H160::from_str(&address).unwrap_or_default(),
+        version,
+        token0: H160::from_str(&token0).unwrap_or_default(),
+        token1: H160::from_str(&token1).unwrap_or_default(),
+        fee: row.get(5)?,
+        block_number: block_number as u64,
+        timestamp: timestamp as u64,
+    })
+}
+
+impl PoolStore {
+    pub fn open(env: &Env) -> Result<Self> {
+        let mut pool_store = Self {
+            store: open_cache_store(env)?,
+        };
+        for field in ["token0", "token1", "block_number"] {
+            pool_store.store.create_index(POOLS_COLLECTION, field)?;
+        }
+        if pool_store.count()? == 0 {
+            pool_store.migrate_from_db(LEGACY_POOL_DB_FILE)?;
+            pool_store.migrate_from_csv(LEGACY_POOL_CSV_FILE)?;
+        }
+        Ok(pool_store)
+    }
+
+    pub fn count(&mut self) -> Result<usize> {
+        self.store.count(POOLS_COLLECTION)
+    }
+
+    pub fn upsert_pools(&mut self, pools: &[Pool]) -> Result<usize> {
+        let items: Vec<(String, Pool)> = pools
+            .iter()
+            .map(|pool| (format!("{:?}", pool.address), *pool))
+            .collect();
+        self.store.put_json(POOLS_COLLECTION, &items)
+    }
+
+    // Ordered by id, the order they were discovered in
+    pub fn load_pools(&mut self) -> Result<Vec<Pool>> {
+        let mut pools: Vec<Pool> = self.store.load_json(POOLS_COLLECTION)?;
+        pools.sort_by_key(|pool| pool.id);
+        Ok(pools)
+    }
+
+    pub fn get_pool(&mut self, address: H160) -> Result<Option<Pool>> {
+        self.store
+            .get_json(POOLS_COLLECTION, &format!("{:?}", address))
+    }
+
+    // Ordered by id
+    pub fn pools_by_token(&mut self, token: H160) -> Result<Vec<Pool>> {
+        let token = format!("{:?}", token);
+        let mut pools: Vec<Pool> = self.store.find_json(POOLS_COLLECTION, "token0", &token)?;
+        pools.extend(
+            self.store
+                .find_json::<Pool>(POOLS_COLLECTION, "token1", &token)?,
+        );
+        pools.sort_by_key(|pool| pool.id);
+        Ok(pools)
+    }
+
+    // One-off import of the legacy SQLite pools table, the file is kept as *.migrated afterwards
+    pub fn migrate_from_db(&mut self, db_path: &str) -> Result<usize> {
+        let file_path = Path::new(db_path);
+        if !file_path.exists() {
+            return Ok(0);
+        }
+
+        let pools = {
+            let conn = Connection::open(file_path)?;
+            let mut stmt = conn.prepare("SELECT * FROM pools ORDER BY id ASC")?;
+            let pools = stmt
+                .query_map([], pool_from_row)?
+                .collect::<rusqlite::Result<Vec<Pool>>>()?;
+            pools
+        };
+        let migrated = self.upsert_pools(&pools)?;
+        fs::rename(file_path, format!("{}.migrated", db_path))?;
+        info!("Migrated {:?} pools from {}", migrated, db_path);
+
+        Ok(migrated)
+    }
+
+    // One-off import of the legacy CSV cache, the file is kept as *.migrated afterwards
+    pub fn migrate_from_csv(&mut self, csv_path: &str) -> Result<usize> {
+        let file_path = Path::new(csv_path);
+        if !file_path.exists() {
+            return Ok(0);
+        }
+
+        let mut reader = csv::Reader::from_path(file_path)?;
+        let mut pools = Vec::new();
+        for row in reader.records() {
+            pools.push(Pool::from(row?));
+        }
+        let migrated = self.upsert_pools(&pools)?;
+        fs::rename(file_path, format!("{}.migrated", csv_path))?;
+        info!("Migrated {:?} pools from {}", migrated, csv_path);
+
+        Ok(migrated)
+    }
+}
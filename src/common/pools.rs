@@ -1,23 +1,53 @@
-use anyhow::Result;
+use alloy_primitives::{keccak256, Address};
+use anyhow::{anyhow, Result};
 use csv::StringRecord;
 use ethers::abi::{parse_abi, ParamType};
 use ethers::prelude::*;
 use ethers::{
-    providers::{Provider, Ws},
+    providers::Provider,
     types::{H160, H256},
 };
+use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
+
+use crate::common::chain::CHAIN;
+use crate::common::compat::{ChainReader, LogFilter, ToAlloy, ToEthers};
+use crate::common::constants::{Env, CURVE_REGISTRY};
+use crate::common::evm::EvmSimulator;
+use crate::common::pool_store::{PoolStore, POOLS_COLLECTION};
+use crate::common::provider::{connect_provider, RpcClient};
+use crate::common::store_writer::STORE_WRITER;
+use crate::common::tokens::get_cached_balance_slot;
+use crate::common::utils::{is_main_currency, MainCurrency};
 use std::{
-    collections::HashMap,
-    fs::{create_dir_all, OpenOptions},
-    path::Path,
+    collections::{BTreeMap, HashMap},
+    fs::create_dir_all,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
+/*
+Fee is denominated in V2_FEE_DENOMINATOR units:
+300 --> 0.3% (Uniswap V2), 250 --> 0.25% (PancakeSwap), 100 --> 0.1%
+*/
+pub static V2_FEE_DENOMINATOR: u32 = 100000;
+
+pub fn get_v2_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee: u32) -> U256 {
+    let fee = std::cmp::min(fee, V2_FEE_DENOMINATOR);
+    let amount_in_with_fee = amount_in * U256::from(V2_FEE_DENOMINATOR - fee);
+    let numerator = amount_in_with_fee * reserve_out;
+    let denominator = (reserve_in * U256::from(V2_FEE_DENOMINATOR)) + amount_in_with_fee;
+    let amount_out = numerator.checked_div(denominator);
+    amount_out.unwrap_or_default()
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum DexVariant {
     UniswapV2, // 2
@@ -38,7 +68,7 @@ pub struct Pool {
     pub version: DexVariant,
     pub token0: H160,
     pub token1: H160,
-    pub fee: u32, // uniswap v3 specific
+    pub fee: u32, // V2: swap fee in 1/100000 (300 = 0.3%), see detect_v2_pool_fee
     pub block_number: u64,
     pub timestamp: u64,
 }
@@ -63,19 +93,6 @@ impl From<StringRecord> for Pool {
 }
 
 impl Pool {
-    pub fn cache_row(&self) -> (i64, String, i32, String, String, u32, u64, u64) {
-        (
-            self.id,
-            format!("{:?}", self.address),
-            self.version.num() as i32,
-            format!("{:?}", self.token0),
-            format!("{:?}", self.token1),
-            self.fee,
-            self.block_number,
-            self.timestamp,
-        )
-    }
-
     pub fn trades(&self, token_a: H160, token_b: H160) -> bool {
         let is_zero_for_one = self.token0 == token_a && self.token1 == token_b;
         let is_one_for_zero = self.token1 == token_a && self.token0 == token_b;
@@ -94,71 +111,93 @@ impl Pool {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurvePool {
+    pub address: H160,
+    pub coins: Vec<H160>,
+}
+
+impl CurvePool {
+    pub fn coin_index(&self, token: H160) -> Option<i128> {
+        self.coins
+            .iter()
+            .position(|coin| *coin == token)
+            .map(|idx| idx as i128)
+    }
+}
+
+abigen!(
+    ICurveRegistry,
+    r#"[
+        function pool_count() external view returns (uint256)
+        function pool_list(uint256) external view returns (address)
+        function get_coins(address) external view returns (address[8])
+    ]"#,
+);
+
+pub async fn load_curve_pools(provider: &Arc<Provider<RpcClient>>) -> Result<Vec<CurvePool>> {
+    let registry = ICurveRegistry::new(H160::from_str(CURVE_REGISTRY).unwrap(), provider.clone());
+    let pool_count = registry.pool_count().call().await?.as_u64();
+
+    let mut pools = Vec::new();
+    for i in 0..pool_count {
+        let address = registry.pool_list(U256::from(i)).call().await?;
+        let coins: Vec<H160> = registry
+            .get_coins(address)
+            .call()
+            .await?
+            .into_iter()
+            .filter(|coin| !coin.is_zero())
+            .collect();
+        pools.push(CurvePool { address, coins });
+    }
+    info!("Curve pools: {:?}", pools.len());
+
+    Ok(pools)
+}
+
 pub async fn get_touched_pools(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     block_number: U64,
 ) -> Result<Vec<H160>> {
     let v2_swap_event = "Swap(address,uint256,uint256,uint256,uint256,address)";
-    let event_filter = Filter::new()
-        .from_block(block_number)
-        .to_block(block_number)
-        .events(vec![v2_swap_event]);
-    let logs = provider.get_logs(&event_filter).await?;
-    let touched_pools: Vec<H160> = logs.iter().map(|log| log.address).unique().collect();
+    let event_filter = LogFilter {
+        from_block: block_number.as_u64(),
+        to_block: block_number.as_u64(),
+        addresses: Vec::new(),
+        topic0: vec![keccak256(v2_swap_event.as_bytes())],
+    };
+    let logs = provider.logs(&event_filter).await?;
+    let touched_pools: Vec<H160> = logs
+        .iter()
+        .map(|log| log.address.to_ethers())
+        .unique()
+        .collect();
     Ok(touched_pools)
 }
 
 pub async fn load_all_pools(
-    wss_url: String,
+    rpc_url: String,
     from_block: u64,
     chunk: u64,
 ) -> Result<(Vec<Pool>, i64)> {
     match create_dir_all("cache") {
         _ => {}
     }
-    let cache_file = "cache/.cached-pools.csv";
-    let file_path = Path::new(cache_file);
-    let file_exists = file_path.exists();
-    let file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(file_path)
-        .unwrap();
-    let mut writer = csv::Writer::from_writer(file);
+    let mut store = PoolStore::open(&Env::new())?;
 
-    let mut pools = Vec::new();
+    let mut pools = store.load_pools()?;
 
     let mut v2_pool_cnt = 0;
-
-    if file_exists {
-        let mut reader = csv::Reader::from_path(file_path)?;
-
-        for row in reader.records() {
-            let row = row.unwrap();
-            let pool = Pool::from(row);
-            match pool.version {
-                DexVariant::UniswapV2 => v2_pool_cnt += 1,
-            }
-            pools.push(pool);
+    for pool in &pools {
+        match pool.version {
+            DexVariant::UniswapV2 => v2_pool_cnt += 1,
         }
-    } else {
-        writer.write_record(&[
-            "id",
-            "address",
-            "version",
-            "token0",
-            "token1",
-            "fee",
-            "block_number",
-            "timestamp",
-        ])?;
     }
     info!("Pools loaded: {:?}", pools.len());
     info!("V2 pools: {:?}", v2_pool_cnt);
 
-    let ws = Ws::connect(wss_url).await?;
-    let provider = Arc::new(Provider::new(ws));
+    let provider = connect_provider(&rpc_url).await?;
 
     // Uniswap V2
     let pair_created_event = "PairCreated(address,address,address,uint256)";
@@ -167,17 +206,12 @@ pub async fn load_all_pools(
 
     let pair_created_signature = abi.event("PairCreated").unwrap().signature();
 
-    let mut id = if pools.len() > 0 {
-        pools.last().as_ref().unwrap().id as i64
-    } else {
-        -1
-    };
-    let last_id = id as i64;
+    let mut id = pools.iter().map(|p| p.id).max().unwrap_or(-1);
+    let last_id = id;
 
-    let from_block = if id != -1 {
-        pools.last().as_ref().unwrap().block_number + 1
-    } else {
-        from_block
+    let from_block = match pools.iter().map(|p| p.block_number).max() {
+        Some(block_number) => block_number + 1,
+        None => from_block,
     };
     let to_block = provider.get_block_number().await.unwrap().as_u64();
     let mut blocks_processed = 0;
@@ -206,85 +240,163 @@ pub async fn load_all_pools(
         .progress_chars("##-"),
     );
 
-    for range in block_range {
-        let mut requests = Vec::new();
-        requests.push(tokio::task::spawn(load_uniswap_v2_pools(
-            provider.clone(),
-            range.0,
-            range.1,
-            pair_created_event,
-            pair_created_signature,
-        )));
-        let results = futures::future::join_all(requests).await;
-        for result in results {
-            match result {
-                Ok(response) => match response {
-                    Ok(pools_response) => {
-                        pools.extend(pools_response);
-                    }
-                    _ => {}
-                },
-                _ => {}
+    // fetch up to POOL_SYNC_CONCURRENCY ranges at once, the progress bar ticks as each range completes
+    let concurrency = std::cmp::max(Env::new().pool_sync_concurrency, 1);
+    // shrunk by any range the provider rejects, and kept small for the remaining ranges
+    let chunk_size = Arc::new(AtomicU64::new(chunk));
+    let mut requests = futures::stream::iter(block_range.into_iter().enumerate())
+        .map(|(idx, range)| {
+            let request = tokio::task::spawn(load_uniswap_v2_pools_with_retry(
+                provider.clone(),
+                range.0,
+                range.1,
+                pair_created_signature,
+                chunk_size.clone(),
+            ));
+            async move { (idx, request.await) }
+        })
+        .buffer_unordered(concurrency);
+
+    /*
+    Ranges complete out of order, but the next sync resumes after the latest stored pool,
+    so pools are only persisted once every range before theirs is done (no gaps on a crash)
+    */
+    let mut completed: BTreeMap<usize, Vec<Pool>> = BTreeMap::new();
+    let mut next_range = 0;
+    let mut added = 0;
+    while let Some((idx, result)) = requests.next().await {
+        // a range that keeps failing would leave a gap behind the synced block, so we bail out
+        let pools_response = result??;
+        completed.insert(idx, pools_response);
+        while let Some(mut range_pools) = completed.remove(&next_range) {
+            range_pools.sort_by_key(|p| p.block_number);
+            for pool in range_pools.iter_mut() {
+                id += 1;
+                pool.id = id;
             }
+            let items: Vec<(String, Pool)> = range_pools
+                .iter()
+                .map(|pool| (format!("{:?}", pool.address), *pool))
+                .collect();
+            STORE_WRITER.put_json(POOLS_COLLECTION, &items)?;
+            added += range_pools.len();
+            pools.extend(range_pools);
+            next_range += 1;
         }
-
         pb.inc(1);
     }
+    pb.finish();
+    STORE_WRITER.flush()?;
 
-    let mut added = 0;
-    pools.sort_by_key(|p| p.block_number);
-    for pool in pools.iter_mut() {
-        if pool.id == -1 {
-            id += 1;
-            pool.id = id;
-        }
-        if (pool.id as i64) > last_id {
-            writer.serialize(pool.cache_row())?;
-            added += 1;
-        }
-    }
-    writer.flush()?;
     info!("Added {:?} new pools", added);
 
     Ok((pools, last_id))
 }
 
+pub static POOL_SYNC_MAX_RETRIES: u32 = 5;
+pub static POOL_SYNC_MIN_CHUNK: u64 = 100;
+
+/*
+Loads the range in sub-ranges of chunk_size blocks.
+A failing request first halves chunk_size (down to POOL_SYNC_MIN_CHUNK), since providers
+reject ranges that are too large or return too many logs, and only then is retried with backoff.
+*/
+pub async fn load_uniswap_v2_pools_with_retry(
+    provider: Arc<Provider<RpcClient>>,
+    from_block: u64,
+    to_block: u64,
+    signature: H256,
+    chunk_size: Arc<AtomicU64>,
+) -> Result<Vec<Pool>> {
+    let mut pools = Vec::new();
+    let mut start = from_block;
+    let mut retries = 0;
+    while start <= to_block {
+        let chunk = chunk_size.load(Ordering::Relaxed);
+        let end = std::cmp::min(start + chunk - 1, to_block);
+        match load_uniswap_v2_pools(provider.clone(), start, end, signature).await {
+            Ok(loaded) => {
+                pools.extend(loaded);
+                start = end + 1;
+                retries = 0;
+            }
+            Err(e) => {
+                if end > start && chunk > POOL_SYNC_MIN_CHUNK {
+                    let smaller = std::cmp::max(chunk / 2, POOL_SYNC_MIN_CHUNK);
+                    // another range may have shrunk it already
+                    match chunk_size.compare_exchange(
+                        chunk,
+                        smaller,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    ) {
+                        Ok(_) => warn!(
+                            "load_uniswap_v2_pools error ({}-{}), chunk size {} -> {}: {e:?}",
+                            start, end, chunk, smaller
+                        ),
+                        Err(_) => {}
+                    }
+                    continue;
+                }
+                if retries >= POOL_SYNC_MAX_RETRIES {
+                    return Err(anyhow!(
+                        "Failed to load pools in range {}-{}: {e:?}",
+                        start,
+                        end
+                    ));
+                }
+                // exponential backoff: 0.5s, 1s, 2s, 4s, 8s
+                let backoff = Duration::from_millis(500 * 2u64.pow(retries));
+                warn!(
+                    "load_uniswap_v2_pools error ({}-{}), retrying in {:?}: {e:?}",
+                    start, end, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                retries += 1;
+            }
+        }
+    }
+    Ok(pools)
+}
+
 pub async fn load_uniswap_v2_pools(
-    provider: Arc<Provider<Ws>>,
+    provider: Arc<Provider<RpcClient>>,
     from_block: u64,
     to_block: u64,
-    event: &str,
     signature: H256,
 ) -> Result<Vec<Pool>> {
     let mut pools = Vec::new();
     let mut timestamp_map = HashMap::new();
 
-    let event_filter = Filter::new()
-        .from_block(U64::from(from_block))
-        .to_block(U64::from(to_block))
-        .event(event);
-    let logs = provider.get_logs(&event_filter).await?;
+    let event_filter = LogFilter {
+        from_block,
+        to_block,
+        addresses: CHAIN
+            .factories
+            .iter()
+            .map(|f| f.address.to_alloy())
+            .collect(),
+        topic0: vec![signature.to_alloy()],
+    };
+    let logs = provider.logs(&event_filter).await?;
 
     for log in logs {
-        let topic = log.topics[0];
         let block_number = log.block_number.unwrap_or_default();
 
-        if topic != signature {
+        if log.topics.len() < 3 || log.topics[0].to_ethers() != signature {
             continue;
         }
 
         let timestamp = if !timestamp_map.contains_key(&block_number) {
-            let block = provider.get_block(block_number).await.unwrap().unwrap();
-            let timestamp = block.timestamp.as_u64();
-            timestamp_map.insert(block_number, timestamp);
-            timestamp
+            let block = provider.block_header(Some(block_number)).await?.unwrap();
+            timestamp_map.insert(block_number, block.timestamp);
+            block.timestamp
         } else {
-            let timestamp = *timestamp_map.get(&block_number).unwrap();
-            timestamp
+            *timestamp_map.get(&block_number).unwrap()
         };
 
-        let token0 = H160::from(log.topics[1]);
-        let token1 = H160::from(log.topics[2]);
+        let token0 = Address::from_word(log.topics[1]).to_ethers();
+        let token1 = Address::from_word(log.topics[2]).to_ethers();
         if let Ok(input) =
             ethers::abi::decode(&[ParamType::Address, ParamType::Uint(256)], &log.data)
         {
@@ -295,8 +407,8 @@ pub async fn load_uniswap_v2_pools(
                 version: DexVariant::UniswapV2,
                 token0,
                 token1,
-                fee: 300,
-                block_number: block_number.as_u64(),
+                fee: CHAIN.factory_fee(log.address.to_ethers()),
+                block_number,
                 timestamp,
             };
             pools.push(pool_data);
@@ -305,3 +417,91 @@ pub async fn load_uniswap_v2_pools(
 
     Ok(pools)
 }
+
+// V2 fees in units of 1/100000 (300 = 0.3%), probed from the cheapest up
+pub static V2_FEE_CANDIDATES: [u32; 7] = [0, 100, 200, 250, 300, 500, 1000];
+
+/*
+Forks don't always charge Uniswap's 0.3% (e.g. 0.25% on Pancakeswap, or a configurable fee),
+and using the wrong fee in get_v2_amount_out asks the pair for more than the K check allows.
+We send 0.1% of the main currency reserve to the pair once, then ask for the amount out
+of every fee candidate from the cheapest: the first swap that doesn't revert is the pool's fee.
+*/
+pub fn detect_v2_pool_fee(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    pool: &Pool,
+) -> Result<u32> {
+    let owner = simulator.owner;
+    let pair = pool.address;
+    let (token_in, token_in_is_0) = if is_main_currency(pool.token0) {
+        (pool.token0, true)
+    } else if is_main_currency(pool.token1) {
+        (pool.token1, false)
+    } else {
+        return Err(anyhow!("Pool {:?} has no main currency", pair));
+    };
+    let mc = MainCurrency::new(token_in);
+    let balance_slot = get_cached_balance_slot(token_in).unwrap_or(mc.balance_slot());
+
+    let (reserve0, reserve1) = simulator.get_pair_reserves(pair)?;
+    let (reserve_in, reserve_out) = if token_in_is_0 {
+        (reserve0, reserve1)
+    } else {
+        (reserve1, reserve0)
+    };
+    let amount_in = reserve_in / U256::from(1000);
+    if amount_in.is_zero() {
+        return Err(anyhow!("Pool {:?} has no liquidity", pair));
+    }
+    simulator.set_token_balance(token_in, owner, balance_slot, amount_in.into())?;
+    simulator.transfer_token(token_in, pair, amount_in)?;
+
+    for fee in V2_FEE_CANDIDATES {
+        let amount_out = get_v2_amount_out(amount_in, reserve_in, reserve_out, fee);
+        let (amount0_out, amount1_out) = if token_in_is_0 {
+            (U256::zero(), amount_out)
+        } else {
+            (amount_out, U256::zero())
+        };
+        if simulator
+            .v2_swap(pair, amount0_out, amount1_out, owner)
+            .is_ok()
+        {
+            return Ok(fee);
+        }
+    }
+
+    Err(anyhow!("No fee candidate matched pool {:?}", pair))
+}
+
+// Detects the fee of pools we haven't checked yet, updating the pools map and the pool store
+pub fn detect_v2_pool_fees(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    pools_map: &mut HashMap<H160, Pool>,
+    pairs: &Vec<H160>,
+    store: &mut PoolStore,
+) {
+    let mut updated = Vec::new();
+    for pair in pairs {
+        let pool = match pools_map.get_mut(pair) {
+            Some(pool) => pool,
+            None => continue,
+        };
+        match detect_v2_pool_fee(simulator, pool) {
+            Ok(fee) => {
+                if fee != pool.fee {
+                    info!("Pool {:?} fee: {} -> {}", pair, pool.fee, fee);
+                    pool.fee = fee;
+                    updated.push(*pool);
+                }
+            }
+            Err(e) => warn!("detect_v2_pool_fee error: {e:?}"),
+        }
+    }
+    if !updated.is_empty() {
+        match store.upsert_pools(&updated) {
+            Err(e) => warn!("PoolStore.upsert_pools error: {e:?}"),
+            _ => {}
+        }
+    }
+}
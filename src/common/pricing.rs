@@ -0,0 +1,238 @@
+use anyhow::{anyhow, Result};
+use ethers::prelude::Lazy;
+use ethers::{
+    providers::Provider,
+    types::{H160, U256, U64},
+};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+};
+
+use crate::common::chain::{ConversionPair, CHAIN};
+use crate::common::evm::EvmSimulator;
+use crate::common::pools::{get_v2_amount_out, Pool};
+use crate::common::provider::RpcClient;
+use crate::common::utils::{is_weth, scaled_amount, MainCurrency};
+
+/*
+Main currency -> WETH conversion, to value what a sandwich made in USDT, USDC, DAI... in WETH.
+A conversion route is either a V2 pool pairing the main currency with the wrapped native token,
+or two pools through another main currency (e.g. DAI -> USDC -> WETH) for currencies whose direct pools are thin.
+The candidates come from the loaded pools map (see set_conversion_pools), plus the chain's own pair if there's one.
+At simulation time the best of them is used: the one that gets the most WETH for
+CONVERSION_PROBE_UNITS whole tokens, which favors deep pools and pays for the extra hop's fee,
+read on the simulator's fork once per block and main currency.
+A main currency added to the chain profile is priced as soon as a route to WETH exists.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConversionPool {
+    pub address: H160,
+    pub in_is_token0: bool, // the token sold at this pool is token0
+    pub fee: u32,
+}
+
+impl ConversionPool {
+    fn from_pair(pair: ConversionPair) -> Self {
+        Self {
+            address: pair.0,
+            in_is_token0: pair.1,
+            fee: CHAIN.default_fee,
+        }
+    }
+
+    fn from_pool(pool: &Pool, token_in: H160) -> Self {
+        Self {
+            address: pool.address,
+            in_is_token0: pool.token0 == token_in,
+            fee: pool.fee,
+        }
+    }
+
+    // (token in reserve, token out reserve)
+    fn reserves(&self, simulator: &mut EvmSimulator<Provider<RpcClient>>) -> Result<(U256, U256)> {
+        let reserves = simulator.get_pair_reserves(self.address)?;
+        if self.in_is_token0 {
+            Ok((reserves.0, reserves.1))
+        } else {
+            Ok((reserves.1, reserves.0))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionRoute {
+    pub hops: Vec<ConversionPool>, // main currency first, WETH last
+}
+
+impl ConversionRoute {
+    // What selling amount for WETH along the route gets, fees and price impact included
+    pub fn convert(
+        &self,
+        simulator: &mut EvmSimulator<Provider<RpcClient>>,
+        amount: U256,
+    ) -> Result<U256> {
+        let mut amount = amount;
+        for hop in &self.hops {
+            let (reserve_in, reserve_out) = hop.reserves(simulator)?;
+            amount = get_v2_amount_out(amount, reserve_in, reserve_out, hop.fee);
+        }
+        Ok(amount)
+    }
+
+    // What amount is worth at the route's mid price, without fees or price impact
+    pub fn spot_value(
+        &self,
+        simulator: &mut EvmSimulator<Provider<RpcClient>>,
+        amount: U256,
+    ) -> Result<U256> {
+        let mut amount = amount;
+        for hop in &self.hops {
+            let (reserve_in, reserve_out) = hop.reserves(simulator)?;
+            amount = (amount * reserve_out)
+                .checked_div(reserve_in)
+                .unwrap_or_default();
+        }
+        Ok(amount)
+    }
+}
+
+// Size of the conversion the routes are compared on, in whole tokens of the main currency
+pub static CONVERSION_PROBE_UNITS: u64 = 1000;
+
+// Main currency -> its candidate routes to WETH, filled by set_conversion_pools
+pub static CONVERSION_ROUTES: Lazy<RwLock<HashMap<H160, Vec<ConversionRoute>>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+// Main currency -> (block, best route at that block)
+static BEST_ROUTES: Lazy<Mutex<HashMap<H160, (U64, ConversionRoute)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn chain_pair(main_currency: H160) -> Option<ConversionPair> {
+    if main_currency == CHAIN.usdt.address {
+        Some(CHAIN.usdt_weth_pair)
+    } else if main_currency == CHAIN.usdc.address {
+        Some(CHAIN.usdc_weth_pair)
+    } else if main_currency == CHAIN.dai.address {
+        Some(CHAIN.dai_weth_pair)
+    } else {
+        None
+    }
+}
+
+// The pools pairing token_in with token_out, to sell token_in at
+fn pools_between(
+    pools_map: &HashMap<H160, Pool>,
+    token_in: H160,
+    token_out: H160,
+) -> Vec<ConversionPool> {
+    pools_map
+        .values()
+        .filter(|pool| {
+            (pool.token0 == token_in && pool.token1 == token_out)
+                || (pool.token0 == token_out && pool.token1 == token_in)
+        })
+        .map(|pool| ConversionPool::from_pool(pool, token_in))
+        .collect()
+}
+
+pub fn set_conversion_pools(pools_map: &HashMap<H160, Pool>) {
+    let weth = CHAIN.weth.address;
+    let stables: Vec<H160> = CHAIN
+        .main_currencies()
+        .into_iter()
+        .map(|mc| mc.address)
+        .filter(|mc| !is_weth(*mc))
+        .collect();
+
+    let mut direct_pools = HashMap::new();
+    for mc in &stables {
+        let mut pools = pools_between(pools_map, *mc, weth);
+        if let Some(pair) = chain_pair(*mc) {
+            if !pools.iter().any(|pool| pool.address == pair.0) {
+                pools.push(ConversionPool::from_pair(pair));
+            }
+        }
+        direct_pools.insert(*mc, pools);
+    }
+
+    let mut conversion_routes = HashMap::new();
+    for mc in &stables {
+        let mut routes: Vec<ConversionRoute> = direct_pools[mc]
+            .iter()
+            .map(|pool| ConversionRoute { hops: vec![*pool] })
+            .collect();
+        for intermediate in stables.iter().filter(|other| *other != mc) {
+            for first in pools_between(pools_map, *mc, *intermediate) {
+                for second in &direct_pools[intermediate] {
+                    routes.push(ConversionRoute {
+                        hops: vec![first, *second],
+                    });
+                }
+            }
+        }
+        conversion_routes.insert(*mc, routes);
+    }
+    *CONVERSION_ROUTES.write().unwrap() = conversion_routes;
+    BEST_ROUTES.lock().unwrap().clear();
+}
+
+// The best route to convert main_currency to WETH with, None for WETH itself and currencies without one
+pub fn conversion_route(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    main_currency: H160,
+) -> Option<ConversionRoute> {
+    if is_weth(main_currency) {
+        return None;
+    }
+    let block_number = simulator.block_number;
+    if let Some((block, route)) = BEST_ROUTES.lock().unwrap().get(&main_currency) {
+        if *block == block_number {
+            return Some(route.clone());
+        }
+    }
+
+    let candidates = match CONVERSION_ROUTES.read().unwrap().get(&main_currency) {
+        Some(candidates) => candidates.clone(),
+        None => chain_pair(main_currency)
+            .map(|pair| {
+                vec![ConversionRoute {
+                    hops: vec![ConversionPool::from_pair(pair)],
+                }]
+            })
+            .unwrap_or_default(),
+    };
+    let probe = scaled_amount(
+        CONVERSION_PROBE_UNITS,
+        MainCurrency::new(main_currency).decimals(),
+        0,
+    );
+    let best = candidates
+        .into_iter()
+        .filter_map(|route| {
+            let weth_out = route.convert(simulator, probe).ok()?;
+            Some((weth_out, route))
+        })
+        .filter(|(weth_out, _)| !weth_out.is_zero())
+        .max_by_key(|(weth_out, _)| *weth_out)
+        .map(|(_, route)| route)?;
+    BEST_ROUTES
+        .lock()
+        .unwrap()
+        .insert(main_currency, (block_number, best.clone()));
+    Some(best)
+}
+
+pub fn convert_to_weth(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    main_currency: H160,
+    amount: U256,
+) -> Result<U256> {
+    if is_weth(main_currency) {
+        return Ok(amount);
+    }
+    match conversion_route(simulator, main_currency) {
+        Some(route) => route.convert(simulator, amount),
+        None => Err(anyhow!("No route to convert {:?} to WETH", main_currency)),
+    }
+}
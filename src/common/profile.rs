@@ -0,0 +1,173 @@
+use ethers::types::{H160, U256};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use teloxide::prelude::*;
+use teloxide::types::{ChatId, UpdateKind};
+
+use crate::common::constants::Env;
+use crate::common::control::SharedControlState;
+use crate::common::tokens::Token;
+use crate::common::utils::is_weth;
+use crate::sandwich::simulation::{SwapInfo, CURVE_VERSION};
+
+/*
+Built-in operating profiles, switchable at runtime (control API: POST /mode/{name}, Telegram: /mode {name}).
+A profile is swapped as a whole in ControlState, and the strategy takes one copy per event,
+so a victim is never evaluated with half of one profile and half of the other.
+- aggressive: what the bot has always done, every safe pool, 99% bribe, no minimum profit
+- conservative: WETH pairs only, V2 pools only, screened tokens only, lower bribe, a minimum profit
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperatingMode {
+    Aggressive,
+    Conservative,
+}
+
+impl OperatingMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "aggressive" => Some(OperatingMode::Aggressive),
+            "conservative" => Some(OperatingMode::Conservative),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperatingProfile {
+    pub mode: OperatingMode,
+    pub bribe_pct: U256,     // in bps of the sandwich revenue
+    pub min_profit: U256,    // in wei, after the bribe
+    pub weth_only: bool,     // skip USDT/USDC pairs
+    pub curve_pools: bool,   // also simulate Curve pools
+    pub strict_safety: bool, // only tokens whose balance slot was found when screening
+    pub max_victims_per_bundle: usize,
+}
+
+impl OperatingProfile {
+    pub fn new(mode: OperatingMode) -> Self {
+        let env = Env::new();
+        match mode {
+            OperatingMode::Aggressive => Self {
+                mode,
+                bribe_pct: U256::from(env.bribe_pct_aggressive.min(10000)), // 99% by default
+                min_profit: U256::zero(),
+                weth_only: false,
+                curve_pools: true,
+                strict_safety: false,
+                max_victims_per_bundle: usize::MAX, // MAX_VICTIMS_PER_BUNDLE still applies
+            },
+            OperatingMode::Conservative => Self {
+                mode,
+                bribe_pct: U256::from(env.bribe_pct_conservative.min(10000)), // 90% by default
+                min_profit: U256::from(10).pow(U256::from(16)),               // 0.01 ETH
+                weth_only: true,
+                curve_pools: false,
+                strict_safety: true,
+                max_victims_per_bundle: 3,
+            },
+        }
+    }
+
+    pub fn allows(&self, info: &SwapInfo, tokens_map: &HashMap<H160, Token>) -> bool {
+        if self.weth_only && !is_weth(info.main_currency) {
+            return false;
+        }
+        if !self.curve_pools && info.version == CURVE_VERSION {
+            return false;
+        }
+        if self.strict_safety {
+            return match tokens_map.get(&info.target_token) {
+                Some(token) => token.metadata.balance_slot >= 0,
+                None => false,
+            };
+        }
+        true
+    }
+
+    pub fn from_env(env: &Env) -> Self {
+        match OperatingMode::parse(&env.operating_mode) {
+            Some(mode) => Self::new(mode),
+            None => Self::new(OperatingMode::Aggressive),
+        }
+    }
+}
+
+pub fn switch_mode(state: &SharedControlState, mode: OperatingMode) -> OperatingProfile {
+    let profile = OperatingProfile::new(mode);
+    state.write().unwrap().profile = profile.clone();
+    info!("Operating mode switched to {:?}", mode);
+    profile
+}
+
+// Long-polls Telegram for "/mode aggressive" / "/mode conservative" / "/halt" / "/resume" from the alert chat
+pub async fn listen_mode_commands(state: SharedControlState) {
+    let env = Env::new();
+    let bot = Bot::from_env();
+    let chat_id = ChatId(env.telegram_chat_id.parse::<i64>().unwrap());
+    let mut offset = 0;
+
+    loop {
+        let updates = match bot.get_updates().offset(offset).timeout(30).await {
+            Ok(updates) => updates,
+            Err(e) => {
+                warn!("Telegram get_updates error: {e:?}");
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        for update in updates {
+            offset = update.id + 1;
+            let message = match update.kind {
+                UpdateKind::Message(message) => message,
+                _ => continue,
+            };
+            if message.chat.id != chat_id {
+                continue;
+            }
+            let text = message.text().unwrap_or_default().trim();
+            if text == "/halt" || text == "/resume" {
+                let kill_switch = state.read().unwrap().kill_switch.clone();
+                let reply = if text == "/halt" {
+                    kill_switch.halt("Telegram");
+                    "Kill switch on: bundle submission halted"
+                } else {
+                    kill_switch.resume("Telegram");
+                    if kill_switch.is_halted() {
+                        "Still halted: the kill switch file exists"
+                    } else {
+                        "Kill switch off: bundle submission resumed"
+                    }
+                };
+                match bot.send_message(chat_id, reply).await {
+                    Err(e) => warn!("Telegram error: {e:?}"),
+                    _ => {}
+                }
+                continue;
+            }
+            let name = match text.strip_prefix("/mode") {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let reply = match OperatingMode::parse(&name) {
+                Some(mode) => {
+                    let profile = switch_mode(&state, mode);
+                    format!("Operating mode: {:?}\n{:?}", mode, profile)
+                }
+                None => {
+                    let current = state.read().unwrap().profile.mode;
+                    format!(
+                        "Operating mode: {:?} (use /mode aggressive or /mode conservative)",
+                        current
+                    )
+                }
+            };
+            match bot.send_message(chat_id, reply).await {
+                Err(e) => warn!("Telegram error: {e:?}"),
+                _ => {}
+            }
+        }
+    }
+}
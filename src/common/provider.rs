@@ -0,0 +1,324 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, JsonRpcError, Provider, ProviderError, PubsubClient,
+    RpcError, Ws, WsClientError,
+};
+use ethers::types::U256;
+use log::warn;
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::common::constants::Env;
+
+/*
+Hosted providers (Alchemy, Infura, ...) meter requests in compute units (CU)
+and answer with HTTP 429 / JSON-RPC -32005 once you go over the limit.
+RateLimitedClient wraps the transport so that every request made through the provider:
+1. waits in a FIFO queue until the CU budget (RPC_COMPUTE_UNITS_PER_SECOND) allows it
+2. is retried with exponential backoff when the provider says we're rate limited
+*/
+pub type RpcClient = RateLimitedClient<RpcTransport>;
+
+// Picks the transport from the url scheme: ws(s):// or http(s)://
+pub async fn connect_provider(url: &str) -> Result<Arc<Provider<RpcClient>>> {
+    let env = Env::new();
+    let transport = if url.starts_with("http") {
+        RpcTransport::Http(Http::from_str(url)?)
+    } else {
+        RpcTransport::Ws(Ws::connect(url).await?)
+    };
+    let client = RateLimitedClient::new(
+        transport,
+        env.rpc_compute_units_per_second,
+        env.rpc_max_retries,
+    );
+    Ok(Arc::new(Provider::new(client)))
+}
+
+/*
+Provider presets, for limits that differ between providers (RPC_PROVIDER, or guessed from the url):
+- alchemy: eth_getLogs ranges are capped on the free tier
+- infura: eth_getLogs responses are capped at 10k logs
+- self-hosted: no limits
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RpcPreset {
+    Alchemy,
+    Infura,
+    SelfHosted,
+}
+
+impl RpcPreset {
+    pub fn from_env(env: &Env) -> Self {
+        match env.rpc_provider.trim().to_lowercase().as_str() {
+            "alchemy" => RpcPreset::Alchemy,
+            "infura" => RpcPreset::Infura,
+            "self-hosted" | "selfhosted" => RpcPreset::SelfHosted,
+            _ => {
+                let url = env.rpc_url();
+                if url.contains("alchemy.com") {
+                    RpcPreset::Alchemy
+                } else if url.contains("infura.io") {
+                    RpcPreset::Infura
+                } else {
+                    RpcPreset::SelfHosted
+                }
+            }
+        }
+    }
+
+    // Block range of a single eth_getLogs request when syncing pools
+    pub fn log_chunk_size(&self) -> u64 {
+        match self {
+            RpcPreset::Alchemy => 2000,
+            RpcPreset::Infura => 10000,
+            RpcPreset::SelfHosted => 50000,
+        }
+    }
+}
+
+/*
+Heavy calls (traces, getLogs, token loading) go through HTTPS_URL when it's set,
+so they don't compete with the subscriptions for the websocket connection,
+and keep working if the websocket drops.
+*/
+pub async fn connect_http_provider(env: &Env) -> Result<Arc<Provider<RpcClient>>> {
+    connect_provider(&env.rpc_url()).await
+}
+
+#[derive(Debug)]
+pub enum RpcTransport {
+    Ws(Ws),
+    Http(Http),
+}
+
+#[derive(Debug)]
+pub enum RpcTransportError {
+    Ws(WsClientError),
+    Http(HttpClientError),
+    PubsubUnsupported,
+}
+
+impl std::fmt::Display for RpcTransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcTransportError::Ws(e) => write!(f, "{}", e),
+            RpcTransportError::Http(e) => write!(f, "{}", e),
+            RpcTransportError::PubsubUnsupported => {
+                write!(f, "subscriptions are not supported over HTTP")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RpcTransportError {}
+
+impl From<WsClientError> for RpcTransportError {
+    fn from(e: WsClientError) -> Self {
+        RpcTransportError::Ws(e)
+    }
+}
+
+impl From<HttpClientError> for RpcTransportError {
+    fn from(e: HttpClientError) -> Self {
+        RpcTransportError::Http(e)
+    }
+}
+
+impl From<RpcTransportError> for ProviderError {
+    fn from(e: RpcTransportError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(e))
+    }
+}
+
+impl RpcError for RpcTransportError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            RpcTransportError::Ws(e) => e.as_error_response(),
+            RpcTransportError::Http(e) => e.as_error_response(),
+            RpcTransportError::PubsubUnsupported => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            RpcTransportError::Ws(e) => e.as_serde_error(),
+            RpcTransportError::Http(e) => e.as_serde_error(),
+            RpcTransportError::PubsubUnsupported => None,
+        }
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcTransport {
+    type Error = RpcTransportError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            RpcTransport::Ws(ws) => Ok(ws.request(method, params).await?),
+            RpcTransport::Http(http) => Ok(http.request(method, params).await?),
+        }
+    }
+}
+
+impl PubsubClient for RpcTransport {
+    type NotificationStream = <Ws as PubsubClient>::NotificationStream;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        match self {
+            RpcTransport::Ws(ws) => Ok(ws.subscribe(id)?),
+            RpcTransport::Http(_) => Err(RpcTransportError::PubsubUnsupported),
+        }
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        match self {
+            RpcTransport::Ws(ws) => Ok(ws.unsubscribe(id)?),
+            RpcTransport::Http(_) => Err(RpcTransportError::PubsubUnsupported),
+        }
+    }
+}
+
+// Roughly follows Alchemy's CU table, unknown methods are charged the default
+pub fn compute_units(method: &str) -> u64 {
+    match method {
+        "eth_chainId" | "net_version" | "eth_blockNumber" => 10,
+        "eth_getBalance" | "eth_getTransactionCount" | "eth_getStorageAt" | "eth_getCode" => 19,
+        "eth_getBlockByNumber" | "eth_getBlockByHash" | "eth_getTransactionReceipt" => 16,
+        "eth_call" | "eth_estimateGas" => 26,
+        "eth_getLogs" => 75,
+        "eth_sendRawTransaction" => 250,
+        "debug_traceCall" | "trace_call" => 309,
+        "debug_traceCallMany" => 500,
+        _ => 20,
+    }
+}
+
+#[derive(Debug)]
+struct ComputeUnitBucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimitedClient<C> {
+    inner: C,
+    compute_units_per_second: u64, // 0 --> unlimited
+    max_retries: u32,
+    bucket: Mutex<ComputeUnitBucket>,
+    pub compute_units_used: AtomicU64,
+    pub rate_limited_count: AtomicU64,
+}
+
+pub static RATE_LIMIT_INITIAL_BACKOFF_MS: u64 = 250;
+
+pub fn is_rate_limited<E: RpcError>(e: &E) -> bool {
+    if let Some(response) = e.as_error_response() {
+        if response.code == 429 || response.code == -32005 {
+            return true;
+        }
+    }
+    let message = e.to_string().to_lowercase();
+    message.contains("429")
+        || message.contains("rate limit")
+        || message.contains("too many requests")
+        || message.contains("exceeded its compute units")
+}
+
+impl<C> RateLimitedClient<C> {
+    pub fn new(inner: C, compute_units_per_second: u64, max_retries: u32) -> Self {
+        Self {
+            inner,
+            compute_units_per_second,
+            max_retries,
+            bucket: Mutex::new(ComputeUnitBucket {
+                available: compute_units_per_second as f64,
+                last_refill: Instant::now(),
+            }),
+            compute_units_used: AtomicU64::new(0),
+            rate_limited_count: AtomicU64::new(0),
+        }
+    }
+
+    async fn acquire(&self, cost: u64) {
+        self.compute_units_used.fetch_add(cost, Ordering::Relaxed);
+        if self.compute_units_per_second == 0 {
+            return;
+        }
+        let rate = self.compute_units_per_second as f64;
+        // a single request can never cost more than a full second of budget
+        let cost = (cost as f64).min(rate);
+
+        // holding the lock while waiting keeps the queue FIFO
+        let mut bucket = self.bucket.lock().await;
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.available = (bucket.available + elapsed * rate).min(rate);
+            bucket.last_refill = now;
+            if bucket.available >= cost {
+                bucket.available -= cost;
+                return;
+            }
+            let wait = (cost - bucket.available) / rate;
+            tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for RateLimitedClient<C> {
+    type Error = C::Error;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut retries = 0;
+        loop {
+            self.acquire(compute_units(method)).await;
+            match self.inner.request(method, &params).await {
+                Err(e) if is_rate_limited(&e) && retries < self.max_retries => {
+                    self.rate_limited_count.fetch_add(1, Ordering::Relaxed);
+                    let backoff =
+                        Duration::from_millis(RATE_LIMIT_INITIAL_BACKOFF_MS * 2u64.pow(retries));
+                    warn!(
+                        "Rate limited on {}, retrying in {:?} ({}/{})",
+                        method,
+                        backoff,
+                        retries + 1,
+                        self.max_retries
+                    );
+                    tokio::time::sleep(backoff).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<C: PubsubClient> PubsubClient for RateLimitedClient<C> {
+    type NotificationStream = C::NotificationStream;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        self.inner.subscribe(id)
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        self.inner.unsubscribe(id)
+    }
+}
@@ -0,0 +1,115 @@
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use ethers::prelude::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+
+/*
+Keeps secrets out of logs (see setup_logger) and control API responses (RedactedJson):
+- the value of every env var that looks like a secret (*_KEY, *_TOKEN, *_SECRET, *_PASSWORD, *_AUTH_HEADER),
+  which covers PRIVATE_KEY, IDENTITY_KEY, TELEGRAM_TOKEN, CONTROL_API_TOKEN, the execution contexts' keys
+  and the keystore passwords (KEYSTORE_PASSWORD, <VAR>_PASSWORD),
+  with or without a 0x prefix
+- raw signed transactions: RLP encoded hex (0xf8.., 0xf9.., typed 0x01f8.., 0x02f9..)
+  long enough to be a signed tx rather than a hash
+*/
+pub const REDACTED: &str = "[REDACTED]";
+
+// Shortest signed tx: a legacy transfer is ~110 bytes, hashes and addresses are far shorter
+pub static MIN_SIGNED_TX_HEX_LEN: usize = 200;
+
+static SECRET_SUFFIXES: [&str; 5] = ["_KEY", "_TOKEN", "_SECRET", "_PASSWORD", "_AUTH_HEADER"];
+
+pub static SECRETS: Lazy<Vec<String>> = Lazy::new(|| {
+    let mut secrets = Vec::new();
+    for (name, value) in std::env::vars() {
+        let name = name.to_uppercase();
+        let value = value.trim();
+        // short values are flags or placeholders, redacting them would garble the logs
+        if value.len() < 8 || !SECRET_SUFFIXES.iter().any(|s| name.ends_with(s)) {
+            continue;
+        }
+        secrets.push(value.to_string());
+        match value.strip_prefix("0x") {
+            Some(stripped) => secrets.push(stripped.to_string()),
+            None => secrets.push(format!("0x{}", value)),
+        }
+    }
+    // longest first, so a secret containing another is replaced whole
+    secrets.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    secrets.dedup();
+    secrets
+});
+
+fn is_signed_tx_hex(hex: &str) -> bool {
+    let body = hex.trim_start_matches("0x");
+    let rlp = match body.get(..2) {
+        Some("01") | Some("02") => &body[2..],
+        _ => body,
+    };
+    hex.len() >= MIN_SIGNED_TX_HEX_LEN && (rlp.starts_with("f8") || rlp.starts_with("f9"))
+}
+
+fn redact_signed_txs(text: &str) -> String {
+    let mut redacted = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("0x") {
+        redacted.push_str(&rest[..start]);
+        let candidate = &rest[start..];
+        let end = 2 + candidate[2..]
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(candidate.len() - 2);
+        let hex = &candidate[..end];
+        if is_signed_tx_hex(&hex.to_lowercase()) {
+            redacted.push_str(REDACTED);
+        } else {
+            redacted.push_str(hex);
+        }
+        rest = &candidate[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+pub fn redact(text: &str) -> String {
+    let mut redacted = text.to_string();
+    for secret in SECRETS.iter() {
+        if redacted.contains(secret.as_str()) {
+            redacted = redacted.replace(secret.as_str(), REDACTED);
+        }
+    }
+    if redacted.contains("0x") {
+        redacted = redact_signed_txs(&redacted);
+    }
+    redacted
+}
+
+pub fn redact_json(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = redact(s),
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::Object(map) => map.values_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+// Json response with every string redacted
+pub struct RedactedJson<T>(pub T);
+
+impl<T: Serialize> IntoResponse for RedactedJson<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_value(&self.0) {
+            Ok(mut value) => {
+                redact_json(&mut value);
+                Json(value).into_response()
+            }
+            Err(e) => (
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Serialization error: {e}"),
+            )
+                .into_response(),
+        }
+    }
+}
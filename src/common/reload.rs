@@ -0,0 +1,68 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::common::constants::reload_config_file;
+
+/*
+Config reload without a restart, on SIGHUP or POST /reload to the control API:
+1. .env is read again and overrides the process environment, and so is CONFIG_FILE,
+   so whatever reads Env::new() as it goes (main_dish's bundle limits, min profits...) picks it up right away
+2. between two events, the strategy swaps what it built from the env at startup:
+   victim budget, candidate bound, bribe policy settings, builder list, token lists and Telegram alerts
+Live state stays as is: pending txs, promising sandwiches, tracked bundles, nonce reservations, bribe history.
+Settings only read at startup (CHAIN_ID, RPC endpoints, the cache store, the control API itself)
+still need a restart.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ConfigReloader {
+    requested: Arc<AtomicBool>,
+}
+
+impl ConfigReloader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn request(&self, by: &str) {
+        self.requested.store(true, Ordering::SeqCst);
+        info!("Config reload requested ({})", by);
+    }
+
+    // true once per request, for the strategy to reload on
+    pub fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::SeqCst)
+    }
+}
+
+// Re-reads .env over the process environment and the config file, returns how many settings were read
+pub fn reload_env() -> Result<usize> {
+    let mut count = 0;
+    // no .env is fine when everything is in the config file
+    if let Ok(items) = dotenv::dotenv_iter() {
+        for item in items {
+            let (key, value) = item?;
+            std::env::set_var(key, value);
+            count += 1;
+        }
+    }
+    count += reload_config_file();
+    Ok(count)
+}
+
+pub async fn listen_reload_signal(reloader: ConfigReloader) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    loop {
+        match hangup.recv().await {
+            Some(_) => reloader.request("SIGHUP"),
+            None => {
+                warn!("SIGHUP stream closed, config reload by signal disabled");
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+use ethers::{
+    providers::{Middleware, Provider},
+    types::{Transaction, H256},
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Arc,
+};
+
+use crate::common::provider::RpcClient;
+use crate::common::streams::NewBlock;
+
+/*
+Keeps the last few blocks we've processed (with their txs) and compares each new block's
+parent hash against them. If they don't line up, the blocks we had are walked back
+until we meet the new chain again: those are the orphaned blocks.
+Txs in orphaned blocks that didn't make it into the new chain are pending again.
+*/
+pub static REORG_TRACK_DEPTH: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Reorg {
+    pub depth: usize,
+    pub orphaned_txs: Vec<Transaction>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReorgDetector {
+    pub recent_blocks: VecDeque<(NewBlock, Vec<Transaction>)>,
+}
+
+impl ReorgDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn on_block(
+        &mut self,
+        provider: &Arc<Provider<RpcClient>>,
+        new_block: &NewBlock,
+        txs: Vec<Transaction>,
+    ) -> Result<Option<Reorg>> {
+        // the same block again, e.g. after a stream restart
+        if self
+            .recent_blocks
+            .iter()
+            .any(|(block, _)| block.hash == new_block.hash)
+        {
+            return Ok(None);
+        }
+
+        let mut canonical_txs: HashSet<H256> = txs.iter().map(|tx| tx.hash).collect();
+        let mut orphaned = Vec::new();
+
+        // the hash the canonical chain has at expected_number
+        let mut expected_number = new_block.block_number.saturating_sub(1.into());
+        let mut expected_hash = new_block.parent_hash;
+
+        while let Some((block, _)) = self.recent_blocks.back() {
+            if block.block_number > expected_number {
+                orphaned.push(self.recent_blocks.pop_back().unwrap());
+                continue;
+            }
+            if block.block_number == expected_number {
+                if block.hash == expected_hash {
+                    break;
+                }
+                orphaned.push(self.recent_blocks.pop_back().unwrap());
+            }
+            // walk the canonical chain back one block, either to compare against the
+            // next block we have, or to skip over blocks we never received
+            let canonical = match provider.get_block(expected_number).await? {
+                Some(canonical) => canonical,
+                None => break,
+            };
+            canonical_txs.extend(canonical.transactions);
+            expected_hash = canonical.parent_hash;
+            expected_number = expected_number.saturating_sub(1.into());
+        }
+
+        self.recent_blocks.push_back((new_block.clone(), txs));
+        while self.recent_blocks.len() > REORG_TRACK_DEPTH {
+            self.recent_blocks.pop_front();
+        }
+
+        if orphaned.is_empty() {
+            return Ok(None);
+        }
+
+        let orphaned_txs = orphaned
+            .iter()
+            .flat_map(|(_, txs)| txs.iter())
+            .filter(|tx| !canonical_txs.contains(&tx.hash))
+            .cloned()
+            .collect();
+        Ok(Some(Reorg {
+            depth: orphaned.len(),
+            orphaned_txs,
+        }))
+    }
+}
@@ -0,0 +1,183 @@
+use ethers::types::{H128, H256, U256, U64};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::common::execution::SendBundleResponse;
+
+// How long the responses of a scheduled bundle are kept for the tracker, past its target block
+const SCHEDULED_RETENTION_BLOCKS: u64 = 64;
+
+/*
+Every bundle is sent with a replacementUuid, remembered per victim for its target block.
+When a new sandwich for the same victim(s) comes up in the same block:
+- worth more than what we've sent: it's sent with the old bundle's uuid, so builders replace the old one
+  instead of having our own bundles compete for the same victim,
+  and any other bundle of ours on those victims is cancelled (eth_cancelBundle)
+- worth less: it's not sent at all
+*/
+#[derive(Debug, Clone)]
+pub struct SentBundle {
+    pub uuid: String,
+    pub target_block: U64,
+    pub value: U256, // expected profit
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Replacement {
+    New(String),
+    Replace(String),
+    Stale, // we already have a better bundle on these victims
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BundleReplacements {
+    pub by_victim: HashMap<H256, SentBundle>,
+}
+
+// Random (v4) UUID, the format builders expect for replacementUuid
+pub fn new_replacement_uuid() -> String {
+    let mut bytes = H128::random().to_fixed_bytes();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// Each block targeted by a bundle gets its own uuid, derived from the bundle's, so variants don't replace each other
+pub fn variant_uuid(uuid: &str, blocks_ahead: u64) -> String {
+    if blocks_ahead == 0 || uuid.len() < 36 {
+        return uuid.to_string();
+    }
+    let node = u64::from_str_radix(&uuid[24..36], 16).unwrap_or_default();
+    format!(
+        "{}{:012x}",
+        &uuid[..24],
+        (node + blocks_ahead) & 0xffff_ffff_ffff
+    )
+}
+
+impl BundleReplacements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns what to do with the new bundle, and the uuids of our bundles to cancel
+    pub fn plan(
+        &mut self,
+        victims: &[H256],
+        target_block: U64,
+        value: U256,
+    ) -> (Replacement, Vec<String>) {
+        self.by_victim
+            .retain(|_, sent| sent.target_block >= target_block);
+
+        let mut previous: Vec<&SentBundle> = Vec::new();
+        for victim in victims {
+            if let Some(sent) = self.by_victim.get(victim) {
+                if sent.target_block == target_block
+                    && !previous.iter().any(|p| p.uuid == sent.uuid)
+                {
+                    previous.push(sent);
+                }
+            }
+        }
+        if previous.iter().any(|sent| sent.value >= value) {
+            return (Replacement::Stale, Vec::new());
+        }
+        match previous.split_first() {
+            Some((replaced, others)) => (
+                Replacement::Replace(replaced.uuid.clone()),
+                others.iter().map(|sent| sent.uuid.clone()).collect(),
+            ),
+            None => (Replacement::New(new_replacement_uuid()), Vec::new()),
+        }
+    }
+
+    pub fn record(&mut self, victims: &[H256], target_block: U64, value: U256, uuid: &str) {
+        for victim in victims {
+            self.by_victim.insert(
+                *victim,
+                SentBundle {
+                    uuid: uuid.to_string(),
+                    target_block,
+                    value,
+                },
+            );
+        }
+    }
+}
+
+/*
+Bundles broadcast_bundle holds back until the slot's submission window opens, by replacementUuid.
+A bundle cancelled, or replaced by one sent with the same uuid, while it waits isn't sent at all.
+Once sent, the builders' responses are kept for the tracker to pick up (bundle hash, latency timeline).
+*/
+#[derive(Debug, Clone)]
+pub struct ScheduledSend {
+    pub target_block: U64,
+    pub responses: HashMap<String, SendBundleResponse>, // the builders that accepted it
+    pub acknowledged_at: Vec<(String, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct ScheduledState {
+    next_ticket: u64,
+    waiting: HashMap<String, u64>, // uuid -> ticket of the bundle to send once the window opens
+    sent: HashMap<String, ScheduledSend>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ScheduledBundles {
+    inner: Arc<Mutex<ScheduledState>>,
+}
+
+impl ScheduledBundles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The ticket the delayed send has to hold when the window opens, an older bundle with this uuid loses its own
+    pub fn schedule(&self, uuid: &str) -> u64 {
+        let mut state = self.inner.lock().unwrap();
+        state.next_ticket += 1;
+        let ticket = state.next_ticket;
+        state.waiting.insert(uuid.to_string(), ticket);
+        ticket
+    }
+
+    // The bundle with this uuid was cancelled, or another one was sent with it right away
+    pub fn cancel(&self, uuid: &str) {
+        self.inner.lock().unwrap().waiting.remove(uuid);
+    }
+
+    // true if the bundle holding the ticket is still the one to send
+    pub fn start(&self, uuid: &str, ticket: u64) -> bool {
+        let mut state = self.inner.lock().unwrap();
+        if state.waiting.get(uuid) != Some(&ticket) {
+            return false;
+        }
+        state.waiting.remove(uuid);
+        true
+    }
+
+    pub fn finish(&self, uuid: &str, sent: ScheduledSend) {
+        let mut state = self.inner.lock().unwrap();
+        let target_block = sent.target_block;
+        state.sent.retain(|_, other| {
+            other.target_block + U64::from(SCHEDULED_RETENTION_BLOCKS) >= target_block
+        });
+        state.sent.insert(uuid.to_string(), sent);
+    }
+
+    // The responses of a scheduled bundle, once it's been sent
+    pub fn take(&self, uuid: &str) -> Option<ScheduledSend> {
+        self.inner.lock().unwrap().sent.remove(uuid)
+    }
+}
@@ -0,0 +1,112 @@
+use ethers::types::U64;
+use log::{info, warn};
+use std::time::{Duration, Instant};
+
+use crate::common::alert::Alert;
+use crate::common::constants::Env;
+use crate::common::execution::Executor;
+use crate::common::tracker::Tracker;
+
+/*
+Flashbots reputation monitoring.
+Builders prioritize bundles from searchers with high priority status (earned by paying validators),
+and losing it quietly hurts inclusion long before the PnL shows it.
+Every REPUTATION_CHECK_INTERVAL_SECS we fetch flashbots_getUserStatsV2 for our IDENTITY_KEY and alert when:
+1. high priority status is lost (or regained)
+2. the inclusion rate of the last interval falls below half of the one before it
+*/
+// Don't judge the inclusion rate on fewer bundles than this
+pub static REPUTATION_MIN_SAMPLE: u64 = 20;
+
+pub struct ReputationMonitor {
+    pub enabled: bool,
+    pub interval: Duration,
+    pub last_check: Instant,
+    pub is_high_priority: Option<bool>,
+    pub last_sent: u64,
+    pub last_included: u64,
+    pub last_inclusion_rate: Option<f64>,
+}
+
+impl ReputationMonitor {
+    pub fn new(env: &Env, sequencer_mode: bool) -> Self {
+        Self {
+            enabled: !sequencer_mode && env.reputation_check_interval_secs > 0,
+            interval: Duration::from_secs(env.reputation_check_interval_secs),
+            last_check: Instant::now(),
+            is_high_priority: None,
+            last_sent: 0,
+            last_included: 0,
+            last_inclusion_rate: None,
+        }
+    }
+
+    // Called from the block loop, checks at most once per interval
+    pub async fn maybe_check(
+        &mut self,
+        executor: &Executor,
+        block_number: U64,
+        tracker: &Tracker,
+        alert: &Alert,
+    ) {
+        if !self.enabled || self.last_check.elapsed() < self.interval {
+            return;
+        }
+        self.last_check = Instant::now();
+
+        let mut messages = Vec::new();
+
+        match executor.get_user_stats(block_number).await {
+            Ok(Some(stats)) => {
+                info!(
+                    "Flashbots reputation: high priority: {} / Validator payments (1d): {} / (7d): {}",
+                    stats.is_high_priority,
+                    stats.last_1d_validator_payments,
+                    stats.last_7d_validator_payments
+                );
+                match self.is_high_priority {
+                    Some(true) if !stats.is_high_priority => messages.push(String::from(
+                        "⚠️ Flashbots high priority status lost, bundles will be deprioritized by builders",
+                    )),
+                    Some(false) if stats.is_high_priority => {
+                        messages.push(String::from("Flashbots high priority status regained"))
+                    }
+                    _ => {}
+                }
+                self.is_high_priority = Some(stats.is_high_priority);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("flashbots_getUserStatsV2 error: {e:?}"),
+        }
+
+        // inclusion rate of the bundles resolved during this interval only
+        let sent = tracker.bundles_sent.saturating_sub(self.last_sent);
+        let included = tracker.bundles_included.saturating_sub(self.last_included);
+        if sent >= REPUTATION_MIN_SAMPLE {
+            let inclusion_rate = included as f64 / sent as f64;
+            if let Some(last_inclusion_rate) = self.last_inclusion_rate {
+                if inclusion_rate < last_inclusion_rate / 2.0 {
+                    messages.push(format!(
+                        "⚠️ Inclusion rate dropped: {:.2}% ({}/{}) from {:.2}% in the previous {:?}",
+                        inclusion_rate * 100.0,
+                        included,
+                        sent,
+                        last_inclusion_rate * 100.0,
+                        self.interval
+                    ));
+                }
+            }
+            self.last_inclusion_rate = Some(inclusion_rate);
+            self.last_sent = tracker.bundles_sent;
+            self.last_included = tracker.bundles_included;
+        }
+
+        for message in messages {
+            warn!("{}", message);
+            match alert.send(&message).await {
+                Err(e) => warn!("Telegram error: {e:?}"),
+                _ => {}
+            }
+        }
+    }
+}
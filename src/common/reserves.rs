@@ -0,0 +1,125 @@
+use anyhow::Result;
+use ethers::prelude::Lazy;
+use ethers::{
+    providers::{Middleware, Provider},
+    types::{Filter, Log, H160, H256, U256, U64},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use crate::common::constants::Env;
+use crate::common::evm::EvmSimulator;
+use crate::common::provider::RpcClient;
+use crate::common::streams::NewBlock;
+
+/*
+Reserves of the cached V2 pools, kept up to date from their Sync events.
+On every new block its Sync logs are fetched by block hash (one eth_getLogs) and applied in log order,
+so the map holds the reserves at the end of the head block without a getReserves per pool and simulation.
+- a pool without a Sync yet is read once with getReserves and kept from there (see pair_reserves)
+- a block that doesn't build on the last one we applied (missed blocks, reorgs) drops everything
+  but what its own Sync logs tell
+- a failed eth_getLogs drops everything too, until the next block
+RESERVE_TRACKING=false turns it off.
+*/
+pub static V2_SYNC_TOPIC: &str =
+    "0x1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1";
+
+pub static RESERVES: Lazy<Mutex<ReserveTracker>> =
+    Lazy::new(|| Mutex::new(ReserveTracker::default()));
+
+#[derive(Debug, Default)]
+pub struct ReserveTracker {
+    pub block_number: U64, // reserves are the ones at the end of this block
+    pub block_hash: H256,
+    pub tracked: HashSet<H160>,
+    pub reserves: HashMap<H160, (U256, U256)>,
+}
+
+impl ReserveTracker {
+    pub fn track(&mut self, pools: impl IntoIterator<Item = H160>) {
+        self.tracked.extend(pools);
+    }
+
+    pub fn get(&self, block_number: U64, pair: H160) -> Option<(U256, U256)> {
+        if block_number != self.block_number {
+            return None;
+        }
+        self.reserves.get(&pair).cloned()
+    }
+
+    // Reserves read with getReserves on the state at the end of block_number
+    pub fn seed(&mut self, block_number: U64, pair: H160, reserves: (U256, U256)) {
+        if block_number == self.block_number && self.tracked.contains(&pair) {
+            self.reserves.insert(pair, reserves);
+        }
+    }
+
+    pub fn apply_block(&mut self, new_block: &NewBlock, logs: &[Log]) {
+        if new_block.parent_hash != self.block_hash {
+            self.reserves.clear();
+        }
+        self.block_number = new_block.block_number;
+        self.block_hash = new_block.hash;
+        for log in logs {
+            if log.removed == Some(true) || !self.tracked.contains(&log.address) {
+                continue;
+            }
+            if log.data.len() < 64 {
+                continue;
+            }
+            let reserve0 = U256::from_big_endian(&log.data[0..32]);
+            let reserve1 = U256::from_big_endian(&log.data[32..64]);
+            self.reserves.insert(log.address, (reserve0, reserve1));
+        }
+    }
+
+    pub fn reset(&mut self, new_block: &NewBlock) {
+        self.block_number = new_block.block_number;
+        self.block_hash = H256::zero();
+        self.reserves.clear();
+    }
+}
+
+// Called on every new block, before anything is simulated on it
+pub async fn update_reserves(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+) -> Result<()> {
+    let filter = Filter::new()
+        .at_block_hash(new_block.hash)
+        .topic0(H256::from_str(V2_SYNC_TOPIC).unwrap());
+    match provider.get_logs(&filter).await {
+        Ok(logs) => {
+            RESERVES.lock().unwrap().apply_block(new_block, &logs);
+            Ok(())
+        }
+        Err(e) => {
+            RESERVES.lock().unwrap().reset(new_block);
+            Err(e.into())
+        }
+    }
+}
+
+/*
+Reserves of a V2 pair at the end of the simulator's block, from the tracker when it has them.
+Only for a simulator nothing has run on yet: the tracker knows nothing about the txs simulated on it.
+*/
+pub fn pair_reserves(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    pair: H160,
+) -> Result<(U256, U256)> {
+    if !Env::new().reserve_tracking {
+        return simulator.get_pair_reserves(pair);
+    }
+    let block_number = simulator.block_number;
+    if let Some(reserves) = RESERVES.lock().unwrap().get(block_number, pair) {
+        return Ok(reserves);
+    }
+    let reserves = simulator.get_pair_reserves(pair)?;
+    RESERVES.lock().unwrap().seed(block_number, pair, reserves);
+    Ok(reserves)
+}
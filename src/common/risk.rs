@@ -0,0 +1,356 @@
+use anyhow::{anyhow, Result};
+use ethers::providers::{Middleware, Provider};
+use ethers::signers::Signer;
+use ethers::types::{H160, I256, U256, U64};
+use log::{info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::common::alert::{Alert, AlertEvent};
+use crate::common::chain::CHAIN;
+use crate::common::constants::*;
+use crate::common::contexts::ExecutionContext;
+use crate::common::kill_switch::KillSwitch;
+use crate::common::provider::RpcClient;
+use crate::common::tracker::TrackedBundle;
+use crate::common::utils::{get_token_balance_at, is_weth, scaled_amount, MainCurrency};
+
+/*
+Limits enforced by the planner (main_dish) to bound the worst-case loss
+from a single bad assumption:
+- max victims per bundle
+- max concurrent inflight bundles per pool
+- max total inflight exposure per main currency
+- max amount in of a single sandwich (MAX_SANDWICH_AMOUNT)
+- max inflight exposure per target token, for each main currency (MAX_TOKEN_EXPOSURE)
+- max total amount in of the bundles targeting the same block (MAX_BLOCK_NOTIONAL)
+All of them are enforced while sizing the sandwiches, before the bundle is created.
+*/
+#[derive(Debug, Clone)]
+pub struct BundleLimits {
+    pub max_victims_per_bundle: usize,
+    pub max_inflight_bundles_per_pool: usize,
+    pub max_inflight_exposure: HashMap<H160, U256>,
+    pub max_sandwich_amount: HashMap<H160, U256>,
+    pub max_token_exposure: HashMap<H160, U256>,
+    pub max_block_notional: HashMap<H160, U256>,
+}
+
+// Main currencies without a configured limit are unbounded
+fn amount_left(limits: &HashMap<H160, U256>, main_currency: H160, used: U256) -> U256 {
+    match limits.get(&main_currency) {
+        Some(limit) => limit.saturating_sub(used),
+        None => U256::MAX,
+    }
+}
+
+/*
+Parses: "WETH:100,USDT:300000,USDC:300000" (symbols of the chain profile, e.g. WBNB/BUSD on BSC)
+Amounts are in whole units of the main currency
+*/
+pub fn parse_exposure_limits(raw: &str) -> HashMap<H160, U256> {
+    let mut limits = HashMap::new();
+    for item in raw.split(',') {
+        let mut parts = item.trim().split(':');
+        let (symbol, amount) = match (parts.next(), parts.next()) {
+            (Some(symbol), Some(amount)) => (symbol.trim().to_uppercase(), amount.trim()),
+            _ => continue,
+        };
+        let address = match CHAIN
+            .main_currencies()
+            .iter()
+            .find(|mc| mc.symbol == symbol)
+        {
+            Some(mc) => mc.address,
+            None => continue,
+        };
+        let amount = match amount.parse::<u64>() {
+            Ok(amount) => amount,
+            Err(_) => continue,
+        };
+        let decimals = MainCurrency::new(address).decimals();
+        limits.insert(address, scaled_amount(amount, decimals, 0));
+    }
+    limits
+}
+
+impl BundleLimits {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            max_victims_per_bundle: env.max_victims_per_bundle,
+            max_inflight_bundles_per_pool: env.max_inflight_bundles_per_pool,
+            max_inflight_exposure: parse_exposure_limits(&env.max_inflight_exposure),
+            max_sandwich_amount: parse_exposure_limits(&env.max_sandwich_amount),
+            max_token_exposure: parse_exposure_limits(&env.max_token_exposure),
+            max_block_notional: parse_exposure_limits(&env.max_block_notional),
+        }
+    }
+
+    pub fn exposure_left(&self, main_currency: H160, inflight: U256) -> U256 {
+        amount_left(&self.max_inflight_exposure, main_currency, inflight)
+    }
+
+    pub fn sandwich_cap(&self, main_currency: H160) -> U256 {
+        amount_left(&self.max_sandwich_amount, main_currency, U256::zero())
+    }
+
+    pub fn token_exposure_left(&self, main_currency: H160, inflight: U256) -> U256 {
+        amount_left(&self.max_token_exposure, main_currency, inflight)
+    }
+
+    pub fn block_notional_left(&self, main_currency: H160, sent: U256) -> U256 {
+        amount_left(&self.max_block_notional, main_currency, sent)
+    }
+}
+
+/*
+Max drawdown circuit breaker.
+Equity (owner's native token + bot's wrapped native token, e.g. ETH + WETH) is sampled every block, and if it falls more than
+MAX_DRAWDOWN_ETH below its peak within the last DRAWDOWN_WINDOW_HOURS,
+submission is paused and we alert. This catches systematic bugs that slowly
+bleed gas or inventory without anyone noticing.
+Once tripped, the breaker stays tripped until the bot is restarted.
+*/
+pub struct DrawdownBreaker {
+    pub max_drawdown: U256, // zero --> disabled
+    pub window: Duration,
+    pub samples: VecDeque<(Instant, U256)>,
+    pub tripped: bool,
+}
+
+pub async fn get_equity(
+    provider: &Arc<Provider<RpcClient>>,
+    contexts: &[ExecutionContext],
+    block_number: U64,
+) -> Result<U256> {
+    // contexts can share an owner or a bot contract, each is only counted once
+    let owners: HashSet<H160> = contexts.iter().map(|c| c.owner.address()).collect();
+    let bot_addresses: HashSet<H160> = contexts.iter().map(|c| c.bot_address).collect();
+    let mut equity = U256::zero();
+    for owner in owners {
+        equity += provider
+            .get_balance(owner, Some(block_number.into()))
+            .await?;
+    }
+    for bot_address in bot_addresses {
+        equity += get_token_balance_at(
+            provider.clone(),
+            bot_address,
+            CHAIN.wrapped_native().address,
+            block_number,
+        )
+        .await?;
+    }
+    Ok(equity)
+}
+
+impl DrawdownBreaker {
+    pub fn new(env: &Env) -> Self {
+        let max_drawdown = U256::from(
+            (env.max_drawdown_eth.max(0.0) * 10f64.powi(CHAIN.native.decimals as i32)) as u128,
+        );
+        Self {
+            max_drawdown,
+            window: Duration::from_secs(env.drawdown_window_hours * 3600),
+            samples: VecDeque::new(),
+            tripped: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.max_drawdown.is_zero()
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.tripped
+    }
+
+    // Peak equity within the window minus the latest equity
+    pub fn drawdown(&self) -> U256 {
+        let peak = self
+            .samples
+            .iter()
+            .map(|(_, equity)| *equity)
+            .max()
+            .unwrap_or_default();
+        match self.samples.back() {
+            Some((_, current)) => peak.saturating_sub(*current),
+            None => U256::zero(),
+        }
+    }
+
+    pub async fn record(&mut self, equity: U256, alert: &Alert) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let now = Instant::now();
+        self.samples.push_back((now, equity));
+        while let Some((sampled_at, _)) = self.samples.front() {
+            if now.duration_since(*sampled_at) > self.window {
+                self.samples.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        let drawdown = self.drawdown();
+        if !self.tripped && drawdown > self.max_drawdown {
+            self.tripped = true;
+            let message = format!(
+                "🚨 Drawdown circuit breaker tripped: lost {:?} wei (limit {:?}) within {:?}h. Bundle submission paused",
+                drawdown,
+                self.max_drawdown,
+                self.window.as_secs() / 3600
+            );
+            warn!("{}", message);
+            match alert.send(&message).await {
+                Err(e) => warn!("Telegram error: {e:?}"),
+                _ => {}
+            }
+            alert.event(AlertEvent::CircuitBreaker {
+                breaker: String::from("drawdown"),
+                reason: message,
+            });
+        }
+    }
+}
+
+/*
+Consecutive loss circuit breaker (LOSS_STREAK_LIMIT, 0 disables).
+Every bundle that lands is checked onchain, in the block that included it:
+- reverted: one of the owner's txs in that block failed (sequencers don't enforce bundle atomicity)
+- unprofitable: the bot's WETH balance change didn't cover the gas the owner paid,
+  or the bot lost some of a stable main currency (stables can't be weighed against gas in ETH)
+After LOSS_STREAK_LIMIT such bundles in a row, the kill switch is turned on and we alert.
+Resuming is manual (Telegram /resume, POST /resume, SIGUSR2), and starts a new streak.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BundleOutcome {
+    Profitable(i128), // WETH balance change minus gas, in wei
+    Unprofitable(i128),
+    Reverted,
+}
+
+pub async fn get_bundle_outcome(
+    provider: &Arc<Provider<RpcClient>>,
+    owner: H160,
+    bot_address: H160,
+    bundle: &TrackedBundle,
+) -> Result<BundleOutcome> {
+    let included_number = match bundle.included_block {
+        Some((included_number, _)) => included_number,
+        None => return Err(anyhow!("Bundle {} wasn't included", bundle.bundle_id)),
+    };
+    let block = match provider.get_block_with_txs(included_number).await? {
+        Some(block) => block,
+        None => return Err(anyhow!("Block #{:?} not found", included_number)),
+    };
+
+    let mut gas_cost = U256::zero();
+    for tx in block.transactions.iter().filter(|tx| tx.from == owner) {
+        let receipt = match provider.get_transaction_receipt(tx.hash).await? {
+            Some(receipt) => receipt,
+            None => continue,
+        };
+        if receipt.status == Some(U64::zero()) {
+            return Ok(BundleOutcome::Reverted);
+        }
+        gas_cost +=
+            receipt.gas_used.unwrap_or_default() * receipt.effective_gas_price.unwrap_or_default();
+    }
+
+    let mut net = -I256::from_raw(gas_cost);
+    let mut lost_stables = false;
+    for main_currency in bundle.exposure.keys() {
+        let before = get_token_balance_at(
+            provider.clone(),
+            bot_address,
+            *main_currency,
+            included_number - U64::one(),
+        )
+        .await?;
+        let after = get_token_balance_at(
+            provider.clone(),
+            bot_address,
+            *main_currency,
+            included_number,
+        )
+        .await?;
+        let delta = I256::from_raw(after) - I256::from_raw(before);
+        if is_weth(*main_currency) {
+            net += delta;
+        } else if delta.is_negative() {
+            lost_stables = true;
+        }
+    }
+
+    let net = net.as_i128();
+    if net < 0 || lost_stables {
+        Ok(BundleOutcome::Unprofitable(net))
+    } else {
+        Ok(BundleOutcome::Profitable(net))
+    }
+}
+
+pub struct LossStreakBreaker {
+    pub limit: u64, // zero --> disabled
+    pub streak: u64,
+}
+
+impl LossStreakBreaker {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            limit: env.loss_streak_limit,
+            streak: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub async fn record(
+        &mut self,
+        bundle_id: &str,
+        outcome: BundleOutcome,
+        kill_switch: &KillSwitch,
+        alert: &Alert,
+    ) {
+        if !self.is_enabled() {
+            return;
+        }
+        match outcome {
+            BundleOutcome::Profitable(_) => {
+                self.streak = 0;
+                return;
+            }
+            _ => {
+                self.streak += 1;
+                info!(
+                    "[{}] Bundle landed as {:?} ({}/{} in a row)",
+                    bundle_id, outcome, self.streak, self.limit
+                );
+            }
+        }
+
+        if self.streak >= self.limit {
+            self.streak = 0;
+            kill_switch.halt("loss streak");
+            let message = format!(
+                "🚨 Loss circuit breaker tripped: {} bundles in a row landed unprofitable or reverted (last: {} {:?}). Bundle submission paused, send /resume to resume",
+                self.limit, bundle_id, outcome
+            );
+            warn!("{}", message);
+            match alert.send(&message).await {
+                Err(e) => warn!("Telegram error: {e:?}"),
+                _ => {}
+            }
+            alert.event(AlertEvent::CircuitBreaker {
+                breaker: String::from("loss_streak"),
+                reason: message,
+            });
+        }
+    }
+}
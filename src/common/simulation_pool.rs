@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+use ethers::prelude::Lazy;
+use log::info;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    mpsc, Arc, Mutex,
+};
+use tokio::sync::oneshot;
+
+use crate::common::constants::Env;
+
+/*
+Bounded pool of simulation workers, so CPU and RPC usage stay predictable however many
+victims come in at once. The appetizer, the optimizer (every grid point is a job) and main_dish
+submit their EVM simulations here instead of running them on whatever tokio thread they're on.
+- SIMULATION_WORKERS threads (0: one per core) take jobs in the order they're submitted
+- each worker pins the block's forked database (see fork_db.rs) and re-pins it on a new block
+- the submitting task awaits the result without blocking the runtime
+*/
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct SimulationPool {
+    sender: Mutex<mpsc::Sender<Job>>,
+    pub workers: usize,
+    pub queued: Arc<AtomicUsize>, // submitted, not picked up by a worker yet
+}
+
+pub static SIMULATION_POOL: Lazy<SimulationPool> =
+    Lazy::new(|| SimulationPool::new(Env::new().simulation_workers));
+
+impl SimulationPool {
+    pub fn new(workers: usize) -> Self {
+        let workers = match workers {
+            0 => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(4),
+            workers => workers,
+        };
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let queued = Arc::new(AtomicUsize::new(0));
+        for i in 0..workers {
+            let receiver = receiver.clone();
+            let queued = queued.clone();
+            std::thread::Builder::new()
+                .name(format!("simulation-{}", i))
+                .spawn(move || loop {
+                    let job = match receiver.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return, // the pool is gone
+                    };
+                    queued.fetch_sub(1, Ordering::SeqCst);
+                    job();
+                })
+                .expect("failed to spawn a simulation worker");
+        }
+        info!("Simulation pool: {} workers", workers);
+        Self {
+            sender: Mutex::new(sender),
+            workers,
+            queued,
+        }
+    }
+
+    pub async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            // the submitter may have stopped waiting
+            let _ = result_sender.send(job());
+        });
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        if self.sender.lock().unwrap().send(job).is_err() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(anyhow!("Simulation pool is shut down"));
+        }
+        result_receiver
+            .await
+            .map_err(|_| anyhow!("Simulation worker dropped the job"))
+    }
+}
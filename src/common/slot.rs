@@ -0,0 +1,81 @@
+use log::warn;
+use std::time::Duration;
+
+use crate::common::latency::now_ms;
+
+/*
+Slot timing based on the beacon chain genesis time.
+
+Sending bundles too early lets competitors observe and outbid us,
+sending them too late risks missing the builders' cutoff.
+The submission window for the next slot is:
+
+[next_slot_start - earliest_offset_ms, next_slot_start - latest_offset_ms]
+*/
+#[derive(Debug, Clone)]
+pub struct SlotClock {
+    pub genesis_time: u64, // seconds
+    pub seconds_per_slot: u64,
+    pub earliest_offset_ms: u64,
+    pub latest_offset_ms: u64,
+}
+
+impl SlotClock {
+    pub fn new(
+        genesis_time: u64,
+        seconds_per_slot: u64,
+        earliest_offset_ms: u64,
+        latest_offset_ms: u64,
+    ) -> Self {
+        Self {
+            genesis_time,
+            seconds_per_slot,
+            earliest_offset_ms,
+            latest_offset_ms,
+        }
+    }
+
+    pub fn slot_duration_ms(&self) -> u64 {
+        self.seconds_per_slot * 1000
+    }
+
+    pub fn slot_at(&self, timestamp_ms: u64) -> u64 {
+        timestamp_ms.saturating_sub(self.genesis_time * 1000) / self.slot_duration_ms()
+    }
+
+    pub fn slot_start_ms(&self, slot: u64) -> u64 {
+        self.genesis_time * 1000 + slot * self.slot_duration_ms()
+    }
+
+    pub fn current_slot(&self) -> u64 {
+        self.slot_at(now_ms())
+    }
+
+    pub fn next_slot_start_ms(&self) -> u64 {
+        self.slot_start_ms(self.current_slot() + 1)
+    }
+
+    /*
+    How long to wait until the submission window of the next slot opens (zero inside the window).
+    None if we're already past the window (the bundle will likely miss the slot)
+    */
+    pub fn submission_delay(&self) -> Option<Duration> {
+        let next_slot_start = self.next_slot_start_ms();
+        let window_open = next_slot_start.saturating_sub(self.earliest_offset_ms);
+        let window_close = next_slot_start.saturating_sub(self.latest_offset_ms);
+
+        let now = now_ms();
+        if now < window_open {
+            Some(Duration::from_millis(window_open - now))
+        } else if now <= window_close {
+            Some(Duration::ZERO)
+        } else {
+            warn!(
+                "Past the submission window by {:?}ms (slot #{:?})",
+                now - window_close,
+                self.current_slot() + 1
+            );
+            None
+        }
+    }
+}
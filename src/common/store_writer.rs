@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Result};
+use ethers::prelude::Lazy;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{
+    mpsc::{channel, RecvTimeoutError, Sender},
+    Mutex,
+};
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::common::cache_store::{open_cache_store, CacheStore};
+use crate::common::constants::Env;
+
+/*
+A single writer thread that owns a cache store connection, so loaders persist as they go
+instead of writing everything once they're done (and losing it all when they crash half way):
+- writes are buffered per collection (the last write of a key wins) and written in batches,
+  once STORE_WRITE_BATCH_SIZE records are buffered or every STORE_FLUSH_INTERVAL_MS
+- flush() blocks until everything sent so far is written, loaders call it before returning
+- flush_on_shutdown() flushes on SIGINT/SIGTERM before exiting the process
+It runs on its own thread rather than a tokio task, since every cache store backend blocks.
+*/
+pub static STORE_WRITER: Lazy<StoreWriter> = Lazy::new(|| StoreWriter::spawn(&Env::new()));
+
+enum WriteOp {
+    Put(String, Vec<(String, String)>), // collection, (key, JSON value) records
+    Flush(Sender<Result<usize>>),
+}
+
+pub struct StoreWriter {
+    sender: Mutex<Sender<WriteOp>>,
+}
+
+struct WriteBuffer {
+    store: Option<Box<dyn CacheStore>>,
+    pending: BTreeMap<String, HashMap<String, String>>,
+    buffered: usize,
+}
+
+impl WriteBuffer {
+    fn flush(&mut self) -> Result<usize> {
+        if self.buffered == 0 {
+            return Ok(0);
+        }
+        let store = match self.store.as_mut() {
+            Some(store) => store,
+            None => return Err(anyhow!("No cache store to write to")),
+        };
+        let mut written = 0;
+        for (collection, records) in self.pending.iter_mut() {
+            if records.is_empty() {
+                continue;
+            }
+            let batch: Vec<(String, String)> = records.drain().collect();
+            written += store.put_many(collection, &batch)?;
+        }
+        self.buffered = 0;
+        Ok(written)
+    }
+}
+
+impl StoreWriter {
+    pub fn spawn(env: &Env) -> Self {
+        let (sender, receiver) = channel::<WriteOp>();
+        let store = match open_cache_store(env) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                warn!("StoreWriter open_cache_store error: {e:?}");
+                None
+            }
+        };
+        let flush_interval = Duration::from_millis(std::cmp::max(env.store_flush_interval_ms, 1));
+        let batch_size = std::cmp::max(env.store_write_batch_size, 1);
+
+        std::thread::spawn(move || {
+            let mut buffer = WriteBuffer {
+                store,
+                pending: BTreeMap::new(),
+                buffered: 0,
+            };
+            loop {
+                match receiver.recv_timeout(flush_interval) {
+                    Ok(WriteOp::Put(collection, records)) => {
+                        buffer.buffered += records.len();
+                        buffer
+                            .pending
+                            .entry(collection)
+                            .or_default()
+                            .extend(records);
+                        if buffer.buffered < batch_size {
+                            continue;
+                        }
+                    }
+                    Ok(WriteOp::Flush(ack)) => {
+                        let _ = ack.send(buffer.flush());
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        match buffer.flush() {
+                            Err(e) => warn!("StoreWriter.flush error: {e:?}"),
+                            _ => {}
+                        }
+                        break;
+                    }
+                }
+                match buffer.flush() {
+                    Err(e) => warn!("StoreWriter.flush error: {e:?}"),
+                    _ => {}
+                }
+            }
+        });
+
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    fn send(&self, op: WriteOp) -> Result<()> {
+        self.sender
+            .lock()
+            .unwrap()
+            .send(op)
+            .map_err(|_| anyhow!("StoreWriter thread stopped"))
+    }
+
+    // Queues (key, item) records to be written to the collection
+    pub fn put_json<T: Serialize>(&self, collection: &str, items: &[(String, T)]) -> Result<()> {
+        let mut records = Vec::new();
+        for (key, item) in items {
+            records.push((key.clone(), serde_json::to_string(item)?));
+        }
+        self.send(WriteOp::Put(collection.to_string(), records))
+    }
+
+    // Writes everything queued so far, returns how many records were written
+    pub fn flush(&self) -> Result<usize> {
+        let (ack_sender, ack_receiver) = channel();
+        self.send(WriteOp::Flush(ack_sender))?;
+        ack_receiver
+            .recv()
+            .map_err(|_| anyhow!("StoreWriter thread stopped"))?
+    }
+}
+
+pub async fn flush_on_shutdown() -> Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let by = tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    };
+    match STORE_WRITER.flush() {
+        Ok(written) => info!(
+            "{}: flushed {} pending cache writes, shutting down",
+            by, written
+        ),
+        Err(e) => warn!("{}: StoreWriter.flush error: {e:?}", by),
+    }
+    std::process::exit(0);
+}
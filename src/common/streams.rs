@@ -1,24 +1,45 @@
+use ethers::core::rand::{thread_rng, Rng};
 use ethers::{
-    providers::{Middleware, Provider, Ws},
+    providers::{Middleware, Provider, TransactionStream},
     types::*,
 };
-use std::sync::Arc;
+use futures::SinkExt;
+use log::warn;
+use serde::Deserialize;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::broadcast::Sender;
 use tokio_stream::StreamExt;
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+};
 
-use crate::common::utils::calculate_next_block_base_fee;
+use crate::common::chain::CHAIN;
+use crate::common::compat::{BlockHeader, ChainReader, ToEthers};
+use crate::common::connection::ConnectionManager;
+use crate::common::dedup::{TxDedup, TxSource};
+use crate::common::latency::now_ms;
+use crate::common::provider::RpcClient;
 
 #[derive(Default, Debug, Clone)]
 pub struct NewBlock {
     pub block_number: U64,
+    pub hash: H256,
+    pub parent_hash: H256,
     pub base_fee: U256,
     pub next_base_fee: U256,
+    pub gas_limit: U256,
 }
 
 #[derive(Debug, Clone)]
 pub struct NewPendingTx {
     pub added_block: Option<U64>,
     pub tx: Transaction,
+    pub hint: Option<MevShareHint>, // Some: partial tx from MEV-Share, only what the user chose to share
+    pub first_seen_ms: u64,         // when we first received it, unix ms
 }
 
 impl Default for NewPendingTx {
@@ -26,32 +47,149 @@ impl Default for NewPendingTx {
         Self {
             added_block: None,
             tx: Transaction::default(),
+            hint: None,
+            first_seen_ms: now_ms(),
         }
     }
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MevShareLog {
+    pub address: H160,
+    #[serde(default)]
+    pub topics: Vec<H256>,
+    #[serde(default)]
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MevShareTx {
+    pub to: Option<H160>,
+    pub call_data: Option<Bytes>,
+    pub function_selector: Option<Bytes>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MevShareHint {
+    pub hash: H256,
+    #[serde(default)]
+    pub logs: Option<Vec<MevShareLog>>,
+    #[serde(default)]
+    pub txs: Option<Vec<MevShareTx>>,
+}
+
+impl MevShareHint {
+    pub fn logs(&self) -> Vec<MevShareLog> {
+        self.logs.clone().unwrap_or_default()
+    }
+
+    // Fills in whatever the hint reveals, everything else is left as default
+    pub fn to_pending_tx(&self) -> NewPendingTx {
+        let mut tx = Transaction::default();
+        tx.hash = self.hash;
+        if let Some(hint_tx) = self.txs.as_ref().and_then(|txs| txs.first()) {
+            tx.to = hint_tx.to;
+            tx.input = hint_tx
+                .call_data
+                .clone()
+                .or(hint_tx.function_selector.clone())
+                .unwrap_or_default();
+        }
+        NewPendingTx {
+            added_block: None,
+            tx,
+            hint: Some(self.clone()),
+            first_seen_ms: now_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamKind {
+    Blocks,
+    PendingTxs,
+}
+
 #[derive(Debug, Clone)]
 pub enum Event {
     Block(NewBlock),
     PendingTx(NewPendingTx),
+    StreamRestarted(StreamKind), // events may have been missed while reconnecting
 }
 
-pub async fn stream_new_blocks(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
-    let stream = provider.subscribe_blocks().await.unwrap();
-    let mut stream = stream.filter_map(|block| match block.number {
-        Some(number) => Some(NewBlock {
-            block_number: number,
-            base_fee: block.base_fee_per_gas.unwrap_or_default(),
-            next_base_fee: U256::from(calculate_next_block_base_fee(
-                block.gas_used,
-                block.gas_limit,
-                block.base_fee_per_gas.unwrap_or_default(),
+pub static RECONNECT_INITIAL_BACKOFF_MS: u64 = 500;
+pub static RECONNECT_MAX_BACKOFF_MS: u64 = 30000;
+
+// Exponential backoff with up to 50% jitter, so the streams don't all hit the endpoint at once
+pub fn reconnect_backoff(attempt: u32) -> Duration {
+    let backoff = std::cmp::min(
+        RECONNECT_INITIAL_BACKOFF_MS.saturating_mul(2u64.pow(attempt.min(16))),
+        RECONNECT_MAX_BACKOFF_MS,
+    );
+    let jitter = thread_rng().gen_range(0..=backoff / 2);
+    Duration::from_millis(backoff + jitter)
+}
+
+impl NewBlock {
+    pub fn from_header(header: &BlockHeader) -> Self {
+        let base_fee = header.base_fee.to_ethers();
+        NewBlock {
+            block_number: U64::from(header.number),
+            hash: header.hash.to_ethers(),
+            parent_hash: header.parent_hash.to_ethers(),
+            base_fee,
+            next_base_fee: U256::from(CHAIN.next_block_base_fee(
+                header.gas_used.to_ethers(),
+                header.gas_limit.to_ethers(),
+                base_fee,
             )),
-        }),
-        None => None,
-    });
+            gas_limit: header.gas_limit.to_ethers(),
+        }
+    }
+
+    pub fn from_block<TX>(block: &Block<TX>) -> Option<Self> {
+        match block.number {
+            Some(number) => Some(NewBlock {
+                block_number: number,
+                hash: block.hash.unwrap_or_default(),
+                parent_hash: block.parent_hash,
+                base_fee: block.base_fee_per_gas.unwrap_or_default(),
+                next_base_fee: U256::from(CHAIN.next_block_base_fee(
+                    block.gas_used,
+                    block.gas_limit,
+                    block.base_fee_per_gas.unwrap_or_default(),
+                )),
+                gas_limit: block.gas_limit,
+            }),
+            None => None,
+        }
+    }
+}
+
+pub async fn stream_new_blocks(
+    provider: Arc<Provider<RpcClient>>,
+    event_sender: Sender<Event>,
+    stall_timeout: Duration,
+) {
+    let stream = match provider.subscribe_blocks().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("subscribe_blocks error: {e:?}");
+            return;
+        }
+    };
+    let mut stream = stream.filter_map(|block| NewBlock::from_block(&block));
 
-    while let Some(block) = stream.next().await {
+    loop {
+        let block = match tokio::time::timeout(stall_timeout, stream.next()).await {
+            Ok(Some(block)) => block,
+            Ok(None) => return,
+            Err(_) => {
+                warn!("No new block in {:?}, block stream stalled", stall_timeout);
+                return;
+            }
+        };
         match event_sender.send(Event::Block(block)) {
             Ok(_) => {}
             Err(_) => {}
@@ -59,15 +197,131 @@ pub async fn stream_new_blocks(provider: Arc<Provider<Ws>>, event_sender: Sender
     }
 }
 
-pub async fn stream_pending_transactions(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
-    let stream = provider.subscribe_pending_txs().await.unwrap();
-    let mut stream = stream.transactions_unordered(256).fuse();
+pub static BLOCK_POLL_INTERVAL_MS: u64 = 1000;
+
+// Polls eth_getBlockByNumber for `duration`, works over plain HTTP when there's no websocket to subscribe on
+pub async fn poll_new_blocks(
+    provider: Arc<Provider<RpcClient>>,
+    event_sender: Sender<Event>,
+    duration: Duration,
+) {
+    let mut last_block_number = U64::zero();
+    let mut interval = tokio::time::interval(Duration::from_millis(BLOCK_POLL_INTERVAL_MS));
+    let started_at = Instant::now();
 
-    while let Some(result) = stream.next().await {
+    while started_at.elapsed() < duration {
+        interval.tick().await;
+
+        let new_block = match provider.block_header(None).await {
+            Ok(Some(header)) => NewBlock::from_header(&header),
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("poll_new_blocks error: {e:?}");
+                continue;
+            }
+        };
+        if new_block.block_number <= last_block_number {
+            continue;
+        }
+        last_block_number = new_block.block_number;
+
+        match event_sender.send(Event::Block(new_block)) {
+            Ok(_) => {}
+            Err(_) => {}
+        }
+    }
+}
+
+// How long we poll over HTTP before trying the websocket endpoints again
+pub static WS_RETRY_INTERVAL_SECS: u64 = 60;
+
+/*
+Subscribes to new heads over the websocket, switching endpoints whenever the subscription
+ends or stalls. If every endpoint is down, keeps the strategy fed by polling the HTTP provider
+and periodically tries the websockets again.
+*/
+pub async fn stream_new_blocks_with_fallback(
+    mut manager: ConnectionManager,
+    http_provider: Arc<Provider<RpcClient>>,
+    event_sender: Sender<Event>,
+) {
+    let mut restarts = 0;
+    let mut started = false;
+    loop {
+        match manager.connect().await {
+            Some(provider) => {
+                if started {
+                    match event_sender.send(Event::StreamRestarted(StreamKind::Blocks)) {
+                        Ok(_) => {}
+                        Err(_) => {}
+                    }
+                }
+                started = true;
+
+                let started_at = Instant::now();
+                stream_new_blocks(provider, event_sender.clone(), manager.stall_timeout).await;
+                // the stream was healthy for a while, start the backoff over
+                if started_at.elapsed() > manager.stall_timeout * 2 {
+                    restarts = 0;
+                }
+                let backoff = reconnect_backoff(restarts);
+                restarts += 1;
+                warn!(
+                    "Block stream on endpoint #{} ended, switching endpoint in {:?}",
+                    manager.current, backoff
+                );
+                manager.rotate();
+                tokio::time::sleep(backoff).await;
+            }
+            None => {
+                warn!("All websocket endpoints are down, polling blocks over HTTP");
+                poll_new_blocks(
+                    http_provider.clone(),
+                    event_sender.clone(),
+                    Duration::from_secs(WS_RETRY_INTERVAL_SECS),
+                )
+                .await;
+            }
+        }
+    }
+}
+
+pub async fn stream_pending_transactions(
+    provider: Arc<Provider<RpcClient>>,
+    event_sender: Sender<Event>,
+    stall_timeout: Duration,
+    dedup: TxDedup,
+) {
+    let stream = match provider.subscribe_pending_txs().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("subscribe_pending_txs error: {e:?}");
+            return;
+        }
+    };
+    let node_dedup = dedup.clone();
+    // drop hashes another source already delivered before fetching the tx body
+    let stream = stream.filter(move |tx_hash| node_dedup.observe(*tx_hash, TxSource::Node));
+    let mut stream = TransactionStream::new(&provider, stream, 256).fuse();
+
+    loop {
+        let result = match tokio::time::timeout(stall_timeout, stream.next()).await {
+            Ok(Some(result)) => result,
+            Ok(None) => return,
+            Err(_) => {
+                warn!(
+                    "No pending tx in {:?}, pending tx stream stalled",
+                    stall_timeout
+                );
+                return;
+            }
+        };
         match result {
             Ok(tx) => match event_sender.send(Event::PendingTx(NewPendingTx {
                 added_block: None,
                 tx,
+                hint: None,
+                first_seen_ms: now_ms(),
             })) {
                 Ok(_) => {}
                 Err(_) => {}
@@ -76,3 +330,208 @@ pub async fn stream_pending_transactions(provider: Arc<Provider<Ws>>, event_send
         };
     }
 }
+
+pub async fn stream_pending_transactions_with_failover(
+    mut manager: ConnectionManager,
+    event_sender: Sender<Event>,
+    dedup: TxDedup,
+) {
+    let mut restarts = 0;
+    let mut started = false;
+    loop {
+        match manager.connect().await {
+            Some(provider) => {
+                if started {
+                    match event_sender.send(Event::StreamRestarted(StreamKind::PendingTxs)) {
+                        Ok(_) => {}
+                        Err(_) => {}
+                    }
+                }
+                started = true;
+
+                let started_at = Instant::now();
+                stream_pending_transactions(
+                    provider,
+                    event_sender.clone(),
+                    manager.stall_timeout,
+                    dedup.clone(),
+                )
+                .await;
+                if started_at.elapsed() > manager.stall_timeout * 2 {
+                    restarts = 0;
+                }
+                let backoff = reconnect_backoff(restarts);
+                restarts += 1;
+                warn!(
+                    "Pending tx stream on endpoint #{} ended, switching endpoint in {:?}",
+                    manager.current, backoff
+                );
+                manager.rotate();
+                tokio::time::sleep(backoff).await;
+            }
+            None => {
+                warn!("All websocket endpoints are down, retrying pending tx stream");
+                tokio::time::sleep(Duration::from_secs(WS_RETRY_INTERVAL_SECS)).await;
+            }
+        }
+    }
+}
+
+/*
+MEV-Share: Flashbots streams hints about txs sent to its matchmaker over SSE.
+Each hint only has what the user chose to share (hash, and usually the logs or the pools touched),
+so they're sent to the strategy as partial NewPendingTx events that can only be backrun.
+*/
+pub async fn stream_mev_share_hints(url: String, event_sender: Sender<Event>) {
+    let client = reqwest::Client::new();
+    let mut restarts = 0;
+    loop {
+        match client
+            .get(&url)
+            .header("Accept", "text/event-stream")
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let started_at = Instant::now();
+                let mut stream = response.bytes_stream();
+                let mut buffer = String::new();
+                while let Some(chunk) = stream.next().await {
+                    let chunk = match chunk {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            warn!("MEV-Share stream error: {e:?}");
+                            break;
+                        }
+                    };
+                    buffer.push_str(&String::from_utf8_lossy(&chunk));
+                    while let Some(idx) = buffer.find('\n') {
+                        let line: String = buffer.drain(..=idx).collect();
+                        let data = match line.trim().strip_prefix("data:") {
+                            Some(data) => data.trim().to_string(),
+                            None => continue, // comments (keepalives), empty lines
+                        };
+                        match serde_json::from_str::<MevShareHint>(&data) {
+                            Ok(hint) => {
+                                match event_sender.send(Event::PendingTx(hint.to_pending_tx())) {
+                                    Ok(_) => {}
+                                    Err(_) => {}
+                                }
+                            }
+                            Err(_) => {}
+                        }
+                    }
+                }
+                if started_at.elapsed() > Duration::from_secs(WS_RETRY_INTERVAL_SECS) {
+                    restarts = 0;
+                }
+            }
+            Err(e) => warn!("MEV-Share connection error: {e:?}"),
+        }
+        let backoff = reconnect_backoff(restarts);
+        restarts += 1;
+        warn!("MEV-Share stream ended, reconnecting in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BloxrouteTxResult {
+    #[serde(rename = "txContents")]
+    tx_contents: Transaction,
+}
+
+#[derive(Debug, Deserialize)]
+struct BloxrouteParams {
+    result: BloxrouteTxResult,
+}
+
+#[derive(Debug, Deserialize)]
+struct BloxrouteNotification {
+    params: BloxrouteParams,
+}
+
+/*
+bloXroute BDN: the gateway / cloud API pushes new txs over a websocket,
+usually a few hundred ms before they reach our node's mempool.
+Txs go into the same channel as the node's pending txs, deduped through TxDedup.
+*/
+pub async fn stream_bloxroute_pending_txs(
+    url: String,
+    auth_header: String,
+    event_sender: Sender<Event>,
+    dedup: TxDedup,
+) {
+    let subscribe = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "subscribe",
+        "params": ["newTxs", {"include": ["tx_hash", "tx_contents"]}],
+    })
+    .to_string();
+
+    let mut restarts = 0;
+    loop {
+        let mut request = match url.as_str().into_client_request() {
+            Ok(request) => request,
+            Err(e) => {
+                warn!("Invalid bloXroute url: {e:?}");
+                return;
+            }
+        };
+        if !auth_header.is_empty() {
+            match auth_header.parse() {
+                Ok(value) => {
+                    request.headers_mut().insert("Authorization", value);
+                }
+                Err(e) => warn!("Invalid bloXroute auth header: {e:?}"),
+            }
+        }
+
+        match connect_async(request).await {
+            Ok((mut ws, _)) => {
+                let started_at = Instant::now();
+                match ws.send(Message::Text(subscribe.clone())).await {
+                    Ok(_) => {}
+                    Err(e) => warn!("bloXroute subscribe error: {e:?}"),
+                }
+                while let Some(msg) = ws.next().await {
+                    let text = match msg {
+                        Ok(Message::Text(text)) => text,
+                        Ok(Message::Close(_)) => break,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            warn!("bloXroute stream error: {e:?}");
+                            break;
+                        }
+                    };
+                    // the subscription id reply and anything else that isn't a tx is skipped
+                    let tx = match serde_json::from_str::<BloxrouteNotification>(&text) {
+                        Ok(notification) => notification.params.result.tx_contents,
+                        Err(_) => continue,
+                    };
+                    if !dedup.observe(tx.hash, TxSource::Bloxroute) {
+                        continue;
+                    }
+                    match event_sender.send(Event::PendingTx(NewPendingTx {
+                        added_block: None,
+                        tx,
+                        hint: None,
+                        first_seen_ms: now_ms(),
+                    })) {
+                        Ok(_) => {}
+                        Err(_) => {}
+                    }
+                }
+                if started_at.elapsed() > Duration::from_secs(WS_RETRY_INTERVAL_SECS) {
+                    restarts = 0;
+                }
+            }
+            Err(e) => warn!("bloXroute connection error: {e:?}"),
+        }
+        let backoff = reconnect_backoff(restarts);
+        restarts += 1;
+        warn!("bloXroute stream ended, reconnecting in {:?}", backoff);
+        tokio::time::sleep(backoff).await;
+    }
+}
@@ -0,0 +1,118 @@
+use log::{info, warn};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::common::capabilities::ProviderCapabilities;
+use crate::common::chain::CHAIN;
+use crate::common::constants::Env;
+
+/*
+Opt-in (TELEMETRY=true) anonymous aggregate stats.
+No addresses, keys, tx hashes or amounts are ever reported, only:
+version, chain, node capabilities, opportunities/hour and an inclusion rate bucket.
+*/
+#[derive(Debug, Clone, Serialize)]
+pub struct TelemetryReport {
+    pub version: String,
+    pub chain: String,
+    pub client: String,
+    pub debug_trace_call: bool,
+    pub pending_tx_subscription: bool,
+    pub uptime_hours: u64,
+    pub opportunities_per_hour: f64,
+    pub inclusion_rate_bucket: String,
+}
+
+pub struct Telemetry {
+    pub enabled: bool,
+    pub url: String,
+    pub client: reqwest::Client,
+    pub capabilities: ProviderCapabilities,
+    pub started_at: Instant,
+    pub last_report: Instant,
+    pub report_interval: Duration,
+    pub opportunities: u64,
+}
+
+pub fn inclusion_rate_bucket(sent: u64, included: u64) -> String {
+    if sent == 0 {
+        return "n/a".to_string();
+    }
+    let rate = (included as f64) / (sent as f64) * 100.0;
+    let bucket = if rate == 0.0 {
+        "0%"
+    } else if rate < 1.0 {
+        "0-1%"
+    } else if rate < 5.0 {
+        "1-5%"
+    } else if rate < 20.0 {
+        "5-20%"
+    } else {
+        "20%+"
+    };
+    bucket.to_string()
+}
+
+impl Telemetry {
+    pub fn new(capabilities: ProviderCapabilities) -> Self {
+        let env = Env::new();
+        if env.telemetry {
+            info!("Telemetry enabled: {}", env.telemetry_url);
+        }
+        Self {
+            enabled: env.telemetry,
+            url: env.telemetry_url,
+            client: reqwest::Client::new(),
+            capabilities,
+            started_at: Instant::now(),
+            last_report: Instant::now(),
+            report_interval: Duration::from_secs(3600),
+            opportunities: 0,
+        }
+    }
+
+    pub fn record_opportunities(&mut self, count: u64) {
+        self.opportunities += count;
+    }
+
+    pub fn build_report(&self, bundles_sent: u64, bundles_included: u64) -> TelemetryReport {
+        let uptime = self.started_at.elapsed();
+        let hours = std::cmp::max(uptime.as_secs(), 1) as f64 / 3600.0;
+        // only the client name, e.g. "Geth" from "Geth/v1.13.5-stable/linux-amd64/go1.21.4"
+        let client = self
+            .capabilities
+            .client_version
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        TelemetryReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            chain: CHAIN.name.clone(),
+            client,
+            debug_trace_call: self.capabilities.debug_trace_call,
+            pending_tx_subscription: self.capabilities.pending_tx_subscription,
+            uptime_hours: uptime.as_secs() / 3600,
+            opportunities_per_hour: (self.opportunities as f64) / hours,
+            inclusion_rate_bucket: inclusion_rate_bucket(bundles_sent, bundles_included),
+        }
+    }
+
+    // Called from the block loop, sends a report at most once per report_interval
+    pub fn maybe_report(&mut self, bundles_sent: u64, bundles_included: u64) {
+        if !self.enabled || self.last_report.elapsed() < self.report_interval {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        let report = self.build_report(bundles_sent, bundles_included);
+        let client = self.client.clone();
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            match client.post(url).json(&report).send().await {
+                Err(e) => warn!("Telemetry error: {e:?}"),
+                _ => {}
+            }
+        });
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use ethers::types::H160;
+use log::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::common::constants::Env;
+use crate::common::tokens::Token;
+use crate::common::utils::is_main_currency;
+
+/*
+Operator curated token lists, applied to the target token of every victim swap
+(main currencies are never filtered):
+- TOKEN_DENYLIST_FILE: tokens we never trade, e.g. known scams
+- TOKEN_ALLOWLIST_FILE: when set, the only tokens we trade
+One entry per line, either an address or a symbol (matched case-insensitively against
+the tokens we know of, so a symbol can match several tokens). Empty lines and # comments are ignored.
+A missing file is the same as no list.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct TokenLists {
+    pub allow: Option<HashSet<H160>>, // None: every token is allowed
+    pub deny: HashSet<H160>,
+}
+
+fn read_list(path: &str, tokens_map: &HashMap<H160, Token>) -> Result<HashSet<H160>> {
+    let mut list = HashSet::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let entry = line.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        match H160::from_str(entry) {
+            Ok(address) => {
+                list.insert(address);
+            }
+            Err(_) => {
+                let matches: Vec<H160> = tokens_map
+                    .values()
+                    .filter(|token| token.symbol.eq_ignore_ascii_case(entry))
+                    .map(|token| token.address)
+                    .collect();
+                if matches.is_empty() {
+                    warn!("{}: no known token with symbol {}", path, entry);
+                }
+                list.extend(matches);
+            }
+        }
+    }
+    Ok(list)
+}
+
+fn load_list(path: &str, tokens_map: &HashMap<H160, Token>) -> Option<HashSet<H160>> {
+    if path.is_empty() || !Path::new(path).exists() {
+        return None;
+    }
+    match read_list(path, tokens_map) {
+        Ok(list) => {
+            info!("Loaded {} tokens from {}", list.len(), path);
+            Some(list)
+        }
+        Err(e) => {
+            warn!("Invalid token list {}: {e:?}", path);
+            None
+        }
+    }
+}
+
+impl TokenLists {
+    pub fn load(env: &Env, tokens_map: &HashMap<H160, Token>) -> Self {
+        Self {
+            allow: load_list(&env.token_allowlist_file, tokens_map),
+            deny: load_list(&env.token_denylist_file, tokens_map).unwrap_or_default(),
+        }
+    }
+
+    pub fn allows(&self, token: H160) -> bool {
+        if is_main_currency(token) {
+            return true;
+        }
+        if self.deny.contains(&token) {
+            return false;
+        }
+        match &self.allow {
+            Some(allow) => allow.contains(&token),
+            None => true,
+        }
+    }
+}
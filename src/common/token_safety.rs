@@ -0,0 +1,169 @@
+use anyhow::Result;
+use ethers::providers::Provider;
+use ethers::types::{H160, U64};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path, str::FromStr, sync::Arc};
+
+use crate::common::cache_store::{open_cache_store, CacheStore};
+use crate::common::constants::Env;
+use crate::common::evm::EvmSimulator;
+use crate::common::pools::Pool;
+use crate::common::provider::RpcClient;
+use crate::common::tokens::{discover_token_metadata, find_main_currency_pool, TokenMetadata};
+
+/*
+Token safety results, written offline by `cargo run --bin screen_tokens`:
+every cached token is run through the balance slot/tax/honeypot checks against a fork,
+in parallel, and the results are kept in the cache store (see cache_store.rs) under "token_safety".
+load_all_tokens reads them at startup, so only tokens that were never screened
+need a fresh safety simulation while the bot is running.
+Results in the old SQLite database (cache/.token-safety.db) are migrated once on first open.
+*/
+pub const TOKEN_SAFETY_COLLECTION: &str = "token_safety";
+pub const LEGACY_TOKEN_SAFETY_DB_FILE: &str = "cache/.token-safety.db";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScreenedToken {
+    pub address: H160,
+    pub metadata: TokenMetadata,
+    pub block_number: u64,
+}
+
+pub struct TokenSafetyStore {
+    pub store: Box<dyn CacheStore>,
+}
+
+impl TokenSafetyStore {
+    pub fn open(env: &Env) -> Result<Self> {
+        let mut safety_store = Self {
+            store: open_cache_store(env)?,
+        };
+        match safety_store.migrate_from_db(LEGACY_TOKEN_SAFETY_DB_FILE) {
+            Err(e) => warn!("TokenSafetyStore.migrate_from_db error: {e:?}"),
+            _ => {}
+        }
+        Ok(safety_store)
+    }
+
+    pub fn upsert(
+        &mut self,
+        screened: &HashMap<H160, TokenMetadata>,
+        block_number: u64,
+    ) -> Result<usize> {
+        let items: Vec<(String, ScreenedToken)> = screened
+            .iter()
+            .map(|(token, metadata)| {
+                (
+                    format!("{:?}", token),
+                    ScreenedToken {
+                        address: *token,
+                        metadata: *metadata,
+                        block_number,
+                    },
+                )
+            })
+            .collect();
+        self.store.put_json(TOKEN_SAFETY_COLLECTION, &items)
+    }
+
+    pub fn load_all(&mut self) -> Result<HashMap<H160, TokenMetadata>> {
+        let screened: Vec<ScreenedToken> = self.store.load_json(TOKEN_SAFETY_COLLECTION)?;
+        Ok(screened
+            .into_iter()
+            .map(|token| (token.address, token.metadata))
+            .collect())
+    }
+
+    // One-off import of the legacy SQLite database, the file is kept as *.migrated afterwards
+    pub fn migrate_from_db(&mut self, db_path: &str) -> Result<usize> {
+        let file_path = Path::new(db_path);
+        if !file_path.exists() {
+            return Ok(0);
+        }
+
+        let mut screened = HashMap::new();
+        let mut block_number = 0;
+        {
+            let conn = Connection::open(file_path)?;
+            let mut stmt = conn.prepare(
+                "SELECT address, balance_slot, buy_tax_bps, sell_tax_bps, honeypot, block_number FROM token_safety",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                let address: String = row.get(0)?;
+                let screened_at: i64 = row.get(5)?;
+                Ok((
+                    H160::from_str(&address).unwrap_or_default(),
+                    TokenMetadata {
+                        balance_slot: row.get(1)?,
+                        buy_tax_bps: row.get(2)?,
+                        sell_tax_bps: row.get(3)?,
+                        honeypot: row.get(4)?,
+                    },
+                    screened_at as u64,
+                ))
+            })?;
+            for row in rows {
+                let (address, metadata, screened_at) = row?;
+                screened.insert(address, metadata);
+                block_number = std::cmp::max(block_number, screened_at);
+            }
+        }
+        let migrated = self.upsert(&screened, block_number)?;
+        fs::rename(file_path, format!("{}.migrated", db_path))?;
+        info!("Migrated {:?} screened tokens from {}", migrated, db_path);
+
+        Ok(migrated)
+    }
+}
+
+// Splits the tokens across `concurrency` forks, each running on a blocking thread
+pub async fn screen_tokens(
+    provider: Arc<Provider<RpcClient>>,
+    block_number: U64,
+    tokens: Vec<H160>,
+    pools: &Vec<Pool>,
+    concurrency: usize,
+) -> Result<HashMap<H160, TokenMetadata>> {
+    let pb = ProgressBar::new(tokens.len() as u64);
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+        )
+        .unwrap()
+        .progress_chars("##-"),
+    );
+
+    let chunk_size = std::cmp::max(tokens.len() / std::cmp::max(concurrency, 1) + 1, 1);
+    let mut handles = Vec::new();
+    for chunk in tokens.chunks(chunk_size) {
+        let jobs: Vec<(H160, Option<Pool>)> = chunk
+            .iter()
+            .map(|token| (*token, find_main_currency_pool(pools, *token).cloned()))
+            .collect();
+        let provider = provider.clone();
+        let pb = pb.clone();
+        handles.push(tokio::task::spawn_blocking(move || {
+            let mut simulator = EvmSimulator::new(provider, None, block_number);
+            let mut results = Vec::new();
+            for (token, pool) in jobs {
+                let metadata = discover_token_metadata(&mut simulator, token, pool.as_ref());
+                results.push((token, metadata));
+                pb.inc(1);
+            }
+            results
+        }));
+    }
+
+    let mut screened = HashMap::new();
+    for handle in handles {
+        for (token, metadata) in handle.await? {
+            screened.insert(token, metadata);
+        }
+    }
+    pb.finish();
+
+    Ok(screened)
+}
@@ -1,29 +1,133 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use csv::StringRecord;
 use ethers::abi::parse_abi;
 use ethers::prelude::BaseContract;
-use ethers::providers::{call_raw::RawCall, Provider, Ws};
+use ethers::prelude::Lazy;
+use ethers::providers::{call_raw::RawCall, Provider};
 use ethers::types::{spoof, BlockNumber, TransactionRequest, H160, U256, U64};
 use indicatif::{ProgressBar, ProgressStyle};
-use log::info;
-use std::{collections::HashMap, fs::OpenOptions, path::Path, str::FromStr, sync::Arc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
 
 use crate::common::bytecode::REQUEST_BYTECODE;
-use crate::common::pools::Pool;
-use crate::common::utils::create_new_wallet;
+use crate::common::cache_store::{open_cache_store, CacheStore};
+use crate::common::constants::Env;
+use crate::common::evm::EvmSimulator;
+use crate::common::multicall::get_token_info_multicall;
+use crate::common::pools::{get_v2_amount_out, Pool};
+use crate::common::provider::RpcClient;
+use crate::common::store_writer::STORE_WRITER;
+use crate::common::token_safety::TokenSafetyStore;
+use crate::common::utils::{create_new_wallet, is_main_currency, MainCurrency};
 
-#[derive(Debug, Clone)]
+/*
+Metadata discovered by simulating against the token at load time:
+- balance_slot: storage slot of the balances mapping (-1 if not found, e.g. proxies)
+- buy_tax_bps / sell_tax_bps: transfer tax measured on a small buy/sell through a V2 pool
+- honeypot: the token could be bought but not sold back
+Tokens cached before this metadata existed are loaded with unknown (default) values.
+*/
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub balance_slot: i32,
+    pub buy_tax_bps: u32,
+    pub sell_tax_bps: u32,
+    pub honeypot: bool,
+}
+
+impl Default for TokenMetadata {
+    fn default() -> Self {
+        Self {
+            balance_slot: -1,
+            buy_tax_bps: 0,
+            sell_tax_bps: 0,
+            honeypot: false,
+        }
+    }
+}
+
+impl TokenMetadata {
+    // Fee-on-transfer tokens break the exact-amount V2 math our sandwiches rely on
+    pub fn is_sandwichable(&self) -> bool {
+        !self.honeypot && self.buy_tax_bps == 0 && self.sell_tax_bps == 0
+    }
+}
+
+// Selling at a loss above this is treated the same as not being able to sell
+pub static HONEYPOT_SELL_TAX_BPS: u32 = 5000;
+
+// Lookups for the simulation code, filled by load_all_tokens
+pub static TOKEN_METADATA: Lazy<RwLock<HashMap<H160, TokenMetadata>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub fn get_token_metadata(token: H160) -> Option<TokenMetadata> {
+    TOKEN_METADATA.read().unwrap().get(&token).cloned()
+}
+
+// Verdicts of the round-trip check the appetizer runs before optimizing, true: honeypot
+pub static ROUND_TRIP_VERDICTS: Lazy<RwLock<HashMap<H160, bool>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub fn get_round_trip_verdict(token: H160) -> Option<bool> {
+    ROUND_TRIP_VERDICTS.read().unwrap().get(&token).cloned()
+}
+
+pub fn set_round_trip_verdict(token: H160, honeypot: bool) {
+    ROUND_TRIP_VERDICTS.write().unwrap().insert(token, honeypot);
+    if honeypot {
+        if let Some(metadata) = TOKEN_METADATA.write().unwrap().get_mut(&token) {
+            metadata.honeypot = true;
+        }
+    }
+}
+
+pub fn get_cached_balance_slot(token: H160) -> Option<i32> {
+    match get_token_metadata(token) {
+        Some(metadata) if metadata.balance_slot >= 0 => Some(metadata.balance_slot),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub id: i64,
     pub address: H160,
     pub name: String,
     pub symbol: String,
     pub decimals: u8,
+    #[serde(skip)]
     pub pool_ids: Vec<i64>, // refers to the "id" field of Pool struct
+    pub metadata: TokenMetadata,
 }
 
 impl From<StringRecord> for Token {
     fn from(record: StringRecord) -> Self {
+        let default = TokenMetadata::default();
+        let metadata = TokenMetadata {
+            balance_slot: record
+                .get(5)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.balance_slot),
+            buy_tax_bps: record
+                .get(6)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.buy_tax_bps),
+            sell_tax_bps: record
+                .get(7)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.sell_tax_bps),
+            honeypot: record
+                .get(8)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.honeypot),
+        };
         Self {
             id: record.get(0).unwrap().parse().unwrap(),
             address: H160::from_str(record.get(1).unwrap()).unwrap(),
@@ -31,22 +135,11 @@ impl From<StringRecord> for Token {
             symbol: String::from(record.get(3).unwrap()),
             decimals: record.get(4).unwrap().parse().unwrap(),
             pool_ids: Vec::new(),
+            metadata,
         }
     }
 }
 
-impl Token {
-    pub fn cache_row(&self) -> (i64, String, String, String, u8) {
-        (
-            self.id,
-            format!("{:?}", self.address),
-            self.name.clone(),
-            self.symbol.clone(),
-            self.decimals,
-        )
-    }
-}
-
 // for eth_call response
 #[derive(Debug, Clone)]
 pub struct TokenInfo {
@@ -56,37 +149,50 @@ pub struct TokenInfo {
     pub decimals: u8,
 }
 
+// Tokens are cached in the cache store (see cache_store.rs) under "tokens", keyed by address
+pub const TOKENS_COLLECTION: &str = "tokens";
+pub const LEGACY_TOKEN_CSV_FILE: &str = "cache/.cached-tokens.csv";
+
+// One-off import of the legacy CSV cache, the file is kept as *.migrated afterwards
+pub fn migrate_tokens_from_csv(store: &mut dyn CacheStore, csv_path: &str) -> Result<usize> {
+    let file_path = Path::new(csv_path);
+    if !file_path.exists() {
+        return Ok(0);
+    }
+
+    // rows written before token metadata was cached only have 5 columns
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(file_path)?;
+    let mut tokens = Vec::new();
+    for row in reader.records() {
+        let token = Token::from(row?);
+        tokens.push((format!("{:?}", token.address), token));
+    }
+    let migrated = store.put_json(TOKENS_COLLECTION, &tokens)?;
+    fs::rename(file_path, format!("{}.migrated", csv_path))?;
+    info!("Migrated {:?} tokens from {}", migrated, csv_path);
+
+    Ok(migrated)
+}
+
 pub async fn load_all_tokens(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     block_number: U64,
     pools: &Vec<Pool>,
     prev_pool_id: i64,
 ) -> Result<HashMap<H160, Token>> {
-    let cache_file = "cache/.cached-tokens.csv";
-    let file_path = Path::new(cache_file);
-    let file_exists = file_path.exists();
-    let file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(file_path)
-        .unwrap();
-    let mut writer = csv::Writer::from_writer(file);
+    let env = Env::new();
+    let mut store = open_cache_store(&env)?;
+    migrate_tokens_from_csv(store.as_mut(), LEGACY_TOKEN_CSV_FILE)?;
 
     let mut tokens_map: HashMap<H160, Token> = HashMap::new();
     let mut token_id = 0;
 
-    if file_exists {
-        let mut reader = csv::Reader::from_path(file_path)?;
-
-        for row in reader.records() {
-            let row = row.unwrap();
-            let token = Token::from(row);
-            tokens_map.insert(token.address, token);
-            token_id += 1;
-        }
-    } else {
-        writer.write_record(&["id", "address", "name", "symbol", "decimals"])?;
+    let cached_tokens: Vec<Token> = store.load_json(TOKENS_COLLECTION)?;
+    for token in cached_tokens {
+        token_id = std::cmp::max(token_id, token.id + 1);
+        tokens_map.insert(token.address, token);
     }
 
     let pb = ProgressBar::new(pools.len() as u64);
@@ -100,35 +206,54 @@ pub async fn load_all_tokens(
 
     let new_token_id = token_id;
 
+    let mut missing_tokens = Vec::new();
     for pool in pools {
         let pool_id = pool.id;
         if pool_id < prev_pool_id - 50 {
             continue;
         }
 
-        let token0 = pool.token0;
-        let token1 = pool.token1;
+        for token in vec![pool.token0, pool.token1] {
+            if !tokens_map.contains_key(&token) && !missing_tokens.contains(&token) {
+                missing_tokens.push(token);
+            }
+        }
+    }
 
-        for token in vec![token0, token1] {
-            if !tokens_map.contains_key(&token) {
-                match get_token_info(provider, block_number.into(), token).await {
-                    Ok(token_info) => {
-                        tokens_map.insert(
-                            token,
-                            Token {
-                                id: token_id,
-                                address: token,
-                                name: token_info.name,
-                                symbol: token_info.symbol,
-                                decimals: token_info.decimals,
-                                pool_ids: Vec::new(),
-                            },
-                        );
-                        token_id += 1;
-                    }
-                    Err(_) => {}
-                }
+    // fetch token info in Multicall3 batches, falling back to one call per token
+    // for tokens the batch couldn't decode (e.g. bytes32 names)
+    let mut token_info_map =
+        match get_token_info_multicall(provider, block_number, &missing_tokens).await {
+            Ok(token_info_map) => token_info_map,
+            Err(e) => {
+                warn!("get_token_info_multicall error: {e:?}");
+                HashMap::new()
+            }
+        };
+
+    pb.set_length(missing_tokens.len() as u64);
+    for token in missing_tokens {
+        let token_info = match token_info_map.remove(&token) {
+            Some(token_info) => Ok(token_info),
+            None => get_token_info(provider, block_number.into(), token).await,
+        };
+        match token_info {
+            Ok(token_info) => {
+                tokens_map.insert(
+                    token,
+                    Token {
+                        id: token_id,
+                        address: token,
+                        name: token_info.name,
+                        symbol: token_info.symbol,
+                        decimals: token_info.decimals,
+                        pool_ids: Vec::new(),
+                        metadata: TokenMetadata::default(),
+                    },
+                );
+                token_id += 1;
             }
+            Err(_) => {}
         }
 
         pb.inc(1);
@@ -148,23 +273,185 @@ pub async fn load_all_tokens(
 
     info!("Token count: {:?}", tokens_map.len());
 
-    let mut added = 0;
-    let mut tokens_vec: Vec<&Token> = tokens_map.values().collect();
-    tokens_vec.sort_by_key(|t| t.id);
-    for token in tokens_vec.iter() {
-        if token.id >= new_token_id {
-            writer.serialize(token.cache_row())?;
-            added += 1;
+    // tokens screened offline (bin/screen_tokens) don't need a fresh safety simulation
+    let screened = match TokenSafetyStore::open(&env).and_then(|mut s| s.load_all()) {
+        Ok(screened) => screened,
+        Err(e) => {
+            warn!("TokenSafetyStore error: {e:?}");
+            HashMap::new()
         }
+    };
+    for token in tokens_map.values_mut() {
+        if let Some(metadata) = screened.get(&token.address) {
+            token.metadata = *metadata;
+        }
+    }
+
+    // discover balance slots and taxes for the newly added tokens
+    let mut simulator = EvmSimulator::new(provider.clone(), None, block_number);
+    let mut new_tokens: Vec<&mut Token> = tokens_map
+        .values_mut()
+        .filter(|t| t.id >= new_token_id && !screened.contains_key(&t.address))
+        .collect();
+    new_tokens.sort_by_key(|t| t.id);
+    // metadata discovery is the slow part, so every token is persisted as soon as it's done
+    for token in new_tokens {
+        let pool = find_main_currency_pool(pools, token.address);
+        token.metadata = discover_token_metadata(&mut simulator, token.address, pool);
+        STORE_WRITER.put_json(
+            TOKENS_COLLECTION,
+            &[(format!("{:?}", token.address), token.clone())],
+        )?;
     }
-    writer.flush()?;
+
+    {
+        let mut token_metadata = TOKEN_METADATA.write().unwrap();
+        for token in tokens_map.values() {
+            token_metadata.insert(token.address, token.metadata);
+        }
+    }
+
+    // new tokens that were already screened offline
+    let screened_tokens: Vec<(String, Token)> = tokens_map
+        .values()
+        .filter(|t| t.id >= new_token_id && screened.contains_key(&t.address))
+        .map(|t| (format!("{:?}", t.address), t.clone()))
+        .collect();
+    STORE_WRITER.put_json(TOKENS_COLLECTION, &screened_tokens)?;
+    STORE_WRITER.flush()?;
+    let added = tokens_map.values().filter(|t| t.id >= new_token_id).count();
     info!("Added {:?} new tokens", added);
 
     Ok(tokens_map)
 }
 
+// The most recent pool pairing the token with a main currency, used to measure its taxes
+pub fn find_main_currency_pool(pools: &[Pool], token: H160) -> Option<&Pool> {
+    if is_main_currency(token) {
+        return None;
+    }
+    pools.iter().rev().find(|p| {
+        let paired = if p.token0 == token {
+            p.token1
+        } else if p.token1 == token {
+            p.token0
+        } else {
+            return false;
+        };
+        is_main_currency(paired)
+    })
+}
+
+pub fn discover_token_metadata(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    token: H160,
+    pool: Option<&Pool>,
+) -> TokenMetadata {
+    let mut metadata = TokenMetadata::default();
+    metadata.balance_slot = simulator.get_balance_slot(token).unwrap_or(-1);
+
+    if let Some(pool) = pool {
+        // an error means the check couldn't run (no liquidity, RPC), not that the token can't be sold:
+        // the round trip check before every sandwich still covers it
+        match estimate_v2_taxes(simulator, token, pool) {
+            Ok((buy_tax_bps, Some(sell_tax_bps))) => {
+                metadata.buy_tax_bps = buy_tax_bps;
+                metadata.sell_tax_bps = sell_tax_bps;
+                metadata.honeypot = sell_tax_bps >= HONEYPOT_SELL_TAX_BPS;
+            }
+            Ok((buy_tax_bps, None)) => {
+                metadata.buy_tax_bps = buy_tax_bps;
+                metadata.honeypot = true;
+            }
+            Err(e) => warn!("estimate_v2_taxes error: {:?} {e:?}", token),
+        }
+    }
+
+    metadata
+}
+
+/*
+Buys a small amount (0.1% of the main currency reserve) of the token through the V2 pool,
+then sells everything back, comparing what we received with what the pool math expects.
+Returns (buy_tax_bps, sell_tax_bps), sell_tax_bps is None when the sell reverts (a honeypot).
+Anything else failing (reserves, the buy, balance reads) is returned as an error.
+*/
+pub fn estimate_v2_taxes(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    token: H160,
+    pool: &Pool,
+) -> Result<(u32, Option<u32>)> {
+    let owner = simulator.owner;
+    let pair = pool.address;
+    let token_is_0 = pool.token0 == token;
+    let main_currency = if token_is_0 { pool.token1 } else { pool.token0 };
+    let mc = MainCurrency::new(main_currency);
+    let mc_balance_slot = get_cached_balance_slot(main_currency).unwrap_or(mc.balance_slot());
+
+    // Buy
+    let (reserve0, reserve1) = simulator.get_pair_reserves(pair)?;
+    let (reserve_token, reserve_mc) = if token_is_0 {
+        (reserve0, reserve1)
+    } else {
+        (reserve1, reserve0)
+    };
+    let amount_in = reserve_mc / U256::from(1000);
+    if amount_in.is_zero() {
+        return Err(anyhow!("Pool {:?} has no liquidity", pair));
+    }
+    simulator.set_token_balance(main_currency, owner, mc_balance_slot, amount_in.into())?;
+    simulator.transfer_token(main_currency, pair, amount_in)?;
+
+    let expected_out = get_v2_amount_out(amount_in, reserve_mc, reserve_token, pool.fee);
+    let (amount0_out, amount1_out) = if token_is_0 {
+        (expected_out, U256::zero())
+    } else {
+        (U256::zero(), expected_out)
+    };
+    let balance_before = simulator.get_token_balance(token, owner)?;
+    simulator.v2_swap(pair, amount0_out, amount1_out, owner)?;
+    let received = simulator
+        .get_token_balance(token, owner)?
+        .saturating_sub(balance_before);
+    if received.is_zero() || expected_out.is_zero() {
+        return Err(anyhow!("Bought nothing from {:?}", pair));
+    }
+    let buy_tax_bps =
+        (expected_out.saturating_sub(received) * U256::from(10000) / expected_out).as_u32();
+
+    // Sell
+    let (reserve0, reserve1) = simulator.get_pair_reserves(pair)?;
+    let (reserve_token, reserve_mc) = if token_is_0 {
+        (reserve0, reserve1)
+    } else {
+        (reserve1, reserve0)
+    };
+    if simulator.transfer_token(token, pair, received).is_err() {
+        return Ok((buy_tax_bps, None));
+    }
+    let actual_in = simulator
+        .get_token_balance(token, pair)?
+        .saturating_sub(reserve_token);
+    let sell_tax_bps = (received.saturating_sub(actual_in) * U256::from(10000) / received).as_u32();
+
+    let mc_out = get_v2_amount_out(actual_in, reserve_token, reserve_mc, pool.fee);
+    let (amount0_out, amount1_out) = if token_is_0 {
+        (U256::zero(), mc_out)
+    } else {
+        (mc_out, U256::zero())
+    };
+    if simulator
+        .v2_swap(pair, amount0_out, amount1_out, owner)
+        .is_err()
+    {
+        return Ok((buy_tax_bps, None));
+    }
+
+    Ok((buy_tax_bps, Some(sell_tax_bps)))
+}
+
 pub async fn get_token_info(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     block_number: BlockNumber,
     token_address: H160,
 ) -> Result<TokenInfo> {
@@ -194,7 +481,7 @@ pub async fn get_token_info(
         .nonce(U256::zero())
         .gas(5000000)
         .gas_price(gas_price)
-        .chain_id(1)
+        .chain_id(Env::new().chain_id)
         .into();
     let result = provider
         .call_raw(&tx)
@@ -212,7 +499,7 @@ pub async fn get_token_info(
 }
 
 pub async fn get_token_info_wrapper(
-    provider: Arc<Provider<Ws>>,
+    provider: Arc<Provider<RpcClient>>,
     block: BlockNumber,
     token_address: H160,
 ) -> Result<TokenInfo> {
@@ -220,7 +507,7 @@ pub async fn get_token_info_wrapper(
 }
 
 pub async fn get_token_info_multi(
-    provider: Arc<Provider<Ws>>,
+    provider: Arc<Provider<RpcClient>>,
     block: BlockNumber,
     tokens: &Vec<H160>,
 ) -> Result<HashMap<H160, TokenInfo>> {
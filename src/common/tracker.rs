@@ -0,0 +1,474 @@
+use anyhow::Result;
+use ethers::{
+    providers::{Middleware, Provider},
+    types::{H160, H256, U256, U64},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::common::alert::Alert;
+use crate::common::cache_store::CacheStore;
+use crate::common::experiments::ArmStats;
+use crate::common::journal::{TradeEntry, TradeJournal};
+use crate::common::latency::BundleTimeline;
+use crate::common::provider::RpcClient;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BundleStatus {
+    Pending,   // sent to builders, waiting for the target block
+    Included,  // frontrun tx found onchain, waiting for confirmations
+    Confirmed, // included with enough confirmations, profit is realized
+    Reorged,   // including block got orphaned
+    Missed,    // target block passed without inclusion
+}
+
+// One of the per-block copies of a bundle (see BUNDLE_TARGET_BLOCKS), only one of them can land
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleVariant {
+    pub target_block: U64,
+    pub frontrun_tx_hash: H256,
+    pub replacement_uuid: String, // empty when sent to a sequencer
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackedBundle {
+    pub bundle_id: String,
+    pub target_block: U64, // the variant that landed, otherwise the first one
+    pub frontrun_tx_hash: H256, // the variant that landed, otherwise the first one
+    pub variants: Vec<BundleVariant>,
+    pub bundle_hash: Option<H256>, // None when sent to a sequencer
+    pub expected_profit: i128,
+    pub pairs: Vec<H160>,
+    pub exposure: HashMap<H160, U256>, // main_currency -> amount_in
+    #[serde(default)]
+    pub token_exposure: Vec<(H160, H160, U256)>, // (target_token, main_currency, amount_in)
+    pub inventory_deltas: HashMap<H160, i128>, // main_currency -> expected balance change
+    pub included_block: Option<(U64, H256)>, // (block number, block hash)
+    pub status: BundleStatus,
+    pub arm: Option<String>, // experiment arm this bundle was assigned to
+    #[serde(default)]
+    pub timeline: BundleTimeline,
+    #[serde(default)]
+    pub context: String, // execution context (bot contract) it was sent through
+}
+
+/*
+Keeps track of the bundles we've sent and accounts profit in a reorg-safe way:
+1. Pending --> Included: when the frontrun tx receipt of any variant shows up
+2. Included --> Confirmed: after N confirmations, profit becomes realized
+3. Included/Confirmed --> Reorged: if the including block gets orphaned,
+   the recorded profit is reverted and we alert
+With a cache store attached (persist_to), the tracker's state is saved after every change
+(not every block, most blocks don't change anything) and restored on startup, so a restart doesn't lose in-flight bundles or profit accounting.
+With a trade journal attached (journal_to), every attempt and status change is also recorded there.
+*/
+pub struct Tracker {
+    pub confirmations: u64,
+    pub bundles: HashMap<String, TrackedBundle>,
+    pub unconfirmed_profit: i128,
+    pub realized_profit: i128,
+    pub inventory_snapshot: Option<(U64, HashMap<H160, U256>)>, // last balances read from RPC
+    pub bundles_sent: u64,
+    pub bundles_included: u64,
+    pub arm_stats: HashMap<String, ArmStats>,
+    pub store: Option<Box<dyn CacheStore>>,
+    pub journal: Option<TradeJournal>,
+}
+
+pub const TRACKER_COLLECTION: &str = "tracker";
+pub const TRACKER_STATE_KEY: &str = "state";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackerSnapshot {
+    pub bundles: Vec<TrackedBundle>,
+    pub unconfirmed_profit: i128,
+    pub realized_profit: i128,
+    pub bundles_sent: u64,
+    pub bundles_included: u64,
+    pub arm_stats: HashMap<String, ArmStats>,
+}
+
+// Confirmed bundles are watched for deep reorgs for this many blocks before being dropped
+pub static REORG_WATCH_DEPTH: u64 = 64;
+
+impl Tracker {
+    pub fn new(confirmations: u64) -> Self {
+        Self {
+            confirmations,
+            bundles: HashMap::new(),
+            unconfirmed_profit: 0,
+            realized_profit: 0,
+            inventory_snapshot: None,
+            bundles_sent: 0,
+            bundles_included: 0,
+            arm_stats: HashMap::new(),
+            store: None,
+            journal: None,
+        }
+    }
+
+    // Restores the last saved state from the store, and keeps saving to it
+    pub fn persist_to(&mut self, mut store: Box<dyn CacheStore>) -> Result<()> {
+        let snapshot: Option<TrackerSnapshot> =
+            store.get_json(TRACKER_COLLECTION, TRACKER_STATE_KEY)?;
+        if let Some(snapshot) = snapshot {
+            info!(
+                "Restored {} tracked bundles (realized profit: {:?})",
+                snapshot.bundles.len(),
+                snapshot.realized_profit
+            );
+            self.bundles = snapshot
+                .bundles
+                .into_iter()
+                .map(|b| (b.bundle_id.clone(), b))
+                .collect();
+            self.unconfirmed_profit = snapshot.unconfirmed_profit;
+            self.realized_profit = snapshot.realized_profit;
+            self.bundles_sent = snapshot.bundles_sent;
+            self.bundles_included = snapshot.bundles_included;
+            self.arm_stats = snapshot.arm_stats;
+        }
+        self.store = Some(store);
+        Ok(())
+    }
+
+    pub fn journal_to(&mut self, journal: TradeJournal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn record_trade(&mut self, trade: &TradeEntry) {
+        if let Some(journal) = self.journal.as_mut() {
+            match journal.record_attempt(trade) {
+                Err(e) => warn!("TradeJournal.record_attempt error: {e:?}"),
+                _ => {}
+            }
+        }
+    }
+
+    fn journal_status(journal: &mut Option<TradeJournal>, bundle: &TrackedBundle) {
+        if let Some(journal) = journal.as_mut() {
+            let realized_profit = match bundle.status {
+                BundleStatus::Confirmed => Some(bundle.expected_profit),
+                _ => None,
+            };
+            match journal.record_status(
+                &bundle.bundle_id,
+                &bundle.status,
+                bundle.included_block.map(|(number, _)| number),
+                realized_profit,
+            ) {
+                Err(e) => warn!("TradeJournal.record_status error: {e:?}"),
+                _ => {}
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        let snapshot = TrackerSnapshot {
+            bundles: self.bundles.values().cloned().collect(),
+            unconfirmed_profit: self.unconfirmed_profit,
+            realized_profit: self.realized_profit,
+            bundles_sent: self.bundles_sent,
+            bundles_included: self.bundles_included,
+            arm_stats: self.arm_stats.clone(),
+        };
+        if let Some(store) = self.store.as_mut() {
+            match store.put_json(
+                TRACKER_COLLECTION,
+                &[(TRACKER_STATE_KEY.to_string(), snapshot)],
+            ) {
+                Err(e) => warn!("Tracker.save error: {e:?}"),
+                _ => {}
+            }
+        }
+    }
+
+    // Share of sent bundles that landed, smoothed so the first few bundles don't swing it to 0 or 1
+    pub fn inclusion_rate(&self) -> f64 {
+        (self.bundles_included as f64 + 1.0) / (self.bundles_sent as f64 + 2.0)
+    }
+
+    pub fn track(
+        &mut self,
+        bundle_id: String,
+        variants: Vec<BundleVariant>,
+        bundle_hash: Option<H256>,
+        expected_profit: i128,
+        pairs: Vec<H160>,
+        exposure: HashMap<H160, U256>,
+        token_exposure: Vec<(H160, H160, U256)>,
+        inventory_deltas: HashMap<H160, i128>,
+        arm: Option<String>,
+        timeline: BundleTimeline,
+        context: String,
+    ) {
+        self.bundles_sent += 1;
+        if let Some(arm) = &arm {
+            self.arm_stats.entry(arm.clone()).or_default().sent += 1;
+        }
+        self.bundles.insert(
+            bundle_id.clone(),
+            TrackedBundle {
+                bundle_id,
+                target_block: variants[0].target_block,
+                frontrun_tx_hash: variants[0].frontrun_tx_hash,
+                variants,
+                bundle_hash,
+                expected_profit,
+                pairs,
+                exposure,
+                token_exposure,
+                inventory_deltas,
+                included_block: None,
+                status: BundleStatus::Pending,
+                arm,
+                timeline,
+                context,
+            },
+        );
+        self.save();
+    }
+
+    pub fn inflight_bundles_for_pool(&self, pair: H160) -> usize {
+        self.bundles
+            .values()
+            .filter(|b| b.status == BundleStatus::Pending && b.pairs.contains(&pair))
+            .count()
+    }
+
+    pub fn inflight_exposure(&self, main_currency: H160) -> U256 {
+        self.bundles
+            .values()
+            .filter(|b| b.status == BundleStatus::Pending)
+            .map(|b| *b.exposure.get(&main_currency).unwrap_or(&U256::zero()))
+            .fold(U256::zero(), |acc, amount| acc.saturating_add(amount))
+    }
+
+    pub fn inflight_token_exposure(&self, target_token: H160, main_currency: H160) -> U256 {
+        self.bundles
+            .values()
+            .filter(|b| b.status == BundleStatus::Pending)
+            .flat_map(|b| b.token_exposure.iter())
+            .filter(|(token, mc, _)| *token == target_token && *mc == main_currency)
+            .fold(U256::zero(), |acc, (_, _, amount)| {
+                acc.saturating_add(*amount)
+            })
+    }
+
+    // Amount in of the pending bundles with a variant for this block
+    pub fn block_notional(&self, main_currency: H160, target_block: U64) -> U256 {
+        self.bundles
+            .values()
+            .filter(|b| {
+                b.status == BundleStatus::Pending
+                    && b.variants.iter().any(|v| v.target_block == target_block)
+            })
+            .map(|b| *b.exposure.get(&main_currency).unwrap_or(&U256::zero()))
+            .fold(U256::zero(), |acc, amount| acc.saturating_add(amount))
+    }
+
+    /*
+    Balances read from the RPC right after our bundle lands can lag behind.
+    If fresh balances for block_number are available we snapshot them,
+    otherwise we forecast the inventory from the last snapshot plus the
+    expected balance changes of bundles included after it.
+    */
+    pub fn forecast_inventory(
+        &mut self,
+        fetched: Option<HashMap<H160, U256>>,
+        block_number: U64,
+    ) -> Option<HashMap<H160, U256>> {
+        if let Some(balances) = fetched {
+            self.inventory_snapshot = Some((block_number, balances.clone()));
+            return Some(balances);
+        }
+
+        let (snapshot_block, mut balances) = self.inventory_snapshot.clone()?;
+        for bundle in self.bundles.values() {
+            if bundle.status != BundleStatus::Included && bundle.status != BundleStatus::Confirmed {
+                continue;
+            }
+            let included_number = bundle.included_block.unwrap().0;
+            if included_number <= snapshot_block || included_number > block_number {
+                continue;
+            }
+            for (main_currency, delta) in &bundle.inventory_deltas {
+                let balance = balances.entry(*main_currency).or_insert(U256::zero());
+                *balance = if *delta >= 0 {
+                    balance.saturating_add(U256::from(*delta as u128))
+                } else {
+                    balance.saturating_sub(U256::from(delta.unsigned_abs()))
+                };
+            }
+        }
+        Some(balances)
+    }
+
+    pub async fn update(
+        &mut self,
+        provider: &Arc<Provider<RpcClient>>,
+        block_number: U64,
+        alert: &Alert,
+    ) -> Result<Vec<TrackedBundle>> {
+        let mut messages = Vec::new();
+        // bundles whose target block passed in this update, landed or not
+        let mut resolved = Vec::new();
+        // whether anything changed that's worth saving
+        let mut dirty = false;
+
+        for bundle in self.bundles.values_mut() {
+            match bundle.status {
+                BundleStatus::Pending => {
+                    if block_number < bundle.target_block {
+                        continue;
+                    }
+                    let mut landed = None;
+                    let mut lookup_failed = false;
+                    for variant in &bundle.variants {
+                        if variant.target_block > block_number {
+                            break;
+                        }
+                        match provider
+                            .get_transaction_receipt(variant.frontrun_tx_hash)
+                            .await
+                        {
+                            Ok(Some(receipt)) => {
+                                landed = Some((variant.clone(), receipt));
+                                break;
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                warn!(
+                                    "[{}] get_transaction_receipt error: {e:?}",
+                                    bundle.bundle_id
+                                );
+                                lookup_failed = true;
+                                break;
+                            }
+                        }
+                    }
+                    // don't call it missed on an RPC error, look again next block
+                    if landed.is_none() && lookup_failed {
+                        continue;
+                    }
+                    let last_target_block = bundle
+                        .variants
+                        .last()
+                        .map(|v| v.target_block)
+                        .unwrap_or(bundle.target_block);
+                    if landed.is_none() && block_number < last_target_block {
+                        // later variants can still land
+                        continue;
+                    }
+                    match landed {
+                        Some((variant, receipt)) => {
+                            bundle.target_block = variant.target_block;
+                            bundle.frontrun_tx_hash = variant.frontrun_tx_hash;
+                            let included_number = receipt.block_number.unwrap_or_default();
+                            let included_hash = receipt.block_hash.unwrap_or_default();
+                            bundle.included_block = Some((included_number, included_hash));
+                            bundle.status = BundleStatus::Included;
+                            self.unconfirmed_profit += bundle.expected_profit;
+                            self.bundles_included += 1;
+                            if let Some(arm) = &bundle.arm {
+                                self.arm_stats.entry(arm.clone()).or_default().included += 1;
+                            }
+                            info!(
+                                "[{}] Bundle included in block #{:?}",
+                                bundle.bundle_id, included_number
+                            );
+                        }
+                        None => {
+                            bundle.status = BundleStatus::Missed;
+                        }
+                    }
+                    Self::journal_status(&mut self.journal, bundle);
+                    resolved.push(bundle.clone());
+                    dirty = true;
+                }
+                BundleStatus::Included | BundleStatus::Confirmed => {
+                    let (included_number, included_hash) = bundle.included_block.unwrap();
+                    let canonical_hash = match provider.get_block(included_number).await {
+                        Ok(Some(block)) => block.hash,
+                        Ok(None) => None,
+                        Err(e) => {
+                            warn!("[{}] get_block error: {e:?}", bundle.bundle_id);
+                            continue;
+                        }
+                    };
+
+                    if canonical_hash != Some(included_hash) {
+                        if bundle.status == BundleStatus::Confirmed {
+                            self.realized_profit -= bundle.expected_profit;
+                            if let Some(arm) = &bundle.arm {
+                                self.arm_stats
+                                    .entry(arm.clone())
+                                    .or_default()
+                                    .realized_profit -= bundle.expected_profit;
+                            }
+                        } else {
+                            self.unconfirmed_profit -= bundle.expected_profit;
+                        }
+                        bundle.status = BundleStatus::Reorged;
+                        if let Some(arm) = &bundle.arm {
+                            self.arm_stats.entry(arm.clone()).or_default().reorged += 1;
+                        }
+                        let message = format!(
+                            "[{}] Landed bundle reorged out of block #{:?} ({:?}). Reverted profit: {:?}",
+                            bundle.bundle_id, included_number, included_hash, bundle.expected_profit
+                        );
+                        warn!("{}", message);
+                        messages.push(message);
+                        Self::journal_status(&mut self.journal, bundle);
+                        dirty = true;
+                        continue;
+                    }
+
+                    let depth = block_number.saturating_sub(included_number).as_u64();
+                    if bundle.status == BundleStatus::Included && depth >= self.confirmations {
+                        bundle.status = BundleStatus::Confirmed;
+                        self.unconfirmed_profit -= bundle.expected_profit;
+                        self.realized_profit += bundle.expected_profit;
+                        if let Some(arm) = &bundle.arm {
+                            self.arm_stats
+                                .entry(arm.clone())
+                                .or_default()
+                                .realized_profit += bundle.expected_profit;
+                        }
+                        info!(
+                            "[{}] Bundle confirmed ({} confirmations). Realized profit: {:?}",
+                            bundle.bundle_id, depth, self.realized_profit
+                        );
+                        Self::journal_status(&mut self.journal, bundle);
+                        dirty = true;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // drop bundles that can no longer change state
+        let tracked = self.bundles.len();
+        self.bundles.retain(|_, b| match b.status {
+            BundleStatus::Pending | BundleStatus::Included => true,
+            BundleStatus::Confirmed => {
+                let included_number = b.included_block.unwrap().0;
+                block_number.saturating_sub(included_number).as_u64() < REORG_WATCH_DEPTH
+            }
+            _ => false,
+        });
+        if dirty || self.bundles.len() != tracked {
+            self.save();
+        }
+
+        for message in messages {
+            match alert.send(&message).await {
+                Err(e) => warn!("Telegram error: {e:?}"),
+                _ => {}
+            }
+        }
+
+        Ok(resolved)
+    }
+}
@@ -16,7 +16,10 @@ use revm::primitives::{B160, U256 as rU256};
 use std::str::FromStr;
 use std::sync::Arc;
 
+use crate::common::chain::{CurrencyInfo, CHAIN};
 use crate::common::constants::*;
+use crate::common::provider::RpcClient;
+use crate::common::redact::redact;
 
 pub fn setup_logger() -> Result<()> {
     let colors = ColoredLevelConfig {
@@ -34,7 +37,7 @@ pub fn setup_logger() -> Result<()> {
                 "{}[{}] {}",
                 chrono::Local::now().format("[%H:%M:%S]"),
                 colors.color(record.level()),
-                message
+                redact(&message.to_string())
             ))
         })
         .chain(std::io::stdout())
@@ -50,9 +53,24 @@ pub fn calculate_next_block_base_fee(
     gas_limit: U256,
     base_fee_per_gas: U256,
 ) -> U256 {
+    calculate_next_block_base_fee_with(gas_used, gas_limit, base_fee_per_gas, 2, 8)
+}
+
+pub fn calculate_next_block_base_fee_with(
+    gas_used: U256,
+    gas_limit: U256,
+    base_fee_per_gas: U256,
+    elasticity_multiplier: u64,
+    base_fee_max_change_denominator: u64,
+) -> U256 {
+    // flat base fee (e.g. Arbitrum)
+    if base_fee_max_change_denominator == 0 {
+        return base_fee_per_gas;
+    }
+
     let gas_used = gas_used;
 
-    let mut target_gas_used = gas_limit / 2;
+    let mut target_gas_used = gas_limit / U256::from(elasticity_multiplier);
     target_gas_used = if target_gas_used == U256::zero() {
         U256::one()
     } else {
@@ -63,11 +81,11 @@ pub fn calculate_next_block_base_fee(
         if gas_used > target_gas_used {
             base_fee_per_gas
                 + ((base_fee_per_gas * (gas_used - target_gas_used)) / target_gas_used)
-                    / U256::from(8u64)
+                    / U256::from(base_fee_max_change_denominator)
         } else {
             base_fee_per_gas
                 - ((base_fee_per_gas * (target_gas_used - gas_used)) / target_gas_used)
-                    / U256::from(8u64)
+                    / U256::from(base_fee_max_change_denominator)
         }
     };
 
@@ -114,7 +132,7 @@ abigen!(
 );
 
 pub async fn get_token_balance(
-    provider: Arc<Provider<Ws>>,
+    provider: Arc<Provider<RpcClient>>,
     owner: H160,
     token: H160,
 ) -> Result<U256> {
@@ -123,6 +141,21 @@ pub async fn get_token_balance(
     Ok(token_balance)
 }
 
+pub async fn get_token_balance_at(
+    provider: Arc<Provider<RpcClient>>,
+    owner: H160,
+    token: H160,
+    block_number: U64,
+) -> Result<U256> {
+    let contract = IERC20::new(token, provider);
+    let token_balance = contract
+        .balance_of(owner)
+        .block(block_number)
+        .call()
+        .await?;
+    Ok(token_balance)
+}
+
 pub fn create_new_wallet() -> (LocalWallet, H160) {
     let wallet = LocalWallet::new(&mut thread_rng());
     let address = wallet.address();
@@ -134,68 +167,109 @@ pub fn to_h160(str_address: &'static str) -> H160 {
 }
 
 pub fn is_weth(token_address: H160) -> bool {
-    token_address == to_h160(WETH)
+    token_address == CHAIN.weth.address
 }
 
 pub fn is_main_currency(token_address: H160) -> bool {
-    let main_currencies = vec![to_h160(WETH), to_h160(USDT), to_h160(USDC)];
-    main_currencies.contains(&token_address)
+    CHAIN
+        .main_currencies()
+        .iter()
+        .any(|mc| mc.address == token_address)
 }
 
 #[derive(Debug, Clone)]
 pub enum MainCurrency {
-    WETH,
+    WETH, // wrapped native token of the chain (WETH, WBNB)
     USDT,
-    USDC,
+    USDC, // secondary stable of the chain (USDC, BUSD)
+    DAI,
 
     Default, // Pairs that aren't WETH/Stable pairs. Default to WETH for now
 }
 
 impl MainCurrency {
     pub fn new(address: H160) -> Self {
-        if address == to_h160(WETH) {
+        if address == CHAIN.weth.address {
             MainCurrency::WETH
-        } else if address == to_h160(USDT) {
+        } else if address == CHAIN.usdt.address {
             MainCurrency::USDT
-        } else if address == to_h160(USDC) {
+        } else if address == CHAIN.usdc.address {
             MainCurrency::USDC
+        } else if address == CHAIN.dai.address {
+            MainCurrency::DAI
         } else {
             MainCurrency::Default
         }
     }
 
-    pub fn decimals(&self) -> u8 {
+    pub fn info(&self) -> &'static CurrencyInfo {
         match self {
-            MainCurrency::WETH => WETH_DECIMALS,
-            MainCurrency::USDT => USDC_DECIMALS,
-            MainCurrency::USDC => USDC_DECIMALS,
-            MainCurrency::Default => WETH_DECIMALS,
+            MainCurrency::WETH => &CHAIN.weth,
+            MainCurrency::USDT => &CHAIN.usdt,
+            MainCurrency::USDC => &CHAIN.usdc,
+            MainCurrency::DAI => &CHAIN.dai,
+            MainCurrency::Default => &CHAIN.weth,
         }
     }
 
+    pub fn decimals(&self) -> u8 {
+        self.info().decimals
+    }
+
     pub fn balance_slot(&self) -> i32 {
-        match self {
-            MainCurrency::WETH => WETH_BALANCE_SLOT,
-            MainCurrency::USDT => USDT_BALANCE_SLOT,
-            MainCurrency::USDC => USDC_BALANCE_SLOT,
-            MainCurrency::Default => WETH_BALANCE_SLOT,
-        }
+        self.info().balance_slot
     }
 
     /*
     We score the currencies by importance
-    WETH has the highest importance, and USDT, USDC in the following order
+    WETH has the highest importance, and USDT, USDC, DAI in the following order
     */
     pub fn weight(&self) -> u8 {
         match self {
-            MainCurrency::WETH => 3,
-            MainCurrency::USDT => 2,
-            MainCurrency::USDC => 1,
-            MainCurrency::Default => 3, // default is WETH
+            MainCurrency::WETH => 4,
+            MainCurrency::USDT => 3,
+            MainCurrency::USDC => 2,
+            MainCurrency::DAI => 1,
+            MainCurrency::Default => 4, // default is WETH
+        }
+    }
+}
+
+/*
+Scales a human readable amount into token units: value * 10^(decimals + exp)
+exp can be negative to express fractions (e.g. value=1, exp=-2 --> 0.01 tokens)
+
+This makes no assumption on decimals being 6 or 18, so tokens with 0 decimals
+or more than 18 decimals produce sane values. Amounts that would be smaller than
+a single unit are clamped to 1, and amounts that would overflow are saturated.
+*/
+pub fn scaled_amount(value: u64, decimals: u8, exp: i32) -> U256 {
+    let shift = decimals as i32 + exp;
+    let ten = U256::from(10);
+    if shift >= 0 {
+        match ten.checked_pow(U256::from(shift as u64)) {
+            Some(unit) => U256::from(value).saturating_mul(unit),
+            None => U256::MAX,
+        }
+    } else {
+        match ten.checked_pow(U256::from((-shift) as u64)) {
+            Some(unit) => std::cmp::max(U256::from(value) / unit, U256::one()),
+            None => U256::one(),
         }
     }
 }
 
+/*
+Lossy conversion of U256 to f64 that never panics.
+U256::as_u128 panics on values bigger than u128::MAX, which can happen
+for tokens with a large number of decimals
+*/
+pub fn u256_to_f64(value: U256) -> f64 {
+    value.0.iter().rev().fold(0.0, |acc, limb| {
+        acc * 18446744073709551616.0 + (*limb as f64)
+    })
+}
+
 pub fn return_main_and_target_currency(token0: H160, token1: H160) -> Option<(H160, H160)> {
     let token0_supported = is_main_currency(token0);
     let token1_supported = is_main_currency(token1);
@@ -224,3 +298,35 @@ pub fn return_main_and_target_currency(token0: H160, token1: H160) -> Option<(H1
         return Some((token1, token0));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_amount_extreme_decimals() {
+        assert_eq!(scaled_amount(10, 0, 0), U256::from(10));
+        assert_eq!(scaled_amount(10, 2, 0), U256::from(1_000));
+        assert_eq!(scaled_amount(10, 24, 0), U256::exp10(25));
+        assert_eq!(scaled_amount(1, 18, -2), U256::exp10(16));
+    }
+
+    #[test]
+    fn scaled_amount_clamps_and_saturates() {
+        // less than a unit is clamped to 1
+        assert_eq!(scaled_amount(1, 0, -2), U256::one());
+        assert_eq!(scaled_amount(1, 2, -5), U256::one());
+        // 10^(255+24) doesn't fit
+        assert_eq!(scaled_amount(1, 255, 24), U256::MAX);
+    }
+
+    #[test]
+    fn u256_to_f64_large_values() {
+        assert_eq!(u256_to_f64(U256::zero()), 0.0);
+        assert_eq!(u256_to_f64(U256::from(1_000)), 1_000.0);
+        let value = u256_to_f64(U256::exp10(30));
+        assert!((value / 1e30 - 1.0).abs() < 1e-12);
+        // beyond u128::MAX, where as_u128 would panic
+        assert!(u256_to_f64(U256::MAX) > u128::MAX as f64);
+    }
+}
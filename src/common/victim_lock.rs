@@ -0,0 +1,97 @@
+use ethers::types::{H160, H256, U256, U64};
+use log::info;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/*
+Sandwiches and MEV-Share backruns can go after the same victim (or the same pool) in the same block,
+and their bundles would conflict: at most one of them can land, and they compete on bribes against each other.
+Before sending, a strategy claims the victim tx and the pools its bundle touches for the target block.
+The claim with the highest expected profit wins:
+- a lower-value claim from another strategy is refused, and that bundle is not sent
+- a higher-value claim takes over, the previous holder won't send for those keys again this block,
+  and its bundle is cancelled with its replacementUuid, so two of our bundles never compete for a victim
+- a claim whose bundle can't be cancelled once sent (MEV-Share bundles, private txs) is never taken over
+Claims expire on their own once the target block has passed.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrategyKind {
+    Sandwich,
+    Backrun,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockKey {
+    Victim(H256),
+    Pool(H160),
+}
+
+#[derive(Debug, Clone)]
+pub struct Claim {
+    pub strategy: StrategyKind,
+    pub target_block: U64,
+    pub value: U256,                      // expected profit of the bundle
+    pub replacement_uuid: Option<String>, // None: the bundle can't be cancelled once sent
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VictimLocks {
+    inner: Arc<Mutex<HashMap<LockKey, Claim>>>,
+}
+
+impl VictimLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /*
+    All or nothing: either every key is claimed for this strategy, or none is (None).
+    Returns the replacementUuids of the other strategy's bundles it took over, for the caller to cancel.
+    */
+    pub fn try_claim(&self, keys: &[LockKey], claim: &Claim) -> Option<Vec<String>> {
+        let mut claims = self.inner.lock().unwrap();
+        claims.retain(|_, c| c.target_block >= claim.target_block);
+
+        let mut taken_over = Vec::new();
+        for key in keys {
+            if let Some(current) = claims.get(key) {
+                if current.strategy == claim.strategy || current.target_block != claim.target_block
+                {
+                    continue;
+                }
+                let outbids = claim.value > current.value;
+                match (&current.replacement_uuid, outbids) {
+                    (Some(uuid), true) => {
+                        if !taken_over.contains(uuid) {
+                            taken_over.push(uuid.clone());
+                        }
+                    }
+                    _ => {
+                        info!(
+                            "{:?} lost {:?} to {:?} for block #{:?}",
+                            claim.strategy, key, current.strategy, claim.target_block
+                        );
+                        return None;
+                    }
+                }
+            }
+        }
+
+        for key in keys {
+            let keep_current = match claims.get(key) {
+                Some(current) => {
+                    current.strategy == claim.strategy
+                        && current.target_block == claim.target_block
+                        && current.value >= claim.value
+                }
+                None => false,
+            };
+            if !keep_current {
+                claims.insert(*key, claim.clone());
+            }
+        }
+        Some(taken_over)
+    }
+}
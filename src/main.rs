@@ -1,40 +1,271 @@
-use anyhow::Result;
-use ethers::providers::{Provider, Ws};
-use log::info;
-use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand};
+use ethers::providers::Middleware;
+use ethers::types::{H160, H256, U256};
+use log::{info, warn};
+use std::str::FromStr;
 use tokio::sync::broadcast::{self, Sender};
 use tokio::task::JoinSet;
 
+use sandooo::common::capabilities::detect_capabilities;
+use sandooo::common::chain::CHAIN;
+use sandooo::common::connection::ConnectionManager;
 use sandooo::common::constants::Env;
-use sandooo::common::streams::{stream_new_blocks, stream_pending_transactions, Event};
+use sandooo::common::dedup::TxDedup;
+use sandooo::common::evm::replay_tx;
+use sandooo::common::execution::{parse_withdrawal_token, Executor};
+use sandooo::common::external_feed::{serve_external_feed, ExternalFeedValidator};
+use sandooo::common::pools::{load_all_pools, Pool};
+use sandooo::common::provider::{connect_http_provider, RpcPreset};
+use sandooo::common::store_writer::flush_on_shutdown;
+use sandooo::common::streams::{
+    stream_bloxroute_pending_txs, stream_mev_share_hints, stream_new_blocks_with_fallback,
+    stream_pending_transactions_with_failover, Event,
+};
+use sandooo::common::tokens::load_all_tokens;
 use sandooo::common::utils::setup_logger;
+use sandooo::sandwich::backtest::run_backtest;
 use sandooo::sandwich::strategy::run_sandwich_strategy;
 
+#[derive(Parser)]
+#[command(name = "sandooo", about = "A sandwich bot")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>, // run when omitted
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the sandwich bot
+    Run,
+    /// Sync V2 pools up to the latest block into the cache, then exit
+    SyncPools,
+    /// Sync pools, then token metadata and safety checks into the cache, then exit
+    SyncTokens,
+    /// Send tokens from a bot contract to its owner
+    Withdraw {
+        /// Main currency symbol, native currency symbol or token address
+        #[arg(long)]
+        token: String,
+        /// Amount in wei (default: the whole balance)
+        #[arg(long)]
+        amount: Option<String>,
+        /// Execution context (default: the first one)
+        #[arg(long)]
+        context: Option<String>,
+    },
+    /// Replay a transaction on a fork and print gas used / revert
+    Simulate {
+        #[arg(long)]
+        tx: String,
+    },
+    /// Replay historical blocks as if their txs were pending and report the hypothetical PnL (archive node)
+    Backtest {
+        /// First block to replay
+        #[arg(long)]
+        from: u64,
+        /// Last block to replay (default: from)
+        #[arg(long)]
+        to: Option<u64>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv::dotenv().ok();
     setup_logger().unwrap();
 
+    let cli = Cli::parse();
+    let env = Env::new();
+
+    tokio::spawn(async move {
+        match flush_on_shutdown().await {
+            Err(e) => warn!("flush_on_shutdown error: {e:?}"),
+            _ => {}
+        }
+    });
+
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(env).await,
+        Command::SyncPools => sync_pools(&env).await.map(|_| ()),
+        Command::SyncTokens => sync_tokens(&env).await,
+        Command::Withdraw {
+            token,
+            amount,
+            context,
+        } => withdraw(&env, token, amount, context).await,
+        Command::Simulate { tx } => simulate(&env, tx).await,
+        Command::Backtest { from, to } => backtest(&env, from, to.unwrap_or(from)).await,
+    }
+}
+
+async fn sync_pools(env: &Env) -> Result<(Vec<Pool>, i64)> {
+    let (pools, prev_pool_id) = load_all_pools(
+        env.rpc_url(),
+        CHAIN.pool_sync_from_block,
+        RpcPreset::from_env(env).log_chunk_size(),
+    )
+    .await?;
+    info!("Pools synced: {:?}", pools.len());
+    Ok((pools, prev_pool_id))
+}
+
+async fn sync_tokens(env: &Env) -> Result<()> {
+    let (pools, prev_pool_id) = sync_pools(env).await?;
+    let provider = connect_http_provider(env).await?;
+    let block_number = provider.get_block_number().await?;
+    let tokens_map = load_all_tokens(&provider, block_number, &pools, prev_pool_id).await?;
+    info!("Tokens synced: {:?}", tokens_map.len());
+    Ok(())
+}
+
+async fn withdraw(
+    env: &Env,
+    token: String,
+    amount: Option<String>,
+    context: Option<String>,
+) -> Result<()> {
+    let token = parse_withdrawal_token(&token).ok_or(anyhow!("Unknown token {}", token))?;
+    let provider = connect_http_provider(env).await?;
+    let executor = Executor::new(provider);
+    let context = match &context {
+        Some(name) => executor
+            .contexts
+            .iter()
+            .find(|c| &c.name == name)
+            .ok_or(anyhow!("Unknown context {}", name))?,
+        None => &executor.contexts[0],
+    };
+    let executor = executor.with_context(context);
+
+    let amount = match amount {
+        Some(amount) => U256::from_dec_str(&amount)?,
+        None => executor.bot_balance(token).await?,
+    };
+    if amount.is_zero() {
+        return Err(anyhow!("Nothing to withdraw"));
+    }
+    let tx_hash = executor.withdraw(token, amount).await?;
+    info!(
+        "Withdrawal of {} {:?} from {} ({:?}) sent: {:?}",
+        amount, token, executor.context, executor.bot_address, tx_hash
+    );
+    Ok(())
+}
+
+async fn simulate(env: &Env, tx: String) -> Result<()> {
+    let tx_hash = H256::from_str(&tx)?;
+    let provider = connect_http_provider(env).await?;
+    let (block_number, result) = replay_tx(provider, tx_hash).await?;
+    info!(
+        "Simulated {:?} on top of block #{:?}",
+        tx_hash, block_number
+    );
+    match result {
+        Ok(result) => {
+            info!(
+                "✅ Success / Gas used: {:?} / Gas refunded: {:?} / Logs: {:?}",
+                result.gas_used,
+                result.gas_refunded,
+                result.logs.map(|logs| logs.len()).unwrap_or(0)
+            );
+            info!("Output: {:?}", result.output);
+        }
+        Err(e) => info!("❌ Reverted: {e:?}"),
+    }
+    Ok(())
+}
+
+async fn backtest(env: &Env, from: u64, to: u64) -> Result<()> {
+    let provider = connect_http_provider(env).await?;
+    let report = run_backtest(provider, from, to).await?;
+    for sandwich in &report.sandwiches {
+        info!(
+            "[#{}] {:?} / Pair: {:?} / Amount in: {:?} / Revenue: {:?} / Bribe: {:?} / Profit: {:?}",
+            sandwich.block_number,
+            sandwich.victim_tx_hash,
+            sandwich.pair,
+            sandwich.amount_in,
+            sandwich.revenue,
+            sandwich.bribe,
+            sandwich.profit
+        );
+    }
+    report.pretty_print();
+    Ok(())
+}
+
+async fn run(env: Env) -> Result<()> {
+    if CHAIN.is_fcfs {
+        return Err(anyhow!(
+            "{} orders txs first come first served, a frontrun can't be placed before its victim",
+            CHAIN.name
+        ));
+    }
     info!("Starting Sandooo");
 
-    let env = Env::new();
+    let mut connection_manager = ConnectionManager::new(&env);
+    let provider = connection_manager.connect().await.unwrap();
+    let http_provider = connect_http_provider(&env).await.unwrap();
 
-    let ws = Ws::connect(env.wss_url.clone()).await.unwrap();
-    let provider = Arc::new(Provider::new(ws));
+    let capabilities = detect_capabilities(&provider).await;
+    capabilities.pretty_print();
+    capabilities.warn_degraded();
 
-    let (event_sender, _): (Sender<Event>, _) = broadcast::channel(512);
+    let (event_sender, _): (Sender<Event>, _) = broadcast::channel(env.event_channel_capacity);
 
     let mut set = JoinSet::new();
+    let dedup = TxDedup::new();
 
-    set.spawn(stream_new_blocks(provider.clone(), event_sender.clone()));
-    set.spawn(stream_pending_transactions(
-        provider.clone(),
+    set.spawn(stream_new_blocks_with_fallback(
+        connection_manager.clone(),
+        http_provider.clone(),
         event_sender.clone(),
     ));
+    if capabilities.pending_tx_subscription {
+        set.spawn(stream_pending_transactions_with_failover(
+            connection_manager.clone(),
+            event_sender.clone(),
+            dedup.clone(),
+        ));
+    }
+
+    if !env.bloxroute_ws_url.is_empty() {
+        set.spawn(stream_bloxroute_pending_txs(
+            env.bloxroute_ws_url.clone(),
+            env.bloxroute_auth_header.clone(),
+            event_sender.clone(),
+            dedup.clone(),
+        ));
+    }
+
+    if !env.external_feed_addr.is_empty() {
+        let validator = ExternalFeedValidator {
+            chain_id: env.chain_id,
+            signer: H160::from_str(&env.external_feed_signer).ok(),
+        };
+        let addr = env.external_feed_addr.clone();
+        let sender = event_sender.clone();
+        let dedup = dedup.clone();
+        set.spawn(async move {
+            match serve_external_feed(addr, validator, sender, dedup).await {
+                Err(e) => warn!("External feed error: {e:?}"),
+                _ => {}
+            }
+        });
+    }
+
+    if env.mev_share {
+        set.spawn(stream_mev_share_hints(
+            env.mev_share_url.clone(),
+            event_sender.clone(),
+        ));
+    }
 
     set.spawn(run_sandwich_strategy(
-        provider.clone(),
+        http_provider.clone(),
         event_sender.clone(),
+        capabilities.clone(),
     ));
 
     while let Some(res) = set.join_next().await {
@@ -0,0 +1,171 @@
+use ethers::prelude::Lazy;
+use ethers::providers::Provider;
+use ethers::types::{
+    transaction::eip2930::{AccessList, AccessListItem},
+    H160, H256, U64,
+};
+use revm::primitives::B256;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    sync::Mutex,
+};
+
+use crate::common::evm::{EvmSimulator, Tx};
+use crate::common::failures::TxStage;
+use crate::common::provider::RpcClient;
+use crate::sandwich::simulation::{BatchSandwich, SwapDirection};
+
+/*
+Access lists of the frontrun/backrun, traced once per (pair, direction) instead of on every simulation
+that isn't given one (the appetizer, victim ordering in main_dish, backruns...).
+- a single sandwich's lists are traced and kept, a batch gets the union of its sandwiches' lists
+  (a batch with a sandwich we haven't seen is traced as a whole, and isn't kept)
+- keyed by the bot contract too: token balance slots of the bot are part of the list
+- a list is traced again when the pair's code changes, or after ACCESS_LIST_REFRESH_BLOCKS
+  since storage can move under the same code (e.g. a token behind an upgraded proxy)
+- the ACCESS_LIST_CACHE_SIZE least recently used lists are kept
+An extra or missing entry only costs gas, never a revert.
+*/
+pub static ACCESS_LIST_CACHE_SIZE: usize = 2000;
+pub static ACCESS_LIST_REFRESH_BLOCKS: u64 = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AccessListKey {
+    pub pair: H160,
+    pub direction: SwapDirection,
+    pub stage: TxStage,
+    pub bot_address: Option<H160>, // None: a bot deployed for the simulation
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedAccessList {
+    pub access_list: AccessList,
+    pub code_hash: B256, // of the pair, when traced
+    pub traced_block: U64,
+}
+
+#[derive(Debug, Default)]
+pub struct AccessListCache {
+    pub entries: HashMap<AccessListKey, CachedAccessList>,
+    order: VecDeque<AccessListKey>, // least recently used first
+}
+
+pub static ACCESS_LIST_CACHE: Lazy<Mutex<AccessListCache>> =
+    Lazy::new(|| Mutex::new(AccessListCache::default()));
+
+impl AccessListCache {
+    fn touch(&mut self, key: &AccessListKey) {
+        if let Some(idx) = self.order.iter().position(|k| k == key) {
+            self.order.remove(idx);
+        }
+        self.order.push_back(*key);
+    }
+
+    pub fn get(
+        &mut self,
+        key: &AccessListKey,
+        code_hash: B256,
+        block_number: U64,
+    ) -> Option<AccessList> {
+        let fresh = match self.entries.get(key) {
+            Some(cached) => {
+                cached.code_hash == code_hash
+                    && block_number < cached.traced_block + U64::from(ACCESS_LIST_REFRESH_BLOCKS)
+            }
+            None => return None,
+        };
+        if !fresh {
+            self.entries.remove(key);
+            return None;
+        }
+        self.touch(key);
+        self.entries
+            .get(key)
+            .map(|cached| cached.access_list.clone())
+    }
+
+    pub fn insert(&mut self, key: AccessListKey, cached: CachedAccessList) {
+        self.entries.insert(key, cached);
+        self.touch(&key);
+        while self.order.len() > ACCESS_LIST_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Union of the lists, storage keys deduplicated
+pub fn merge_access_lists(access_lists: Vec<AccessList>) -> AccessList {
+    let mut merged: BTreeMap<H160, BTreeSet<H256>> = BTreeMap::new();
+    for access_list in access_lists {
+        for item in access_list.0 {
+            merged
+                .entry(item.address)
+                .or_default()
+                .extend(item.storage_keys);
+        }
+    }
+    AccessList(
+        merged
+            .into_iter()
+            .map(|(address, storage_keys)| AccessListItem {
+                address,
+                storage_keys: storage_keys.into_iter().collect(),
+            })
+            .collect(),
+    )
+}
+
+// The cached access list of the batch's tx at this stage, traced with the simulator if we don't have one
+pub fn get_or_trace_access_list(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    batch_sandwich: &BatchSandwich,
+    stage: TxStage,
+    tx: Tx,
+    bot_address: Option<H160>,
+) -> AccessList {
+    let block_number = simulator.block_number;
+    let mut legs = Vec::new();
+    for sandwich in &batch_sandwich.sandwiches {
+        let pair = sandwich.swap_info.target_pair;
+        let code_hash = match simulator.basic(pair) {
+            Ok(Some(account)) => account.code_hash,
+            _ => B256::zero(),
+        };
+        let key = AccessListKey {
+            pair,
+            direction: sandwich.swap_info.direction,
+            stage,
+            bot_address,
+        };
+        legs.push((key, code_hash));
+    }
+    let mut seen = HashSet::new();
+    legs.retain(|(key, _)| seen.insert(*key));
+
+    {
+        let mut cache = ACCESS_LIST_CACHE.lock().unwrap();
+        let cached: Vec<AccessList> = legs
+            .iter()
+            .filter_map(|(key, code_hash)| cache.get(key, *code_hash, block_number))
+            .collect();
+        if !legs.is_empty() && cached.len() == legs.len() {
+            return merge_access_lists(cached);
+        }
+    }
+
+    let access_list = simulator.get_access_list(tx).unwrap_or_default();
+    if legs.len() == 1 && !access_list.0.is_empty() {
+        let (key, code_hash) = legs[0];
+        ACCESS_LIST_CACHE.lock().unwrap().insert(
+            key,
+            CachedAccessList {
+                access_list: access_list.clone(),
+                code_hash,
+                traced_block: block_number,
+            },
+        );
+    }
+    access_list
+}
@@ -1,24 +1,38 @@
 use anyhow::Result;
 use ethers::{
-    providers::{Provider, Ws},
+    providers::Provider,
     types::{H256, U256},
 };
-use log::warn;
+use log::{info, warn};
 use std::{collections::HashMap, sync::Arc};
 
-use crate::common::evm::VictimTx;
+use crate::common::constants::Env;
+use crate::common::evm::{EvmSimulator, VictimTx};
+use crate::common::failures::FailureStats;
+use crate::common::provider::RpcClient;
 use crate::common::streams::NewBlock;
-use crate::common::utils::{is_weth, MainCurrency};
-use crate::sandwich::simulation::{BatchSandwich, PendingTxInfo, Sandwich, SwapDirection};
+use crate::common::token_lists::TokenLists;
+use crate::common::utils::{is_weth, scaled_amount, MainCurrency};
+use crate::sandwich::candidates::compact;
+use crate::sandwich::liquidity::check_liquidity;
+use crate::sandwich::routes::sandwich_targets;
+use crate::sandwich::simulation::{
+    check_pool_state, check_round_trip, simulate_curve_sandwich, BatchSandwich, PendingTxInfo,
+    Sandwich, SkipReason, SwapDirection, SwapInfo, CURVE_VERSION,
+};
+use crate::sandwich::slippage::victim_slippage_cap;
 
 pub async fn appetizer(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     new_block: &NewBlock,
     tx_hash: H256,
     victim_gas_price: U256,
     pending_txs: &HashMap<H256, PendingTxInfo>,
     promising_sandwiches: &mut HashMap<H256, Vec<Sandwich>>,
-) -> Result<()> {
+    failures: &mut FailureStats,
+    token_lists: &TokenLists,
+) -> Result<Vec<(SwapInfo, SkipReason)>> {
+    let env = Env::new();
     let pending_tx_info = pending_txs.get(&tx_hash).unwrap();
     let pending_tx = &pending_tx_info.pending_tx;
     // make sandwiches and simulate
@@ -34,30 +48,121 @@ pub async fn appetizer(
 
     let swap_info = &pending_tx_info.touched_pairs;
 
+    let mut skipped = Vec::new();
+    let mut simulator = EvmSimulator::new(provider.clone(), None, new_block.block_number);
+
     /*
     For now, we focus on the buys:
     1. Frontrun: Buy
     2. Victim: Buy
     3. Backrun: Sell
     */
-    for info in swap_info {
+    let targets = sandwich_targets(swap_info);
+    for (idx, info) in swap_info.iter().enumerate() {
         match info.direction {
             SwapDirection::Sell => continue,
             _ => {}
         }
+        if !targets.contains(&idx) {
+            info!(
+                "Skipping [{:?}] Pool: {:?} / Reason: {:?} ({}/{})",
+                tx_hash,
+                info.target_pair,
+                SkipReason::IntermediateHop,
+                info.route.hop + 1,
+                info.route.hops
+            );
+            skipped.push((info.clone(), SkipReason::IntermediateHop));
+            continue;
+        }
+        if info.route.is_multi_hop() {
+            info!(
+                "🔀 Multi-hop victim [{:?}] Pool: {:?} / Hop: {}/{} / Impact: {} bps",
+                tx_hash,
+                info.target_pair,
+                info.route.hop + 1,
+                info.route.hops,
+                info.route.impact_bps
+            );
+        }
+
+        let skip_reason = if token_lists.allows(info.target_token) {
+            None
+        } else {
+            Some(SkipReason::TokenNotAllowed)
+        };
+        // the pool state and round trip checks go through getReserves and the V2 router
+        let is_curve = info.version == CURVE_VERSION;
+        let skip_reason = skip_reason
+            .or_else(|| check_liquidity(&mut simulator, info))
+            .or_else(|| (!is_curve).then(|| check_pool_state(&mut simulator, info))?)
+            .or_else(|| {
+                (!is_curve)
+                    .then(|| check_round_trip(provider.clone(), new_block.block_number, info))?
+            });
+        // the largest frontrun the victim's amountOutMin / amountInMax still lets through
+        let slippage_cap = match skip_reason {
+            Some(_) => None,
+            None => victim_slippage_cap(&mut simulator, info, &pending_tx.tx),
+        };
+        let skip_reason = skip_reason.or_else(|| match slippage_cap {
+            Some(cap) if cap.is_zero() => Some(SkipReason::NoSlippageRoom),
+            _ => None,
+        });
+        if let Some(reason) = skip_reason {
+            info!(
+                "Skipping [{:?}] Pool: {:?} / Reason: {:?}",
+                tx_hash, info.target_pair, reason
+            );
+            skipped.push((info.clone(), reason));
+            continue;
+        }
 
         let main_currency = info.main_currency;
         let mc = MainCurrency::new(main_currency);
         let decimals = mc.decimals();
 
-        let small_amount_in = if is_weth(main_currency) {
-            U256::from(10).pow(U256::from(decimals - 2)) // 0.01 WETH
-        } else {
-            U256::from(10) * U256::from(10).pow(U256::from(decimals)) // 10 USDT, 10 USDC
-        };
+        let small_amount_in = probe_amount_in(is_weth(main_currency), decimals, slippage_cap);
         let base_fee = new_block.next_base_fee;
         let max_fee = base_fee;
 
+        if is_curve {
+            // the Sandooo contract can't swap on Curve pools: measure the opportunity, never send it
+            // coarse sweep: 10, 100, ..., 1,000,000 stables
+            let mut best: Option<(U256, i128)> = None;
+            for i in 0..6 {
+                let amount_in = small_amount_in * U256::from(10).pow(U256::from(i));
+                match simulate_curve_sandwich(
+                    provider.clone(),
+                    new_block.block_number,
+                    base_fee,
+                    info,
+                    &victim_tx,
+                    amount_in,
+                )
+                .await
+                {
+                    Ok(simulated) => {
+                        if simulated.revenue > best.map(|b| b.1).unwrap_or(0) {
+                            best = Some((amount_in, simulated.revenue));
+                        }
+                    }
+                    Err(e) => {
+                        failures.record_error(&e);
+                        warn!("simulate_curve_sandwich error: {e:?}");
+                    }
+                }
+            }
+            if let Some((amount_in, revenue)) = best {
+                info!(
+                    "🌀 Curve sandwich [{:?}] Pool: {:?} / Amount in: {:?} / Revenue: {:?}",
+                    tx_hash, info.target_pair, amount_in, revenue
+                );
+            }
+            skipped.push((info.clone(), SkipReason::CurveNotExecutable));
+            continue;
+        }
+
         let mut sandwich = Sandwich {
             amount_in: small_amount_in,
             swap_info: info.clone(),
@@ -83,6 +188,7 @@ pub async fn appetizer(
             .await;
         if simulated_sandwich.is_err() {
             let e = simulated_sandwich.as_ref().err().unwrap();
+            failures.record_error(e);
             warn!("BatchSandwich.simulate error: {e:?}");
             continue;
         }
@@ -91,11 +197,21 @@ pub async fn appetizer(
         if simulated_sandwich.profit <= 0 {
             continue;
         }
-        let ceiling_amount_in = if is_weth(main_currency) {
-            U256::from(100) * U256::from(10).pow(U256::from(18)) // 100 ETH
+        let max_amount_in = if is_weth(main_currency) {
+            env.max_amount_in_weth
         } else {
-            U256::from(300000) * U256::from(10).pow(U256::from(decimals)) // 300000 USDT/USDC (both 6 decimals)
+            env.max_amount_in_stables
         };
+        let ceiling_amount_in = ceiling_amount_in(max_amount_in, decimals, slippage_cap);
+        // victim still pending from a previous block: warm-start from the last optimal amount
+        let prev_amount_in = promising_sandwiches.get(&tx_hash).and_then(|sandwiches| {
+            sandwiches
+                .iter()
+                .rev()
+                .find(|s| s.swap_info.target_pair == info.target_pair)
+                .and_then(|s| s.optimized_sandwich.as_ref())
+                .map(|o| o.amount_in)
+        });
         let optimized_sandwich = sandwich
             .optimize(
                 provider.clone(),
@@ -105,10 +221,12 @@ pub async fn appetizer(
                 max_fee,
                 simulated_sandwich.front_access_list.clone(),
                 simulated_sandwich.back_access_list.clone(),
+                prev_amount_in,
             )
             .await;
         if optimized_sandwich.is_err() {
             let e = optimized_sandwich.as_ref().err().unwrap();
+            failures.record_error(e);
             warn!("Sandwich.optimize error: {e:?}");
             continue;
         }
@@ -116,13 +234,85 @@ pub async fn appetizer(
         if optimized_sandwich.max_revenue > U256::zero() {
             // add optimized sandwiches to promising_sandwiches
             if !promising_sandwiches.contains_key(&tx_hash) {
-                promising_sandwiches.insert(tx_hash, vec![sandwich.clone()]);
+                promising_sandwiches.insert(tx_hash, vec![compact(&sandwich)]);
             } else {
                 let sandwiches = promising_sandwiches.get_mut(&tx_hash).unwrap();
-                sandwiches.push(sandwich.clone());
+                sandwiches.push(compact(&sandwich));
             }
         }
     }
 
-    Ok(())
+    Ok(skipped)
+}
+
+// First amount simulated: 0.01 WETH, or 10 USDT/USDC/DAI, whatever the main currency's decimals
+pub fn probe_amount_in(weth: bool, decimals: u8, slippage_cap: Option<U256>) -> U256 {
+    let amount_in = if weth {
+        scaled_amount(1, decimals, -2)
+    } else {
+        scaled_amount(10, decimals, 0)
+    };
+    match slippage_cap {
+        Some(cap) => std::cmp::min(amount_in, cap),
+        None => amount_in,
+    }
+}
+
+// Largest amount the optimizer tries: max_amount_in whole tokens, capped by the victim's slippage
+pub fn ceiling_amount_in(max_amount_in: u64, decimals: u8, slippage_cap: Option<U256>) -> U256 {
+    let amount_in = scaled_amount(max_amount_in, decimals, 0);
+    match slippage_cap {
+        Some(cap) => std::cmp::min(amount_in, cap),
+        None => amount_in,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_amount_in_scales_with_decimals() {
+        assert_eq!(probe_amount_in(true, 18, None), U256::exp10(16));
+        assert_eq!(probe_amount_in(false, 6, None), U256::from(10_000_000));
+        // 0.01 of a token with 0 or 2 decimals: at least one unit
+        assert_eq!(probe_amount_in(true, 0, None), U256::one());
+        assert_eq!(probe_amount_in(true, 2, None), U256::one());
+        assert_eq!(probe_amount_in(false, 0, None), U256::from(10));
+        assert_eq!(probe_amount_in(false, 2, None), U256::from(1_000));
+        assert_eq!(probe_amount_in(true, 24, None), U256::exp10(22));
+        assert_eq!(probe_amount_in(false, 24, None), U256::exp10(25));
+    }
+
+    #[test]
+    fn probe_amount_in_respects_slippage_cap() {
+        let cap = U256::from(5);
+        assert_eq!(probe_amount_in(false, 2, Some(cap)), cap);
+        assert_eq!(
+            probe_amount_in(false, 0, Some(U256::from(100))),
+            U256::from(10)
+        );
+    }
+
+    #[test]
+    fn ceiling_amount_in_scales_with_decimals() {
+        assert_eq!(ceiling_amount_in(100, 18, None), U256::exp10(20));
+        assert_eq!(ceiling_amount_in(100, 0, None), U256::from(100));
+        assert_eq!(ceiling_amount_in(100, 2, None), U256::from(10_000));
+        assert_eq!(ceiling_amount_in(100, 24, None), U256::exp10(26));
+        assert_eq!(
+            ceiling_amount_in(100, 24, Some(U256::exp10(25))),
+            U256::exp10(25)
+        );
+    }
+
+    #[test]
+    fn ceiling_is_above_probe() {
+        for decimals in [0u8, 2, 6, 18, 24] {
+            assert!(ceiling_amount_in(1, decimals, None) >= probe_amount_in(true, decimals, None));
+            assert!(
+                ceiling_amount_in(10, decimals, None) >= probe_amount_in(false, decimals, None)
+            );
+        }
+    }
 }
@@ -0,0 +1,60 @@
+use anyhow::Result;
+use ethers::{
+    providers::Provider,
+    types::{Transaction, H160},
+};
+use std::{collections::HashMap, sync::Arc};
+
+use crate::common::execution::Executor;
+use crate::common::pools::Pool;
+use crate::common::provider::RpcClient;
+use crate::common::streams::NewBlock;
+use crate::common::victim_lock::VictimLocks;
+use crate::sandwich::backrun::backrun_txs;
+use crate::sandwich::simulation::{SwapInfo, CURVE_VERSION};
+
+/*
+Backrun-only arbitrage of the victims we can't sandwich.
+A big victim swap often leaves its V2 pool mispriced against a sibling pool of the same pair,
+even when there's no sandwich in it (too little slippage left, a sell, a token the optimizer loses on).
+When the appetizer comes back empty for a victim, the victim is replayed on the block's fork and
+the V2 pools it moved are arbitraged against their siblings (see backrun_txs),
+sent as a [victim, backrun] bundle. BACKRUN_ARBITRAGE=false turns it off.
+*/
+pub async fn backrun_victim(
+    provider: &Arc<Provider<RpcClient>>,
+    executor: &Executor,
+    new_block: &NewBlock,
+    victim_tx: &Transaction,
+    swap_info: &[SwapInfo],
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    victim_locks: &VictimLocks,
+    bundle_target_blocks: u64,
+    debug: bool,
+) -> Result<()> {
+    let mut pairs: Vec<H160> = swap_info
+        .iter()
+        .filter(|info| info.version != CURVE_VERSION)
+        .map(|info| info.target_pair)
+        .collect();
+    pairs.sort();
+    pairs.dedup();
+    if pairs.is_empty() {
+        return Ok(());
+    }
+    backrun_txs(
+        provider,
+        executor,
+        new_block,
+        &[victim_tx.clone()],
+        &pairs,
+        pools_map,
+        pools_by_tokens,
+        victim_locks,
+        bundle_target_blocks,
+        debug,
+        "Victim",
+    )
+    .await
+}
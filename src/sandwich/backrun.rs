@@ -0,0 +1,592 @@
+use anyhow::Result;
+use eth_encode_packed::ethabi::ethereum_types::{H160 as eH160, U256 as eU256};
+use eth_encode_packed::{SolidityDataType, TakeLastXBytes};
+use ethers::providers::{Middleware, Provider};
+use ethers::signers::Signer;
+use ethers::types::{
+    transaction::eip2718::TypedTransaction, Bytes, Eip1559TransactionRequest, NameOrAddress,
+    Transaction, H160, H256, U256, U64,
+};
+use log::{info, warn};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use crate::common::chain::CHAIN;
+use crate::common::evm::{EvmSimulator, Tx, VictimTx};
+use crate::common::execution::{accepted_responses, Executor};
+use crate::common::pools::get_v2_amount_out;
+use crate::common::pools::Pool;
+use crate::common::provider::RpcClient;
+use crate::common::replacement::new_replacement_uuid;
+use crate::common::streams::{MevShareHint, NewBlock};
+use crate::common::utils::{get_token_balance_at, is_weth};
+use crate::common::victim_lock::{Claim, LockKey, StrategyKind, VictimLocks};
+
+/*
+Backrun-only path for MEV-Share hints.
+We never see the user's tx, only the logs they chose to share. When those include the
+V2 Sync/Swap event data, we know the pool's reserves after the user's swap, and can
+arbitrage the pool against another pool of the same pair: WETH -> token -> WETH.
+Hints that only reveal the pool address are skipped, since we can't price the backrun.
+If the user's tx has already landed by the time we get to it, the imbalance is on-chain
+and only the backrun is needed: it goes out as a private tx instead of an MEV-Share bundle.
+*/
+pub static V2_SWAP_EVENT: &str =
+    "0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822";
+pub static V2_SYNC_EVENT: &str =
+    "0x1c411e9a96e071241c2f21f7726b17ae89e3cab4c78be50e062b03a9fffbbad1";
+
+pub static BACKRUN_GAS_LIMIT: u64 = 250000;
+pub static BACKRUN_BRIBE_BPS: u64 = 9000; // of the profit left after gas, paid as priority fee
+
+#[derive(Debug, Clone)]
+pub struct Backrun {
+    pub buy_pool: Pool,
+    pub sell_pool: Pool,
+    pub main_currency: H160,
+    pub token: H160,
+    pub amount_in: U256,
+    pub amount_mid: U256,
+    pub amount_out: U256,
+    pub profit: U256,
+}
+
+pub fn index_pools_by_tokens(pools_map: &HashMap<H160, Pool>) -> HashMap<(H160, H160), Vec<H160>> {
+    let mut index: HashMap<(H160, H160), Vec<H160>> = HashMap::new();
+    for pool in pools_map.values() {
+        index
+            .entry(token_pair_key(pool.token0, pool.token1))
+            .or_default()
+            .push(pool.address);
+    }
+    index
+}
+
+pub fn token_pair_key(token_a: H160, token_b: H160) -> (H160, H160) {
+    if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    }
+}
+
+// Reserves of the pools touched by the user's tx, after the tx
+pub fn hinted_pair_reserves(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    hint: &MevShareHint,
+    pools_map: &HashMap<H160, Pool>,
+) -> HashMap<H160, (U256, U256)> {
+    let sync_event = H256::from_str(V2_SYNC_EVENT).unwrap();
+    let swap_event = H256::from_str(V2_SWAP_EVENT).unwrap();
+
+    let mut reserves = HashMap::new();
+    for log in hint.logs() {
+        if !pools_map.contains_key(&log.address) || log.topics.is_empty() {
+            continue;
+        }
+        let data = log.data.0.as_ref();
+        let word = |i: usize| U256::from_big_endian(&data[i * 32..(i + 1) * 32]);
+
+        if log.topics[0] == sync_event && data.len() >= 64 {
+            // Sync has the exact reserves after the swap
+            reserves.insert(log.address, (word(0), word(1)));
+        } else if log.topics[0] == swap_event && data.len() >= 128 {
+            if reserves.contains_key(&log.address) {
+                continue;
+            }
+            let (reserve0, reserve1) = match simulator.get_pair_reserves(log.address) {
+                Ok(r) => r,
+                Err(_) => continue,
+            };
+            let (amount0_in, amount1_in, amount0_out, amount1_out) =
+                (word(0), word(1), word(2), word(3));
+            reserves.insert(
+                log.address,
+                (
+                    (reserve0 + amount0_in).saturating_sub(amount0_out),
+                    (reserve1 + amount1_in).saturating_sub(amount1_out),
+                ),
+            );
+        }
+    }
+    reserves
+}
+
+// WETH -> token on the first pool, token -> WETH on the second. Profit is concave, so ternary search it
+pub fn optimize_cycle(
+    amount_in_ceiling: U256,
+    buy_reserves: (U256, U256, u32), // (reserve_mc, reserve_token, fee)
+    sell_reserves: (U256, U256, u32), // (reserve_token, reserve_mc, fee)
+) -> (U256, U256, U256, U256) {
+    let cycle = |amount_in: U256| {
+        let amount_mid =
+            get_v2_amount_out(amount_in, buy_reserves.0, buy_reserves.1, buy_reserves.2);
+        let amount_out = get_v2_amount_out(
+            amount_mid,
+            sell_reserves.0,
+            sell_reserves.1,
+            sell_reserves.2,
+        );
+        (amount_mid, amount_out, amount_out.saturating_sub(amount_in))
+    };
+
+    let mut lo = U256::zero();
+    let mut hi = amount_in_ceiling;
+    while hi - lo > U256::from(2) {
+        let third = (hi - lo) / U256::from(3);
+        let m1 = lo + third;
+        let m2 = hi - third;
+        if cycle(m1).2 < cycle(m2).2 {
+            lo = m1;
+        } else {
+            hi = m2;
+        }
+    }
+    let amount_in = (lo + hi) / U256::from(2);
+    let (amount_mid, amount_out, profit) = cycle(amount_in);
+    (amount_in, amount_mid, amount_out, profit)
+}
+
+pub fn find_backrun(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    hint: &MevShareHint,
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    amount_in_ceiling: U256,
+) -> Option<Backrun> {
+    let post_reserves = hinted_pair_reserves(simulator, hint, pools_map);
+    find_cycle(
+        simulator,
+        &post_reserves,
+        pools_map,
+        pools_by_tokens,
+        amount_in_ceiling,
+    )
+}
+
+// Best WETH -> token -> WETH cycle between a pool with the given (moved) reserves and its siblings
+pub fn find_cycle(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    post_reserves: &HashMap<H160, (U256, U256)>,
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    amount_in_ceiling: U256,
+) -> Option<Backrun> {
+    let mut best: Option<Backrun> = None;
+    for (pair, (reserve0, reserve1)) in post_reserves {
+        let pool = pools_map.get(pair).unwrap();
+        let (main_currency, token) = if is_weth(pool.token0) {
+            (pool.token0, pool.token1)
+        } else if is_weth(pool.token1) {
+            (pool.token1, pool.token0)
+        } else {
+            continue;
+        };
+        let (hinted_mc, hinted_token) = if pool.token0 == main_currency {
+            (*reserve0, *reserve1)
+        } else {
+            (*reserve1, *reserve0)
+        };
+
+        let siblings = match pools_by_tokens.get(&token_pair_key(pool.token0, pool.token1)) {
+            Some(siblings) => siblings,
+            None => continue,
+        };
+        for sibling in siblings {
+            if sibling == pair {
+                continue;
+            }
+            let sibling_pool = pools_map.get(sibling).unwrap();
+            let (sibling_mc, sibling_token) = match simulator.get_pair_reserves(*sibling) {
+                Ok((r0, r1)) if sibling_pool.token0 == main_currency => (r0, r1),
+                Ok((r0, r1)) => (r1, r0),
+                Err(_) => continue,
+            };
+
+            // the user's swap moved the price of the hinted pool, so try both directions
+            let directions = vec![
+                (
+                    pool,
+                    sibling_pool,
+                    (hinted_mc, hinted_token, pool.fee),
+                    (sibling_token, sibling_mc, sibling_pool.fee),
+                ),
+                (
+                    sibling_pool,
+                    pool,
+                    (sibling_mc, sibling_token, sibling_pool.fee),
+                    (hinted_token, hinted_mc, pool.fee),
+                ),
+            ];
+            for (buy_pool, sell_pool, buy_reserves, sell_reserves) in directions {
+                let (amount_in, amount_mid, amount_out, profit) =
+                    optimize_cycle(amount_in_ceiling, buy_reserves, sell_reserves);
+                if profit > best.as_ref().map(|b| b.profit).unwrap_or_default() {
+                    best = Some(Backrun {
+                        buy_pool: *buy_pool,
+                        sell_pool: *sell_pool,
+                        main_currency,
+                        token,
+                        amount_in,
+                        amount_mid,
+                        amount_out,
+                        profit,
+                    });
+                }
+            }
+        }
+    }
+    best
+}
+
+impl Backrun {
+    pub async fn to_tx(
+        &self,
+        executor: &Executor,
+        target_block: U64,
+        base_fee: U256,
+        priority_fee_per_gas: U256,
+    ) -> Result<TypedTransaction> {
+        let calldata = self.encode_calldata(target_block)?;
+        let owner = executor.owner.address();
+        let chain_id = U64::from(executor.chain_id);
+        let nonce = executor.bundle_nonce().await?;
+        Ok(TypedTransaction::Eip1559(Eip1559TransactionRequest {
+            to: Some(NameOrAddress::Address(executor.bot_address)),
+            from: Some(owner),
+            data: Some(calldata),
+            value: Some(U256::zero()),
+            chain_id: Some(chain_id),
+            max_priority_fee_per_gas: Some(priority_fee_per_gas),
+            max_fee_per_gas: Some(base_fee + priority_fee_per_gas),
+            gas: Some(U256::from(BACKRUN_GAS_LIMIT)),
+            nonce: Some(nonce),
+            access_list: Default::default(),
+        }))
+    }
+
+    // Same packed format as the sandwich txs: blockNumber + (zeroForOne, pair, tokenIn, amountIn, amountOut) per swap
+    pub fn encode_calldata(&self, block_number: U64) -> Result<Bytes> {
+        let block_number_u256 = eU256::from_dec_str(&block_number.to_string())?;
+        let mut swap_params = vec![SolidityDataType::NumberWithShift(
+            block_number_u256,
+            TakeLastXBytes(64),
+        )];
+
+        let swaps = vec![
+            (
+                &self.buy_pool,
+                self.main_currency,
+                self.amount_in,
+                self.amount_mid,
+            ),
+            (
+                &self.sell_pool,
+                self.token,
+                self.amount_mid,
+                self.amount_out,
+            ),
+        ];
+        for (pool, token_in, amount_in, amount_out) in swaps {
+            let zero_for_one = pool.token0 == token_in;
+            swap_params.extend(vec![
+                SolidityDataType::NumberWithShift(
+                    eU256::from(zero_for_one as u8),
+                    TakeLastXBytes(8),
+                ),
+                SolidityDataType::Address(eH160::from_str(&format!("{:?}", pool.address))?),
+                SolidityDataType::Address(eH160::from_str(&format!("{:?}", token_in))?),
+                SolidityDataType::NumberWithShift(
+                    eU256::from_dec_str(&amount_in.to_string())?,
+                    TakeLastXBytes(256),
+                ),
+                SolidityDataType::NumberWithShift(
+                    eU256::from_dec_str(&amount_out.to_string())?,
+                    TakeLastXBytes(256),
+                ),
+            ]);
+        }
+
+        let calldata = eth_encode_packed::abi::encode_packed(&swap_params);
+        Ok(Bytes::from_str(&calldata.1).unwrap_or_default())
+    }
+}
+
+pub async fn backrun_hint(
+    provider: &Arc<Provider<RpcClient>>,
+    executor: &Executor,
+    new_block: &NewBlock,
+    hint: &MevShareHint,
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    victim_locks: &VictimLocks,
+    bundle_target_blocks: u64,
+    debug: bool,
+) -> Result<()> {
+    let mut simulator = EvmSimulator::new(provider.clone(), None, new_block.block_number);
+
+    let weth = CHAIN.weth.address;
+    let amount_in_ceiling = get_token_balance_at(
+        provider.clone(),
+        executor.bot_address,
+        weth,
+        new_block.block_number,
+    )
+    .await?;
+
+    let backrun = match find_backrun(
+        &mut simulator,
+        hint,
+        pools_map,
+        pools_by_tokens,
+        amount_in_ceiling,
+    ) {
+        Some(backrun) => backrun,
+        None => return Ok(()),
+    };
+
+    let base_fee = new_block.next_base_fee;
+    let gas_cost = base_fee * U256::from(BACKRUN_GAS_LIMIT);
+    if backrun.profit <= gas_cost {
+        return Ok(());
+    }
+    let bribe = (backrun.profit - gas_cost) * U256::from(BACKRUN_BRIBE_BPS) / U256::from(10000);
+    let priority_fee_per_gas = bribe / U256::from(BACKRUN_GAS_LIMIT);
+
+    info!(
+        "🏃 MEV-Share backrun [{:?}] {:?} -> {:?} / Amount in: {:?} / Profit: {:?} / Bribe: {:?}",
+        hint.hash,
+        backrun.buy_pool.address,
+        backrun.sell_pool.address,
+        backrun.amount_in,
+        backrun.profit,
+        bribe
+    );
+
+    let target_block = new_block.block_number + U64::from(1);
+    let lock_keys = [
+        LockKey::Victim(hint.hash),
+        LockKey::Pool(backrun.buy_pool.address),
+        LockKey::Pool(backrun.sell_pool.address),
+    ];
+    // MEV-Share bundles and private txs can't be cancelled, a sandwich can't take this one over
+    let claim = Claim {
+        strategy: StrategyKind::Backrun,
+        target_block,
+        value: backrun.profit - gas_cost - bribe,
+        replacement_uuid: None,
+    };
+    let taken_over = match victim_locks.try_claim(&lock_keys, &claim) {
+        Some(taken_over) => taken_over,
+        None => return Ok(()),
+    };
+    if debug {
+        return Ok(());
+    }
+    for uuid in &taken_over {
+        executor
+            .cancel_bundle_variants(uuid, bundle_target_blocks)
+            .await;
+    }
+
+    let backrun_tx = backrun
+        .to_tx(executor, target_block, base_fee, priority_fee_per_gas)
+        .await?;
+
+    // a bundle referencing an already included tx can't land
+    let user_tx_landed = match provider.get_transaction_receipt(hint.hash).await {
+        Ok(receipt) => receipt.is_some(),
+        Err(_) => false,
+    };
+    if user_tx_landed {
+        // valid in target_block only, like its calldata
+        match executor.send_private_tx(backrun_tx, target_block).await {
+            Ok((tx_hash, builders)) => info!(
+                "Private backrun tx sent: {:?} / Accepted by: {:?}",
+                tx_hash, builders
+            ),
+            Err(e) => warn!("send_private_tx error: {e:?}"),
+        }
+        return Ok(());
+    }
+
+    // the calldata names target_block, the contract reverts in any later one
+    match executor
+        .send_mev_share_backrun(hint.hash, backrun_tx, target_block, target_block)
+        .await
+    {
+        Ok(response) => info!("MEV-Share bundle sent: {:?}", response),
+        Err(e) => warn!("send_mev_share_backrun error: {e:?}"),
+    }
+
+    Ok(())
+}
+
+/*
+Backrun the frontrunner: when another bot is already frontrunning our victim,
+we don't race it for the top of the block. Instead we bundle its frontrun and the victim as they are,
+and arbitrage the pool they both pushed against a sibling pool, which takes the profit
+the frontrunner's own backrun was going to make.
+*/
+pub async fn backrun_frontrunner(
+    provider: &Arc<Provider<RpcClient>>,
+    executor: &Executor,
+    new_block: &NewBlock,
+    frontrun_tx: &Transaction,
+    victim_tx: &Transaction,
+    pair: H160,
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    victim_locks: &VictimLocks,
+    bundle_target_blocks: u64,
+    debug: bool,
+) -> Result<()> {
+    backrun_txs(
+        provider,
+        executor,
+        new_block,
+        &[frontrun_tx.clone(), victim_tx.clone()],
+        &[pair],
+        pools_map,
+        pools_by_tokens,
+        victim_locks,
+        bundle_target_blocks,
+        debug,
+        "Frontrunner",
+    )
+    .await
+}
+
+/*
+Replays the txs (the victim last) on top of the block, and arbitrages the given pairs they moved
+against their sibling pools with the best WETH -> token -> WETH cycle.
+The txs and the backrun go out together as one bundle, the victim is locked for the target block.
+*/
+pub async fn backrun_txs(
+    provider: &Arc<Provider<RpcClient>>,
+    executor: &Executor,
+    new_block: &NewBlock,
+    txs: &[Transaction],
+    pairs: &[H160],
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    victim_locks: &VictimLocks,
+    bundle_target_blocks: u64, // of the sandwiches a backrun takes over, to cancel all their variants
+    debug: bool,
+    label: &str,
+) -> Result<()> {
+    let victim_hash = match txs.last() {
+        Some(victim_tx) => victim_tx.hash,
+        None => return Ok(()),
+    };
+    let mut simulator = EvmSimulator::new(provider.clone(), None, new_block.block_number);
+
+    let base_fee = new_block.next_base_fee;
+    for tx in txs {
+        let gas_price = match tx.max_fee_per_gas {
+            Some(max_fee) => std::cmp::min(
+                max_fee,
+                base_fee + tx.max_priority_fee_per_gas.unwrap_or_default(),
+            ),
+            None => tx.gas_price.unwrap_or_default(),
+        };
+        let tx = VictimTx {
+            tx_hash: tx.hash,
+            from: tx.from,
+            to: tx.to.unwrap_or_default(),
+            data: tx.input.0.clone().into(),
+            value: tx.value,
+            gas_price,
+            gas_limit: Some(tx.gas.as_u64()),
+        };
+        simulator.fund_caller(&tx)?;
+        simulator.call(Tx::from(tx))?;
+    }
+    let mut post_reserves = HashMap::new();
+    for pair in pairs {
+        if pools_map.contains_key(pair) {
+            post_reserves.insert(*pair, simulator.get_pair_reserves(*pair)?);
+        }
+    }
+
+    let weth = CHAIN.weth.address;
+    let amount_in_ceiling = get_token_balance_at(
+        provider.clone(),
+        executor.bot_address,
+        weth,
+        new_block.block_number,
+    )
+    .await?;
+
+    let backrun = match find_cycle(
+        &mut simulator,
+        &post_reserves,
+        pools_map,
+        pools_by_tokens,
+        amount_in_ceiling,
+    ) {
+        Some(backrun) => backrun,
+        None => return Ok(()),
+    };
+
+    let gas_cost = base_fee * U256::from(BACKRUN_GAS_LIMIT);
+    if backrun.profit <= gas_cost {
+        return Ok(());
+    }
+    let bribe = (backrun.profit - gas_cost) * U256::from(BACKRUN_BRIBE_BPS) / U256::from(10000);
+    let priority_fee_per_gas = bribe / U256::from(BACKRUN_GAS_LIMIT);
+
+    let tx_hashes: Vec<H256> = txs.iter().map(|tx| tx.hash).collect();
+    info!(
+        "🏃 {} backrun {:?} {:?} -> {:?} / Amount in: {:?} / Profit: {:?} / Bribe: {:?}",
+        label,
+        tx_hashes,
+        backrun.buy_pool.address,
+        backrun.sell_pool.address,
+        backrun.amount_in,
+        backrun.profit,
+        bribe
+    );
+
+    let target_block = new_block.block_number + U64::from(1);
+    let lock_keys = [
+        LockKey::Victim(victim_hash),
+        LockKey::Pool(backrun.buy_pool.address),
+        LockKey::Pool(backrun.sell_pool.address),
+    ];
+    let replacement_uuid = new_replacement_uuid();
+    let claim = Claim {
+        strategy: StrategyKind::Backrun,
+        target_block,
+        value: backrun.profit - gas_cost - bribe,
+        replacement_uuid: Some(replacement_uuid.clone()),
+    };
+    let taken_over = match victim_locks.try_claim(&lock_keys, &claim) {
+        Some(taken_over) => taken_over,
+        None => return Ok(()),
+    };
+    if debug {
+        return Ok(());
+    }
+    for uuid in &taken_over {
+        executor
+            .cancel_bundle_variants(uuid, bundle_target_blocks)
+            .await;
+    }
+
+    let backrun_tx = backrun
+        .to_tx(executor, target_block, base_fee, priority_fee_per_gas)
+        .await?;
+    let bundle_request = executor
+        .to_backrun_bundle_request(txs.to_vec(), backrun_tx, target_block)
+        .await?;
+    let results = executor
+        .broadcast_bundle(bundle_request, Some(replacement_uuid), None, None)
+        .await?;
+    info!(
+        "{} backrun bundle sent: {:?}",
+        label,
+        accepted_responses(&results)
+    );
+
+    Ok(())
+}
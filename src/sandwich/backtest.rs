@@ -0,0 +1,287 @@
+use anyhow::{anyhow, Result};
+use ethers::{
+    providers::{Middleware, Provider},
+    types::{H160, H256, U256, U64},
+};
+use log::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::common::chain::CHAIN;
+use crate::common::constants::Env;
+use crate::common::evm::EvmSimulator;
+use crate::common::failures::FailureStats;
+use crate::common::latency::now_ms;
+use crate::common::pool_store::PoolStore;
+use crate::common::pools::{
+    detect_v2_pool_fees, load_all_pools, load_curve_pools, CurvePool, Pool,
+};
+use crate::common::profile::OperatingProfile;
+use crate::common::provider::{RpcClient, RpcPreset};
+use crate::common::streams::{NewBlock, NewPendingTx};
+use crate::common::token_lists::TokenLists;
+use crate::common::tokens::load_all_tokens;
+use crate::sandwich::appetizer::appetizer;
+use crate::sandwich::simulation::{extract_swap_info, PendingTxInfo};
+use crate::sandwich::victims::VictimBudget;
+
+/*
+Backtesting over historical blocks (sandooo backtest --from <block> --to <block>),
+to see what a parameter change would have made before running it live.
+For every block in the range, its txs stand in for the mempool we would have seen right after the parent block:
+each one goes through extract_swap_info, the appetizer and the optimizer on top of the parent block's state,
+with the pools, token lists, victim budget and operating profile the bot would run with.
+Needs an archive node: debug_traceCall and state at past blocks.
+The PnL is hypothetical: every promising sandwich is assumed to land alone, right before its victim,
+paying the profile's bribe. No competition, no batching, no ordering within the block.
+*/
+#[derive(Debug, Clone)]
+pub struct BacktestSandwich {
+    pub block_number: U64, // the block the victim landed in
+    pub victim_tx_hash: H256,
+    pub pair: H160,
+    pub main_currency: H160,
+    pub amount_in: U256,
+    pub revenue: U256, // after gas, before the bribe
+    pub bribe: U256,
+    pub profit: U256, // revenue - bribe
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BacktestReport {
+    pub blocks: u64,
+    pub txs: u64,
+    pub victims: u64, // txs touching at least one pool we know
+    pub sandwiches: Vec<BacktestSandwich>,
+}
+
+impl BacktestReport {
+    pub fn revenue(&self) -> U256 {
+        self.sandwiches
+            .iter()
+            .fold(U256::zero(), |acc, s| acc + s.revenue)
+    }
+
+    pub fn bribes(&self) -> U256 {
+        self.sandwiches
+            .iter()
+            .fold(U256::zero(), |acc, s| acc + s.bribe)
+    }
+
+    pub fn profit(&self) -> U256 {
+        self.sandwiches
+            .iter()
+            .fold(U256::zero(), |acc, s| acc + s.profit)
+    }
+
+    pub fn pretty_print(&self) {
+        info!("\n[🧪 Backtest]");
+        info!("- Blocks: {:?}", self.blocks);
+        info!("- Txs: {:?} / Victims: {:?}", self.txs, self.victims);
+        info!("- Sandwiches: {:?}", self.sandwiches.len());
+        info!("- Revenue: {:?}", self.revenue());
+        info!("- Bribes: {:?}", self.bribes());
+        info!("- Profit: {:?}", self.profit());
+    }
+}
+
+pub async fn run_backtest(
+    provider: Arc<Provider<RpcClient>>,
+    from_block: u64,
+    to_block: u64,
+) -> Result<BacktestReport> {
+    if from_block == 0 || from_block > to_block {
+        return Err(anyhow!("Invalid block range {} - {}", from_block, to_block));
+    }
+    let env = Env::new();
+    let profile = OperatingProfile::from_env(&env);
+
+    let (pools, prev_pool_id) = load_all_pools(
+        env.rpc_url(),
+        CHAIN.pool_sync_from_block,
+        RpcPreset::from_env(&env).log_chunk_size(),
+    )
+    .await?;
+    let block_number = provider.get_block_number().await?;
+    let tokens_map = load_all_tokens(&provider, block_number, &pools, prev_pool_id).await?;
+
+    // same pool filter as the strategy
+    let mut pools_map: HashMap<H160, Pool> = pools
+        .into_iter()
+        .filter(
+            |p| match (tokens_map.get(&p.token0), tokens_map.get(&p.token1)) {
+                (Some(token0), Some(token1)) => {
+                    token0.metadata.is_sandwichable() && token1.metadata.is_sandwichable()
+                }
+                _ => false,
+            },
+        )
+        .map(|p| (p.address, p))
+        .collect();
+    let curve_pools_map: HashMap<H160, CurvePool> = match load_curve_pools(&provider).await {
+        Ok(curve_pools) => curve_pools.into_iter().map(|p| (p.address, p)).collect(),
+        Err(e) => {
+            warn!("load_curve_pools error: {e:?}");
+            HashMap::new()
+        }
+    };
+    let token_lists = TokenLists::load(&env, &tokens_map);
+    let victim_budget = VictimBudget::new(&env);
+    let mut pool_store = PoolStore::open(&env)?;
+    let mut fee_checked: HashSet<H160> = HashSet::new();
+    let mut failures = FailureStats::new();
+
+    let mut report = BacktestReport::default();
+
+    for number in from_block..=to_block {
+        let parent = match provider.get_block(number - 1).await? {
+            Some(parent) => parent,
+            None => return Err(anyhow!("Block #{} not found", number - 1)),
+        };
+        let block = match provider.get_block_with_txs(number).await? {
+            Some(block) => block,
+            None => return Err(anyhow!("Block #{} not found", number)),
+        };
+        let mut new_block = match NewBlock::from_block(&parent) {
+            Some(new_block) => new_block,
+            None => continue,
+        };
+        // we know the base fee the victims actually paid
+        new_block.next_base_fee = block.base_fee_per_gas.unwrap_or(new_block.next_base_fee);
+
+        let mut pending_txs: HashMap<H256, PendingTxInfo> = HashMap::new();
+        let mut promising_sandwiches = HashMap::new();
+
+        for tx in block.transactions {
+            report.txs += 1;
+            let tx_hash = tx.hash;
+
+            let victim_gas_price = match tx.transaction_type {
+                Some(tx_type) if tx_type == U64::zero() => tx.gas_price.unwrap_or_default(),
+                Some(tx_type) if tx_type == U64::from(2) => tx.max_fee_per_gas.unwrap_or_default(),
+                _ => continue,
+            };
+            if victim_gas_price < new_block.base_fee {
+                continue;
+            }
+
+            let pending_tx = NewPendingTx {
+                added_block: Some(new_block.block_number),
+                tx,
+                hint: None,
+                first_seen_ms: now_ms(),
+            };
+            let mut swap_info = match extract_swap_info(
+                &provider,
+                &new_block,
+                &pending_tx,
+                &pools_map,
+                &curve_pools_map,
+                &victim_budget,
+                &token_lists,
+            )
+            .await
+            {
+                Ok(swap_info) => swap_info,
+                Err(e) => {
+                    failures.record_error(&e);
+                    warn!("extract_swap_info error: {e:?}");
+                    continue;
+                }
+            };
+            swap_info.retain(|info| profile.allows(info, &tokens_map));
+            if swap_info.is_empty() {
+                continue;
+            }
+            report.victims += 1;
+
+            let unchecked_pairs: Vec<H160> = swap_info
+                .iter()
+                .map(|info| info.target_pair)
+                .filter(|pair| pools_map.contains_key(pair) && !fee_checked.contains(pair))
+                .collect();
+            if !unchecked_pairs.is_empty() {
+                let mut simulator =
+                    EvmSimulator::new(provider.clone(), None, new_block.block_number);
+                detect_v2_pool_fees(
+                    &mut simulator,
+                    &mut pools_map,
+                    &unchecked_pairs,
+                    &mut pool_store,
+                );
+                fee_checked.extend(unchecked_pairs);
+            }
+            for info in swap_info.iter_mut() {
+                if let Some(pool) = pools_map.get(&info.target_pair) {
+                    info.fee = pool.fee;
+                }
+            }
+
+            pending_txs.insert(
+                tx_hash,
+                PendingTxInfo {
+                    pending_tx,
+                    touched_pairs: swap_info,
+                    traced_ms: now_ms(),
+                },
+            );
+            match appetizer(
+                &provider,
+                &new_block,
+                tx_hash,
+                victim_gas_price,
+                &pending_txs,
+                &mut promising_sandwiches,
+                &mut failures,
+                &token_lists,
+            )
+            .await
+            {
+                Err(e) => warn!("appetizer error: {e:?}"),
+                _ => {}
+            }
+        }
+
+        let mut block_sandwiches = 0;
+        let mut block_profit = U256::zero();
+        for (tx_hash, sandwiches) in &promising_sandwiches {
+            for sandwich in sandwiches {
+                let optimized = match &sandwich.optimized_sandwich {
+                    Some(optimized) => optimized,
+                    None => continue,
+                };
+                let revenue = optimized.max_revenue;
+                let bribe = (revenue * profile.bribe_pct) / U256::from(10000);
+                let profit = revenue - bribe;
+                if profit < profile.min_profit {
+                    continue;
+                }
+                block_sandwiches += 1;
+                block_profit += profit;
+                report.sandwiches.push(BacktestSandwich {
+                    block_number: U64::from(number),
+                    victim_tx_hash: *tx_hash,
+                    pair: sandwich.swap_info.target_pair,
+                    main_currency: sandwich.swap_info.main_currency,
+                    amount_in: optimized.amount_in,
+                    revenue,
+                    bribe,
+                    profit,
+                });
+            }
+        }
+        report.blocks += 1;
+        info!(
+            "▶️ Block #{}: {} sandwiches / Profit: {:?}",
+            number, block_sandwiches, block_profit
+        );
+    }
+
+    if !failures.counts.is_empty() {
+        info!("Simulation failures:\n{}", failures.summary());
+    }
+
+    Ok(report)
+}
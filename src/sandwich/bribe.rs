@@ -0,0 +1,260 @@
+use anyhow::Result;
+use ethers::types::{H160, U256};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::constants::Env;
+use crate::common::tracker::{BundleStatus, TrackedBundle};
+
+/*
+How much of the sandwich revenue goes to the builder (BRIBE_POLICY):
+- fixed: the operating profile's bribe_pct, as always
+- adaptive: learned from our own included sandwiches, stored in BRIBE_HISTORY_PATH
+  1. per pair, the median bribe of the last BRIBE_HISTORY_SIZE sandwiches that landed,
+     taking the highest one when a bundle touches several pairs (we have to win all of them)
+  2. undercut by BRIBE_UNDERCUT_BPS, to probe whether less would still win,
+     as long as the last bundle on these pairs didn't miss
+  3. raised by BRIBE_MISS_RAISE_BPS for every bundle in a row that missed on one of the pairs,
+     so a too low bribe is walked back up, and the win it gets is what goes into the history
+  4. adjusted for the time of day: how the current UTC hour's winning bribes compare to all of them
+  5. clamped to [BRIBE_FLOOR_BPS, BRIBE_CEILING_BPS]
+  Pairs without history start from the operating profile's bribe_pct, only raised after misses.
+When a sandwich bot is after the same pools (COMPETITOR_POLICY=outbid, see competitors.rs),
+the bribe goes COMPETITOR_BRIBE_BUMP_BPS over that, and at least to the median of what won
+contested blocks on these pools before. Contested wins are kept apart, they'd push up the uncontested bribes.
+*/
+pub static BRIBE_HISTORY_SIZE: usize = 20;
+pub static BRIBE_UNDERCUT_BPS: i64 = 50;
+pub static BRIBE_MISS_RAISE_BPS: i64 = 100;
+// An hour's adjustment is only trusted after this many wins in that hour
+pub static BRIBE_MIN_HOURLY_SAMPLES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BribeMode {
+    Fixed,
+    Adaptive,
+}
+
+impl BribeMode {
+    pub fn parse(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "adaptive" => BribeMode::Adaptive,
+            _ => BribeMode::Fixed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BribeHistory {
+    pub by_pair: HashMap<H160, VecDeque<u64>>, // pair -> winning bribes (bps), oldest first
+    pub by_hour: HashMap<u64, VecDeque<u64>>,  // UTC hour -> winning bribes (bps), oldest first
+    #[serde(default)]
+    pub miss_streaks: HashMap<H160, u64>, // pair -> bundles on it that missed in a row
+    #[serde(default)]
+    pub contested: HashMap<H160, VecDeque<u64>>, // pair -> bribes (bps) that won against a bot, oldest first
+}
+
+#[derive(Debug)]
+pub struct BribePolicy {
+    pub mode: BribeMode,
+    pub floor_bps: u64,
+    pub ceiling_bps: u64,
+    pub history_path: String,
+    pub history: BribeHistory,
+    pub sent: HashMap<String, (Vec<H160>, u64, bool)>, // bundle id -> (pairs, bribe bps, contested)
+}
+
+fn median(samples: &VecDeque<u64>) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<u64> = samples.iter().cloned().collect();
+    sorted.sort();
+    Some(sorted[sorted.len() / 2])
+}
+
+fn push_sample(samples: &mut VecDeque<u64>, bps: u64) {
+    samples.push_back(bps);
+    while samples.len() > BRIBE_HISTORY_SIZE {
+        samples.pop_front();
+    }
+}
+
+pub fn utc_hour() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    (now / 3600) % 24
+}
+
+impl BribePolicy {
+    pub fn new(env: &Env) -> Self {
+        let mut policy = Self {
+            mode: BribeMode::parse(&env.bribe_policy),
+            floor_bps: env.bribe_floor_bps.min(10000),
+            ceiling_bps: env.bribe_ceiling_bps.min(10000),
+            history_path: env.bribe_history_path.clone(),
+            history: BribeHistory::default(),
+            sent: HashMap::new(),
+        };
+        if policy.mode == BribeMode::Adaptive && Path::new(&policy.history_path).exists() {
+            match policy.load() {
+                Ok(_) => info!(
+                    "Loaded bribe history of {} pairs from {}",
+                    policy.history.by_pair.len(),
+                    policy.history_path
+                ),
+                Err(e) => warn!("BribePolicy.load error: {e:?}"),
+            }
+        }
+        policy
+    }
+
+    // Settings from a reloaded env, the history is kept
+    pub fn reconfigure(&mut self, env: &Env) {
+        self.mode = BribeMode::parse(&env.bribe_policy);
+        self.floor_bps = env.bribe_floor_bps.min(10000);
+        self.ceiling_bps = env.bribe_ceiling_bps.min(10000);
+    }
+
+    fn load(&mut self) -> Result<()> {
+        self.history = serde_json::from_str(&fs::read_to_string(&self.history_path)?)?;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        if self.history_path.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = Path::new(&self.history_path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.history_path, serde_json::to_string(&self.history)?)?;
+        Ok(())
+    }
+
+    // Bribe in bps of the revenue for a bundle on these pairs, default_bps is the operating profile's
+    pub fn bribe_pct(&self, pairs: &[H160], default_bps: U256) -> U256 {
+        if self.mode == BribeMode::Fixed {
+            return default_bps;
+        }
+        let learned = pairs
+            .iter()
+            .filter_map(|pair| self.history.by_pair.get(pair).and_then(median))
+            .max();
+        let miss_streak = pairs
+            .iter()
+            .filter_map(|pair| self.history.miss_streaks.get(pair))
+            .max()
+            .cloned()
+            .unwrap_or(0);
+        let (learned, undercut) = match (learned, miss_streak) {
+            (Some(bps), 0) => (bps as i64, BRIBE_UNDERCUT_BPS),
+            (Some(bps), _) => (bps as i64, 0),
+            (None, 0) => return default_bps,
+            (None, _) => (default_bps.as_u64() as i64, 0),
+        };
+
+        let all_hours: VecDeque<u64> = self.history.by_hour.values().flatten().cloned().collect();
+        let hour_adjustment = match (self.history.by_hour.get(&utc_hour()), median(&all_hours)) {
+            (Some(hour), Some(overall)) if hour.len() >= BRIBE_MIN_HOURLY_SAMPLES => {
+                median(hour).unwrap_or(overall) as i64 - overall as i64
+            }
+            _ => 0,
+        };
+
+        let bps = learned - undercut + miss_streak as i64 * BRIBE_MISS_RAISE_BPS + hour_adjustment;
+        let bps = bps.clamp(
+            self.floor_bps as i64,
+            self.ceiling_bps.max(self.floor_bps) as i64,
+        );
+        U256::from(bps as u64)
+    }
+
+    /*
+    Bribe for a bundle a sandwich bot is after, bribe_bps being what it would get uncontested.
+    None when outbidding the bot goes over the ceiling: the bundle is skipped.
+    */
+    pub fn contested_bribe_pct(
+        &self,
+        pairs: &[H160],
+        bribe_bps: U256,
+        bump_bps: u64,
+    ) -> Option<U256> {
+        let learned = pairs
+            .iter()
+            .filter_map(|pair| self.history.contested.get(pair).and_then(median))
+            .max()
+            .unwrap_or(0);
+        let bps = std::cmp::max(bribe_bps.as_u64() + bump_bps, learned);
+        if bps > self.ceiling_bps.max(self.floor_bps) {
+            return None;
+        }
+        Some(U256::from(bps))
+    }
+
+    pub fn on_sent(&mut self, bundle_id: &str, pairs: Vec<H160>, bribe_pct: U256, contested: bool) {
+        if self.mode == BribeMode::Fixed {
+            return;
+        }
+        self.sent.insert(
+            bundle_id.to_string(),
+            (pairs, bribe_pct.as_u64(), contested),
+        );
+    }
+
+    // Called with every bundle the tracker resolves: included ones teach us a winning bribe, missed ones raise the next
+    pub fn on_resolved(&mut self, bundle: &TrackedBundle) {
+        let (pairs, bps, contested) = match self.sent.remove(&bundle.bundle_id) {
+            Some(sent) => sent,
+            None => return,
+        };
+        if contested {
+            info!(
+                "Contested bundle {} at {} bps: {:?}",
+                bundle.bundle_id, bps, bundle.status
+            );
+        }
+        match bundle.status {
+            BundleStatus::Included => {
+                for pair in &pairs {
+                    self.history.miss_streaks.remove(pair);
+                }
+            }
+            BundleStatus::Missed => {
+                for pair in pairs {
+                    *self.history.miss_streaks.entry(pair).or_default() += 1;
+                }
+                match self.save() {
+                    Err(e) => warn!("BribePolicy.save error: {e:?}"),
+                    _ => {}
+                }
+                return;
+            }
+            _ => return,
+        }
+        if contested {
+            for pair in pairs {
+                push_sample(self.history.contested.entry(pair).or_default(), bps);
+            }
+            match self.save() {
+                Err(e) => warn!("BribePolicy.save error: {e:?}"),
+                _ => {}
+            }
+            return;
+        }
+        for pair in pairs {
+            push_sample(self.history.by_pair.entry(pair).or_default(), bps);
+        }
+        push_sample(self.history.by_hour.entry(utc_hour()).or_default(), bps);
+        match self.save() {
+            Err(e) => warn!("BribePolicy.save error: {e:?}"),
+            _ => {}
+        }
+    }
+}
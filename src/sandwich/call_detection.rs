@@ -0,0 +1,265 @@
+use anyhow::Result;
+use ethers::abi::parse_abi;
+use ethers::prelude::{BaseContract, Lazy};
+use ethers::providers::{call_raw::RawCall, Provider};
+use ethers::types::{spoof, Bytes, Transaction, TransactionRequest, H160, H256};
+use log::info;
+use std::{collections::HashMap, str::FromStr, sync::Arc, sync::Mutex};
+
+use crate::common::abi::Abi;
+use crate::common::chain::CHAIN;
+use crate::common::pools::Pool;
+use crate::common::provider::RpcClient;
+use crate::common::streams::{NewBlock, NewPendingTx};
+use crate::common::token_lists::TokenLists;
+use crate::common::utils::return_main_and_target_currency;
+use crate::sandwich::backrun::token_pair_key;
+use crate::sandwich::liquidity::drop_illiquid_victim;
+use crate::sandwich::router_decoder::{decode_router_swaps, skip_by_calldata, DecodedPath};
+use crate::sandwich::routes::{assign_routes, RouteHop};
+use crate::sandwich::simulation::{SwapDirection, SwapInfo};
+use crate::sandwich::victims::{classify_victim_tx, VictimBudget};
+
+/*
+Victim detection for nodes without debug_traceCall (most hosted RPCs),
+picked automatically when the capability probe at startup doesn't find it.
+Instead of reading the Swap events off a trace:
+- the touched pairs come from the calldata (see router_decoder.rs): the V2 hops of routers and
+  the Universal Router, and the pairs of 1inch unoswap, each hop mapped to the pool we know for it
+- an eth_call of the victim on top of the block, with the sender's balance overridden to cover its value,
+  tells whether the swap goes through at all
+A token pair with pools on several factories is resolved through the router's factory when it has a getter
+(factory() / factoryV2()), otherwise every one of them is kept: a pool the victim doesn't trade on
+just simulates to no profit. Opaque aggregator routes, and calldata we can't read, are missed.
+The price impact of each hop isn't known this way, so a multi-hop route is sandwiched on its final hop.
+*/
+pub static NATIVE_TOKEN_PLACEHOLDER: &str = "0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE";
+
+// Router -> its V2 factory, None: the router has no getter we know
+pub static ROUTER_FACTORIES: Lazy<Mutex<HashMap<H160, Option<H160>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+pub fn native_to_wrapped(token: H160) -> H160 {
+    if token.is_zero() || token == H160::from_str(NATIVE_TOKEN_PLACEHOLDER).unwrap() {
+        CHAIN.wrapped_native().address
+    } else {
+        token
+    }
+}
+
+async fn eth_call(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    to: H160,
+    data: Bytes,
+) -> Result<Bytes> {
+    let tx = TransactionRequest::default().to(to).data(data).into();
+    Ok(provider
+        .call_raw(&tx)
+        .block(new_block.block_number.into())
+        .await?)
+}
+
+async fn router_factory(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    router: H160,
+) -> Option<H160> {
+    if let Some(factory) = ROUTER_FACTORIES.lock().unwrap().get(&router) {
+        return *factory;
+    }
+    let getters = BaseContract::from(
+        parse_abi(&[
+            "function factory() external view returns (address)",
+            "function factoryV2() external view returns (address)",
+        ])
+        .unwrap(),
+    );
+    let mut factory = None;
+    for getter in ["factory", "factoryV2"] {
+        let calldata = getters.encode(getter, ()).unwrap();
+        if let Ok(output) = eth_call(provider, new_block, router, calldata).await {
+            if let Ok(address) = getters.decode_output::<H160, _>(getter, output) {
+                factory = Some(address);
+                break;
+            }
+        }
+    }
+    ROUTER_FACTORIES.lock().unwrap().insert(router, factory);
+    factory
+}
+
+// The pools a V2 hop from token_in to token_out can go through
+async fn resolve_pairs(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    router: H160,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    token_in: H160,
+    token_out: H160,
+) -> Vec<H160> {
+    let candidates = pools_by_tokens
+        .get(&token_pair_key(token_in, token_out))
+        .cloned()
+        .unwrap_or_default();
+    if candidates.len() < 2 {
+        return candidates;
+    }
+    if let Some(factory) = router_factory(provider, new_block, router).await {
+        let abi = Abi::new();
+        if let Ok(calldata) = abi.factory.encode("getPair", (token_in, token_out)) {
+            if let Ok(output) = eth_call(provider, new_block, factory, calldata).await {
+                if let Ok(pair) = abi.factory.decode_output::<H160, _>("getPair", output) {
+                    if candidates.contains(&pair) {
+                        return vec![pair];
+                    }
+                }
+            }
+        }
+    }
+    candidates
+}
+
+// Whether the victim goes through on top of the block, with enough balance for its value
+async fn victim_succeeds(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    tx: &Transaction,
+) -> bool {
+    let to = match tx.to {
+        Some(to) => to,
+        None => return false,
+    };
+    let mut state = spoof::state();
+    state.account(tx.from).balance(tx.value);
+    let request = TransactionRequest::default()
+        .from(tx.from)
+        .to(to)
+        .value(tx.value)
+        .data(tx.input.clone())
+        .gas(tx.gas)
+        .into();
+    provider
+        .call_raw(&request)
+        .state(&state)
+        .block(new_block.block_number.into())
+        .await
+        .is_ok()
+}
+
+fn swap_info_for_hop(
+    tx_hash: H256,
+    pools_map: &HashMap<H160, Pool>,
+    pair: H160,
+    token_in: H160,
+) -> Option<SwapInfo> {
+    let pool = pools_map.get(&pair)?;
+    let (main_currency, target_token) = return_main_and_target_currency(pool.token0, pool.token1)?;
+    let direction = if token_in == main_currency {
+        SwapDirection::Buy
+    } else {
+        SwapDirection::Sell
+    };
+    Some(SwapInfo {
+        tx_hash,
+        target_pair: pair,
+        main_currency,
+        target_token,
+        version: 2,
+        fee: pool.fee,
+        token0_is_main: main_currency == pool.token0,
+        direction,
+        curve_indices: None,
+        route: RouteHop::single(0),
+    })
+}
+
+pub async fn extract_swap_info_by_call(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    pending_tx: &NewPendingTx,
+    pools_map: &HashMap<H160, Pool>,
+    pools_by_tokens: &HashMap<(H160, H160), Vec<H160>>,
+    budget: &VictimBudget,
+    token_lists: &TokenLists,
+) -> Result<Vec<SwapInfo>> {
+    let tx = &pending_tx.tx;
+    if !classify_victim_tx(tx).is_sandwichable() {
+        return Ok(Vec::new());
+    }
+    if let Some(reason) = budget.exceeded_by_tx(tx) {
+        info!("Skipping {:?}: {}", tx.hash, reason);
+        return Ok(Vec::new());
+    }
+    if let Some(reason) = skip_by_calldata(tx, pools_map) {
+        info!("Skipping {:?}: {}", tx.hash, reason);
+        return Ok(Vec::new());
+    }
+    let swaps = match decode_router_swaps(tx) {
+        Some(swaps) => swaps,
+        None => return Ok(Vec::new()),
+    };
+    let router = tx.to.unwrap_or_default();
+
+    // (pair, token in) of every hop, in the order the victim swaps
+    let mut hops = Vec::new();
+    for swap in &swaps {
+        match &swap.path {
+            DecodedPath::V2Tokens(tokens) => {
+                for hop in tokens.windows(2) {
+                    let (token_in, token_out) =
+                        (native_to_wrapped(hop[0]), native_to_wrapped(hop[1]));
+                    for pair in resolve_pairs(
+                        provider,
+                        new_block,
+                        router,
+                        pools_by_tokens,
+                        token_in,
+                        token_out,
+                    )
+                    .await
+                    {
+                        hops.push((pair, token_in));
+                    }
+                }
+            }
+            DecodedPath::V2Pairs(pairs) => {
+                let mut token_in = match swap.token_in {
+                    Some(token_in) => native_to_wrapped(token_in),
+                    None => continue,
+                };
+                for pair in pairs {
+                    // past a pool we don't know, the tokens of the route can't be followed
+                    let pool = match pools_map.get(pair) {
+                        Some(pool) => pool,
+                        None => break,
+                    };
+                    hops.push((*pair, token_in));
+                    token_in = if pool.token0 == token_in {
+                        pool.token1
+                    } else {
+                        pool.token0
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut swap_info: Vec<SwapInfo> = hops
+        .into_iter()
+        .filter_map(|(pair, token_in)| swap_info_for_hop(tx.hash, pools_map, pair, token_in))
+        .collect();
+    if swap_info.is_empty() {
+        return Ok(swap_info);
+    }
+    if !victim_succeeds(provider, new_block, tx).await {
+        info!("Skipping {:?}: eth_call reverts", tx.hash);
+        return Ok(Vec::new());
+    }
+
+    assign_routes(&mut swap_info);
+    swap_info.retain(|info| token_lists.allows(info.target_token));
+    drop_illiquid_victim(provider, new_block, tx.hash, &mut swap_info);
+    Ok(swap_info)
+}
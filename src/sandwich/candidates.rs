@@ -0,0 +1,154 @@
+use ethers::types::{transaction::eip2930::AccessList, Bytes, H160, H256, U256};
+use log::info;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+use crate::common::constants::Env;
+use crate::sandwich::simulation::{PendingTxInfo, Sandwich};
+
+pub static CANDIDATE_REPORT_INTERVAL_SECS: u64 = 60;
+
+/*
+Keeps promising_sandwiches (victim tx hash --> optimized sandwiches) from ballooning during busy periods:
+- compaction: main_dish rebuilds and re-simulates every bundle from the amount in and the victim,
+  so the optimizer's calldata and access lists are dropped before a candidate is stored
+- GC on every block: candidates of victims that are no longer pending are dropped,
+  and only the latest candidate per (victim, pair) is kept (re-optimizations on later blocks replace it)
+- size bound: past MAX_PROMISING_SANDWICHES, the candidates with the lowest max revenue are evicted
+Memory usage is estimated and logged every CANDIDATE_REPORT_INTERVAL_SECS.
+*/
+#[derive(Debug, Clone)]
+pub struct CandidateGc {
+    pub max_candidates: usize, // 0: unbounded
+    pub evicted: u64,          // dropped by the size bound since the last report
+    pub last_report: Instant,
+}
+
+// Only what main_dish needs to rebuild the sandwich: the optimized amount in, revenue and gas used
+pub fn compact(sandwich: &Sandwich) -> Sandwich {
+    let mut sandwich = sandwich.clone();
+    if let Some(optimized) = sandwich.optimized_sandwich.as_mut() {
+        optimized.front_calldata = Bytes::default();
+        optimized.back_calldata = Bytes::default();
+        optimized.front_access_list = AccessList::default();
+        optimized.back_access_list = AccessList::default();
+    }
+    sandwich
+}
+
+fn max_revenue(sandwich: &Sandwich) -> U256 {
+    sandwich
+        .optimized_sandwich
+        .as_ref()
+        .map(|o| o.max_revenue)
+        .unwrap_or_default()
+}
+
+fn access_list_bytes(access_list: &AccessList) -> usize {
+    access_list
+        .0
+        .iter()
+        .map(|item| size_of::<H160>() + item.storage_keys.len() * size_of::<H256>())
+        .sum()
+}
+
+// Rough heap + inline size of a stored candidate
+pub fn estimate_bytes(sandwich: &Sandwich) -> usize {
+    let mut bytes = size_of::<Sandwich>() + sandwich.victim_tx.data.len();
+    if let Some(optimized) = &sandwich.optimized_sandwich {
+        bytes += optimized.front_calldata.len()
+            + optimized.back_calldata.len()
+            + access_list_bytes(&optimized.front_access_list)
+            + access_list_bytes(&optimized.back_access_list);
+    }
+    bytes
+}
+
+impl CandidateGc {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            max_candidates: env.max_promising_sandwiches,
+            evicted: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn collect(
+        &mut self,
+        promising_sandwiches: &mut HashMap<H256, Vec<Sandwich>>,
+        pending_txs: &HashMap<H256, PendingTxInfo>,
+    ) {
+        promising_sandwiches.retain(|h, _| pending_txs.contains_key(h));
+
+        for sandwiches in promising_sandwiches.values_mut() {
+            // newest first, so the latest candidate of every pair is the one kept
+            let mut latest: Vec<Sandwich> = Vec::new();
+            for sandwich in sandwiches.drain(..).rev() {
+                if !latest
+                    .iter()
+                    .any(|s| s.swap_info.target_pair == sandwich.swap_info.target_pair)
+                {
+                    latest.push(sandwich);
+                }
+            }
+            latest.reverse();
+            *sandwiches = latest;
+        }
+
+        let total: usize = promising_sandwiches.values().map(|s| s.len()).sum();
+        if self.max_candidates == 0 || total <= self.max_candidates {
+            return;
+        }
+        let mut revenues: Vec<U256> = promising_sandwiches
+            .values()
+            .flatten()
+            .map(max_revenue)
+            .collect();
+        revenues.sort_by(|a, b| b.cmp(a));
+        let cutoff = revenues[self.max_candidates - 1];
+        // ties at the cutoff are kept until the bound is met, in no particular order
+        let mut keep_at_cutoff = self.max_candidates
+            - revenues
+                .iter()
+                .take_while(|revenue| **revenue > cutoff)
+                .count();
+        for sandwiches in promising_sandwiches.values_mut() {
+            sandwiches.retain(|s| {
+                let revenue = max_revenue(s);
+                if revenue > cutoff {
+                    true
+                } else if revenue == cutoff && keep_at_cutoff > 0 {
+                    keep_at_cutoff -= 1;
+                    true
+                } else {
+                    false
+                }
+            });
+        }
+        promising_sandwiches.retain(|_, sandwiches| !sandwiches.is_empty());
+        self.evicted += (total - self.max_candidates) as u64;
+    }
+
+    pub fn maybe_report(&mut self, promising_sandwiches: &HashMap<H256, Vec<Sandwich>>) {
+        if self.last_report.elapsed() < Duration::from_secs(CANDIDATE_REPORT_INTERVAL_SECS) {
+            return;
+        }
+        let candidates: usize = promising_sandwiches.values().map(|s| s.len()).sum();
+        let bytes: usize = promising_sandwiches
+            .values()
+            .flatten()
+            .map(estimate_bytes)
+            .sum();
+        info!(
+            "Promising sandwiches: {} candidates of {} victims, ~{} KB, {} evicted in the last {}s",
+            candidates,
+            promising_sandwiches.len(),
+            bytes / 1024,
+            self.evicted,
+            CANDIDATE_REPORT_INTERVAL_SECS
+        );
+        self.evicted = 0;
+        self.last_report = Instant::now();
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::Result;
+use ethers::providers::{Middleware, Provider};
+use ethers::types::{Transaction, H160, H256, U256, U64};
+use log::{info, warn};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common::constants::Env;
+use crate::common::provider::RpcClient;
+use crate::sandwich::simulation::{PendingTxInfo, Sandwich, SwapDirection, SwapInfo};
+
+/*
+Other sandwich bots see the same victims we do. When one of them already has a frontrun pending
+on our target pool, racing it is a predictable losing battle: the builder takes the higher bribe,
+and our bribe is bounded by the same revenue as theirs.
+A pending tx is a likely frontrun of our victim when it:
+1. swaps the victim's pool in the same direction
+2. doesn't go through a known router (bots call their own contracts)
+3. bids at least the victim's priority fee, to be ordered in front of it
+4. carries calldata that isn't ABI encoded, or shades the victim's priority fee by a few wei
+or when it calls a contract of the bot registry, whatever it bids and however it's encoded.
+The contracts caught by 1-4 are only suspects: they go into the registry (SANDWICH_BOTS_PATH) once
+their frontrun lands right before the victim with a backrun after it (see BotRegistry.confirm),
+and are forgotten when they haven't sandwiched anything for BOT_REGISTRY_TTL_SECS.
+Right before a bundle is sent, the pending txs of registry bots on its pools, in its victims' direction,
+are looked for again: they may have come in after the victim was traced (see contested_pairs).
+(COMPETITOR_POLICY):
+- ignore: keep racing
+- drop: skip the contested pools
+- backrun: skip the contested pools, and backrun the frontrunner instead
+- outbid: keep the contested pools, and bid COMPETITOR_BRIBE_BUMP_BPS more of the revenue on them,
+  or skip the bundle when that's over BRIBE_CEILING_BPS (see BribePolicy.contested_bribe_pct)
+*/
+// Bots bid just over the victim, anything closer than this is shading
+pub static BID_SHADING_WEI: u64 = 1_000_000; // 0.001 gwei
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompetitorPolicy {
+    Ignore,
+    Drop,
+    Backrun,
+    Outbid,
+}
+
+impl CompetitorPolicy {
+    pub fn parse(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "ignore" => CompetitorPolicy::Ignore,
+            "backrun" => CompetitorPolicy::Backrun,
+            "outbid" => CompetitorPolicy::Outbid,
+            _ => CompetitorPolicy::Drop,
+        }
+    }
+}
+
+// A bot is forgotten when none of its sandwiches landed for this long
+pub static BOT_REGISTRY_TTL_SECS: u64 = 7 * 24 * 3600;
+// A suspected frontrun that hasn't landed around its victim by then is dropped
+pub static SUSPECT_MAX_BLOCKS: u64 = 3;
+
+// A pending tx that looks like a frontrun of victim, its contract isn't a bot until the sandwich lands
+#[derive(Debug, Clone)]
+struct Suspect {
+    bot: H160,
+    victim: H256,
+    seen_block: U64,
+}
+
+#[derive(Debug, Default)]
+struct RegistryState {
+    bots: HashMap<H160, u64>, // bot contract -> last time (unix secs) one of its sandwiches landed
+    suspects: HashMap<H256, Suspect>, // suspected frontrun tx -> its bot and victim
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BotRegistry {
+    pub path: String,
+    inner: Arc<Mutex<RegistryState>>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl BotRegistry {
+    pub fn new(env: &Env) -> Self {
+        let registry = Self {
+            path: env.sandwich_bots_path.clone(),
+            inner: Arc::new(Mutex::new(RegistryState::default())),
+        };
+        if Path::new(&registry.path).exists() {
+            match registry.load() {
+                Ok(count) => info!("Loaded {} sandwich bots from {}", count, registry.path),
+                Err(e) => warn!("BotRegistry.load error: {e:?}"),
+            }
+        }
+        registry
+    }
+
+    fn load(&self) -> Result<usize> {
+        let bots: HashMap<H160, u64> = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        let mut state = self.inner.lock().unwrap();
+        state.bots = bots;
+        Ok(state.bots.len())
+    }
+
+    fn save(&self, bots: &HashMap<H160, u64>) -> Result<()> {
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = Path::new(&self.path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(bots)?)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, address: &H160) -> bool {
+        match self.inner.lock().unwrap().bots.get(address) {
+            Some(last_seen) => now_secs().saturating_sub(*last_seen) <= BOT_REGISTRY_TTL_SECS,
+            None => false,
+        }
+    }
+
+    // A competing frontrun of victim, its contract is learned once it lands around the victim (see confirm)
+    pub fn suspect(&self, frontrun: &Transaction, victim: &Transaction, block_number: U64) {
+        let bot = match frontrun.to {
+            Some(to) => to,
+            None => return,
+        };
+        self.inner.lock().unwrap().suspects.insert(
+            frontrun.hash,
+            Suspect {
+                bot,
+                victim: victim.hash,
+                seen_block: block_number,
+            },
+        );
+    }
+
+    /*
+    Called with every new block: a suspect whose tx landed right before its victim,
+    with a tx to the same contract after the victim, is a sandwich, and its contract a bot.
+    Bots seen sandwiching again are kept for another BOT_REGISTRY_TTL_SECS.
+    */
+    pub async fn confirm(
+        &self,
+        provider: &Arc<Provider<RpcClient>>,
+        block_number: U64,
+    ) -> Result<()> {
+        let suspects: Vec<(H256, Suspect)> = {
+            let mut state = self.inner.lock().unwrap();
+            state
+                .suspects
+                .retain(|_, s| s.seen_block + U64::from(SUSPECT_MAX_BLOCKS) >= block_number);
+            state
+                .suspects
+                .iter()
+                .map(|(hash, s)| (*hash, s.clone()))
+                .collect()
+        };
+        if suspects.is_empty() {
+            return Ok(());
+        }
+        let block = match provider.get_block_with_txs(block_number).await? {
+            Some(block) => block,
+            None => return Ok(()),
+        };
+        let position = |hash: H256| block.transactions.iter().position(|tx| tx.hash == hash);
+
+        let mut state = self.inner.lock().unwrap();
+        let mut learned = false;
+        for (frontrun_hash, suspect) in suspects {
+            let frontrun_idx = match position(frontrun_hash) {
+                Some(idx) => idx,
+                None => continue,
+            };
+            // landed, whether around the victim or not it's settled
+            state.suspects.remove(&frontrun_hash);
+            let sandwiched = match position(suspect.victim) {
+                Some(victim_idx) if victim_idx > frontrun_idx => block.transactions[victim_idx..]
+                    .iter()
+                    .any(|tx| tx.to == Some(suspect.bot)),
+                _ => false,
+            };
+            if sandwiched {
+                if !state.bots.contains_key(&suspect.bot) {
+                    info!(
+                        "New sandwich bot: {:?} (frontrun {:?} in block #{:?})",
+                        suspect.bot, frontrun_hash, block_number
+                    );
+                }
+                state.bots.insert(suspect.bot, now_secs());
+                learned = true;
+            }
+        }
+        if learned {
+            let now = now_secs();
+            state
+                .bots
+                .retain(|_, last_seen| now.saturating_sub(*last_seen) <= BOT_REGISTRY_TTL_SECS);
+            match self.save(&state.bots) {
+                Err(e) => warn!("BotRegistry.save error: {e:?}"),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompetingFrontrun {
+    pub tx: Transaction,
+    pub pair: H160,
+}
+
+pub fn priority_fee(tx: &Transaction) -> U256 {
+    match tx.max_priority_fee_per_gas {
+        Some(priority_fee) => priority_fee,
+        None => tx.gas_price.unwrap_or_default(),
+    }
+}
+
+pub fn looks_like_frontrun(
+    candidate: &Transaction,
+    victim: &Transaction,
+    routers: &HashSet<H160>,
+) -> bool {
+    match candidate.to {
+        Some(to) if !routers.contains(&to) => {}
+        _ => return false,
+    }
+    let candidate_fee = priority_fee(candidate);
+    let victim_fee = priority_fee(victim);
+    if candidate_fee < victim_fee {
+        return false;
+    }
+    let packed_calldata = candidate.input.len() < 4 || (candidate.input.len() - 4) % 32 != 0;
+    let shaded_bid = candidate_fee - victim_fee <= U256::from(BID_SHADING_WEI);
+    packed_calldata || shaded_bid
+}
+
+fn same_direction(a: &SwapDirection, b: &SwapDirection) -> bool {
+    matches!(
+        (a, b),
+        (SwapDirection::Buy, SwapDirection::Buy) | (SwapDirection::Sell, SwapDirection::Sell)
+    )
+}
+
+pub fn find_competing_frontruns(
+    victim: &Transaction,
+    swap_info: &Vec<SwapInfo>,
+    pending_txs: &HashMap<H256, PendingTxInfo>,
+    routers: &HashSet<H160>,
+    bots: &BotRegistry,
+) -> Vec<CompetingFrontrun> {
+    let mut competitors = Vec::new();
+    for (tx_hash, pending_tx_info) in pending_txs {
+        if *tx_hash == victim.hash || pending_tx_info.pending_tx.hint.is_some() {
+            continue;
+        }
+        let candidate = &pending_tx_info.pending_tx.tx;
+        if candidate.from == victim.from {
+            continue;
+        }
+        let known_bot = candidate.to.map_or(false, |to| bots.contains(&to));
+        if !known_bot && !looks_like_frontrun(candidate, victim, routers) {
+            continue;
+        }
+        for info in swap_info {
+            let contested = pending_tx_info.touched_pairs.iter().any(|touched| {
+                touched.target_pair == info.target_pair
+                    && same_direction(&touched.direction, &info.direction)
+            });
+            if contested {
+                competitors.push(CompetingFrontrun {
+                    tx: candidate.clone(),
+                    pair: info.target_pair,
+                });
+            }
+        }
+    }
+    competitors
+}
+
+/*
+The other way around: a new pending tx against the victims we already have sandwiches for,
+on the pools it swaps in the same direction. Returns the (victim, pool) pairs it contests.
+*/
+pub fn find_contested_victims(
+    candidate: &Transaction,
+    touched_pairs: &[SwapInfo],
+    promising_sandwiches: &HashMap<H256, Vec<Sandwich>>,
+    pending_txs: &HashMap<H256, PendingTxInfo>,
+    routers: &HashSet<H160>,
+    bots: &BotRegistry,
+) -> Vec<(H256, H160)> {
+    let known_bot = candidate.to.map_or(false, |to| bots.contains(&to));
+    let mut contested = Vec::new();
+    for (victim_hash, sandwiches) in promising_sandwiches {
+        if *victim_hash == candidate.hash {
+            continue;
+        }
+        let victim = match pending_txs.get(victim_hash) {
+            Some(victim) => &victim.pending_tx.tx,
+            None => continue,
+        };
+        if candidate.from == victim.from {
+            continue;
+        }
+        if !known_bot && !looks_like_frontrun(candidate, victim, routers) {
+            continue;
+        }
+        for sandwich in sandwiches {
+            let pair = sandwich.swap_info.target_pair;
+            let frontruns = touched_pairs.iter().any(|touched| {
+                touched.target_pair == pair
+                    && same_direction(&touched.direction, &sandwich.swap_info.direction)
+            });
+            if frontruns && !contested.contains(&(*victim_hash, pair)) {
+                contested.push((*victim_hash, pair));
+            }
+        }
+    }
+    contested
+}
+
+// Pools of a bundle a registry bot has a pending swap on in the victim's direction (a frontrun, not a backrun)
+pub fn contested_pairs(
+    victim_swaps: &[SwapInfo],
+    victim_tx_hashes: &[H256],
+    pending_txs: &HashMap<H256, PendingTxInfo>,
+    bots: &BotRegistry,
+) -> Vec<H160> {
+    let mut contested = Vec::new();
+    for (tx_hash, pending_tx_info) in pending_txs {
+        if victim_tx_hashes.contains(tx_hash) {
+            continue;
+        }
+        match pending_tx_info.pending_tx.tx.to {
+            Some(to) if bots.contains(&to) => {}
+            _ => continue,
+        }
+        for touched in &pending_tx_info.touched_pairs {
+            let frontruns = victim_swaps.iter().any(|info| {
+                info.target_pair == touched.target_pair
+                    && same_direction(&info.direction, &touched.direction)
+            });
+            if frontruns && !contested.contains(&touched.target_pair) {
+                contested.push(touched.target_pair);
+            }
+        }
+    }
+    contested
+}
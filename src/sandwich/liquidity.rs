@@ -0,0 +1,107 @@
+use ethers::prelude::Lazy;
+use ethers::{
+    providers::Provider,
+    types::{H160, H256, U256, U64},
+};
+use log::info;
+use std::{collections::HashMap, sync::Arc, sync::Mutex};
+
+use crate::common::chain::CHAIN;
+use crate::common::constants::Env;
+use crate::common::evm::EvmSimulator;
+use crate::common::pricing::conversion_route;
+use crate::common::provider::RpcClient;
+use crate::common::reserves::pair_reserves;
+use crate::common::streams::NewBlock;
+use crate::common::utils::is_weth;
+use crate::sandwich::simulation::{SkipReason, SwapInfo, CURVE_VERSION};
+
+/*
+Pools too small to be worth simulating on, or tracing their victims again.
+A V2 pool's liquidity is its main currency reserve, valued in WETH at the best conversion route's mid price
+(see pricing.rs), times two for the other side.
+Values are kept for the block they were read on, so each pool is valued once per block
+(its reserves come from the Sync tracker when it has them, see reserves.rs).
+Victims whose pools are all below MIN_POOL_LIQUIDITY_WETH are dropped once traced, and never come back
+to the appetizer on later blocks, the appetizer skips the small pools of the others.
+MIN_POOL_LIQUIDITY_WETH=0 turns it off. Curve pools aren't checked.
+*/
+pub static LIQUIDITY_SNAPSHOT: Lazy<Mutex<(U64, HashMap<H160, U256>)>> =
+    Lazy::new(|| Mutex::new((U64::zero(), HashMap::new())));
+
+// Both sides of the pool in WETH, None if it can't be valued
+pub fn liquidity_in_weth(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    info: &SwapInfo,
+) -> Option<U256> {
+    let block_number = simulator.block_number;
+    {
+        let snapshot = LIQUIDITY_SNAPSHOT.lock().unwrap();
+        if snapshot.0 == block_number {
+            if let Some(liquidity) = snapshot.1.get(&info.target_pair) {
+                return Some(*liquidity);
+            }
+        }
+    }
+
+    let (reserve0, reserve1) = pair_reserves(simulator, info.target_pair).ok()?;
+    let mc_reserve = if info.token0_is_main {
+        reserve0
+    } else {
+        reserve1
+    };
+    let mc_value = if is_weth(info.main_currency) {
+        mc_reserve
+    } else {
+        conversion_route(simulator, info.main_currency)?
+            .spot_value(simulator, mc_reserve)
+            .ok()?
+    };
+    let liquidity = mc_value.saturating_mul(U256::from(2));
+
+    let mut snapshot = LIQUIDITY_SNAPSHOT.lock().unwrap();
+    if snapshot.0 != block_number {
+        *snapshot = (block_number, HashMap::new());
+    }
+    snapshot.1.insert(info.target_pair, liquidity);
+    Some(liquidity)
+}
+
+// Only for a simulator nothing has run on yet (see pair_reserves)
+pub fn check_liquidity(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    info: &SwapInfo,
+) -> Option<SkipReason> {
+    let min = Env::new().min_pool_liquidity_weth;
+    if info.version == CURVE_VERSION || min <= 0.0 {
+        return None;
+    }
+    let min = U256::from((min * 10f64.powi(CHAIN.weth.decimals as i32)) as u128);
+    match liquidity_in_weth(simulator, info) {
+        Some(liquidity) if liquidity < min => Some(SkipReason::LowLiquidity),
+        _ => None,
+    }
+}
+
+// Empties swap_info when every pool the victim trades on is below MIN_POOL_LIQUIDITY_WETH
+pub fn drop_illiquid_victim(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    tx_hash: H256,
+    swap_info: &mut Vec<SwapInfo>,
+) {
+    if swap_info.is_empty() || Env::new().min_pool_liquidity_weth <= 0.0 {
+        return;
+    }
+    let mut simulator = EvmSimulator::new(provider.clone(), None, new_block.block_number);
+    if swap_info
+        .iter()
+        .all(|info| check_liquidity(&mut simulator, info).is_some())
+    {
+        info!(
+            "Skipping {:?}: pools below MIN_POOL_LIQUIDITY_WETH",
+            tx_hash
+        );
+        swap_info.clear();
+    }
+}
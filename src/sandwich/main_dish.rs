@@ -1,56 +1,337 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use bounded_vec_deque::BoundedVecDeque;
 use ethers::{
-    providers::{Provider, Ws},
-    types::{H160, H256, U256, U64},
+    providers::Provider,
+    signers::Signer,
+    types::{transaction::eip2930::AccessList, Bytes, H160, H256, U256, U64},
 };
 use log::{info, warn};
-use std::str::FromStr;
 use std::{collections::HashMap, sync::Arc};
 
-use crate::common::alert::Alert;
+use crate::common::alert::{Alert, AlertEvent};
+use crate::common::chain::CHAIN;
 use crate::common::constants::*;
-use crate::common::execution::{Executor, SandoBundle};
+use crate::common::contexts::ExecutionContext;
+use crate::common::evm::VictimTx;
+use crate::common::execution::{
+    accepted_responses, max_base_fee_after, BuilderResult, Executor, SandoBundle,
+};
+use crate::common::experiments::Experiment;
+use crate::common::failures::{Failure, FailureStats, TxStage};
+use crate::common::journal::TradeEntry;
+use crate::common::latency::{now_ms, BundleTimeline};
+use crate::common::multicall::get_token_balances_multicall;
+use crate::common::profile::OperatingProfile;
+use crate::common::provider::RpcClient;
+use crate::common::replacement::{variant_uuid, BundleReplacements, Replacement};
+use crate::common::risk::BundleLimits;
 use crate::common::streams::NewBlock;
-use crate::common::utils::get_token_balance;
-use crate::sandwich::simulation::{BatchSandwich, PendingTxInfo, Sandwich};
+use crate::common::tracker::{BundleVariant, Tracker};
+use crate::common::utils::{
+    get_token_balance_at, is_weth, scaled_amount, u256_to_f64, MainCurrency,
+};
+use crate::common::victim_lock::{Claim, LockKey, StrategyKind, VictimLocks};
+use crate::sandwich::bribe::BribePolicy;
+use crate::sandwich::competitors::{contested_pairs, BotRegistry, CompetitorPolicy};
+use crate::sandwich::simulation::{
+    BatchSandwich, PendingTxInfo, Sandwich, SimulatedSandwich, SwapInfo,
+};
 
 pub async fn get_token_balances(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     owner: H160,
     tokens: &Vec<H160>,
-) -> HashMap<H160, U256> {
+    block_number: U64,
+) -> Option<HashMap<H160, U256>> {
+    match get_token_balances_multicall(provider, owner, tokens, block_number).await {
+        Ok(token_balances) => return Some(token_balances),
+        Err(e) => warn!("get_token_balances_multicall error: {e:?}"),
+    }
+
     let mut token_balances = HashMap::new();
     for token in tokens {
-        let balance = get_token_balance(provider.clone(), owner, *token)
+        let balance = get_token_balance_at(provider.clone(), owner, *token, block_number)
             .await
-            .unwrap_or_default();
+            .ok()?;
         token_balances.insert(*token, balance);
     }
-    token_balances
+    Some(token_balances)
+}
+
+/*
+Pending txs expected to land ahead of our bundle in the target block, for DUAL_SIMULATION and COMPETING_PRESTATE:
+swaps on the bundle's pools (other than its victims) paying at least the highest victim priority fee,
+highest fee first.
+*/
+pub fn predicted_preceding_txs(
+    pending_txs: &HashMap<H256, PendingTxInfo>,
+    batch_sandwich: &BatchSandwich,
+    base_fee: U256,
+) -> Vec<VictimTx> {
+    let priority_fee = |info: &PendingTxInfo| {
+        let tx = &info.pending_tx.tx;
+        tx.max_priority_fee_per_gas
+            .unwrap_or_else(|| tx.gas_price.unwrap_or_default().saturating_sub(base_fee))
+    };
+    let victims = batch_sandwich.victim_tx_hashes();
+    let pairs: Vec<H160> = batch_sandwich
+        .sandwiches
+        .iter()
+        .map(|s| s.swap_info.target_pair)
+        .collect();
+    let victim_priority_fee = victims
+        .iter()
+        .filter_map(|h| pending_txs.get(h))
+        .map(priority_fee)
+        .max()
+        .unwrap_or_default();
+
+    let mut preceding: Vec<(U256, &PendingTxInfo)> = pending_txs
+        .iter()
+        .filter(|(h, info)| {
+            !victims.contains(*h)
+                && info
+                    .touched_pairs
+                    .iter()
+                    .any(|swap| pairs.contains(&swap.target_pair))
+        })
+        .map(|(_, info)| (priority_fee(info), info))
+        .filter(|(fee, _)| *fee >= victim_priority_fee)
+        .collect();
+    preceding.sort_by(|a, b| b.0.cmp(&a.0));
+
+    preceding
+        .into_iter()
+        .map(|(_, info)| {
+            let tx = &info.pending_tx.tx;
+            VictimTx {
+                tx_hash: tx.hash,
+                from: tx.from,
+                to: tx.to.unwrap_or_default(),
+                data: tx.input.0.clone().into(),
+                value: tx.value,
+                gas_price: tx
+                    .max_fee_per_gas
+                    .unwrap_or_else(|| tx.gas_price.unwrap_or_default()),
+                gas_limit: Some(tx.gas.as_u64()),
+            }
+        })
+        .collect()
+}
+
+/*
+The contract checks the block number its calldata names, so the variants for n+2..n+BUNDLE_TARGET_BLOCKS
+can't reuse the first block's calldata: each is simulated again in its own block,
+at the highest base fee it can have by then. Blocks it isn't profitable in get no variant.
+*/
+pub async fn retarget_calldata(
+    provider: &Arc<Provider<RpcClient>>,
+    batch_sandwich: &BatchSandwich,
+    owner: Option<H160>,
+    block_number: U64,
+    target_blocks: u64,
+    base_fee: U256,
+    front_access_list: &AccessList,
+    back_access_list: &AccessList,
+    bot_address: Option<H160>,
+) -> HashMap<u64, (Bytes, Bytes)> {
+    let mut retargeted = HashMap::new();
+    for blocks_ahead in 1..target_blocks {
+        let base_fee = max_base_fee_after(base_fee, blocks_ahead);
+        match batch_sandwich
+            .simulate_for_block(
+                provider.clone(),
+                owner,
+                block_number,
+                blocks_ahead,
+                base_fee,
+                base_fee,
+                Some(front_access_list.clone()),
+                Some(back_access_list.clone()),
+                bot_address,
+            )
+            .await
+        {
+            Ok(simulated) if simulated.revenue > 0 => {
+                retargeted.insert(
+                    blocks_ahead,
+                    (simulated.front_calldata, simulated.back_calldata),
+                );
+            }
+            Ok(simulated) => info!(
+                "No variant for block #{:?}: revenue {}",
+                block_number + U64::from(1 + blocks_ahead),
+                simulated.revenue
+            ),
+            Err(e) => warn!("BatchSandwich.simulate_for_block error: {e:?}"),
+        }
+    }
+    retargeted
 }
 
 pub async fn send_sando_bundle_request(
     executor: &Executor,
     sando_bundle: SandoBundle,
+    bundle_id: &str,
     block_number: U64,
+    replacement_uuid: &str,
+    target_blocks: u64,
+    retargeted_calldata: &HashMap<u64, (Bytes, Bytes)>,
+    bribe_decay_bps: u64,
     alert: &Alert,
-) -> Result<()> {
-    let bundle_request = executor
-        .to_sando_bundle_request(sando_bundle, block_number, 1)
-        .await?;
-    // If you want to check the simulation results provided by Flashbots
-    // run the following code, but this will take something like 0.1 ~ 0.3 seconds
-    // executor.simulate_bundle(&bundle_request).await;
-    let response = executor.broadcast_bundle(bundle_request).await?;
-    info!("Bundle sent: {:?}", response);
+    timeline: &mut BundleTimeline,
+) -> Result<(Option<H256>, Vec<BundleVariant>)> {
+    let mut bundle_hash = None;
+    let mut variants = Vec::new();
+
+    // one variant per target block: n+1, n+2, ..., n+BUNDLE_TARGET_BLOCKS
+    // (the later ones only with their own calldata, see retarget_calldata)
+    for blocks_ahead in 0..std::cmp::max(target_blocks, 1) {
+        let variant = if blocks_ahead == 0 {
+            sando_bundle.clone()
+        } else {
+            match retargeted_calldata.get(&blocks_ahead) {
+                Some((front_calldata, back_calldata)) => sando_bundle.variant(
+                    blocks_ahead,
+                    bribe_decay_bps,
+                    front_calldata.clone(),
+                    back_calldata.clone(),
+                ),
+                None => continue,
+            }
+        };
+        let retries = 1 + blocks_ahead as usize;
+        let bundle_request = executor
+            .to_sando_bundle_request(variant, block_number, retries)
+            .await?;
+        if blocks_ahead == 0 {
+            timeline.signed = now_ms();
+        }
+        let variant_tx_hashes = bundle_request.transaction_hashes();
+        let variant_uuid = variant_uuid(replacement_uuid, blocks_ahead);
+        let results = executor
+            .broadcast_bundle(
+                bundle_request,
+                Some(variant_uuid.clone()),
+                if blocks_ahead == 0 {
+                    Some(&mut *timeline)
+                } else {
+                    None
+                },
+                Some(bundle_id),
+            )
+            .await
+            .context(Failure::BuilderRejected)?;
+        let response = accepted_responses(&results);
+        // scheduled for the slot's submission window: the responses come in later, see report_inclusion
+        let scheduled = results
+            .values()
+            .any(|result| matches!(result, BuilderResult::Scheduled));
+        if response.is_empty() && !scheduled {
+            if blocks_ahead == 0 {
+                return Err(
+                    anyhow!("No builder accepted the bundle").context(Failure::BuilderRejected)
+                );
+            }
+            warn!(
+                "No builder accepted the bundle for block #{:?}",
+                block_number + U64::from(retries)
+            );
+            continue;
+        }
+        if scheduled {
+            info!(
+                "Bundle scheduled for block #{:?}",
+                block_number + U64::from(retries)
+            );
+        } else {
+            info!(
+                "Bundle sent for block #{:?}: {:?}",
+                block_number + U64::from(retries),
+                response
+            );
+        }
+        if blocks_ahead == 0 {
+            // the Flashbots relay's hash, used to look up the bundle's stats later
+            bundle_hash = response
+                .get("flashbots")
+                .map(|r| r.bundle_hash)
+                .filter(|hash| !hash.is_zero());
+        }
+        variants.push(BundleVariant {
+            target_block: block_number + U64::from(retries),
+            frontrun_tx_hash: variant_tx_hashes[0],
+            replacement_uuid: variant_uuid,
+        });
+    }
+
     match alert
         .send(&format!("[{:?}] Bundle sent", block_number))
         .await
     {
         _ => {}
     }
-    Ok(())
+    Ok((bundle_hash, variants))
+}
+
+/*
+DRY_RUN: everything up to create_sando_bundle runs as usual, then the signed bundle
+is simulated by the Flashbots relay (eth_callBundle, ~0.1-0.3s) and never broadcast.
+Without a relay (sequencer mode) there's nothing to simulate it against, it's only logged.
+Nonces committed for it (sequencer mode) are released right away, so the next one is built with the same ones.
+*/
+pub async fn dry_run_sando_bundle(
+    executor: &Executor,
+    sando_bundle: SandoBundle,
+    bundle_id: &str,
+    block_number: U64,
+) -> Result<BundleVariant> {
+    let front_nonce = sando_bundle
+        .frontrun_tx
+        .nonce()
+        .cloned()
+        .unwrap_or_default();
+    let bundle_request = executor
+        .to_sando_bundle_request(sando_bundle, block_number, 1)
+        .await;
+    executor.nonces.release(front_nonce, 2);
+    let bundle_request = bundle_request.context(Failure::EncodingError)?;
+    let tx_hashes = bundle_request.transaction_hashes();
+
+    if executor.sequencer_mode {
+        info!("🧪 [{}] Dry run: not sent to the sequencer", bundle_id);
+    } else {
+        let simulated = executor.simulate_bundle(&bundle_request).await?;
+        let last = simulated.transactions.len().saturating_sub(1);
+        for (idx, tx) in simulated.transactions.iter().enumerate() {
+            if let Some(error) = tx.error.as_ref().or(tx.revert.as_ref()) {
+                let stage = if idx == 0 {
+                    TxStage::Front
+                } else if idx == last {
+                    TxStage::Back
+                } else {
+                    TxStage::Victim
+                };
+                return Err(
+                    anyhow!("Relay simulation of {:?} failed: {}", tx.hash, error)
+                        .context(Failure::SimRevert(stage)),
+                );
+            }
+        }
+        info!(
+            "🧪 [{}] Dry run: simulated by the relay for block #{:?} / Gas used: {:?} / Coinbase diff: {:?}",
+            bundle_id,
+            block_number + U64::from(1),
+            simulated.gas_used,
+            simulated.coinbase_diff
+        );
+    }
+
+    Ok(BundleVariant {
+        target_block: block_number + U64::from(1),
+        frontrun_tx_hash: tx_hashes[0],
+        replacement_uuid: String::new(),
+    })
 }
 
 #[derive(Debug, Clone)]
@@ -61,26 +342,276 @@ pub struct Ingredients {
     pub amount_in: U256,
     pub max_revenue: U256,
     pub score: f64,
+    pub victim_gas: u64,   // gas limit of the victim tx
+    pub sandwich_gas: u64, // frontrun + backrun gas
     pub sandwich: Sandwich,
+    pub riders: Vec<Sandwich>, // other victims on the same pair, stacked behind this one (see stack_victims)
+}
+
+/*
+Several victims buying the same V2 pair: a frontrun ahead of all of them and a backrun after all of them
+takes the price impact of their combined flow, instead of one victim's.
+The victims of each pair are lined up by gas price (highest first, the order a builder puts them in),
+and the frontrun is sized for the whole stack with a ternary search on the simulated bundle,
+between 0 and twice what their single sandwiches add up to.
+A stack that beats the pair's best single sandwich replaces the pair's sandwiches on the plate:
+the first victim carries the frontrun, the others ride along with no amount in of their own
+(BatchSandwich::legs folds them into the same buy and sell).
+*/
+pub async fn stack_victims(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    plate: Vec<Ingredients>,
+    max_victims: usize,
+    failures: &mut FailureStats,
+) -> Vec<Ingredients> {
+    let mut by_pair: HashMap<H160, Vec<usize>> = HashMap::new();
+    for (idx, ingredient) in plate.iter().enumerate() {
+        if ingredient.sandwich.swap_info.version == 2 && ingredient.riders.is_empty() {
+            by_pair.entry(ingredient.pair).or_default().push(idx);
+        }
+    }
+
+    let mut stacks = Vec::new();
+    let mut stacked = Vec::new();
+    for (pair, idxs) in by_pair {
+        let mut members: Vec<(usize, &Ingredients)> =
+            idxs.iter().map(|idx| (*idx, &plate[*idx])).collect();
+        members.sort_by(|(_, a), (_, b)| {
+            b.sandwich
+                .victim_tx
+                .gas_price
+                .cmp(&a.sandwich.victim_tx.gas_price)
+                .then(a.tx_hash.cmp(&b.tx_hash))
+        });
+        members.dedup_by_key(|(_, ingredient)| ingredient.tx_hash);
+        members.truncate(max_victims);
+        if members.len() < 2 {
+            continue;
+        }
+        let group: Vec<&Ingredients> = members.iter().map(|(_, ingredient)| *ingredient).collect();
+
+        let best_single = group
+            .iter()
+            .map(|i| i.max_revenue)
+            .max()
+            .unwrap_or_default();
+        match size_stack(provider, new_block, &group).await {
+            Ok(Some((amount_in, max_revenue, sandwich_gas))) if max_revenue > best_single => {
+                info!(
+                    "🥞 Stacked {} victims on {:?} / Amount in: {:?} / Revenue: {:?} (best single: {:?})",
+                    group.len(),
+                    pair,
+                    amount_in,
+                    max_revenue,
+                    best_single
+                );
+                let lead = group[0];
+                stacks.push(Ingredients {
+                    tx_hash: lead.tx_hash,
+                    pair,
+                    main_currency: lead.main_currency,
+                    amount_in,
+                    max_revenue,
+                    score: u256_to_f64(max_revenue) / u256_to_f64(amount_in),
+                    victim_gas: group.iter().map(|i| i.victim_gas).sum(),
+                    sandwich_gas,
+                    sandwich: Sandwich {
+                        amount_in,
+                        ..lead.sandwich.clone()
+                    },
+                    riders: group[1..]
+                        .iter()
+                        .map(|i| Sandwich {
+                            amount_in: U256::zero(),
+                            ..i.sandwich.clone()
+                        })
+                        .collect(),
+                });
+                // victims left out of the stack still go out as single sandwiches
+                stacked.extend(members.iter().map(|(idx, _)| *idx));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                failures.record_error(&e);
+                warn!("stack_victims error: {e:?}");
+            }
+        }
+    }
+
+    plate
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| !stacked.contains(idx))
+        .map(|(_, ingredient)| ingredient)
+        .chain(stacks)
+        .collect()
+}
+
+// (amount in, revenue, frontrun + backrun gas) of the best frontrun for the stack, None if nothing is profitable
+async fn size_stack(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    group: &[&Ingredients],
+) -> Result<Option<(U256, U256, u64)>> {
+    let env = Env::new();
+    let main_currency = group[0].main_currency;
+    let decimals = MainCurrency::new(main_currency).decimals();
+    let (ceiling, tolerance) = if is_weth(main_currency) {
+        (
+            scaled_amount(env.max_amount_in_weth, decimals, 0),
+            env.optimize_tolerance_weth,
+        )
+    } else {
+        (
+            scaled_amount(env.max_amount_in_stables, decimals, 0),
+            env.optimize_tolerance_stables,
+        )
+    };
+    let tolerance = U256::from((tolerance.max(0.0) * 10f64.powi(decimals as i32)) as u128);
+    let tolerance = std::cmp::max(tolerance, U256::from(2));
+    let summed = group
+        .iter()
+        .fold(U256::zero(), |acc, i| acc.saturating_add(i.amount_in));
+
+    let base_fee = new_block.next_base_fee;
+    let simulate = |amount_in: U256| {
+        let stack = BatchSandwich {
+            sandwiches: group
+                .iter()
+                .enumerate()
+                .map(|(idx, i)| Sandwich {
+                    amount_in: if idx == 0 { amount_in } else { U256::zero() },
+                    ..i.sandwich.clone()
+                })
+                .collect(),
+        };
+        let provider = provider.clone();
+        async move {
+            stack
+                .simulate(
+                    provider,
+                    None,
+                    new_block.block_number,
+                    base_fee,
+                    base_fee,
+                    None,
+                    None,
+                    None,
+                )
+                .await
+        }
+    };
+
+    // a frontrun past a victim's slippage bound reverts it: no revenue, the search moves below it
+    let revenue_of = |simulated: Result<SimulatedSandwich>| match simulated {
+        Ok(simulated) => Ok(Some(simulated)),
+        Err(e) if Failure::of(&e) == Failure::SimRevert(TxStage::Victim) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    let mut best: Option<(U256, SimulatedSandwich)> = None;
+    let mut low = U256::zero();
+    let mut high = std::cmp::min(summed.saturating_mul(U256::from(2)), ceiling);
+    while high - low > tolerance {
+        let third = (high - low) / U256::from(3);
+        let (left, right) = (low + third, high - third);
+        let (left_simulated, right_simulated) = tokio::join!(simulate(left), simulate(right));
+        let left_simulated = revenue_of(left_simulated)?;
+        let right_simulated = revenue_of(right_simulated)?;
+        let revenue = |s: &Option<SimulatedSandwich>| s.as_ref().map_or(i128::MIN, |s| s.revenue);
+        let (left_revenue, right_revenue) = (revenue(&left_simulated), revenue(&right_simulated));
+        for (amount_in, simulated) in [(left, left_simulated), (right, right_simulated)] {
+            match simulated {
+                Some(simulated)
+                    if simulated.revenue > best.as_ref().map(|b| b.1.revenue).unwrap_or(0) =>
+                {
+                    best = Some((amount_in, simulated));
+                }
+                _ => {}
+            }
+        }
+        if left_revenue < right_revenue {
+            low = left;
+        } else {
+            high = right;
+        }
+    }
+
+    Ok(best.map(|(amount_in, simulated)| {
+        (
+            amount_in,
+            U256::from(simulated.revenue as u128),
+            simulated.front_gas_used + simulated.back_gas_used,
+        )
+    }))
+}
+
+/*
+Splits the plate into courses whose combined gas stays under gas_cap,
+so each course can be sent as its own bundle when the block is too full for all of them.
+First-fit in score order: the best sandwiches end up together in the first course.
+A victim shared by several sandwiches in the same course is only counted once.
+*/
+pub fn split_plate(plate: Vec<Ingredients>, gas_cap: u64) -> Vec<Vec<Ingredients>> {
+    let mut courses: Vec<(u64, Vec<Ingredients>)> = Vec::new();
+    for ingredient in plate {
+        let added_gas = |course: &Vec<Ingredients>| {
+            if course.iter().any(|i| i.tx_hash == ingredient.tx_hash) {
+                ingredient.sandwich_gas
+            } else {
+                ingredient.victim_gas + ingredient.sandwich_gas
+            }
+        };
+        match courses
+            .iter()
+            .position(|(gas, course)| gas + added_gas(course) <= gas_cap)
+        {
+            Some(idx) => {
+                let (gas, course) = &mut courses[idx];
+                *gas += added_gas(course);
+                course.push(ingredient);
+            }
+            None => {
+                let gas = ingredient.victim_gas + ingredient.sandwich_gas;
+                if gas > gas_cap {
+                    info!(
+                        "Sandwich on {:?} needs {} gas, over the bundle gas cap of {}",
+                        ingredient.tx_hash, gas, gas_cap
+                    );
+                    continue;
+                }
+                courses.push((gas, vec![ingredient]));
+            }
+        }
+    }
+    courses.into_iter().map(|(_, course)| course).collect()
 }
 
 pub async fn main_dish(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     alert: &Alert,
     executor: &Executor,
     new_block: &NewBlock,
-    owner: H160,
-    bot_address: H160,
-    bribe_pct: U256,
+    profile: &OperatingProfile,
     promising_sandwiches: &HashMap<H256, Vec<Sandwich>>,
     simulated_bundle_ids: &mut BoundedVecDeque<String>,
     pending_txs: &HashMap<H256, PendingTxInfo>,
+    tracker: &mut Tracker,
+    mut experiment: Option<&mut Experiment>,
+    gas_cap: u64,
+    victim_locks: &VictimLocks,
+    failures: &mut FailureStats,
+    replacements: &mut BundleReplacements,
+    bribe_policy: &mut BribePolicy,
+    bot_registry: &BotRegistry,
 ) -> Result<()> {
     let env = Env::new();
 
-    let weth = H160::from_str(WETH).unwrap();
-    let usdt = H160::from_str(USDT).unwrap();
-    let usdc = H160::from_str(USDC).unwrap();
+    let weth = CHAIN.weth.address;
+    let usdt = CHAIN.usdt.address;
+    let usdc = CHAIN.usdc.address;
+    let dai = CHAIN.dai.address;
 
     let bot_balances = if env.debug {
         // assume you have infinite funds when debugging
@@ -88,11 +619,42 @@ pub async fn main_dish(
         bot_balances.insert(weth, U256::MAX);
         bot_balances.insert(usdt, U256::MAX);
         bot_balances.insert(usdc, U256::MAX);
+        bot_balances.insert(dai, U256::MAX);
         bot_balances
     } else {
-        let bot_balances =
-            get_token_balances(&provider, bot_address, &vec![weth, usdt, usdc]).await;
-        bot_balances
+        // every main currency is held by the bot contract of the context it's routed to
+        let mut fetched = Some(HashMap::new());
+        for context in &executor.contexts {
+            let tokens: Vec<H160> = vec![weth, usdt, usdc, dai]
+                .into_iter()
+                .filter(|mc| executor.route(*mc).map(|c| &c.name) == Some(&context.name))
+                .collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            let balances = get_token_balances(
+                &provider,
+                context.bot_address,
+                &tokens,
+                new_block.block_number,
+            )
+            .await;
+            fetched = match (fetched, balances) {
+                (Some(mut fetched), Some(balances)) => {
+                    fetched.extend(balances);
+                    Some(fetched)
+                }
+                _ => None,
+            };
+        }
+        match tracker.forecast_inventory(fetched, new_block.block_number) {
+            Some(bot_balances) => bot_balances,
+            None => {
+                return Err(
+                    anyhow!("Bot balances unavailable").context(Failure::InsufficientInventory)
+                )
+            }
+        }
     };
 
     let mut plate = Vec::new();
@@ -101,13 +663,21 @@ pub async fn main_dish(
             let optimized_sandwich = sandwich.optimized_sandwich.as_ref().unwrap();
             let amount_in = optimized_sandwich.amount_in;
             let max_revenue = optimized_sandwich.max_revenue;
-            let score = (max_revenue.as_u128() as f64) / (amount_in.as_u128() as f64);
+            let score = if amount_in.is_zero() {
+                0.0
+            } else {
+                u256_to_f64(max_revenue) / u256_to_f64(amount_in)
+            };
             let clean_sandwich = Sandwich {
                 amount_in,
                 swap_info: sandwich.swap_info.clone(),
                 victim_tx: sandwich.victim_tx.clone(),
                 optimized_sandwich: None,
             };
+            let victim_gas = match pending_txs.get(promising_tx_hash) {
+                Some(tx_info) => tx_info.pending_tx.tx.gas.as_u64(),
+                None => sandwich.victim_tx.gas_limit.unwrap_or_default(),
+            };
             let ingredients = Ingredients {
                 tx_hash: *promising_tx_hash,
                 pair: sandwich.swap_info.target_pair,
@@ -115,12 +685,21 @@ pub async fn main_dish(
                 amount_in,
                 max_revenue,
                 score,
+                victim_gas,
+                sandwich_gas: optimized_sandwich.front_gas_used + optimized_sandwich.back_gas_used,
                 sandwich: clean_sandwich,
+                riders: Vec::new(),
             };
             plate.push(ingredients);
         }
     }
 
+    let max_victims = std::cmp::min(
+        BundleLimits::new(&env).max_victims_per_bundle,
+        profile.max_victims_per_bundle,
+    );
+    let plate = stack_victims(provider, new_block, plate, max_victims, failures).await;
+
     /*
     [Multi-sandwich algorithm] Sorting by score.
 
@@ -133,6 +712,7 @@ pub async fn main_dish(
     USDT/USDC pairs will always have bigger scores, because amount_in is represented as stable amounts (decimals = 6)
     and max_revenue is represented as WETH amount (decimals = 18)
     However, this is good, because we can pick up stable sandwiches first (where there's less competition)
+    DAI has 18 decimals like WETH, so DAI pairs score ~1/2000 of a WETH pair and come last
 
     After we've go through all stable pair sandwiches, we next pick up WETH pairs by score order
     */
@@ -146,30 +726,118 @@ pub async fn main_dish(
     - <sando1, sando2, sando3>
     3 bundles in total. This way you can optimize your profits.
     However, if you have infinite funds, you can always group all of the sandwich opportunities.
+
+    When the target block is nearly full, the plate is first split into courses that each fit
+    in the gas left for us (see split_plate), and the above is done for every course.
     */
-    for i in 0..plate.len() {
+    let limits = BundleLimits::new(&env);
+
+    let courses = split_plate(plate, gas_cap);
+    let servings: Vec<&[Ingredients]> = courses
+        .iter()
+        .flat_map(|course| (1..=course.len()).map(move |n| &course[..n]))
+        .collect();
+
+    for serving in servings {
         let mut balances = bot_balances.clone();
+        let mut exposure_left = HashMap::new();
+        let mut token_exposure_left = HashMap::new();
+        let mut block_notional_left = HashMap::new();
+        let mut victims = Vec::new();
         let mut sandwiches = Vec::new();
+        let mut out_of_inventory = false;
+        // a bundle goes through a single bot contract, set by its first sandwich
+        let mut context: Option<&ExecutionContext> = None;
+
+        for ingredient in serving {
+            let routed = match executor.route(ingredient.main_currency) {
+                Some(routed) => routed,
+                None => continue,
+            };
+            if context.map_or(false, |c| c.name != routed.name) {
+                continue;
+            }
+            let new_victims: Vec<H256> = std::iter::once(&ingredient.sandwich)
+                .chain(ingredient.riders.iter())
+                .map(|s| s.victim_tx.tx_hash)
+                .filter(|tx_hash| !victims.contains(tx_hash))
+                .collect();
+            if victims.len() + new_victims.len() > limits.max_victims_per_bundle
+                || victims.len() + new_victims.len() > profile.max_victims_per_bundle
+            {
+                continue;
+            }
+
+            if tracker.inflight_bundles_for_pool(ingredient.pair)
+                >= limits.max_inflight_bundles_per_pool
+            {
+                continue;
+            }
 
-        for j in 0..(i + 1) {
-            let ingredient = &plate[j];
             let main_currency = ingredient.main_currency;
             let balance = *balances.get(&main_currency).unwrap();
+            let exposure = *exposure_left.entry(main_currency).or_insert_with(|| {
+                limits.exposure_left(main_currency, tracker.inflight_exposure(main_currency))
+            });
+            let target_token = ingredient.sandwich.swap_info.target_token;
+            let token_exposure = *token_exposure_left
+                .entry((target_token, main_currency))
+                .or_insert_with(|| {
+                    limits.token_exposure_left(
+                        main_currency,
+                        tracker.inflight_token_exposure(target_token, main_currency),
+                    )
+                });
+            let block_notional = *block_notional_left.entry(main_currency).or_insert_with(|| {
+                limits.block_notional_left(
+                    main_currency,
+                    tracker.block_notional(main_currency, new_block.block_number + U64::from(1)),
+                )
+            });
             let optimized = ingredient.amount_in;
-            let amount_in = std::cmp::min(balance, optimized);
+            let amount_in = [
+                balance,
+                optimized,
+                exposure,
+                limits.sandwich_cap(main_currency),
+                token_exposure,
+                block_notional,
+            ]
+            .into_iter()
+            .min()
+            .unwrap_or_default();
+            if amount_in.is_zero() {
+                out_of_inventory = true;
+                continue;
+            }
 
             let mut final_sandwich = ingredient.sandwich.clone();
             final_sandwich.amount_in = amount_in;
 
             let new_balance = balance - amount_in;
             balances.insert(main_currency, new_balance);
+            exposure_left.insert(main_currency, exposure - amount_in);
+            token_exposure_left.insert((target_token, main_currency), token_exposure - amount_in);
+            block_notional_left.insert(main_currency, block_notional - amount_in);
 
             sandwiches.push(final_sandwich);
+            sandwiches.extend(ingredient.riders.iter().cloned());
+            victims.extend(new_victims);
+            context = Some(routed);
         }
 
-        let final_batch_sandwich = BatchSandwich { sandwiches };
+        if sandwiches.is_empty() {
+            if out_of_inventory {
+                failures.record(Failure::InsufficientInventory);
+            }
+            continue;
+        }
 
-        let bundle_id = final_batch_sandwich.bundle_id();
+        let batch_sandwich = BatchSandwich { sandwiches };
+        let context = context.unwrap();
+        let executor = &executor.with_context(context);
+
+        let bundle_id = batch_sandwich.bundle_id(new_block.block_number + U64::from(1));
 
         if simulated_bundle_ids.contains(&bundle_id) {
             continue;
@@ -177,48 +845,110 @@ pub async fn main_dish(
 
         simulated_bundle_ids.push_back(bundle_id.clone());
 
+        let (arm, bribe_pct) = match experiment.as_mut() {
+            Some(experiment) => {
+                let arm = experiment.assign();
+                (Some(arm.name), arm.bribe_pct)
+            }
+            None => (
+                None,
+                bribe_policy.bribe_pct(&batch_sandwich.target_v2_pairs(), profile.bribe_pct),
+            ),
+        };
+
+        // a sandwich bot may have sent its frontrun on our pools since the victims were traced
+        let competitor_policy = CompetitorPolicy::parse(&env.competitor_policy);
+        let contested = if competitor_policy == CompetitorPolicy::Ignore {
+            Vec::new()
+        } else {
+            let victim_swaps: Vec<SwapInfo> = batch_sandwich
+                .sandwiches
+                .iter()
+                .filter(|s| s.swap_info.version == 2)
+                .map(|s| s.swap_info.clone())
+                .collect();
+            contested_pairs(
+                &victim_swaps,
+                &batch_sandwich.victim_tx_hashes(),
+                pending_txs,
+                bot_registry,
+            )
+        };
+        let bribe_pct = if contested.is_empty() {
+            bribe_pct
+        } else if competitor_policy == CompetitorPolicy::Outbid {
+            match bribe_policy.contested_bribe_pct(
+                &batch_sandwich.target_v2_pairs(),
+                bribe_pct,
+                env.competitor_bribe_bump_bps,
+            ) {
+                Some(contested_bribe_pct) => {
+                    info!(
+                        "[{}] Outbidding a sandwich bot on {:?}: {} -> {} bps",
+                        bundle_id, contested, bribe_pct, contested_bribe_pct
+                    );
+                    contested_bribe_pct
+                }
+                None => {
+                    info!(
+                        "[{}] Skipping, outbidding the sandwich bot on {:?} is over BRIBE_CEILING_BPS",
+                        bundle_id, contested
+                    );
+                    continue;
+                }
+            }
+        } else {
+            info!(
+                "[{}] Skipping, a sandwich bot is on {:?}",
+                bundle_id, contested
+            );
+            continue;
+        };
+
         let base_fee = new_block.next_base_fee;
         let max_fee = base_fee;
 
         let (owner, bot_address) = if env.debug {
             (None, None)
         } else {
-            (Some(owner), Some(bot_address))
+            (Some(context.owner.address()), Some(context.bot_address))
         };
 
         // set bribe amount as 1 initially, just so we can add the bribe operation gas usage
         // we'll figure out the priority fee and the bribe amount after this simulation
-        let (bribe_amount, front_access_list, back_access_list) = match final_batch_sandwich
-            .simulate(
-                provider.clone(),
-                owner,
-                new_block.block_number,
-                base_fee,
-                max_fee,
-                None,
-                None,
-                bot_address,
-            )
-            .await
-        {
-            Ok(simulated_sandwich) => {
-                if simulated_sandwich.revenue > 0 {
-                    let bribe_amount =
-                        (U256::from(simulated_sandwich.revenue) * bribe_pct) / U256::from(10000);
-                    (
-                        bribe_amount,
-                        Some(simulated_sandwich.front_access_list),
-                        Some(simulated_sandwich.back_access_list),
-                    )
-                } else {
-                    (U256::zero(), None, None)
+        // (victims are put in the most profitable order where none of them reverts)
+        let (final_batch_sandwich, bribe_amount, front_access_list, back_access_list) =
+            match batch_sandwich
+                .optimize_victim_order(
+                    provider.clone(),
+                    owner,
+                    new_block.block_number,
+                    base_fee,
+                    max_fee,
+                    bot_address,
+                )
+                .await
+            {
+                Ok((ordered_batch_sandwich, simulated_sandwich)) => {
+                    if simulated_sandwich.revenue > 0 {
+                        let bribe_amount = (U256::from(simulated_sandwich.revenue) * bribe_pct)
+                            / U256::from(10000);
+                        (
+                            ordered_batch_sandwich,
+                            bribe_amount,
+                            Some(simulated_sandwich.front_access_list),
+                            Some(simulated_sandwich.back_access_list),
+                        )
+                    } else {
+                        (ordered_batch_sandwich, U256::zero(), None, None)
+                    }
                 }
-            }
-            Err(e) => {
-                warn!("bribe_amount simulated failed: {e:?}");
-                (U256::zero(), None, None)
-            }
-        };
+                Err(e) => {
+                    failures.record_error(&e);
+                    warn!("bribe_amount simulated failed: {e:?}");
+                    (batch_sandwich, U256::zero(), None, None)
+                }
+            };
 
         if bribe_amount.is_zero() {
             continue;
@@ -239,21 +969,210 @@ pub async fn main_dish(
             .await;
         if simulated_sandwich.is_err() {
             let e = simulated_sandwich.as_ref().err().unwrap();
+            failures.record_error(e);
             warn!("BatchSandwich.simulate error: {e:?}");
             continue;
         }
-        let simulated_sandwich = simulated_sandwich.unwrap();
+        let mut simulated_sandwich = simulated_sandwich.unwrap();
         if simulated_sandwich.revenue <= 0 {
             continue;
         }
-        // set limit as 30% above what we simulated
-        let front_gas_limit = (simulated_sandwich.front_gas_used * 13) / 10;
-        let back_gas_limit = (simulated_sandwich.back_gas_used * 13) / 10;
 
-        let realistic_back_gas_limit = (simulated_sandwich.back_gas_used * 105) / 100;
-        let max_priority_fee_per_gas = bribe_amount / U256::from(realistic_back_gas_limit);
+        /*
+        COMPETING_PRESTATE: the revenue we bid and decide on is the one behind the best-paying
+        pending swaps on our pools (at most COMPETING_PRESTATE_TXS of them, see predicted_preceding_txs),
+        the way a builder orders the top of the block, rather than the one on an empty block.
+        The calldata, gas and access lists stay the ones of the head state, that's what we send.
+        */
+        let mut bribe_amount = bribe_amount;
+        if env.competing_prestate {
+            let mut competing_txs =
+                predicted_preceding_txs(pending_txs, &final_batch_sandwich, base_fee);
+            competing_txs.truncate(env.competing_prestate_txs);
+            if !competing_txs.is_empty() {
+                match final_batch_sandwich
+                    .simulate_with_preceding(
+                        provider.clone(),
+                        owner,
+                        new_block.block_number,
+                        base_fee,
+                        max_fee,
+                        None,
+                        None,
+                        bot_address,
+                        &competing_txs,
+                    )
+                    .await
+                {
+                    Ok(on_prestate) if on_prestate.revenue > 0 => {
+                        info!(
+                            "[{}] Revenue behind {} competing pending txs: {} (empty block: {})",
+                            bundle_id,
+                            competing_txs.len(),
+                            on_prestate.revenue,
+                            simulated_sandwich.revenue
+                        );
+                        simulated_sandwich.revenue = on_prestate.revenue;
+                        simulated_sandwich.profit = on_prestate.profit;
+                        simulated_sandwich.conversion_slippage = on_prestate.conversion_slippage;
+                        bribe_amount = std::cmp::min(
+                            bribe_amount,
+                            (U256::from(on_prestate.revenue) * bribe_pct) / U256::from(10000),
+                        );
+                    }
+                    Ok(on_prestate) => {
+                        info!(
+                            "[{}] Unprofitable behind {} competing pending txs: {}",
+                            bundle_id,
+                            competing_txs.len(),
+                            on_prestate.revenue
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        failures.record_error(&e);
+                        warn!("BatchSandwich.simulate_with_preceding error: {e:?}");
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // the head state may not be what we land on: run it again after the pending swaps that likely go first
+        if env.dual_simulation {
+            let preceding_txs =
+                predicted_preceding_txs(pending_txs, &final_batch_sandwich, base_fee);
+            if !preceding_txs.is_empty() {
+                match final_batch_sandwich
+                    .simulate_with_preceding(
+                        provider.clone(),
+                        owner,
+                        new_block.block_number,
+                        base_fee,
+                        max_fee,
+                        None,
+                        None,
+                        bot_address,
+                        &preceding_txs,
+                    )
+                    .await
+                {
+                    Ok(predicted) if predicted.revenue > 0 => {}
+                    Ok(predicted) => {
+                        info!(
+                            "[{}] Unprofitable on the predicted next block ({} pending txs ahead): {}",
+                            bundle_id,
+                            preceding_txs.len(),
+                            predicted.revenue
+                        );
+                        continue;
+                    }
+                    Err(e) => {
+                        failures.record_error(&e);
+                        warn!("BatchSandwich.simulate_with_preceding error: {e:?}");
+                        continue;
+                    }
+                }
+            }
+        }
+        // set limit above what we simulated (GAS_LIMIT_PCT, 30% by default)
+        let front_gas_limit = (simulated_sandwich.front_gas_used * env.gas_limit_pct) / 100;
+        let back_gas_limit = (simulated_sandwich.back_gas_used * env.gas_limit_pct) / 100;
+
+        let victim_tx_hashes = final_batch_sandwich.victim_tx_hashes();
+        let mut victim_txs = Vec::new();
+        for tx_hash in victim_tx_hashes {
+            if let Some(tx_info) = pending_txs.get(&tx_hash) {
+                let tx = tx_info.pending_tx.tx.clone();
+                victim_txs.push(tx);
+            }
+        }
+
+        let (front_priority_fee_per_gas, max_priority_fee_per_gas, bribe_amount) =
+            if executor.sequencer_mode {
+                // no builder to bribe on L2s: outbid the victims for the frontrun,
+                // and stay below them for the backrun (front --> victims --> back)
+                let victim_priority_fee = victim_txs
+                    .iter()
+                    .map(|tx| {
+                        tx.max_priority_fee_per_gas.unwrap_or_else(|| {
+                            tx.gas_price.unwrap_or_default().saturating_sub(base_fee)
+                        })
+                    })
+                    .max()
+                    .unwrap_or_default();
+                let front_priority_fee_per_gas = victim_priority_fee + U256::one();
+                let front_cost =
+                    front_priority_fee_per_gas * U256::from(simulated_sandwich.front_gas_used);
+                (front_priority_fee_per_gas, U256::zero(), front_cost)
+            } else {
+                let realistic_back_gas_limit =
+                    (simulated_sandwich.back_gas_used * env.bribe_gas_pct) / 100;
+                let max_priority_fee_per_gas = bribe_amount / U256::from(realistic_back_gas_limit);
+                (U256::zero(), max_priority_fee_per_gas, bribe_amount)
+            };
         let max_fee_per_gas = base_fee + max_priority_fee_per_gas;
 
+        let expected_profit = simulated_sandwich.revenue - (bribe_amount.as_u128() as i128);
+        if expected_profit < profile.min_profit.as_u128() as i128 {
+            continue;
+        }
+
+        let mut lock_keys: Vec<LockKey> = final_batch_sandwich
+            .victim_tx_hashes()
+            .into_iter()
+            .map(LockKey::Victim)
+            .collect();
+        lock_keys.extend(
+            final_batch_sandwich
+                .target_v2_pairs()
+                .into_iter()
+                .map(LockKey::Pool),
+        );
+        let target_block = new_block.block_number + U64::from(1);
+        let value = U256::from(expected_profit.max(0) as u128);
+
+        let victim_tx_hashes = final_batch_sandwich.victim_tx_hashes();
+        let (replacement, stale_uuids) = if executor.sequencer_mode {
+            (Replacement::New(String::new()), Vec::new())
+        } else {
+            replacements.plan(&victim_tx_hashes, target_block, value)
+        };
+        let replacement_uuid = match replacement {
+            Replacement::Stale => {
+                info!("Better bundle already sent for {}, skipping", bundle_id);
+                continue;
+            }
+            Replacement::Replace(uuid) => {
+                info!("Replacing bundle {} with {}", uuid, bundle_id);
+                uuid
+            }
+            Replacement::New(uuid) => uuid,
+        };
+
+        let claim = Claim {
+            strategy: StrategyKind::Sandwich,
+            target_block,
+            value,
+            // txs sent to the sequencer can't be called back
+            replacement_uuid: if executor.sequencer_mode {
+                None
+            } else {
+                Some(replacement_uuid.clone())
+            },
+        };
+        let taken_over = match victim_locks.try_claim(&lock_keys, &claim) {
+            Some(taken_over) => taken_over,
+            None => continue,
+        };
+        // DRY_RUN: the backrun bundles it takes over were never sent either
+        if !env.dry_run {
+            for uuid in &taken_over {
+                info!("[{}] Cancelling backrun bundle {}", bundle_id, uuid);
+                executor.cancel_bundle(uuid).await;
+            }
+        }
+
         info!(
             "🥪🥪🥪 Sandwiches: {:?} ({})",
             final_batch_sandwich.sandwiches.len(),
@@ -263,13 +1182,26 @@ pub async fn main_dish(
             "> Base fee: {:?} / Priority fee: {:?} / Max fee: {:?} / Bribe: {:?}",
             base_fee, max_priority_fee_per_gas, max_fee_per_gas, bribe_amount
         );
+        let costs = simulated_sandwich.cost_breakdown(
+            bribe_amount,
+            front_priority_fee_per_gas,
+            max_priority_fee_per_gas,
+            tracker.inclusion_rate(),
+        );
+        info!(
+            "> Front gas: {:?} ({:?} wei) / Back gas: {:?} ({:?} wei)",
+            simulated_sandwich.front_gas_used,
+            costs.front_gas_cost,
+            simulated_sandwich.back_gas_used,
+            costs.back_gas_cost
+        );
         info!(
-            "> Revenue: {:?} / Profit: {:?} / Gas cost: {:?}",
-            simulated_sandwich.revenue, simulated_sandwich.profit, simulated_sandwich.gas_cost
+            "> Gross: {:?} / Conversion slippage: {:?} / Bribe: {:?} / Builder payment: {:?}",
+            costs.gross, costs.conversion_slippage, costs.bribe, costs.builder_payment
         );
         info!(
-            "> Front gas: {:?} / Back gas: {:?}",
-            simulated_sandwich.front_gas_used, simulated_sandwich.back_gas_used
+            "> Net: {:?} / Inclusion probability: {:.2} / Expected value: {:?}",
+            costs.net, costs.inclusion_probability, costs.expected_value
         );
 
         let message = format!(
@@ -284,14 +1216,22 @@ pub async fn main_dish(
             _ => {}
         }
 
-        let victim_tx_hashes = final_batch_sandwich.victim_tx_hashes();
-        let mut victim_txs = Vec::new();
-        for tx_hash in victim_tx_hashes {
-            if let Some(tx_info) = pending_txs.get(&tx_hash) {
-                let tx = tx_info.pending_tx.tx.clone();
-                victim_txs.push(tx);
-            }
-        }
+        let retargeted_calldata = if env.dry_run || executor.sequencer_mode {
+            HashMap::new()
+        } else {
+            retarget_calldata(
+                &provider,
+                &final_batch_sandwich,
+                owner,
+                new_block.block_number,
+                env.bundle_target_blocks,
+                base_fee,
+                &simulated_sandwich.front_access_list,
+                &simulated_sandwich.back_access_list,
+                bot_address,
+            )
+            .await
+        };
 
         let sando_bundle = executor
             .create_sando_bundle(
@@ -303,21 +1243,198 @@ pub async fn main_dish(
                 front_gas_limit,
                 back_gas_limit,
                 base_fee,
+                front_priority_fee_per_gas,
                 max_priority_fee_per_gas,
                 max_fee_per_gas,
+                new_block.block_number + U64::from(std::cmp::max(env.bundle_target_blocks, 1)),
             )
             .await;
         if sando_bundle.is_err() {
             let e = sando_bundle.as_ref().err().unwrap();
+            failures.record(Failure::EncodingError);
             warn!("Executor.create_sando_bundle error: {e:?}");
             continue;
         }
         let sando_bundle = sando_bundle.unwrap();
-        match send_sando_bundle_request(&executor, sando_bundle, new_block.block_number, &alert)
+
+        let mut timeline = BundleTimeline::default();
+        for tx_hash in &victim_tx_hashes {
+            if let Some(tx_info) = pending_txs.get(tx_hash) {
+                let first_seen = tx_info.pending_tx.first_seen_ms;
+                if timeline.first_seen == 0 || first_seen < timeline.first_seen {
+                    timeline.first_seen = first_seen;
+                }
+                timeline.traced = timeline.traced.max(tx_info.traced_ms);
+            }
+        }
+        // the final sandwiches are rebuilt without their optimizer results, the candidates have them
+        for sandwich in &final_batch_sandwich.sandwiches {
+            let optimized_ms = promising_sandwiches
+                .get(&sandwich.victim_tx.tx_hash)
+                .and_then(|candidates| {
+                    candidates
+                        .iter()
+                        .find(|c| c.swap_info.target_pair == sandwich.swap_info.target_pair)
+                })
+                .and_then(|c| c.optimized_sandwich.as_ref())
+                .map(|o| o.optimized_ms);
+            if let Some(optimized_ms) = optimized_ms {
+                timeline.optimized = timeline.optimized.max(optimized_ms);
+            }
+        }
+
+        if env.dry_run {
+            match dry_run_sando_bundle(&executor, sando_bundle, &bundle_id, new_block.block_number)
+                .await
+            {
+                Ok(variant) => {
+                    let trade = TradeEntry {
+                        bundle_id: bundle_id.clone(),
+                        victim_tx_hashes: victim_tx_hashes.clone(),
+                        pairs: final_batch_sandwich.target_v2_pairs(),
+                        amounts_in: final_batch_sandwich
+                            .sandwiches
+                            .iter()
+                            .map(|s| (s.swap_info.main_currency, s.amount_in))
+                            .collect(),
+                        simulated_revenue: simulated_sandwich.revenue,
+                        expected_profit,
+                        gas_used: simulated_sandwich.front_gas_used
+                            + simulated_sandwich.back_gas_used,
+                        gas_cost: costs.front_gas_cost + costs.back_gas_cost,
+                        bribe: bribe_amount,
+                        builders: Vec::new(),
+                        target_block: variant.target_block,
+                        dry_run: true,
+                    };
+                    tracker.record_trade(&trade);
+                }
+                Err(e) => {
+                    failures.record_error(&e);
+                    warn!("dry_run_sando_bundle error: {e:?}");
+                }
+            }
+            continue;
+        }
+
+        let sent = if executor.sequencer_mode {
+            executor
+                .send_to_sequencer(sando_bundle)
+                .await
+                .context(Failure::BuilderRejected)
+                .map(|tx_hashes| {
+                    // signed and sent one after the other, there's no separate signing step to time
+                    timeline
+                        .broadcast
+                        .insert(String::from("sequencer"), now_ms());
+                    let variants = vec![BundleVariant {
+                        target_block: new_block.block_number + U64::from(1),
+                        frontrun_tx_hash: tx_hashes[0],
+                        replacement_uuid: String::new(),
+                    }];
+                    (None, variants)
+                })
+        } else {
+            send_sando_bundle_request(
+                &executor,
+                sando_bundle,
+                &bundle_id,
+                new_block.block_number,
+                &replacement_uuid,
+                env.bundle_target_blocks,
+                &retargeted_calldata,
+                env.bundle_bribe_decay_bps,
+                &alert,
+                &mut timeline,
+            )
             .await
-        {
-            Err(e) => warn!("send_sando_bundle_request error: {e:?}"),
-            _ => {}
+        };
+        match sent {
+            Ok((bundle_hash, variants)) => {
+                if !executor.sequencer_mode {
+                    replacements.record(
+                        &victim_tx_hashes,
+                        claim.target_block,
+                        claim.value,
+                        &replacement_uuid,
+                    );
+                    for uuid in &stale_uuids {
+                        executor
+                            .cancel_bundle_variants(uuid, env.bundle_target_blocks)
+                            .await;
+                    }
+                }
+                let mut exposure = HashMap::new();
+                for sandwich in &final_batch_sandwich.sandwiches {
+                    let amount = exposure
+                        .entry(sandwich.swap_info.main_currency)
+                        .or_insert(U256::zero());
+                    *amount = *amount + sandwich.amount_in;
+                }
+                let token_exposure = final_batch_sandwich
+                    .sandwiches
+                    .iter()
+                    .map(|s| {
+                        (
+                            s.swap_info.target_token,
+                            s.swap_info.main_currency,
+                            s.amount_in,
+                        )
+                    })
+                    .collect();
+                bribe_policy.on_sent(
+                    &bundle_id,
+                    final_batch_sandwich.target_v2_pairs(),
+                    bribe_pct,
+                    !contested.is_empty(),
+                );
+                let trade = TradeEntry {
+                    bundle_id: bundle_id.clone(),
+                    victim_tx_hashes: victim_tx_hashes.clone(),
+                    pairs: final_batch_sandwich.target_v2_pairs(),
+                    amounts_in: final_batch_sandwich
+                        .sandwiches
+                        .iter()
+                        .map(|s| (s.swap_info.main_currency, s.amount_in))
+                        .collect(),
+                    simulated_revenue: simulated_sandwich.revenue,
+                    expected_profit,
+                    gas_used: simulated_sandwich.front_gas_used + simulated_sandwich.back_gas_used,
+                    gas_cost: costs.front_gas_cost + costs.back_gas_cost,
+                    bribe: bribe_amount,
+                    builders: timeline.broadcast.keys().cloned().collect(),
+                    target_block: variants[0].target_block,
+                    dry_run: false,
+                };
+                tracker.track(
+                    bundle_id.clone(),
+                    variants,
+                    bundle_hash,
+                    expected_profit,
+                    final_batch_sandwich.target_v2_pairs(),
+                    exposure,
+                    token_exposure,
+                    simulated_sandwich.mc_balance_deltas.clone(),
+                    arm,
+                    timeline,
+                    context.name.clone(),
+                );
+                tracker.record_trade(&trade);
+                alert.event(AlertEvent::BundleSent {
+                    bundle_id: trade.bundle_id,
+                    context: context.name.clone(),
+                    target_block: trade.target_block,
+                    victim_tx_hashes: trade.victim_tx_hashes,
+                    pairs: trade.pairs,
+                    expected_profit: trade.expected_profit,
+                    bribe: trade.bribe,
+                    builders: trade.builders,
+                });
+            }
+            Err(e) => {
+                failures.record_error(&e);
+                warn!("send_sando_bundle_request error: {e:?}");
+            }
         }
     }
 
@@ -1,4 +1,18 @@
+pub mod access_lists;
 pub mod appetizer;
+pub mod arbitrage;
+pub mod backrun;
+pub mod backtest;
+pub mod bribe;
+pub mod call_detection;
+pub mod candidates;
+pub mod competitors;
+pub mod liquidity;
 pub mod main_dish;
+pub mod router_decoder;
+pub mod routes;
 pub mod simulation;
+pub mod simulation_cache;
+pub mod slippage;
 pub mod strategy;
+pub mod victims;
@@ -0,0 +1,585 @@
+use ethers::abi::{ParamType, Token};
+use ethers::prelude::Lazy;
+use ethers::types::{Transaction, H160, U256};
+use ethers::utils::id;
+use std::collections::HashMap;
+
+use crate::common::pools::Pool;
+use crate::common::utils::is_main_currency;
+
+/*
+Decodes the swaps of the usual routers straight from the calldata (pending_tx.tx.input),
+without a debug_traceCall round trip:
+- Uniswap V2 routers (and their forks), V3 SwapRouter / SwapRouter02, multicalls included
+- Uniswap Universal Router: V2 / V3 swap commands of execute
+- 1inch AggregationRouter V4 / V5: swap, unoswap, uniswapV3Swap
+Routers are recognized by selector only, so forks at other addresses decode the same.
+extract_swap_info uses it as a pre-filter: a tx whose swaps can't touch any pool we sandwich
+(V3 only, no main currency on any V2 hop, none of our pairs) isn't traced at all.
+Calldata we don't know, or an aggregator swap with an opaque route, is always traced.
+The amounts and the victim's slippage bound (amount_out_min / amount_in_max) come along.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterKind {
+    UniswapV2,
+    UniswapV3,
+    UniversalRouter,
+    OneInch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedPath {
+    V2Tokens(Vec<H160>), // token in, ..., token out
+    V2Pairs(Vec<H160>),  // pair addresses, in order (1inch unoswap)
+    V3Tokens(Vec<H160>), // token in, ..., token out (fee tiers dropped)
+    V3Pools(Vec<H160>),  // pool addresses, in order (1inch uniswapV3Swap)
+    Opaque,              // aggregator executor calldata: only token in / out are known
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodedSwap {
+    pub router: RouterKind,
+    pub path: DecodedPath,
+    pub token_in: Option<H160>,
+    pub token_out: Option<H160>,
+    pub amount_in: Option<U256>, // exact amount in, None: exact out, or whatever the router holds
+    pub amount_out: Option<U256>, // exact amount out
+    pub amount_out_min: Option<U256>, // slippage bound of an exact in swap
+    pub amount_in_max: Option<U256>, // slippage bound of an exact out swap
+    pub deadline: Option<U256>,
+}
+
+impl DecodedSwap {
+    fn new(router: RouterKind, path: DecodedPath) -> Self {
+        let (token_in, token_out) = match &path {
+            DecodedPath::V2Tokens(tokens) | DecodedPath::V3Tokens(tokens) => {
+                (tokens.first().copied(), tokens.last().copied())
+            }
+            _ => (None, None),
+        };
+        Self {
+            router,
+            path,
+            token_in,
+            token_out,
+            amount_in: None,
+            amount_out: None,
+            amount_out_min: None,
+            amount_in_max: None,
+            deadline: None,
+        }
+    }
+
+    fn exact_in(mut self, amount_in: Option<U256>, amount_out_min: Option<U256>) -> Self {
+        self.amount_in = amount_in;
+        self.amount_out_min = amount_out_min;
+        self
+    }
+
+    fn exact_out(mut self, amount_out: Option<U256>, amount_in_max: Option<U256>) -> Self {
+        self.amount_out = amount_out;
+        self.amount_in_max = amount_in_max;
+        self
+    }
+
+    fn with_deadline(mut self, deadline: Option<U256>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    // Whether any hop could go through a pool we sandwich (V2 pools with a main currency, or Curve)
+    pub fn may_touch(&self, pools_map: &HashMap<H160, Pool>) -> bool {
+        match &self.path {
+            DecodedPath::V2Tokens(tokens) => tokens
+                .windows(2)
+                .any(|hop| is_main_currency(hop[0]) || is_main_currency(hop[1])),
+            DecodedPath::V2Pairs(pairs) => pairs.iter().any(|pair| pools_map.contains_key(pair)),
+            DecodedPath::V3Tokens(_) | DecodedPath::V3Pools(_) => false,
+            DecodedPath::Opaque => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Method {
+    V2ExactIn,     // (amountIn, amountOutMin, path, to, deadline)
+    V2ExactEthIn,  // (amountOutMin, path, to, deadline), amount in: msg.value
+    V2ExactOut,    // (amountOut, amountInMax, path, to, deadline)
+    V2EthExactOut, // (amountOut, path, to, deadline), max amount in: msg.value
+    V2ExactIn02,   // SwapRouter02: (amountIn, amountOutMin, path, to)
+    V2ExactOut02,  // SwapRouter02: (amountOut, amountInMax, path, to)
+    V3ExactInputSingle,
+    V3ExactInput,
+    V3ExactOutputSingle,
+    V3ExactOutput,
+    V3ExactInputSingle02,
+    V3ExactInput02,
+    V3ExactOutputSingle02,
+    V3ExactOutput02,
+    Multicall,
+    MulticallDeadline,
+    MulticallBlockhash,
+    UniversalExecute,
+    UniversalExecuteDeadline,
+    OneInchV4Swap,
+    OneInchV5Swap,
+    OneInchV4Unoswap,
+    OneInchV5Unoswap,
+    OneInchV5UnoswapTo,
+    OneInchUniswapV3Swap,
+    OneInchUniswapV3SwapTo,
+}
+
+static METHODS: &[(&str, Method)] = &[
+    ("swapExactTokensForTokens(uint256,uint256,address[],address,uint256)", Method::V2ExactIn),
+    ("swapExactTokensForETH(uint256,uint256,address[],address,uint256)", Method::V2ExactIn),
+    ("swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)", Method::V2ExactIn),
+    ("swapExactTokensForETHSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)", Method::V2ExactIn),
+    ("swapExactETHForTokens(uint256,address[],address,uint256)", Method::V2ExactEthIn),
+    ("swapExactETHForTokensSupportingFeeOnTransferTokens(uint256,address[],address,uint256)", Method::V2ExactEthIn),
+    ("swapTokensForExactTokens(uint256,uint256,address[],address,uint256)", Method::V2ExactOut),
+    ("swapTokensForExactETH(uint256,uint256,address[],address,uint256)", Method::V2ExactOut),
+    ("swapETHForExactTokens(uint256,address[],address,uint256)", Method::V2EthExactOut),
+    ("swapExactTokensForTokens(uint256,uint256,address[],address)", Method::V2ExactIn02),
+    ("swapTokensForExactTokens(uint256,uint256,address[],address)", Method::V2ExactOut02),
+    ("exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))", Method::V3ExactInputSingle),
+    ("exactInput((bytes,address,uint256,uint256,uint256))", Method::V3ExactInput),
+    ("exactOutputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))", Method::V3ExactOutputSingle),
+    ("exactOutput((bytes,address,uint256,uint256,uint256))", Method::V3ExactOutput),
+    ("exactInputSingle((address,address,uint24,address,uint256,uint256,uint160))", Method::V3ExactInputSingle02),
+    ("exactInput((bytes,address,uint256,uint256))", Method::V3ExactInput02),
+    ("exactOutputSingle((address,address,uint24,address,uint256,uint256,uint160))", Method::V3ExactOutputSingle02),
+    ("exactOutput((bytes,address,uint256,uint256))", Method::V3ExactOutput02),
+    ("multicall(bytes[])", Method::Multicall),
+    ("multicall(uint256,bytes[])", Method::MulticallDeadline),
+    ("multicall(bytes32,bytes[])", Method::MulticallBlockhash),
+    ("execute(bytes,bytes[])", Method::UniversalExecute),
+    ("execute(bytes,bytes[],uint256)", Method::UniversalExecuteDeadline),
+    ("swap(address,(address,address,address,address,uint256,uint256,uint256,bytes),bytes)", Method::OneInchV4Swap),
+    ("swap(address,(address,address,address,address,uint256,uint256,uint256),bytes,bytes)", Method::OneInchV5Swap),
+    ("unoswap(address,uint256,uint256,bytes32[])", Method::OneInchV4Unoswap),
+    ("unoswap(address,uint256,uint256,uint256[])", Method::OneInchV5Unoswap),
+    ("unoswapTo(address,address,uint256,uint256,uint256[])", Method::OneInchV5UnoswapTo),
+    ("uniswapV3Swap(uint256,uint256,uint256[])", Method::OneInchUniswapV3Swap),
+    ("uniswapV3SwapTo(address,uint256,uint256,uint256[])", Method::OneInchUniswapV3SwapTo),
+];
+
+static SELECTORS: Lazy<HashMap<[u8; 4], Method>> = Lazy::new(|| {
+    METHODS
+        .iter()
+        .map(|(sig, method)| (id(sig), *method))
+        .collect()
+});
+
+// Multicall steps that move tokens around a swap without swapping, skipped when decoding a multicall
+static NON_SWAP_METHODS: [&str; 14] = [
+    "refundETH()",
+    "unwrapWETH9(uint256)",
+    "unwrapWETH9(uint256,address)",
+    "unwrapWETH9WithFee(uint256,address,uint256,address)",
+    "sweepToken(address,uint256)",
+    "sweepToken(address,uint256,address)",
+    "sweepTokenWithFee(address,uint256,address,uint256,address)",
+    "wrapETH(uint256)",
+    "pull(address,uint256)",
+    "selfPermit(address,uint256,uint256,uint8,bytes32,bytes32)",
+    "selfPermitIfNecessary(address,uint256,uint256,uint8,bytes32,bytes32)",
+    "selfPermitAllowed(address,uint256,uint256,uint8,bytes32,bytes32)",
+    "selfPermitAllowedIfNecessary(address,uint256,uint256,uint8,bytes32,bytes32)",
+    "approveMax(address)",
+];
+
+static NON_SWAP_SELECTORS: Lazy<Vec<[u8; 4]>> =
+    Lazy::new(|| NON_SWAP_METHODS.iter().map(|sig| id(sig)).collect());
+
+// Universal Router commands (the low 6 bits of each command byte)
+pub static UR_V3_SWAP_EXACT_IN: u8 = 0x00;
+pub static UR_V3_SWAP_EXACT_OUT: u8 = 0x01;
+pub static UR_V2_SWAP_EXACT_IN: u8 = 0x08;
+pub static UR_V2_SWAP_EXACT_OUT: u8 = 0x09;
+pub static UR_COMMAND_MASK: u8 = 0x3f;
+// Commands that don't swap: permit2, transfers, sweeps, (un)wrapping, balance checks.
+// Any other one (sub plans, V4, NFT marketplaces...) makes the plan unknown
+pub static UR_NON_SWAP_COMMANDS: [u8; 10] = [
+    0x02, // PERMIT2_TRANSFER_FROM
+    0x03, // PERMIT2_PERMIT_BATCH
+    0x04, // SWEEP
+    0x05, // TRANSFER
+    0x06, // PAY_PORTION
+    0x0a, // PERMIT2_PERMIT
+    0x0b, // WRAP_ETH
+    0x0c, // UNWRAP_WETH
+    0x0d, // PERMIT2_TRANSFER_FROM_BATCH
+    0x0e, // BALANCE_CHECK_ERC20
+];
+
+// "use the router's whole balance" amount of the Universal Router (SwapRouter02 uses 0)
+static CONTRACT_BALANCE: Lazy<U256> = Lazy::new(|| U256::one() << 255);
+
+// Multicalls can nest, but not forever
+pub static MAX_MULTICALL_DEPTH: usize = 3;
+
+fn uint(token: &Token) -> Option<U256> {
+    token.clone().into_uint()
+}
+
+fn address(token: &Token) -> Option<H160> {
+    token.clone().into_address()
+}
+
+fn addresses(token: &Token) -> Option<Vec<H160>> {
+    token
+        .clone()
+        .into_array()?
+        .iter()
+        .map(address)
+        .collect::<Option<Vec<H160>>>()
+}
+
+// Low 160 bits of each word: 1inch packs flags above the pool address
+fn packed_addresses(token: &Token) -> Option<Vec<H160>> {
+    token
+        .clone()
+        .into_array()?
+        .into_iter()
+        .map(|word| match word {
+            Token::Uint(word) => {
+                let mut bytes = [0u8; 32];
+                word.to_big_endian(&mut bytes);
+                Some(H160::from_slice(&bytes[12..]))
+            }
+            Token::FixedBytes(bytes) if bytes.len() == 32 => Some(H160::from_slice(&bytes[12..])),
+            _ => None,
+        })
+        .collect()
+}
+
+// token (20 bytes) | fee (3 bytes) | token | fee | ... | token
+fn v3_path_tokens(path: &[u8]) -> Option<Vec<H160>> {
+    if path.len() < 43 || (path.len() - 20) % 23 != 0 {
+        return None;
+    }
+    Some(
+        (0..=(path.len() - 20) / 23)
+            .map(|i| H160::from_slice(&path[i * 23..i * 23 + 20]))
+            .collect(),
+    )
+}
+
+fn sized(amount: Option<U256>) -> Option<U256> {
+    amount.filter(|amount| !amount.is_zero() && *amount != *CONTRACT_BALANCE)
+}
+
+fn v2_swap(path: Vec<H160>) -> DecodedSwap {
+    DecodedSwap::new(RouterKind::UniswapV2, DecodedPath::V2Tokens(path))
+}
+
+fn v3_swap(path: Vec<H160>) -> DecodedSwap {
+    DecodedSwap::new(RouterKind::UniswapV3, DecodedPath::V3Tokens(path))
+}
+
+fn decode_universal_router(commands: &[u8], inputs: &[Token]) -> Option<Vec<DecodedSwap>> {
+    let v3_input = [
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Bytes,
+        ParamType::Bool,
+    ];
+    let v2_input = [
+        ParamType::Address,
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Array(Box::new(ParamType::Address)),
+        ParamType::Bool,
+    ];
+    let mut swaps = Vec::new();
+    for (command, input) in commands.iter().zip(inputs) {
+        let command = command & UR_COMMAND_MASK;
+        let input = match input.clone().into_bytes() {
+            Some(input) => input,
+            None => return None,
+        };
+        let is_v3 = command == UR_V3_SWAP_EXACT_IN || command == UR_V3_SWAP_EXACT_OUT;
+        let is_v2 = command == UR_V2_SWAP_EXACT_IN || command == UR_V2_SWAP_EXACT_OUT;
+        if !is_v2 && !is_v3 {
+            if UR_NON_SWAP_COMMANDS.contains(&command) {
+                continue;
+            }
+            return None;
+        }
+        let params = ethers::abi::decode(if is_v3 { &v3_input } else { &v2_input }, &input).ok()?;
+        let (amount, limit) = (uint(&params[1]), uint(&params[2]));
+        let swap = if is_v3 {
+            let mut path = v3_path_tokens(&params[3].clone().into_bytes()?)?;
+            if command == UR_V3_SWAP_EXACT_OUT {
+                path.reverse(); // exact out paths start from the token out
+            }
+            v3_swap(path)
+        } else {
+            v2_swap(addresses(&params[3])?)
+        };
+        let swap = if command == UR_V3_SWAP_EXACT_IN || command == UR_V2_SWAP_EXACT_IN {
+            swap.exact_in(sized(amount), limit)
+        } else {
+            swap.exact_out(amount, limit)
+        };
+        swaps.push(DecodedSwap {
+            router: RouterKind::UniversalRouter,
+            ..swap
+        });
+    }
+    Some(swaps)
+}
+
+fn decode_call(data: &[u8], value: U256, depth: usize) -> Option<Vec<DecodedSwap>> {
+    if data.len() < 4 {
+        return None;
+    }
+    let selector: [u8; 4] = data[0..4].try_into().unwrap();
+    let method = *SELECTORS.get(&selector)?;
+    let args = &data[4..];
+
+    let uint256 = || ParamType::Uint(256);
+    let address_array = || ParamType::Array(Box::new(ParamType::Address));
+    let uint_array = || ParamType::Array(Box::new(ParamType::Uint(256)));
+    let bytes_array = || ParamType::Array(Box::new(ParamType::Bytes));
+    let decode = |types: &[ParamType]| ethers::abi::decode(types, args).ok();
+
+    let swaps = match method {
+        Method::V2ExactIn => {
+            let p = decode(&[
+                uint256(),
+                uint256(),
+                address_array(),
+                ParamType::Address,
+                uint256(),
+            ])?;
+            vec![v2_swap(addresses(&p[2])?)
+                .exact_in(uint(&p[0]), uint(&p[1]))
+                .with_deadline(uint(&p[4]))]
+        }
+        Method::V2ExactEthIn => {
+            let p = decode(&[uint256(), address_array(), ParamType::Address, uint256()])?;
+            vec![v2_swap(addresses(&p[1])?)
+                .exact_in(Some(value), uint(&p[0]))
+                .with_deadline(uint(&p[3]))]
+        }
+        Method::V2ExactOut => {
+            let p = decode(&[
+                uint256(),
+                uint256(),
+                address_array(),
+                ParamType::Address,
+                uint256(),
+            ])?;
+            vec![v2_swap(addresses(&p[2])?)
+                .exact_out(uint(&p[0]), uint(&p[1]))
+                .with_deadline(uint(&p[4]))]
+        }
+        Method::V2EthExactOut => {
+            let p = decode(&[uint256(), address_array(), ParamType::Address, uint256()])?;
+            vec![v2_swap(addresses(&p[1])?)
+                .exact_out(uint(&p[0]), Some(value))
+                .with_deadline(uint(&p[3]))]
+        }
+        Method::V2ExactIn02 | Method::V2ExactOut02 => {
+            let p = decode(&[uint256(), uint256(), address_array(), ParamType::Address])?;
+            let swap = v2_swap(addresses(&p[2])?);
+            match method {
+                Method::V2ExactIn02 => vec![swap.exact_in(sized(uint(&p[0])), uint(&p[1]))],
+                _ => vec![swap.exact_out(uint(&p[0]), uint(&p[1]))],
+            }
+        }
+        Method::V3ExactInputSingle
+        | Method::V3ExactOutputSingle
+        | Method::V3ExactInputSingle02
+        | Method::V3ExactOutputSingle02 => {
+            // (tokenIn, tokenOut, fee, recipient, [deadline,] amount, limit, sqrtPriceLimitX96)
+            let with_deadline = matches!(
+                method,
+                Method::V3ExactInputSingle | Method::V3ExactOutputSingle
+            );
+            let mut fields = vec![
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(24),
+                ParamType::Address,
+            ];
+            if with_deadline {
+                fields.push(uint256());
+            }
+            fields.extend([uint256(), uint256(), ParamType::Uint(160)]);
+            let p = decode(&[ParamType::Tuple(fields)])?;
+            let p = p[0].clone().into_tuple()?;
+            let offset = if with_deadline { 5 } else { 4 };
+            let swap = v3_swap(vec![address(&p[0])?, address(&p[1])?]);
+            let swap = match method {
+                Method::V3ExactInputSingle | Method::V3ExactInputSingle02 => {
+                    swap.exact_in(sized(uint(&p[offset])), uint(&p[offset + 1]))
+                }
+                _ => swap.exact_out(uint(&p[offset]), uint(&p[offset + 1])),
+            };
+            let deadline = if with_deadline { uint(&p[4]) } else { None };
+            vec![swap.with_deadline(deadline)]
+        }
+        Method::V3ExactInput
+        | Method::V3ExactOutput
+        | Method::V3ExactInput02
+        | Method::V3ExactOutput02 => {
+            // (path, recipient, [deadline,] amount, limit)
+            let with_deadline = matches!(method, Method::V3ExactInput | Method::V3ExactOutput);
+            let mut fields = vec![ParamType::Bytes, ParamType::Address];
+            if with_deadline {
+                fields.push(uint256());
+            }
+            fields.extend([uint256(), uint256()]);
+            let p = decode(&[ParamType::Tuple(fields)])?;
+            let p = p[0].clone().into_tuple()?;
+            let offset = if with_deadline { 3 } else { 2 };
+            let mut path = v3_path_tokens(&p[0].clone().into_bytes()?)?;
+            let exact_in = matches!(method, Method::V3ExactInput | Method::V3ExactInput02);
+            if !exact_in {
+                path.reverse();
+            }
+            let swap = if exact_in {
+                v3_swap(path).exact_in(sized(uint(&p[offset])), uint(&p[offset + 1]))
+            } else {
+                v3_swap(path).exact_out(uint(&p[offset]), uint(&p[offset + 1]))
+            };
+            let deadline = if with_deadline { uint(&p[2]) } else { None };
+            vec![swap.with_deadline(deadline)]
+        }
+        Method::Multicall | Method::MulticallDeadline | Method::MulticallBlockhash => {
+            if depth >= MAX_MULTICALL_DEPTH {
+                return None;
+            }
+            let (calls, deadline) = match method {
+                Method::Multicall => (decode(&[bytes_array()])?.remove(0), None),
+                Method::MulticallDeadline => {
+                    let p = decode(&[uint256(), bytes_array()])?;
+                    (p[1].clone(), uint(&p[0]))
+                }
+                _ => (
+                    decode(&[ParamType::FixedBytes(32), bytes_array()])?.remove(1),
+                    None,
+                ),
+            };
+            let mut swaps = Vec::new();
+            for call in calls.into_array()? {
+                let call = call.into_bytes()?;
+                // refundETH, unwrapWETH9, selfPermit... don't swap, any other call we can't read makes the multicall unknown
+                if call.len() >= 4 && NON_SWAP_SELECTORS.iter().any(|s| *s == call[0..4]) {
+                    continue;
+                }
+                for swap in decode_call(&call, value, depth + 1)? {
+                    swaps.push(match swap.deadline {
+                        Some(_) => swap,
+                        None => swap.with_deadline(deadline),
+                    });
+                }
+            }
+            swaps
+        }
+        Method::UniversalExecute | Method::UniversalExecuteDeadline => {
+            let with_deadline = matches!(method, Method::UniversalExecuteDeadline);
+            let mut types = vec![ParamType::Bytes, bytes_array()];
+            if with_deadline {
+                types.push(uint256());
+            }
+            let p = decode(&types)?;
+            let commands = p[0].clone().into_bytes()?;
+            let inputs = p[1].clone().into_array()?;
+            let deadline = if with_deadline { uint(&p[2]) } else { None };
+            decode_universal_router(&commands, &inputs)?
+                .into_iter()
+                .map(|swap| swap.with_deadline(deadline))
+                .collect()
+        }
+        Method::OneInchV4Swap | Method::OneInchV5Swap => {
+            // desc: (srcToken, dstToken, srcReceiver, dstReceiver, amount, minReturnAmount, flags[, permit])
+            let mut desc = vec![
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Address,
+                uint256(),
+                uint256(),
+                uint256(),
+            ];
+            let types = match method {
+                Method::OneInchV4Swap => {
+                    desc.push(ParamType::Bytes);
+                    vec![ParamType::Address, ParamType::Tuple(desc), ParamType::Bytes]
+                }
+                _ => vec![
+                    ParamType::Address,
+                    ParamType::Tuple(desc),
+                    ParamType::Bytes,
+                    ParamType::Bytes,
+                ],
+            };
+            let p = decode(&types)?;
+            let desc = p[1].clone().into_tuple()?;
+            let mut swap = DecodedSwap::new(RouterKind::OneInch, DecodedPath::Opaque)
+                .exact_in(uint(&desc[4]), uint(&desc[5]));
+            swap.token_in = address(&desc[0]);
+            swap.token_out = address(&desc[1]);
+            vec![swap]
+        }
+        Method::OneInchV4Unoswap | Method::OneInchV5Unoswap | Method::OneInchV5UnoswapTo => {
+            let pools = match method {
+                Method::OneInchV4Unoswap => ParamType::Array(Box::new(ParamType::FixedBytes(32))),
+                _ => uint_array(),
+            };
+            let mut types = vec![ParamType::Address, uint256(), uint256(), pools];
+            if matches!(method, Method::OneInchV5UnoswapTo) {
+                types.insert(0, ParamType::Address);
+            }
+            let p = decode(&types)?;
+            let offset = types.len() - 4;
+            let mut swap = DecodedSwap::new(
+                RouterKind::OneInch,
+                DecodedPath::V2Pairs(packed_addresses(&p[offset + 3])?),
+            )
+            .exact_in(uint(&p[offset + 1]), uint(&p[offset + 2]));
+            swap.token_in = address(&p[offset]);
+            vec![swap]
+        }
+        Method::OneInchUniswapV3Swap | Method::OneInchUniswapV3SwapTo => {
+            let mut types = vec![uint256(), uint256(), uint_array()];
+            if matches!(method, Method::OneInchUniswapV3SwapTo) {
+                types.insert(0, ParamType::Address);
+            }
+            let p = decode(&types)?;
+            let offset = types.len() - 3;
+            vec![DecodedSwap::new(
+                RouterKind::OneInch,
+                DecodedPath::V3Pools(packed_addresses(&p[offset + 2])?),
+            )
+            .exact_in(uint(&p[offset]), uint(&p[offset + 1]))]
+        }
+    };
+    Some(swaps)
+}
+
+// The swaps in the tx's calldata, None if it isn't a router call we can read
+pub fn decode_router_swaps(tx: &Transaction) -> Option<Vec<DecodedSwap>> {
+    decode_call(&tx.input, tx.value, 0)
+}
+
+// Runs before debug_traceCall: Some(reason) if the calldata says none of the swaps can touch our pools
+pub fn skip_by_calldata(tx: &Transaction, pools_map: &HashMap<H160, Pool>) -> Option<String> {
+    let swaps = decode_router_swaps(tx)?;
+    if swaps.iter().any(|swap| swap.may_touch(pools_map)) {
+        return None;
+    }
+    match swaps.first() {
+        Some(swap) => Some(format!(
+            "{:?} swap{} through none of our pools",
+            swap.router,
+            if swaps.len() > 1 { "s" } else { "" }
+        )),
+        None => Some(String::from("router call without swaps")),
+    }
+}
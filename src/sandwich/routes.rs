@@ -0,0 +1,102 @@
+use ethers::types::{H160, U256};
+
+use crate::sandwich::simulation::{SwapDirection, SwapInfo};
+
+/*
+Multi-hop victims: a router swap A -> WETH -> B emits one swap event per hop,
+and each used to be sandwiched as if it were a victim of its own.
+swap_info_from_frame now reads the victim's swaps (in log order) as routes:
+consecutive hops where the token out of one is the token in of the next.
+Each SwapInfo knows its hop in the route and its price impact (amount in / reserve in, from the pair's Sync),
+and the appetizer only sandwiches:
+- the most impactful buy of the route (the final hop on a tie)
+- and the other end of the route too, when it's a buy: both end up in the same bundle if both are profitable
+Hops in between move less than the ends we're already on, and are skipped.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouteHop {
+    pub hop: usize,      // position in the route, 0: the first swap of the victim
+    pub hops: usize,     // length of the route, 1: a plain single swap
+    pub impact_bps: u64, // victim's amount in, in bps of the pool's reserve in before the swap (0: unknown)
+}
+
+impl RouteHop {
+    pub fn single(impact_bps: u64) -> Self {
+        Self {
+            hop: 0,
+            hops: 1,
+            impact_bps,
+        }
+    }
+
+    pub fn is_multi_hop(&self) -> bool {
+        self.hops > 1
+    }
+}
+
+// (token in, token out) of the victim's swap
+pub fn swap_tokens(info: &SwapInfo) -> (H160, H160) {
+    match info.direction {
+        SwapDirection::Buy => (info.main_currency, info.target_token),
+        SwapDirection::Sell => (info.target_token, info.main_currency),
+    }
+}
+
+// Amount in, in bps of the reserve in before the swap: reserve_in_after already includes amount_in
+pub fn impact_bps(amount_in: U256, reserve_in_after: U256) -> u64 {
+    let reserve_in = reserve_in_after.saturating_sub(amount_in);
+    if reserve_in.is_zero() {
+        return 0;
+    }
+    let impact = amount_in * U256::from(10000) / reserve_in;
+    std::cmp::min(impact, U256::from(u64::MAX)).as_u64()
+}
+
+// Chains the swaps (in log order) into routes, and sets every hop's place in its route
+pub fn assign_routes(swap_info: &mut [SwapInfo]) {
+    let mut start = 0;
+    for i in 0..=swap_info.len() {
+        let chained = i > start
+            && i < swap_info.len()
+            && swap_tokens(&swap_info[i - 1]).1 == swap_tokens(&swap_info[i]).0;
+        if chained {
+            continue;
+        }
+        if i > start {
+            let hops = i - start;
+            for (hop, info) in swap_info[start..i].iter_mut().enumerate() {
+                info.route.hop = hop;
+                info.route.hops = hops;
+            }
+        }
+        start = i;
+    }
+}
+
+// Indices of the swaps worth sandwiching: every single-hop buy, and the ends of each multi-hop route described above
+pub fn sandwich_targets(swap_info: &[SwapInfo]) -> Vec<usize> {
+    let mut targets = Vec::new();
+    let mut i = 0;
+    while i < swap_info.len() {
+        let hops = std::cmp::max(swap_info[i].route.hops, 1);
+        let end = std::cmp::min(i + hops, swap_info.len());
+        let buys: Vec<usize> = (i..end)
+            .filter(|idx| swap_info[*idx].direction == SwapDirection::Buy)
+            .collect();
+        if hops == 1 {
+            targets.extend(buys);
+        } else if let Some(primary) = buys
+            .iter()
+            .copied()
+            .max_by_key(|idx| (swap_info[*idx].route.impact_bps, *idx))
+        {
+            targets.push(primary);
+            let other_end = if primary == end - 1 { i } else { end - 1 };
+            if other_end != primary && buys.contains(&other_end) {
+                targets.push(other_end);
+            }
+        }
+        i = end;
+    }
+    targets
+}
@@ -1,30 +1,50 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use eth_encode_packed::ethabi::ethereum_types::{H160 as eH160, U256 as eU256};
 use eth_encode_packed::{SolidityDataType, TakeLastXBytes};
 use ethers::abi::ParamType;
 use ethers::prelude::*;
-use ethers::providers::{Provider, Ws};
+use ethers::providers::Provider;
 use ethers::types::{transaction::eip2930::AccessList, Bytes, H160, H256, I256, U256, U64};
-use log::info;
+use itertools::Itertools;
+use log::{info, warn};
 use revm::primitives::{Bytecode, U256 as rU256};
 use std::{collections::HashMap, default::Default, str::FromStr, sync::Arc};
 
 use crate::common::bytecode::SANDOOO_BYTECODE;
-use crate::common::constants::{USDC, USDT};
+use crate::common::chain::CHAIN;
+use crate::common::constants::Env;
 use crate::common::evm::{EvmSimulator, Tx, VictimTx};
-use crate::common::pools::Pool;
+use crate::common::failures::{Failure, TxStage};
+use crate::common::latency::now_ms;
+use crate::common::pools::{get_v2_amount_out, CurvePool, DexVariant, Pool};
+use crate::common::pricing::{conversion_route, convert_to_weth};
+use crate::common::provider::RpcClient;
+use crate::common::reserves::pair_reserves;
+use crate::common::simulation_pool::SIMULATION_POOL;
 use crate::common::streams::{NewBlock, NewPendingTx};
+use crate::common::token_lists::TokenLists;
+use crate::common::tokens::{
+    estimate_v2_taxes, get_cached_balance_slot, get_round_trip_verdict, set_round_trip_verdict,
+    HONEYPOT_SELL_TAX_BPS,
+};
 use crate::common::utils::{
     create_new_wallet, is_weth, return_main_and_target_currency, MainCurrency,
 };
+use crate::sandwich::access_lists::get_or_trace_access_list;
+use crate::sandwich::liquidity::drop_illiquid_victim;
+use crate::sandwich::router_decoder::skip_by_calldata;
+use crate::sandwich::routes::{assign_routes, impact_bps, RouteHop};
+use crate::sandwich::simulation_cache::SIMULATION_CACHE;
+use crate::sandwich::victims::{classify_victim_logs, classify_victim_tx, VictimBudget};
 
 #[derive(Debug, Clone, Default)]
 pub struct PendingTxInfo {
     pub pending_tx: NewPendingTx,
     pub touched_pairs: Vec<SwapInfo>,
+    pub traced_ms: u64, // when touched_pairs were traced, unix ms
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SwapDirection {
     Buy,
     Sell,
@@ -37,8 +57,11 @@ pub struct SwapInfo {
     pub main_currency: H160,
     pub target_token: H160,
     pub version: u8,
+    pub fee: u32, // refers to the "fee" field of Pool struct
     pub token0_is_main: bool,
     pub direction: SwapDirection,
+    pub curve_indices: Option<(i128, i128)>, // Curve pools: (main_currency index, target_token index)
+    pub route: RouteHop, // where the swap is in the victim's route (see routes.rs)
 }
 
 #[derive(Debug, Clone)]
@@ -65,6 +88,53 @@ pub struct SimulatedSandwich {
     pub back_access_list: AccessList,
     pub front_calldata: Bytes,
     pub back_calldata: Bytes,
+    pub mc_balance_deltas: HashMap<H160, i128>, // main_currency -> balance change of the bot
+    pub front_gas_cost: i128,                   // front_gas_used at the simulated base fee
+    pub back_gas_cost: i128,                    // back_gas_used at the simulated base fee
+    pub conversion_slippage: i128, // WETH lost converting USDT/USDC/DAI gains at the pool instead of the mid price
+}
+
+/*
+Everything that goes into a bid, so "why did we bid this" can be answered from logs alone.
+All amounts in wei of the wrapped native token (WETH, WBNB), stables converted through their pair with it.
+Gas is paid in the native token, which is worth the same 1:1.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct CostBreakdown {
+    pub gross: i128, // main currency gained, before gas
+    pub front_gas_cost: i128,
+    pub back_gas_cost: i128,
+    pub conversion_slippage: i128, // already deducted from gross
+    pub bribe: U256,               // what we decided to give up for inclusion
+    pub builder_payment: U256, // priority fees the builder actually receives at the simulated gas
+    pub net: i128,             // gross - gas - bribe
+    pub inclusion_probability: f64,
+    pub expected_value: i128, // net, if the bundle lands (a bundle that misses costs nothing)
+}
+
+impl SimulatedSandwich {
+    pub fn cost_breakdown(
+        &self,
+        bribe: U256,
+        front_priority_fee_per_gas: U256,
+        back_priority_fee_per_gas: U256,
+        inclusion_probability: f64,
+    ) -> CostBreakdown {
+        let builder_payment = front_priority_fee_per_gas * U256::from(self.front_gas_used)
+            + back_priority_fee_per_gas * U256::from(self.back_gas_used);
+        let net = self.revenue - (bribe.as_u128() as i128);
+        CostBreakdown {
+            gross: self.profit,
+            front_gas_cost: self.front_gas_cost,
+            back_gas_cost: self.back_gas_cost,
+            conversion_slippage: self.conversion_slippage,
+            bribe,
+            builder_payment,
+            net,
+            inclusion_probability,
+            expected_value: (net as f64 * inclusion_probability) as i128,
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -77,12 +147,131 @@ pub struct OptimizedSandwich {
     pub back_access_list: AccessList,
     pub front_calldata: Bytes,
     pub back_calldata: Bytes,
+    pub optimized_ms: u64, // when the optimization finished, unix ms
+}
+
+/*
+Pools that can't be sandwiched no matter the amount, caught before the optimizer
+runs into zero reserves (and zero-profit results that look like a bad search)
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    ZeroLiquidity,      // one of the reserves is empty
+    TokenPaused,        // transfers of either token revert
+    PoolUnavailable,    // getReserves reverts
+    CompetingFrontrun,  // another bot already has a frontrun pending on the pool
+    Honeypot,           // a tiny buy of the target token can't be sold back
+    TokenNotAllowed,    // the target token is on the deny list, or off the allow list
+    LowLiquidity,       // the pool is below MIN_POOL_LIQUIDITY_WETH
+    NoSlippageRoom,     // the victim's slippage bound doesn't survive any frontrun
+    IntermediateHop, // a hop in the middle of a multi-hop route, the route's ends are sandwiched instead
+    CurveNotExecutable, // a Curve pool: simulated for the logs, the contract only swaps on V2 pools
+}
+
+impl SkipReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::ZeroLiquidity => "zero_liquidity",
+            SkipReason::TokenPaused => "token_paused",
+            SkipReason::PoolUnavailable => "pool_unavailable",
+            SkipReason::CompetingFrontrun => "competing_frontrun",
+            SkipReason::Honeypot => "honeypot",
+            SkipReason::TokenNotAllowed => "token_not_allowed",
+            SkipReason::LowLiquidity => "low_liquidity",
+            SkipReason::NoSlippageRoom => "no_slippage_room",
+            SkipReason::IntermediateHop => "intermediate_hop",
+            SkipReason::CurveNotExecutable => "curve_not_executable",
+        }
+    }
+}
+
+pub fn check_pool_state(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    info: &SwapInfo,
+) -> Option<SkipReason> {
+    if info.version == 2 {
+        match pair_reserves(simulator, info.target_pair) {
+            Ok((reserve0, reserve1)) => {
+                if reserve0.is_zero() || reserve1.is_zero() {
+                    return Some(SkipReason::ZeroLiquidity);
+                }
+            }
+            Err(_) => return Some(SkipReason::PoolUnavailable),
+        }
+    }
+    for token in [info.main_currency, info.target_token] {
+        if simulator.is_paused(token) {
+            return Some(SkipReason::TokenPaused);
+        }
+    }
+    None
+}
+
+/*
+Buys a tiny amount of the target token through the victim's V2 pool and sells it right back,
+on a fork of its own, before any time is spent optimizing a sandwich on it.
+If the sell reverts or gives back (almost) nothing, the token is a honeypot.
+The verdict is cached per token, so each token is only checked once per run.
+*/
+pub fn check_round_trip(
+    provider: Arc<Provider<RpcClient>>,
+    block_number: U64,
+    info: &SwapInfo,
+) -> Option<SkipReason> {
+    if info.version != 2 {
+        return None;
+    }
+    let honeypot = match get_round_trip_verdict(info.target_token) {
+        Some(honeypot) => honeypot,
+        None => {
+            let (token0, token1) = if info.token0_is_main {
+                (info.main_currency, info.target_token)
+            } else {
+                (info.target_token, info.main_currency)
+            };
+            let pool = Pool {
+                id: -1,
+                address: info.target_pair,
+                version: DexVariant::UniswapV2,
+                token0,
+                token1,
+                fee: info.fee,
+                block_number: 0,
+                timestamp: 0,
+            };
+            let mut simulator = EvmSimulator::new(provider, None, block_number);
+            match estimate_v2_taxes(&mut simulator, info.target_token, &pool) {
+                Ok((_, sell_tax_bps)) => {
+                    let honeypot = match sell_tax_bps {
+                        Some(sell_tax_bps) => sell_tax_bps >= HONEYPOT_SELL_TAX_BPS,
+                        None => true,
+                    };
+                    set_round_trip_verdict(info.target_token, honeypot);
+                    honeypot
+                }
+                // couldn't run the check: no verdict, the sandwich simulation will catch a revert
+                Err(e) => {
+                    warn!("estimate_v2_taxes error: {:?} {e:?}", info.target_token);
+                    false
+                }
+            }
+        }
+    };
+    if honeypot {
+        Some(SkipReason::Honeypot)
+    } else {
+        None
+    }
 }
 
 pub static V2_SWAP_EVENT_ID: &str = "0xd78ad95f";
+pub static V2_SYNC_EVENT_ID: &str = "0x1c411e9a"; // Sync(uint112,uint112), right before the pair's Swap
+pub static CURVE_EXCHANGE_EVENT_ID: &str = "0x8b3e96f2"; // TokenExchange(address,int128,uint256,int128,uint256)
+
+pub static CURVE_VERSION: u8 = 1;
 
 pub async fn debug_trace_call(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     new_block: &NewBlock,
     pending_tx: &NewPendingTx,
 ) -> Result<Option<CallFrame>> {
@@ -122,27 +311,187 @@ pub async fn debug_trace_call(
 }
 
 pub async fn extract_swap_info(
-    provider: &Arc<Provider<Ws>>,
+    provider: &Arc<Provider<RpcClient>>,
     new_block: &NewBlock,
     pending_tx: &NewPendingTx,
     pools_map: &HashMap<H160, Pool>,
+    curve_pools_map: &HashMap<H160, CurvePool>,
+    budget: &VictimBudget,
+    token_lists: &TokenLists,
 ) -> Result<Vec<SwapInfo>> {
-    let tx_hash = pending_tx.tx.hash;
-    let mut swap_info_vec = Vec::new();
+    if !classify_victim_tx(&pending_tx.tx).is_sandwichable() {
+        return Ok(Vec::new());
+    }
+    if let Some(reason) = budget.exceeded_by_tx(&pending_tx.tx) {
+        info!("Skipping {:?}: {}", pending_tx.tx.hash, reason);
+        return Ok(Vec::new());
+    }
+    if let Some(reason) = skip_by_calldata(&pending_tx.tx, pools_map) {
+        info!("Skipping {:?}: {}", pending_tx.tx.hash, reason);
+        return Ok(Vec::new());
+    }
 
-    let frame = debug_trace_call(provider, new_block, pending_tx).await?;
+    let frame = debug_trace_call(provider, new_block, pending_tx)
+        .await
+        .context(Failure::TraceUnavailable)?;
     if frame.is_none() {
-        return Ok(swap_info_vec);
+        return Ok(Vec::new());
     }
     let frame = frame.unwrap();
+    if let Some(reason) = budget.exceeded_by_trace(&pending_tx.tx, &frame) {
+        info!("Skipping {:?}: {}", pending_tx.tx.hash, reason);
+        return Ok(Vec::new());
+    }
+
+    let mut swap_info =
+        swap_info_from_frame(pending_tx.tx.hash, &frame, pools_map, curve_pools_map);
+    swap_info.retain(|info| token_lists.allows(info.target_token));
+    drop_illiquid_victim(provider, new_block, pending_tx.tx.hash, &mut swap_info);
+    Ok(swap_info)
+}
+
+/*
+Traces the candidates with debug_traceCallMany (Erigon/Reth), all requests sent at once
+instead of one debug_traceCall round trip after the other.
+Each candidate is traced in its own request on top of the block: txs in one bundle
+(and bundles in one request) run on each other's state, so a candidate would be traced
+against pools the candidates before it already moved, which the victim won't see if they don't land first.
+Nonces are left out so that candidates from the same sender don't fail each other.
+None: the trace couldn't be fetched or parsed.
+*/
+pub async fn debug_trace_call_many(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    pending_txs: &Vec<NewPendingTx>,
+) -> Vec<Option<CallFrame>> {
+    let state_context = serde_json::json!({
+        "blockNumber": new_block.block_number,
+        "transactionIndex": -1,
+    });
+    let tracing_options = serde_json::json!({
+        "tracer": "callTracer",
+        "tracerConfig": { "withLog": true },
+    });
+
+    let requests = pending_txs.iter().map(|pending_tx| {
+        let tx = &pending_tx.tx;
+        let bundles = serde_json::json!([{ "transactions": [{
+            "from": tx.from,
+            "to": tx.to,
+            "gas": tx.gas,
+            "value": tx.value,
+            "data": tx.input,
+        }] }]);
+        let state_context = state_context.clone();
+        let tracing_options = tracing_options.clone();
+        async move {
+            let traces: Vec<Vec<serde_json::Value>> = provider
+                .request(
+                    "debug_traceCallMany",
+                    (bundles, state_context, tracing_options),
+                )
+                .await
+                .ok()?;
+            let trace = traces.into_iter().next()?.into_iter().next()?;
+            serde_json::from_value::<CallFrame>(trace).ok()
+        }
+    });
+    futures::future::join_all(requests).await
+}
+
+pub async fn extract_swap_info_many(
+    provider: &Arc<Provider<RpcClient>>,
+    new_block: &NewBlock,
+    pending_txs: &Vec<NewPendingTx>,
+    pools_map: &HashMap<H160, Pool>,
+    curve_pools_map: &HashMap<H160, CurvePool>,
+    budget: &VictimBudget,
+    token_lists: &TokenLists,
+) -> Result<HashMap<H256, Vec<SwapInfo>>> {
+    let mut swap_info_map = HashMap::new();
+
+    let mut candidates = Vec::new();
+    for pending_tx in pending_txs {
+        if !classify_victim_tx(&pending_tx.tx).is_sandwichable() {
+            swap_info_map.insert(pending_tx.tx.hash, Vec::new());
+        } else if let Some(reason) = budget.exceeded_by_tx(&pending_tx.tx) {
+            info!("Skipping {:?}: {}", pending_tx.tx.hash, reason);
+            swap_info_map.insert(pending_tx.tx.hash, Vec::new());
+        } else if let Some(reason) = skip_by_calldata(&pending_tx.tx, pools_map) {
+            info!("Skipping {:?}: {}", pending_tx.tx.hash, reason);
+            swap_info_map.insert(pending_tx.tx.hash, Vec::new());
+        } else {
+            candidates.push(pending_tx.clone());
+        }
+    }
+    if candidates.is_empty() {
+        return Ok(swap_info_map);
+    }
+
+    let frames = debug_trace_call_many(provider, new_block, &candidates).await;
+    if frames.iter().all(|frame| frame.is_none()) {
+        return Err(
+            anyhow!("debug_traceCallMany failed for {} txs", candidates.len())
+                .context(Failure::TraceUnavailable),
+        );
+    }
+
+    // candidates without a trace are left out, to be traced one by one with debug_traceCall
+    for (pending_tx, frame) in candidates.iter().zip(frames) {
+        let tx_hash = pending_tx.tx.hash;
+        let frame = match frame {
+            Some(frame) => frame,
+            None => continue,
+        };
+        let mut swap_info = match budget.exceeded_by_trace(&pending_tx.tx, &frame) {
+            Some(reason) => {
+                info!("Skipping {:?}: {}", tx_hash, reason);
+                Vec::new()
+            }
+            None => swap_info_from_frame(tx_hash, &frame, pools_map, curve_pools_map),
+        };
+        swap_info.retain(|info| token_lists.allows(info.target_token));
+        drop_illiquid_victim(provider, new_block, tx_hash, &mut swap_info);
+        swap_info_map.insert(tx_hash, swap_info);
+    }
+
+    Ok(swap_info_map)
+}
+
+pub fn swap_info_from_frame(
+    tx_hash: H256,
+    frame: &CallFrame,
+    pools_map: &HashMap<H160, Pool>,
+    curve_pools_map: &HashMap<H160, CurvePool>,
+) -> Vec<SwapInfo> {
+    let mut swap_info_vec = Vec::new();
 
     let mut logs = Vec::new();
-    extract_logs(&frame, &mut logs);
+    extract_logs(frame, &mut logs);
+
+    if !classify_victim_logs(&logs).is_sandwichable() {
+        return swap_info_vec;
+    }
+
+    // (pair, reserve0, reserve1) of the last Sync, reserves after the swap that follows it
+    let mut last_sync: Option<(H160, U256, U256)> = None;
 
     for log in &logs {
         match &log.topics {
             Some(topics) => {
-                if topics.len() > 1 {
+                if topics.len() == 1 && &format!("{:?}", topics[0])[0..10] == V2_SYNC_EVENT_ID {
+                    last_sync = match ethers::abi::decode(
+                        &[ParamType::Uint(256), ParamType::Uint(256)],
+                        log.data.as_ref().unwrap(),
+                    ) {
+                        Ok(reserves) => Some((
+                            log.address.unwrap_or_default(),
+                            reserves[0].clone().into_uint().unwrap(),
+                            reserves[1].clone().into_uint().unwrap(),
+                        )),
+                        _ => None,
+                    };
+                } else if topics.len() > 1 {
                     let selector = &format!("{:?}", topics[0])[0..10];
                     let is_v2_swap = selector == V2_SWAP_EVENT_ID;
                     if is_v2_swap {
@@ -164,7 +513,7 @@ pub async fn extract_swap_info(
                                 None => continue,
                             };
 
-                        let (in0, _, _, out1) = match ethers::abi::decode(
+                        let (in0, in1, _, out1) = match ethers::abi::decode(
                             &[
                                 ParamType::Uint(256),
                                 ParamType::Uint(256),
@@ -202,14 +551,88 @@ pub async fn extract_swap_info(
                             }
                         };
 
+                        let impact = match last_sync {
+                            Some((pair, reserve0, reserve1)) if pair == pair_address => {
+                                if zero_for_one {
+                                    impact_bps(in0, reserve0)
+                                } else {
+                                    impact_bps(in1, reserve1)
+                                }
+                            }
+                            _ => 0,
+                        };
+
                         let swap_info = SwapInfo {
                             tx_hash,
                             target_pair: pair_address,
                             main_currency,
                             target_token,
                             version: 2,
+                            fee: pool.fee,
                             token0_is_main,
                             direction,
+                            curve_indices: None,
+                            route: RouteHop::single(impact),
+                        };
+                        swap_info_vec.push(swap_info);
+                    } else if selector == CURVE_EXCHANGE_EVENT_ID {
+                        let pool_address = log.address.unwrap();
+
+                        let pool = match curve_pools_map.get(&pool_address) {
+                            Some(pool) => pool,
+                            None => continue,
+                        };
+
+                        // TokenExchange(address indexed buyer, int128 sold_id, uint256 tokens_sold, int128 bought_id, uint256 tokens_bought)
+                        let (sold_id, bought_id) = match ethers::abi::decode(
+                            &[
+                                ParamType::Int(128),
+                                ParamType::Uint(256),
+                                ParamType::Int(128),
+                                ParamType::Uint(256),
+                            ],
+                            log.data.as_ref().unwrap(),
+                        ) {
+                            Ok(input) => (
+                                input[0].clone().into_int().unwrap().low_u64() as usize,
+                                input[2].clone().into_int().unwrap().low_u64() as usize,
+                            ),
+                            _ => continue,
+                        };
+
+                        let (token_sold, token_bought) =
+                            match (pool.coins.get(sold_id), pool.coins.get(bought_id)) {
+                                (Some(sold), Some(bought)) => (*sold, *bought),
+                                _ => continue,
+                            };
+
+                        let (main_currency, target_token) =
+                            match return_main_and_target_currency(token_sold, token_bought) {
+                                Some(out) => out,
+                                None => continue,
+                            };
+
+                        // the victim sells main_currency for target_token --> buy
+                        let direction = if token_sold == main_currency {
+                            SwapDirection::Buy
+                        } else {
+                            SwapDirection::Sell
+                        };
+
+                        let swap_info = SwapInfo {
+                            tx_hash,
+                            target_pair: pool_address,
+                            main_currency,
+                            target_token,
+                            version: CURVE_VERSION,
+                            fee: 0,
+                            token0_is_main: false,
+                            direction,
+                            curve_indices: Some((
+                                pool.coin_index(main_currency).unwrap(),
+                                pool.coin_index(target_token).unwrap(),
+                            )),
+                            route: RouteHop::single(0),
                         };
                         swap_info_vec.push(swap_info);
                     }
@@ -219,7 +642,8 @@ pub async fn extract_swap_info(
         }
     }
 
-    Ok(swap_info_vec)
+    assign_routes(&mut swap_info_vec);
+    swap_info_vec
 }
 
 pub fn extract_logs(call_frame: &CallFrame, logs: &mut Vec<CallLogFrame>) {
@@ -234,55 +658,198 @@ pub fn extract_logs(call_frame: &CallFrame, logs: &mut Vec<CallLogFrame>) {
     }
 }
 
-pub fn get_v2_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256) -> U256 {
-    let amount_in_with_fee = amount_in * U256::from(997);
-    let numerator = amount_in_with_fee * reserve_out;
-    let denominator = (reserve_in * U256::from(1000)) + amount_in_with_fee;
-    let amount_out = numerator.checked_div(denominator);
-    amount_out.unwrap_or_default()
-}
+/*
+Measures a Curve sandwich through the exchange() interface.
+The Sandooo contract only encodes V2 swaps, so the Curve path runs the
+frontrun/backrun from the owner EOA in the fork and is never bundled:
+it only tells us (in the logs and skip stats) whether large stable swaps
+would be worth adding Curve support to the contract for.
+*/
+pub async fn simulate_curve_sandwich(
+    provider: Arc<Provider<RpcClient>>,
+    block_number: U64,
+    base_fee: U256,
+    swap_info: &SwapInfo,
+    victim_tx: &VictimTx,
+    amount_in: U256,
+) -> Result<SimulatedSandwich> {
+    let (main_idx, target_idx) = match swap_info.curve_indices {
+        Some(indices) => indices,
+        None => return Err(anyhow!("Not a Curve swap: {:?}", swap_info.tx_hash)),
+    };
+    let pool = swap_info.target_pair;
+    let main_currency = swap_info.main_currency;
+    let target_token = swap_info.target_token;
 
-pub fn convert_usdt_to_weth(
-    simulator: &mut EvmSimulator<Provider<Ws>>,
-    amount: U256,
-) -> Result<U256> {
-    let conversion_pair = H160::from_str("0x0d4a11d5EEaaC28EC3F61d100daF4d40471f1852").unwrap();
-    // token0: WETH / token1: USDT
-    let reserves = simulator.get_pair_reserves(conversion_pair)?;
-    let (reserve_in, reserve_out) = (reserves.1, reserves.0);
-    let weth_out = get_v2_amount_out(amount, reserve_in, reserve_out);
-    Ok(weth_out)
+    let mut simulator = EvmSimulator::new(provider, None, block_number);
+    let owner = simulator.owner;
+
+    let initial_native_balance = CHAIN.native.units(100);
+    simulator.set_native_balance(owner, initial_native_balance);
+
+    let mc = MainCurrency::new(main_currency);
+    let balance_slot = get_cached_balance_slot(main_currency).unwrap_or(mc.balance_slot());
+    simulator.set_token_balance(main_currency, owner, balance_slot, amount_in.into())?;
+    simulator.approve(main_currency, pool, U256::MAX)?;
+    simulator.approve(target_token, pool, U256::MAX)?;
+
+    let native_balance_before = simulator.get_native_balance_of(owner);
+
+    simulator.set_base_fee(base_fee);
+
+    // Frontrun
+    let front_gas_used = simulator
+        .curve_exchange(
+            pool,
+            main_idx,
+            target_idx,
+            amount_in,
+            U256::zero(),
+            base_fee,
+        )?
+        .gas_used;
+
+    // Victim Tx
+    simulator.fund_caller(victim_tx)?;
+    let victim_tx = Tx::from(victim_tx.clone());
+    match simulator.call(victim_tx) {
+        _ => {}
+    }
+
+    simulator.set_base_fee(U256::zero());
+    let target_balance = simulator.get_token_balance(target_token, owner)?;
+    simulator.set_base_fee(base_fee);
+
+    // Backrun
+    let back_gas_used = simulator
+        .curve_exchange(
+            pool,
+            target_idx,
+            main_idx,
+            target_balance,
+            U256::zero(),
+            base_fee,
+        )?
+        .gas_used;
+
+    simulator.set_base_fee(U256::zero());
+
+    let native_balance_after = simulator.get_native_balance_of(owner);
+    let mc_balance_after = simulator.get_token_balance(main_currency, owner)?;
+
+    let weth_before = convert_to_weth(&mut simulator, main_currency, amount_in)?;
+    let weth_after = convert_to_weth(&mut simulator, main_currency, mc_balance_after)?;
+
+    let native_used_as_gas = native_balance_before
+        .checked_sub(native_balance_after)
+        .unwrap_or(native_balance_before);
+
+    let profit = (I256::from_dec_str(&weth_after.to_string())?
+        - I256::from_dec_str(&weth_before.to_string())?)
+    .as_i128();
+    let gas_cost = I256::from_dec_str(&native_used_as_gas.to_string())?.as_i128();
+    let revenue = profit - gas_cost;
+
+    Ok(SimulatedSandwich {
+        revenue,
+        profit,
+        gas_cost,
+        front_gas_used,
+        back_gas_used,
+        ..Default::default()
+    })
 }
 
-pub fn convert_usdc_to_weth(
-    simulator: &mut EvmSimulator<Provider<Ws>>,
-    amount: U256,
-) -> Result<U256> {
-    let conversion_pair = H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap();
-    // token0: USDC / token1: WETH
-    let reserves = simulator.get_pair_reserves(conversion_pair)?;
-    let (reserve_in, reserve_out) = (reserves.0, reserves.1);
-    let weth_out = get_v2_amount_out(amount, reserve_in, reserve_out);
-    Ok(weth_out)
+// Significant bits of the amounts in kept in the bundle id (~0.02%), finer differences are sizing noise
+pub static BUNDLE_ID_AMOUNT_BITS: usize = 12;
+
+pub fn quantize_amount(amount: U256) -> U256 {
+    let shift = amount.bits().saturating_sub(BUNDLE_ID_AMOUNT_BITS);
+    (amount >> shift) << shift
 }
 
 impl BatchSandwich {
-    pub fn bundle_id(&self) -> String {
+    /*
+    Identifies the bundle in simulated_bundle_ids, the tracker and the journal:
+    the victims' 4 byte prefixes (readable in the logs), then a hash of the target block and
+    every sandwich's pair, main currency and quantized amount in.
+    Re-sizing the same victims or targeting another block makes a new bundle, the exact same one doesn't.
+    */
+    pub fn bundle_id(&self, target_block: U64) -> String {
         let mut tx_hashes = Vec::new();
+        let mut legs = Vec::new();
         for sandwich in &self.sandwiches {
             let tx_hash = sandwich.victim_tx.tx_hash;
             let tx_hash_4_bytes = &format!("{:?}", tx_hash)[0..10];
             tx_hashes.push(String::from_str(tx_hash_4_bytes).unwrap());
+            legs.push(format!(
+                "{:?}:{:?}:{:?}:{}",
+                tx_hash,
+                sandwich.swap_info.target_pair,
+                sandwich.swap_info.main_currency,
+                quantize_amount(sandwich.amount_in)
+            ));
         }
         tx_hashes.sort();
         tx_hashes.dedup();
-        tx_hashes.join("-")
+        // victim order is decided later, the same legs in another order are the same bundle
+        legs.sort();
+        let digest = ethers::utils::keccak256(format!("{}|{}", target_block, legs.join(",")));
+        let digest_4_bytes: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}", tx_hashes.join("-"), digest_4_bytes)
     }
 
+    // In bundle order, a victim with several sandwiches is only listed once
     pub fn victim_tx_hashes(&self) -> Vec<H256> {
         self.sandwiches
             .iter()
             .map(|s| s.victim_tx.tx_hash)
+            .unique()
+            .collect()
+    }
+
+    // Same sandwiches, with the victims in the given order
+    pub fn with_victim_order(&self, order: &[H256]) -> BatchSandwich {
+        let mut sandwiches = Vec::new();
+        for tx_hash in order {
+            sandwiches.extend(
+                self.sandwiches
+                    .iter()
+                    .filter(|s| s.victim_tx.tx_hash == *tx_hash)
+                    .cloned(),
+            );
+        }
+        BatchSandwich { sandwiches }
+    }
+
+    /*
+    Victim orders to try, the current one first: every permutation up to MAX_VICTIM_ORDERINGS,
+    where txs of the same sender keep their relative (nonce) order.
+    */
+    pub fn victim_orderings(&self) -> Vec<Vec<H256>> {
+        let victims = self.victim_tx_hashes();
+        let senders: HashMap<H256, H160> = self
+            .sandwiches
+            .iter()
+            .map(|s| (s.victim_tx.tx_hash, s.victim_tx.from))
+            .collect();
+        let keeps_nonce_order = |order: &Vec<H256>| {
+            let sender_order = |txs: &Vec<H256>, sender: H160| -> Vec<H256> {
+                txs.iter()
+                    .filter(|tx_hash| senders.get(tx_hash) == Some(&sender))
+                    .cloned()
+                    .collect()
+            };
+            senders
+                .values()
+                .all(|sender| sender_order(order, *sender) == sender_order(&victims, *sender))
+        };
+        victims
+            .iter()
+            .cloned()
+            .permutations(victims.len())
+            .filter(keeps_nonce_order)
+            .take(MAX_VICTIM_ORDERINGS)
             .collect()
     }
 
@@ -301,11 +868,31 @@ impl BatchSandwich {
             .collect()
     }
 
+    /*
+    One frontrun / backrun swap per pair, in the order the pair first shows up:
+    sandwiches on the same pair (several victims buying it, see stack_victims in main_dish.rs)
+    share a single buy of their summed amount in, and a single sell.
+    */
+    pub fn legs(&self) -> Vec<(&Sandwich, U256)> {
+        let mut legs: Vec<(&Sandwich, U256)> = Vec::new();
+        for sandwich in &self.sandwiches {
+            let pair = sandwich.swap_info.target_pair;
+            match legs
+                .iter_mut()
+                .find(|(s, _)| s.swap_info.target_pair == pair)
+            {
+                Some(leg) => leg.1 += sandwich.amount_in,
+                None => legs.push((sandwich, sandwich.amount_in)),
+            }
+        }
+        legs
+    }
+
     pub fn encode_frontrun_tx(
         &self,
         block_number: U256,
         pair_reserves: &HashMap<H160, (U256, U256)>,
-    ) -> Result<(Bytes, Vec<Tx>, HashMap<H160, U256>)> {
+    ) -> Result<(Bytes, Vec<VictimTx>, HashMap<H160, U256>)> {
         let mut starting_mc_values = HashMap::new();
 
         let mut added_tx_hash = HashMap::new();
@@ -322,18 +909,17 @@ impl BatchSandwich {
             let tx_hash = sandwich.victim_tx.tx_hash;
             if !added_tx_hash.contains_key(&tx_hash) {
                 added_tx_hash.insert(tx_hash, true);
-                victim_txs.push(Tx::from(sandwich.victim_tx.clone()));
+                victim_txs.push(sandwich.victim_tx.clone());
             }
+        }
 
+        for (sandwich, amount_in) in self.legs() {
             // Token swap 0 -> 1
             // Frontrun tx is a main_currency -> target_token BUY tx
             // thus, if token0_is_main, then it is zero_for_one swap
             let zero_for_one = sandwich.swap_info.token0_is_main;
 
-            let new_amount_in = sandwich
-                .amount_in
-                .checked_sub(U256::from(1))
-                .unwrap_or(U256::zero());
+            let new_amount_in = amount_in.checked_sub(U256::from(1)).unwrap_or(U256::zero());
             let amount_in_u256 = eU256::from_dec_str(&new_amount_in.to_string())?;
             let amount_out_u256 = if sandwich.swap_info.version == 2 {
                 let reserves = pair_reserves.get(&sandwich.swap_info.target_pair).unwrap();
@@ -342,7 +928,12 @@ impl BatchSandwich {
                 } else {
                     (reserves.1, reserves.0)
                 };
-                let amount_out = get_v2_amount_out(new_amount_in, reserve_in, reserve_out);
+                let amount_out = get_v2_amount_out(
+                    new_amount_in,
+                    reserve_in,
+                    reserve_out,
+                    sandwich.swap_info.fee,
+                );
                 eU256::from_dec_str(&amount_out.to_string())?
             } else {
                 eU256::zero()
@@ -391,7 +982,7 @@ impl BatchSandwich {
             SolidityDataType::NumberWithShift(block_number_u256, TakeLastXBytes(64)), // blockNumber (uint64)
         );
 
-        for sandwich in &self.sandwiches {
+        for (sandwich, _) in self.legs() {
             let amount_in = *token_balances
                 .get(&sandwich.swap_info.target_token)
                 .unwrap_or(&U256::zero());
@@ -412,7 +1003,12 @@ impl BatchSandwich {
                     // token1 is main_currency
                     (reserves.0, reserves.1)
                 };
-                let amount_out = get_v2_amount_out(new_amount_in, reserve_in, reserve_out);
+                let amount_out = get_v2_amount_out(
+                    new_amount_in,
+                    reserve_in,
+                    reserve_out,
+                    sandwich.swap_info.fee,
+                );
                 eU256::from_dec_str(&amount_out.to_string())?
             } else {
                 eU256::zero()
@@ -442,26 +1038,183 @@ impl BatchSandwich {
 
     pub async fn simulate(
         &self,
-        provider: Arc<Provider<Ws>>,
+        provider: Arc<Provider<RpcClient>>,
+        owner: Option<H160>,
+        block_number: U64,
+        base_fee: U256,
+        max_fee: U256,
+        front_access_list: Option<AccessList>,
+        back_access_list: Option<AccessList>,
+        bot_address: Option<H160>,
+    ) -> Result<SimulatedSandwich> {
+        let use_cache = Env::new().simulation_cache;
+        let key = self.simulation_key(
+            block_number,
+            base_fee,
+            max_fee,
+            front_access_list.is_some() || back_access_list.is_some(),
+            bot_address,
+        );
+        if use_cache {
+            if let Some(simulated) = SIMULATION_CACHE.lock().unwrap().get(block_number, &key) {
+                return Ok(simulated);
+            }
+        }
+        let simulated = self
+            .simulate_with_preceding(
+                provider,
+                owner,
+                block_number,
+                base_fee,
+                max_fee,
+                front_access_list,
+                back_access_list,
+                bot_address,
+                &[],
+            )
+            .await?;
+        if use_cache {
+            SIMULATION_CACHE
+                .lock()
+                .unwrap()
+                .insert(block_number, key, simulated.clone());
+        }
+        Ok(simulated)
+    }
+
+    // Key of a simulation result in SIMULATION_CACHE, see simulation_cache.rs
+    fn simulation_key(
+        &self,
+        block_number: U64,
+        base_fee: U256,
+        max_fee: U256,
+        access_lists: bool,
+        bot_address: Option<H160>,
+    ) -> String {
+        let order: Vec<String> = self
+            .sandwiches
+            .iter()
+            .map(|s| format!("{:?}", s.victim_tx.tx_hash))
+            .collect();
+        format!(
+            "{}|{}|{:?}|{}:{}|{}",
+            self.bundle_id(block_number),
+            order.join(","),
+            bot_address,
+            base_fee,
+            max_fee,
+            access_lists
+        )
+    }
+
+    /*
+    Same as simulate, for the bundle targeting blocks_ahead blocks after the next one (BUNDLE_TARGET_BLOCKS):
+    the contract only runs in the block its calldata names, so every target block gets its own calldata,
+    simulated on the head state in that block.
+    */
+    pub async fn simulate_for_block(
+        &self,
+        provider: Arc<Provider<RpcClient>>,
         owner: Option<H160>,
         block_number: U64,
+        blocks_ahead: u64,
         base_fee: U256,
         max_fee: U256,
         front_access_list: Option<AccessList>,
         back_access_list: Option<AccessList>,
         bot_address: Option<H160>,
+    ) -> Result<SimulatedSandwich> {
+        let batch_sandwich = self.clone();
+        SIMULATION_POOL
+            .run(move || {
+                batch_sandwich.simulate_blocking(
+                    provider,
+                    owner,
+                    block_number,
+                    blocks_ahead,
+                    base_fee,
+                    max_fee,
+                    front_access_list,
+                    back_access_list,
+                    bot_address,
+                    &[],
+                )
+            })
+            .await?
+    }
+
+    // Same as simulate, on the state after preceding_txs ran on top of block_number (reverts are ignored)
+    // Runs on the simulation pool, this task only waits for the result
+    pub async fn simulate_with_preceding(
+        &self,
+        provider: Arc<Provider<RpcClient>>,
+        owner: Option<H160>,
+        block_number: U64,
+        base_fee: U256,
+        max_fee: U256,
+        front_access_list: Option<AccessList>,
+        back_access_list: Option<AccessList>,
+        bot_address: Option<H160>,
+        preceding_txs: &[VictimTx],
+    ) -> Result<SimulatedSandwich> {
+        let batch_sandwich = self.clone();
+        let preceding_txs = preceding_txs.to_vec();
+        SIMULATION_POOL
+            .run(move || {
+                batch_sandwich.simulate_blocking(
+                    provider,
+                    owner,
+                    block_number,
+                    0,
+                    base_fee,
+                    max_fee,
+                    front_access_list,
+                    back_access_list,
+                    bot_address,
+                    &preceding_txs,
+                )
+            })
+            .await?
+    }
+
+    fn simulate_blocking(
+        &self,
+        provider: Arc<Provider<RpcClient>>,
+        owner: Option<H160>,
+        block_number: U64,
+        blocks_ahead: u64,
+        base_fee: U256,
+        max_fee: U256,
+        front_access_list: Option<AccessList>,
+        back_access_list: Option<AccessList>,
+        bot_address: Option<H160>,
+        preceding_txs: &[VictimTx],
     ) -> Result<SimulatedSandwich> {
         let mut simulator = EvmSimulator::new(provider.clone(), owner, block_number);
+        if blocks_ahead > 0 {
+            simulator.set_block_number(block_number + U64::from(1 + blocks_ahead));
+        }
 
-        // set ETH balance so that it's enough to cover gas fees
+        // set the native token balance so that it's enough to cover gas fees
         match owner {
             None => {
-                let initial_eth_balance = U256::from(100) * U256::from(10).pow(U256::from(18));
-                simulator.set_eth_balance(simulator.owner, initial_eth_balance);
+                let initial_native_balance = CHAIN.native.units(100);
+                simulator.set_native_balance(simulator.owner, initial_native_balance);
             }
             _ => {}
         }
 
+        for preceding_tx in preceding_txs {
+            // a preceding tx that can't pay won't land before the victim, leave it out
+            if simulator.fund_caller(preceding_tx).is_err() {
+                continue;
+            }
+            let tx = Tx::from(preceding_tx.clone());
+            match simulator.call(tx) {
+                _ => {}
+            }
+        }
+
         // get reserves for v2 pairs and target tokens
         let target_v2_pairs = self.target_v2_pairs();
         let target_tokens = self.target_tokens();
@@ -469,17 +1222,23 @@ impl BatchSandwich {
         let mut reserves_before = HashMap::new();
 
         for v2_pair in &target_v2_pairs {
-            let reserves = simulator.get_pair_reserves(*v2_pair)?;
+            let reserves = if preceding_txs.is_empty() {
+                pair_reserves(&mut simulator, *v2_pair)?
+            } else {
+                simulator.get_pair_reserves(*v2_pair)?
+            };
             reserves_before.insert(*v2_pair, reserves);
         }
 
         let next_block_number = simulator.get_block_number();
 
         // create frontrun tx calldata and inject main_currency token balance to bot contract
-        let (frontrun_calldata, victim_txs, starting_mc_values) =
-            self.encode_frontrun_tx(next_block_number, &reserves_before)?;
+        let (frontrun_calldata, victim_txs, starting_mc_values) = self
+            .encode_frontrun_tx(next_block_number, &reserves_before)
+            .context(Failure::EncodingError)?;
 
         // deploy Sandooo bot
+        let bot_address_override = bot_address;
         let bot_address = match bot_address {
             Some(bot_address) => bot_address,
             None => {
@@ -492,7 +1251,8 @@ impl BatchSandwich {
 
                 for (main_currency, starting_value) in &starting_mc_values {
                     let mc = MainCurrency::new(*main_currency);
-                    let balance_slot = mc.balance_slot();
+                    let balance_slot =
+                        get_cached_balance_slot(*main_currency).unwrap_or(mc.balance_slot());
                     simulator.set_token_balance(
                         *main_currency,
                         bot_address,
@@ -505,8 +1265,8 @@ impl BatchSandwich {
             }
         };
 
-        // check ETH, MC balance before any txs are run
-        let eth_balance_before = simulator.get_eth_balance_of(simulator.owner);
+        // check native token, MC balance before any txs are run
+        let native_balance_before = simulator.get_native_balance_of(simulator.owner);
         let mut mc_balances_before = HashMap::new();
         for (main_currency, _) in &starting_mc_values {
             let balance_before = simulator.get_token_balance(*main_currency, bot_address)?;
@@ -527,22 +1287,35 @@ impl BatchSandwich {
         };
         let front_access_list = match front_access_list {
             Some(access_list) => access_list,
-            None => match simulator.get_access_list(front_tx.clone()) {
-                Ok(access_list) => access_list,
-                _ => AccessList::default(),
-            },
+            None => get_or_trace_access_list(
+                &mut simulator,
+                self,
+                TxStage::Front,
+                front_tx.clone(),
+                bot_address_override,
+            ),
         };
         simulator.set_access_list(front_access_list.clone());
-        let front_gas_used = match simulator.call(front_tx) {
-            Ok(result) => result.gas_used,
-            Err(_) => 0,
-        };
+        let (front_result, salmonella_flags) = simulator
+            .call_with_salmonella_check(front_tx, &target_tokens)
+            .context(Failure::SimRevert(TxStage::Front))?;
+        if !salmonella_flags.is_empty() {
+            for (token, _) in &salmonella_flags {
+                set_round_trip_verdict(*token, true);
+            }
+            return Err(anyhow!("Salmonella tokens: {:?}", salmonella_flags))
+                .context(Failure::Salmonella);
+        }
+        let front_gas_used = front_result.gas_used;
 
         // Victim Txs
         for victim_tx in victim_txs {
-            match simulator.call(victim_tx) {
-                _ => {}
-            }
+            simulator
+                .fund_caller(&victim_tx)
+                .context(Failure::SimRevert(TxStage::Victim))?;
+            simulator
+                .call(Tx::from(victim_tx))
+                .context(Failure::SimRevert(TxStage::Victim))?;
         }
 
         simulator.set_base_fee(U256::zero());
@@ -567,8 +1340,9 @@ impl BatchSandwich {
 
         simulator.set_base_fee(base_fee);
 
-        let backrun_calldata =
-            self.encode_backrun_tx(next_block_number, &reserves_after, &token_balances)?;
+        let backrun_calldata = self
+            .encode_backrun_tx(next_block_number, &reserves_after, &token_balances)
+            .context(Failure::EncodingError)?;
 
         // Backrun
         let back_tx = Tx {
@@ -581,67 +1355,80 @@ impl BatchSandwich {
         };
         let back_access_list = match back_access_list.clone() {
             Some(access_list) => access_list,
-            None => match simulator.get_access_list(back_tx.clone()) {
-                Ok(access_list) => access_list,
-                _ => AccessList::default(),
-            },
+            None => get_or_trace_access_list(
+                &mut simulator,
+                self,
+                TxStage::Back,
+                back_tx.clone(),
+                bot_address_override,
+            ),
         };
         let back_access_list = back_access_list.clone();
         simulator.set_access_list(back_access_list.clone());
-        let back_gas_used = match simulator.call(back_tx) {
-            Ok(result) => result.gas_used,
-            Err(_) => 0,
-        };
+        let back_gas_used = simulator
+            .call(back_tx)
+            .context(Failure::SimRevert(TxStage::Back))?
+            .gas_used;
 
         simulator.set_base_fee(U256::zero());
 
-        let eth_balance_after = simulator.get_eth_balance_of(simulator.owner);
+        let native_balance_after = simulator.get_native_balance_of(simulator.owner);
         let mut mc_balances_after = HashMap::new();
         for (main_currency, _) in &starting_mc_values {
             let balance_after = simulator.get_token_balance(*main_currency, bot_address)?;
             mc_balances_after.insert(main_currency, balance_after);
         }
 
-        let eth_used_as_gas = eth_balance_before
-            .checked_sub(eth_balance_after)
-            .unwrap_or(eth_balance_before);
-        let eth_used_as_gas_i256 = I256::from_dec_str(&eth_used_as_gas.to_string())?;
-
-        let usdt = H160::from_str(USDT).unwrap();
-        let usdc = H160::from_str(USDC).unwrap();
+        let native_used_as_gas = native_balance_before
+            .checked_sub(native_balance_after)
+            .unwrap_or(native_balance_before);
+        let native_used_as_gas_i256 = I256::from_dec_str(&native_used_as_gas.to_string())?;
 
         let mut weth_before_i256 = I256::zero();
         let mut weth_after_i256 = I256::zero();
+        let mut mc_balance_deltas = HashMap::new();
+        let mut conversion_slippage_i256 = I256::zero();
 
         for (main_currency, _) in &starting_mc_values {
             let mc_balance_before = *mc_balances_before.get(&main_currency).unwrap();
             let mc_balance_after = *mc_balances_after.get(&main_currency).unwrap();
 
-            let (mc_balance_before, mc_balance_after) = if *main_currency == usdt {
-                let before =
-                    convert_usdt_to_weth(&mut simulator, mc_balance_before).unwrap_or_default();
-                let after =
-                    convert_usdt_to_weth(&mut simulator, mc_balance_after).unwrap_or_default();
-                (before, after)
-            } else if *main_currency == usdc {
-                let before =
-                    convert_usdc_to_weth(&mut simulator, mc_balance_before).unwrap_or_default();
-                let after =
-                    convert_usdc_to_weth(&mut simulator, mc_balance_after).unwrap_or_default();
-                (before, after)
-            } else {
+            let mc_balance_delta = I256::from_dec_str(&mc_balance_after.to_string())?
+                - I256::from_dec_str(&mc_balance_before.to_string())?;
+            mc_balance_deltas.insert(*main_currency, mc_balance_delta.as_i128());
+
+            let conversion_route = conversion_route(&mut simulator, *main_currency);
+            let (mc_balance_before, mc_balance_after) = if is_weth(*main_currency) {
                 (mc_balance_before, mc_balance_after)
+            } else {
+                let before = convert_to_weth(&mut simulator, *main_currency, mc_balance_before)
+                    .unwrap_or_default();
+                let after = convert_to_weth(&mut simulator, *main_currency, mc_balance_after)
+                    .unwrap_or_default();
+                (before, after)
             };
 
             let mc_balance_before_i256 = I256::from_dec_str(&mc_balance_before.to_string())?;
             let mc_balance_after_i256 = I256::from_dec_str(&mc_balance_after.to_string())?;
 
+            if let Some(conversion_route) = conversion_route {
+                let spot_value = conversion_route
+                    .spot_value(&mut simulator, mc_balance_delta.unsigned_abs())
+                    .unwrap_or_default();
+                let mut spot_value_i256 = I256::from_dec_str(&spot_value.to_string())?;
+                if mc_balance_delta.is_negative() {
+                    spot_value_i256 = -spot_value_i256;
+                }
+                conversion_slippage_i256 +=
+                    spot_value_i256 - (mc_balance_after_i256 - mc_balance_before_i256);
+            }
+
             weth_before_i256 += mc_balance_before_i256;
             weth_after_i256 += mc_balance_after_i256;
         }
 
         let profit = (weth_after_i256 - weth_before_i256).as_i128();
-        let gas_cost = eth_used_as_gas_i256.as_i128();
+        let gas_cost = native_used_as_gas_i256.as_i128();
         let revenue = profit - gas_cost;
 
         let simulated_sandwich = SimulatedSandwich {
@@ -654,6 +1441,10 @@ impl BatchSandwich {
             back_access_list,
             front_calldata: frontrun_calldata,
             back_calldata: backrun_calldata,
+            mc_balance_deltas,
+            front_gas_cost: (U256::from(front_gas_used) * base_fee).as_u128() as i128,
+            back_gas_cost: (U256::from(back_gas_used) * base_fee).as_u128() as i128,
+            conversion_slippage: conversion_slippage_i256.as_i128(),
         };
 
         Ok(simulated_sandwich)
@@ -688,25 +1479,163 @@ impl Sandwich {
         }
     }
 
+    /*
+    Analytical sizing of a plain V2 buy. The victim is run alone on the pair to measure its amount in,
+    then the frontrun that maximizes the sandwich on the V2 math (front buy, victim buy, back sell,
+    at the pair's fee) is found from the reserves alone, without the EVM: the profit is unimodal in the
+    frontrun size, so a ternary search narrows it down to the tolerance.
+    None when the pair doesn't trade like a single V2 swap of a fixed amount in
+    (exact output swaps, several hops through the pair...), the grid search handles those.
+    The victim's slippage isn't modeled: optimize checks the result with one EVM simulation.
+    */
+    pub fn analytical_amount_in(
+        &self,
+        provider: Arc<Provider<RpcClient>>,
+        block_number: U64,
+        amount_in_ceiling: U256,
+        tolerance: U256,
+    ) -> Option<U256> {
+        if self.swap_info.version != 2 {
+            return None;
+        }
+        match self.swap_info.direction {
+            SwapDirection::Buy => {}
+            SwapDirection::Sell => return None,
+        }
+        let pair = self.swap_info.target_pair;
+        let fee = self.swap_info.fee;
+        let token0_is_main = self.swap_info.token0_is_main;
+        let in_out = |(reserve0, reserve1): (U256, U256)| {
+            if token0_is_main {
+                (reserve0, reserve1)
+            } else {
+                (reserve1, reserve0)
+            }
+        };
+
+        let mut simulator = EvmSimulator::new(provider, None, block_number);
+        let (reserve_in, reserve_out) = in_out(pair_reserves(&mut simulator, pair).ok()?);
+        simulator.fund_caller(&self.victim_tx).ok()?;
+        simulator.call(Tx::from(self.victim_tx.clone())).ok()?;
+        let (reserve_in_after, reserve_out_after) = in_out(simulator.get_pair_reserves(pair).ok()?);
+
+        // the victim's trade on the pair has to be a single swap at the pair's fee
+        let victim_amount_in = reserve_in_after.checked_sub(reserve_in)?;
+        let victim_amount_out = reserve_out.checked_sub(reserve_out_after)?;
+        if victim_amount_in.is_zero() || victim_amount_out.is_zero() {
+            return None;
+        }
+        let expected_out = get_v2_amount_out(victim_amount_in, reserve_in, reserve_out, fee);
+        let diff = std::cmp::max(expected_out, victim_amount_out)
+            - std::cmp::min(expected_out, victim_amount_out);
+        if diff * U256::from(10000) > victim_amount_out * U256::from(ANALYTICAL_MAX_DEVIATION_BPS) {
+            return None;
+        }
+
+        // backrun proceeds for a frontrun of amount_in
+        let back_amount_out = |amount_in: U256| {
+            let front_out = get_v2_amount_out(amount_in, reserve_in, reserve_out, fee);
+            let reserve_in = reserve_in + amount_in;
+            let reserve_out = reserve_out - front_out;
+            let victim_out = get_v2_amount_out(victim_amount_in, reserve_in, reserve_out, fee);
+            let reserve_in = reserve_in + victim_amount_in;
+            let reserve_out = reserve_out - victim_out;
+            get_v2_amount_out(front_out, reserve_out, reserve_in, fee)
+        };
+        // back_amount_out(x) - x, compared without going negative
+        let better = |x: U256, y: U256| back_amount_out(x) + y > back_amount_out(y) + x;
+
+        let mut low = U256::zero();
+        let mut high = amount_in_ceiling;
+        let tolerance = std::cmp::max(tolerance, U256::from(2));
+        while high - low > tolerance {
+            let third = (high - low) / U256::from(3);
+            let (left, right) = (low + third, high - third);
+            if better(left, right) {
+                high = right;
+            } else {
+                low = left;
+            }
+        }
+        let amount_in = low;
+        if amount_in.is_zero() || back_amount_out(amount_in) <= amount_in {
+            return None;
+        }
+        Some(amount_in)
+    }
+
     pub async fn optimize(
         &mut self,
-        provider: Arc<Provider<Ws>>,
+        provider: Arc<Provider<RpcClient>>,
         block_number: U64,
         amount_in_ceiling: U256,
         base_fee: U256,
         max_fee: U256,
         front_access_list: AccessList,
         back_access_list: AccessList,
+        warm_start: Option<U256>,
     ) -> Result<OptimizedSandwich> {
         let main_currency = self.swap_info.main_currency;
 
         let mut min_amount_in = U256::zero();
-        let mut max_amount_in = amount_in_ceiling;
+        let decimals = MainCurrency::new(main_currency).decimals();
+        let env = Env::new();
         let tolerance = if is_weth(main_currency) {
-            U256::from(1) * U256::from(10).pow(U256::from(14))
+            env.optimize_tolerance_weth
         } else {
-            U256::from(1) * U256::from(10).pow(U256::from(3))
+            env.optimize_tolerance_stables
         };
+        let tolerance = U256::from((tolerance.max(0.0) * 10f64.powi(decimals as i32)) as u128);
+
+        let mut amount_in_ceiling = amount_in_ceiling;
+        if env.analytical_sizing {
+            let analytical = self.analytical_amount_in(
+                provider.clone(),
+                block_number,
+                amount_in_ceiling,
+                tolerance,
+            );
+            if let Some(amount_in) = analytical {
+                let (
+                    _,
+                    amount_in,
+                    revenue,
+                    front_gas_used,
+                    back_gas_used,
+                    front_calldata,
+                    back_calldata,
+                ) = simulate_sandwich(
+                    0,
+                    provider.clone(),
+                    block_number,
+                    self.clone(),
+                    amount_in,
+                    base_fee,
+                    max_fee,
+                    front_access_list.clone(),
+                    back_access_list.clone(),
+                )
+                .await;
+                if revenue > 0 {
+                    let optimized_sandwich = OptimizedSandwich {
+                        amount_in,
+                        max_revenue: U256::from(revenue),
+                        front_gas_used,
+                        back_gas_used,
+                        front_access_list,
+                        back_access_list,
+                        front_calldata,
+                        back_calldata,
+                        optimized_ms: now_ms(),
+                    };
+                    self.optimized_sandwich = Some(optimized_sandwich.clone());
+                    return Ok(optimized_sandwich);
+                }
+                // most likely the victim's slippage: the best frontrun it tolerates is smaller
+                amount_in_ceiling = amount_in;
+            }
+        }
+        let mut max_amount_in = amount_in_ceiling;
 
         if max_amount_in < min_amount_in {
             return Ok(OptimizedSandwich {
@@ -718,6 +1647,7 @@ impl Sandwich {
                 back_access_list: AccessList::default(),
                 front_calldata: Bytes::default(),
                 back_calldata: Bytes::default(),
+                optimized_ms: now_ms(),
             });
         }
 
@@ -730,6 +1660,25 @@ impl Sandwich {
 
         let intervals = U256::from(10);
 
+        /*
+        Warm start: if we've optimized this victim in a previous block,
+        the optimum is most likely close to the previous amount in.
+        We search a small window around it first, and fall back to the full range
+        if the best amount lands on the edge of the window (the optimum moved outside of it).
+        */
+        let mut warm = false;
+        if let Some(prev_amount_in) = warm_start {
+            if !prev_amount_in.is_zero() && prev_amount_in < amount_in_ceiling {
+                let window = std::cmp::max(
+                    prev_amount_in * U256::from(WARM_START_WINDOW_BPS) / U256::from(10000),
+                    tolerance * intervals,
+                );
+                min_amount_in = prev_amount_in.saturating_sub(window);
+                max_amount_in = std::cmp::min(prev_amount_in + window, amount_in_ceiling);
+                warm = true;
+            }
+        }
+
         loop {
             let diff = max_amount_in - min_amount_in;
             let step = diff.checked_div(intervals).unwrap();
@@ -745,10 +1694,11 @@ impl Sandwich {
                 inputs.push(input);
             }
 
+            // every grid point is a job on the simulation pool, no task of its own
             let mut simulations = Vec::new();
 
             for (idx, input) in inputs.iter().enumerate() {
-                let sim = tokio::task::spawn(simulate_sandwich(
+                let sim = simulate_sandwich(
                     idx,
                     provider.clone(),
                     block_number,
@@ -758,13 +1708,12 @@ impl Sandwich {
                     max_fee,
                     front_access_list.clone(),
                     back_access_list.clone(),
-                ));
+                );
                 simulations.push(sim);
             }
 
-            let results = futures::future::join_all(simulations).await;
             let revenue: Vec<(usize, U256, i128, u64, u64, Bytes, Bytes)> =
-                results.into_iter().map(|res| res.unwrap()).collect();
+                futures::future::join_all(simulations).await;
 
             let mut max_idx = 0;
 
@@ -790,6 +1739,18 @@ impl Sandwich {
                 }
             }
 
+            if warm {
+                warm = false;
+                let at_lower_edge = max_idx == 0 && !min_amount_in.is_zero();
+                let at_upper_edge =
+                    max_idx == revenue.len() - 1 && max_amount_in < amount_in_ceiling;
+                if at_lower_edge || at_upper_edge || max_revenue.is_zero() {
+                    min_amount_in = U256::zero();
+                    max_amount_in = amount_in_ceiling;
+                    continue;
+                }
+            }
+
             min_amount_in = if max_idx == 0 {
                 U256::zero()
             } else {
@@ -811,6 +1772,7 @@ impl Sandwich {
             back_access_list,
             front_calldata: max_front_calldata,
             back_calldata: max_back_calldata,
+            optimized_ms: now_ms(),
         };
 
         self.optimized_sandwich = Some(optimized_sandwich.clone());
@@ -818,9 +1780,70 @@ impl Sandwich {
     }
 }
 
+// Half width of the warm start search window, in bps of the previous optimal amount in
+pub static WARM_START_WINDOW_BPS: u64 = 100;
+
+// How far (in bps) the victim's amount out on the pair can be from the V2 math to size it analytically
+pub static ANALYTICAL_MAX_DEVIATION_BPS: u64 = 10;
+
+/*
+Victims on the same pool move the price for each other: a victim with tight slippage
+can revert behind another buyer, and go through when it comes first.
+We simulate the batch with each victim order and keep the most profitable one where every victim succeeds
+(simulate fails on any victim revert).
+*/
+pub static MAX_VICTIM_ORDERINGS: usize = 6; // every order of 3 victims
+
+impl BatchSandwich {
+    pub async fn optimize_victim_order(
+        &self,
+        provider: Arc<Provider<RpcClient>>,
+        owner: Option<H160>,
+        block_number: U64,
+        base_fee: U256,
+        max_fee: U256,
+        bot_address: Option<H160>,
+    ) -> Result<(BatchSandwich, SimulatedSandwich)> {
+        let mut best: Option<(BatchSandwich, SimulatedSandwich)> = None;
+        let mut last_error = None;
+        for order in self.victim_orderings() {
+            let ordered = self.with_victim_order(&order);
+            match ordered
+                .simulate(
+                    provider.clone(),
+                    owner,
+                    block_number,
+                    base_fee,
+                    max_fee,
+                    None,
+                    None,
+                    bot_address,
+                )
+                .await
+            {
+                Ok(simulated) => {
+                    let is_better = match &best {
+                        Some((_, best_simulated)) => simulated.revenue > best_simulated.revenue,
+                        None => true,
+                    };
+                    if is_better {
+                        best = Some((ordered, simulated));
+                    }
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match (best, last_error) {
+            (Some(best), _) => Ok(best),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(anyhow!("No victims to order")),
+        }
+    }
+}
+
 pub async fn simulate_sandwich(
     idx: usize,
-    provider: Arc<Provider<Ws>>,
+    provider: Arc<Provider<RpcClient>>,
     block_number: U64,
     sandwich: Sandwich,
     amount_in: U256,
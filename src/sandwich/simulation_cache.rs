@@ -0,0 +1,73 @@
+use ethers::prelude::Lazy;
+use ethers::types::U64;
+use log::info;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::sandwich::simulation::SimulatedSandwich;
+
+/*
+The appetizer, the optimizer and main_dish simulate near-identical sandwiches over and over within a block
+(the same victim re-optimized, the same batch re-ordered, the final simulation of what was just simulated).
+BatchSandwich.simulate keeps its results here for the block they ran on, keyed by
+the bundle id (victims, pairs, main currencies, amounts in quantized to BUNDLE_ID_AMOUNT_BITS),
+the victim order, the bot contract, the fees and whether access lists were used.
+- amounts within the same bucket get the same result: the calldata is the one of the first amount simulated
+- a new block invalidates everything, a result is never reused across blocks
+- errors aren't cached, and neither are simulations on top of preceding txs (dual simulation)
+SIMULATION_CACHE=false turns it off.
+*/
+pub static SIMULATION_CACHE_MAX_ENTRIES: usize = 10000;
+
+pub static SIMULATION_CACHE: Lazy<Mutex<SimulationCache>> =
+    Lazy::new(|| Mutex::new(SimulationCache::default()));
+
+#[derive(Debug, Default)]
+pub struct SimulationCache {
+    pub block_number: U64,
+    pub entries: HashMap<String, SimulatedSandwich>,
+    pub hits: u64,   // in the current block
+    pub misses: u64, // in the current block
+}
+
+impl SimulationCache {
+    pub fn get(&mut self, block_number: U64, key: &str) -> Option<SimulatedSandwich> {
+        if block_number != self.block_number {
+            self.misses += 1;
+            return None;
+        }
+        match self.entries.get(key) {
+            Some(simulated) => {
+                self.hits += 1;
+                Some(simulated.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, block_number: U64, key: String, simulated: SimulatedSandwich) {
+        if block_number > self.block_number {
+            self.invalidate(block_number);
+        }
+        if block_number != self.block_number || self.entries.len() >= SIMULATION_CACHE_MAX_ENTRIES {
+            return;
+        }
+        self.entries.insert(key, simulated);
+    }
+
+    // Called on every new block (a reorged one too): results of the previous head are dropped
+    pub fn invalidate(&mut self, block_number: U64) {
+        if self.hits > 0 {
+            info!(
+                "Simulation cache for block #{:?}: {} hits / {} misses",
+                self.block_number, self.hits, self.misses
+            );
+        }
+        self.block_number = block_number;
+        self.entries.clear();
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
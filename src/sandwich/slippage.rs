@@ -0,0 +1,125 @@
+use ethers::{
+    providers::Provider,
+    types::{Transaction, U256},
+};
+
+use crate::common::evm::EvmSimulator;
+use crate::common::pools::{get_v2_amount_out, V2_FEE_DENOMINATOR};
+use crate::common::provider::RpcClient;
+use crate::common::reserves::pair_reserves;
+use crate::sandwich::call_detection::native_to_wrapped;
+use crate::sandwich::router_decoder::{decode_router_swaps, DecodedPath, DecodedSwap};
+use crate::sandwich::simulation::{SwapDirection, SwapInfo};
+
+/*
+The victim's slippage bound caps our frontrun: past it the victim reverts,
+and the bundle pays for a frontrun and a backrun around nothing.
+The bound comes from the router calldata (see router_decoder.rs):
+- exact in: amountOutMin, the victim still has to get at least that out of the pair after our buy
+- exact out: amountInMax, what the victim has to put in for its amount out can't go over it
+The largest frontrun the victim tolerates is found on the V2 math from the pair's reserves,
+and the appetizer doesn't size the sandwich above it.
+Only single-hop V2 swaps are capped: on a route, the bound is on the final token out, across pools we don't model.
+V3 swaps (and their sqrtPriceLimitX96) don't matter here, V3 pools aren't sandwiched.
+*/
+
+// Amount in needed for amount_out, None if the pair doesn't have that much
+pub fn get_v2_amount_in(
+    amount_out: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    fee: u32,
+) -> Option<U256> {
+    if amount_out >= reserve_out {
+        return None;
+    }
+    let fee = std::cmp::min(fee, V2_FEE_DENOMINATOR);
+    let numerator = reserve_in
+        .checked_mul(amount_out)?
+        .checked_mul(U256::from(V2_FEE_DENOMINATOR))?;
+    let denominator =
+        (reserve_out - amount_out).checked_mul(U256::from(V2_FEE_DENOMINATOR - fee))?;
+    Some(numerator.checked_div(denominator)? + U256::one())
+}
+
+// The victim's swap on the pair, when it's a single V2 hop
+fn victim_swap(tx: &Transaction, info: &SwapInfo) -> Option<DecodedSwap> {
+    decode_router_swaps(tx)?
+        .into_iter()
+        .find(|swap| match &swap.path {
+            DecodedPath::V2Tokens(tokens) => {
+                tokens.len() == 2
+                    && native_to_wrapped(tokens[0]) == info.main_currency
+                    && native_to_wrapped(tokens[1]) == info.target_token
+            }
+            DecodedPath::V2Pairs(pairs) => pairs.len() == 1 && pairs[0] == info.target_pair,
+            _ => false,
+        })
+}
+
+/*
+Largest frontrun (main currency in) the victim still goes through after,
+None when there's nothing to go by: not a single-hop V2 buy, no bound in the calldata,
+the victim fails even without us, or the bound doesn't bite below 10x the pair's reserve.
+Only for a simulator nothing has run on yet (see pair_reserves).
+*/
+pub fn victim_slippage_cap(
+    simulator: &mut EvmSimulator<Provider<RpcClient>>,
+    info: &SwapInfo,
+    tx: &Transaction,
+) -> Option<U256> {
+    if info.version != 2 || info.direction != SwapDirection::Buy {
+        return None;
+    }
+    let swap = victim_swap(tx, info)?;
+    let (reserve0, reserve1) = pair_reserves(simulator, info.target_pair).ok()?;
+    let (reserve_in, reserve_out) = if info.token0_is_main {
+        (reserve0, reserve1)
+    } else {
+        (reserve1, reserve0)
+    };
+    let fee = info.fee;
+
+    // whether the victim goes through after our frontrun of amount_in
+    let victim_succeeds = |amount_in: U256| -> Option<bool> {
+        let front_out = get_v2_amount_out(amount_in, reserve_in, reserve_out, fee);
+        let reserve_in = reserve_in + amount_in;
+        let reserve_out = reserve_out - front_out;
+        match (
+            swap.amount_in,
+            swap.amount_out_min,
+            swap.amount_out,
+            swap.amount_in_max,
+        ) {
+            (Some(victim_in), Some(amount_out_min), _, _) => {
+                Some(get_v2_amount_out(victim_in, reserve_in, reserve_out, fee) >= amount_out_min)
+            }
+            (_, _, Some(victim_out), Some(amount_in_max)) => Some(
+                match get_v2_amount_in(victim_out, reserve_in, reserve_out, fee) {
+                    Some(victim_in) => victim_in <= amount_in_max,
+                    None => false,
+                },
+            ),
+            _ => None,
+        }
+    };
+
+    if !victim_succeeds(U256::zero())? {
+        return None;
+    }
+    let mut low = U256::zero();
+    let mut high = reserve_in.saturating_mul(U256::from(10));
+    if victim_succeeds(high)? {
+        return None;
+    }
+    // victim_succeeds(low) and !victim_succeeds(high)
+    while high - low > U256::one() {
+        let mid = low + (high - low) / U256::from(2);
+        if victim_succeeds(mid)? {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some(low)
+}
@@ -1,30 +1,87 @@
 use bounded_vec_deque::BoundedVecDeque;
-use ethers::signers::{LocalWallet, Signer};
+use ethers::signers::Signer;
 use ethers::{
-    providers::{Middleware, Provider, Ws},
+    providers::{Middleware, Provider},
     types::{BlockNumber, H160, H256, U256, U64},
 };
 use log::{info, warn};
-use std::{collections::HashMap, str::FromStr, sync::Arc};
-use tokio::sync::broadcast::Sender;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast::{error::RecvError, Sender};
 
-use crate::common::alert::Alert;
+use crate::common::alert::{Alert, AlertEvent};
+use crate::common::block_space::BlockSpaceEstimator;
+use crate::common::bundle_stats::{describe_inclusion, BundleStatsStore, BUNDLE_STATS_DB_FILE};
+use crate::common::cache_store::open_cache_store;
+use crate::common::capabilities::ProviderCapabilities;
+use crate::common::chain::CHAIN;
 use crate::common::constants::Env;
+use crate::common::control::{serve_control_api, ControlState, SandwichRecord};
+use crate::common::dashboard::get_context_balances;
+use crate::common::evm::EvmSimulator;
 use crate::common::execution::Executor;
-use crate::common::pools::{load_all_pools, Pool};
-use crate::common::streams::{Event, NewBlock};
+use crate::common::experiments::Experiment;
+use crate::common::failures::FailureStats;
+use crate::common::fork_db::{prewarm_fork_db, reset_fork_db};
+use crate::common::hardforks::HardforkWatch;
+use crate::common::journal::{TradeJournal, TRADE_JOURNAL_DB_FILE};
+use crate::common::kill_switch::listen_kill_signals;
+use crate::common::latency::now_ms;
+use crate::common::load_shedding::LoadShedder;
+use crate::common::pool_store::PoolStore;
+use crate::common::pools::{
+    detect_v2_pool_fees, load_all_pools, load_curve_pools, CurvePool, Pool,
+};
+use crate::common::pricing::set_conversion_pools;
+use crate::common::profile::{listen_mode_commands, OperatingProfile};
+use crate::common::provider::{RpcClient, RpcPreset};
+use crate::common::reload::{listen_reload_signal, reload_env};
+use crate::common::reorg::ReorgDetector;
+use crate::common::replacement::BundleReplacements;
+use crate::common::reputation::ReputationMonitor;
+use crate::common::reserves::{update_reserves, RESERVES};
+use crate::common::risk::{get_bundle_outcome, get_equity, DrawdownBreaker, LossStreakBreaker};
+use crate::common::streams::{Event, NewBlock, NewPendingTx, StreamKind};
+use crate::common::telemetry::Telemetry;
+use crate::common::token_lists::TokenLists;
 use crate::common::tokens::load_all_tokens;
-use crate::common::utils::calculate_next_block_base_fee;
+use crate::common::tracker::{BundleStatus, TrackedBundle, Tracker};
+use crate::common::victim_lock::VictimLocks;
 use crate::sandwich::appetizer::appetizer;
+use crate::sandwich::arbitrage::backrun_victim;
+use crate::sandwich::backrun::{backrun_frontrunner, backrun_hint, index_pools_by_tokens};
+use crate::sandwich::bribe::BribePolicy;
+use crate::sandwich::call_detection::extract_swap_info_by_call;
+use crate::sandwich::candidates::CandidateGc;
+use crate::sandwich::competitors::{
+    find_competing_frontruns, find_contested_victims, BotRegistry, CompetitorPolicy,
+};
 use crate::sandwich::main_dish::main_dish;
-use crate::sandwich::simulation::{extract_swap_info, PendingTxInfo, Sandwich};
+use crate::sandwich::simulation::{
+    extract_swap_info, extract_swap_info_many, PendingTxInfo, Sandwich, SkipReason, SwapInfo,
+};
+use crate::sandwich::simulation_cache::SIMULATION_CACHE;
+use crate::sandwich::victims::VictimBudget;
 
-pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Sender<Event>) {
-    let env = Env::new();
+// max number of pending txs traced together in one debug_traceCallMany
+pub static MAX_TRACE_BATCH_SIZE: usize = 16;
 
-    let (pools, prev_pool_id) = load_all_pools(env.wss_url.clone(), 10000000, 50000)
-        .await
-        .unwrap();
+pub async fn run_sandwich_strategy(
+    provider: Arc<Provider<RpcClient>>,
+    event_sender: Sender<Event>,
+    capabilities: ProviderCapabilities,
+) {
+    let mut env = Env::new();
+
+    let (pools, prev_pool_id) = load_all_pools(
+        env.rpc_url(),
+        CHAIN.pool_sync_from_block,
+        RpcPreset::from_env(&env).log_chunk_size(),
+    )
+    .await
+    .unwrap();
 
     let block_number = provider.get_block_number().await.unwrap();
     let tokens_map = load_all_tokens(&provider, block_number, &pools, prev_pool_id)
@@ -32,61 +89,185 @@ pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Se
         .unwrap();
     info!("Tokens map count: {:?}", tokens_map.len());
 
-    // filter pools that don't have both token0 / token1 info
+    // filter pools that don't have both token0 / token1 info,
+    // or trade honeypots / fee-on-transfer tokens
     let pools_vec: Vec<Pool> = pools
         .into_iter()
-        .filter(|p| {
-            let token0_exists = tokens_map.contains_key(&p.token0);
-            let token1_exists = tokens_map.contains_key(&p.token1);
-            token0_exists && token1_exists
-        })
+        .filter(
+            |p| match (tokens_map.get(&p.token0), tokens_map.get(&p.token1)) {
+                (Some(token0), Some(token1)) => {
+                    token0.metadata.is_sandwichable() && token1.metadata.is_sandwichable()
+                }
+                _ => false,
+            },
+        )
         .collect();
     info!("Filtered pools by tokens count: {:?}", pools_vec.len());
 
-    let pools_map: HashMap<H160, Pool> = pools_vec
+    let mut pools_map: HashMap<H160, Pool> = pools_vec
         .clone()
         .into_iter()
         .map(|p| (p.address, p))
         .collect();
 
+    let mut executor = Executor::new(provider.clone());
+
+    let control_state = ControlState::new(
+        pools_map.clone(),
+        tokens_map.clone(),
+        OperatingProfile::from_env(&env),
+        TokenLists::load(&env, &tokens_map),
+        executor.with_context(&executor.contexts[0]),
+    );
+    if !env.control_api_addr.is_empty() {
+        let addr = env.control_api_addr.clone();
+        let token = env.control_api_token.clone();
+        let dashboard = env.dashboard;
+        let state = control_state.clone();
+        tokio::spawn(async move {
+            match serve_control_api(addr, token, dashboard, state).await {
+                Err(e) => warn!("Control API error: {e:?}"),
+                _ => {}
+            }
+        });
+    }
+    if env.use_alert {
+        tokio::spawn(listen_mode_commands(control_state.clone()));
+    }
+    let config_reloader = control_state.read().unwrap().config_reloader.clone();
+    let reloader = config_reloader.clone();
+    tokio::spawn(async move {
+        match listen_reload_signal(reloader).await {
+            Err(e) => warn!("listen_reload_signal error: {e:?}"),
+            _ => {}
+        }
+    });
+    let kill_switch = executor.kill_switch.clone();
+    tokio::spawn(async move {
+        match listen_kill_signals(kill_switch).await {
+            Err(e) => warn!("listen_kill_signals error: {e:?}"),
+            _ => {}
+        }
+    });
+
+    let curve_pools_map: HashMap<H160, CurvePool> = match load_curve_pools(&provider).await {
+        Ok(curve_pools) => curve_pools.into_iter().map(|p| (p.address, p)).collect(),
+        Err(e) => {
+            warn!("load_curve_pools error: {e:?}");
+            HashMap::new()
+        }
+    };
+
     let block = provider
         .get_block(BlockNumber::Latest)
         .await
         .unwrap()
         .unwrap();
-    let mut new_block = NewBlock {
-        block_number: block.number.unwrap(),
-        base_fee: block.base_fee_per_gas.unwrap(),
-        next_base_fee: calculate_next_block_base_fee(
-            block.gas_used,
-            block.gas_limit,
-            block.base_fee_per_gas.unwrap(),
-        ),
-    };
+    let mut new_block = NewBlock::from_block(&block).unwrap();
 
-    let alert = Alert::new();
-    let executor = Executor::new(provider.clone());
-
-    let bot_address = H160::from_str(&env.bot_address).unwrap();
-    let wallet = env
-        .private_key
-        .parse::<LocalWallet>()
-        .unwrap()
-        .with_chain_id(1 as u64);
-    let owner = wallet.address();
+    let mut alert = Arc::new(Alert::new());
 
     let mut event_receiver = event_sender.subscribe();
 
     let mut pending_txs: HashMap<H256, PendingTxInfo> = HashMap::new();
     let mut promising_sandwiches: HashMap<H256, Vec<Sandwich>> = HashMap::new();
+    let mut candidate_gc = CandidateGc::new(&env);
+    let mut victim_budget = VictimBudget::new(&env);
     let mut simulated_bundle_ids = BoundedVecDeque::new(30);
+    let mut replacements = BundleReplacements::new();
+    let mut bribe_policy = BribePolicy::new(&env);
+    let mut tracker = Tracker::new(env.confirmations);
+    match open_cache_store(&env).and_then(|store| tracker.persist_to(store)) {
+        Err(e) => warn!("Tracker.persist_to error: {e:?}"),
+        _ => {}
+    }
+    match TradeJournal::open(TRADE_JOURNAL_DB_FILE) {
+        Ok(journal) => tracker.journal_to(journal),
+        Err(e) => warn!("TradeJournal.open error: {e:?}"),
+    }
+    let mut telemetry = Telemetry::new(capabilities.clone());
+    let mut experiment = Experiment::new(&env);
+    let mut drawdown_breaker = DrawdownBreaker::new(&env);
+    let mut loss_breaker = LossStreakBreaker::new(&env);
+
+    // events pulled off the channel while batching traces, processed in order before new ones
+    let mut queued_events: VecDeque<Event> = VecDeque::new();
+    let mut batch_traced: HashMap<H256, Vec<SwapInfo>> = HashMap::new();
+
+    // pools whose fee was already probed this run (see detect_v2_pool_fee)
+    let mut fee_checked: HashSet<H160> = HashSet::new();
+    let mut pool_store = PoolStore::open(&env).unwrap();
+    let mut load_shedder = LoadShedder::new(&env);
+    let competitor_policy = CompetitorPolicy::parse(&env.competitor_policy);
+    let bot_registry = BotRegistry::new(&env);
+    let mut block_space = BlockSpaceEstimator::new(&env);
+    let mut reorg_detector = ReorgDetector::new();
+    let mut hardfork_watch = HardforkWatch::new();
+    // shared by the strategies that can go after the same victim
+    let victim_locks = VictimLocks::new();
+    let mut reputation = ReputationMonitor::new(&env, executor.sequencer_mode);
+    let mut failures = FailureStats::new();
+    let bundle_stats = BundleStatsStore::open(BUNDLE_STATS_DB_FILE).unwrap();
+    match bundle_stats.totals() {
+        Ok((sent, landed)) => info!("Bundle history: {} landed / {} sent", landed, sent),
+        Err(e) => warn!("BundleStatsStore.totals error: {e:?}"),
+    }
+    let bundle_stats = Arc::new(Mutex::new(bundle_stats));
+    // checks inclusion of resolved bundles off the block handler (see report_inclusion)
+    let inclusion_executor = Arc::new(executor.with_context(&executor.contexts[0]));
+    let pools_by_tokens = index_pools_by_tokens(&pools_map);
+    set_conversion_pools(&pools_map);
+    RESERVES.lock().unwrap().track(pools_map.keys().cloned());
 
     loop {
-        match event_receiver.recv().await {
+        // swapped between two events, nothing is half way through with the old settings
+        if config_reloader.take() {
+            match reload_env() {
+                Ok(count) => {
+                    env = Env::new();
+                    victim_budget = VictimBudget::new(&env);
+                    candidate_gc.max_candidates = env.max_promising_sandwiches;
+                    bribe_policy.reconfigure(&env);
+                    executor.reload_builders(&env);
+                    control_state.write().unwrap().token_lists =
+                        Arc::new(TokenLists::load(&env, &tokens_map));
+                    alert = Arc::new(Alert::new());
+                    info!(
+                        "Config reloaded: {} settings from .env / CONFIG_FILE / {} builders",
+                        count,
+                        executor.builders.len()
+                    );
+                }
+                Err(e) => warn!("reload_env error: {e:?}"),
+            }
+        }
+
+        let event = match queued_events.pop_front() {
+            Some(event) => Ok(event),
+            None => event_receiver.recv().await,
+        };
+        match event {
             Ok(event) => match event {
                 Event::Block(block) => {
                     new_block = block;
+                    block_space.on_block();
+                    // traces and simulations were run against the previous block
+                    batch_traced.clear();
+                    SIMULATION_CACHE
+                        .lock()
+                        .unwrap()
+                        .invalidate(new_block.block_number);
+                    reset_fork_db(provider.clone(), new_block.block_number);
+                    if env.reserve_tracking {
+                        match update_reserves(&provider, &new_block).await {
+                            Err(e) => warn!("update_reserves error: {e:?}"),
+                            _ => {}
+                        }
+                    }
                     info!("[Block #{:?}]", new_block.block_number);
+                    hardfork_watch
+                        .on_block(new_block.block_number, &alert)
+                        .await;
 
                     // remove confirmed transactions
                     let block_with_txs = provider
@@ -97,7 +278,7 @@ pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Se
 
                     let txs: Vec<H256> = block_with_txs
                         .transactions
-                        .into_iter()
+                        .iter()
                         .map(|tx| tx.hash)
                         .collect();
 
@@ -115,16 +296,309 @@ pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Se
                         }
                     }
 
-                    // remove pending txs older than 5 blocks
+                    // remove pending txs older than ~36 seconds (3 blocks on mainnet, 12 on BSC)
+                    let pending_tx_expiry = U64::from(match env.pending_tx_expiry_blocks {
+                        0 => CHAIN.blocks_for_secs(36),
+                        blocks => blocks,
+                    });
                     pending_txs.retain(|_, v| {
-                        (new_block.block_number - v.pending_tx.added_block.unwrap()) < U64::from(3)
+                        (new_block.block_number - v.pending_tx.added_block.unwrap())
+                            < pending_tx_expiry
                     });
-                    promising_sandwiches.retain(|h, _| pending_txs.contains_key(h));
+                    candidate_gc.collect(&mut promising_sandwiches, &pending_txs);
+
+                    // the victims still pending get simulated again on this block
+                    let prewarm_pairs: Vec<(H160, Vec<H160>)> = pending_txs
+                        .values()
+                        .flat_map(|info| info.touched_pairs.iter())
+                        .map(|s| (s.target_pair, vec![s.main_currency, s.target_token]))
+                        .collect();
+                    if !prewarm_pairs.is_empty() {
+                        let block_number = new_block.block_number;
+                        tokio::task::spawn_blocking(move || {
+                            prewarm_fork_db(block_number, &prewarm_pairs)
+                        });
+                    }
+
+                    match reorg_detector
+                        .on_block(&provider, &new_block, block_with_txs.transactions)
+                        .await
+                    {
+                        Ok(Some(reorg)) => {
+                            warn!(
+                                "Reorg of {} block(s) at #{:?}, {} orphaned txs are pending again",
+                                reorg.depth,
+                                new_block.block_number,
+                                reorg.orphaned_txs.len()
+                            );
+                            // everything we simulated ran on top of the orphaned blocks
+                            promising_sandwiches.clear();
+                            simulated_bundle_ids.clear();
+                            batch_traced.clear();
+                            // re-trace the victims we had and the orphaned txs on the new chain
+                            let stale_txs: Vec<NewPendingTx> = pending_txs
+                                .drain()
+                                .map(|(_, info)| info.pending_tx)
+                                .collect();
+                            let orphaned_txs =
+                                reorg.orphaned_txs.into_iter().map(|tx| NewPendingTx {
+                                    added_block: None,
+                                    tx,
+                                    hint: None,
+                                    first_seen_ms: now_ms(),
+                                });
+                            for pending_tx in stale_txs.into_iter().chain(orphaned_txs) {
+                                queued_events.push_back(Event::PendingTx(pending_tx));
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => warn!("reorg_detector.on_block error: {e:?}"),
+                    }
+
+                    executor
+                        .nonces
+                        .expire(new_block.block_number + U64::from(1));
+
+                    // suspected frontruns that landed as sandwiches, see competitors.rs
+                    let registry = bot_registry.clone();
+                    let registry_provider = provider.clone();
+                    let block_number = new_block.block_number;
+                    tokio::spawn(async move {
+                        match registry.confirm(&registry_provider, block_number).await {
+                            Err(e) => warn!("BotRegistry.confirm error: {e:?}"),
+                            _ => {}
+                        }
+                    });
+
+                    match tracker
+                        .update(&provider, new_block.block_number, &alert)
+                        .await
+                    {
+                        Ok(resolved) => {
+                            if !resolved.is_empty() {
+                                match executor.resync_nonce().await {
+                                    Err(e) => warn!("Executor.resync_nonce error: {e:?}"),
+                                    _ => {}
+                                }
+                            }
+                            for bundle in resolved {
+                                bribe_policy.on_resolved(&bundle);
+                                if env.dashboard {
+                                    control_state
+                                        .write()
+                                        .unwrap()
+                                        .dashboard
+                                        .record_bundle(&bundle);
+                                }
+                                // only one variant can land, the ones for later blocks are pulled
+                                if bundle.status == BundleStatus::Included {
+                                    for variant in &bundle.variants {
+                                        if variant.target_block > bundle.target_block
+                                            && !variant.replacement_uuid.is_empty()
+                                        {
+                                            executor.cancel_bundle(&variant.replacement_uuid).await;
+                                        }
+                                    }
+                                }
+                                if bundle.status == BundleStatus::Included
+                                    && loss_breaker.is_enabled()
+                                    && !env.debug
+                                {
+                                    let context = executor.get_context(&bundle.context);
+                                    match get_bundle_outcome(
+                                        &provider,
+                                        context.owner.address(),
+                                        context.bot_address,
+                                        &bundle,
+                                    )
+                                    .await
+                                    {
+                                        Ok(outcome) => {
+                                            loss_breaker
+                                                .record(
+                                                    &bundle.bundle_id,
+                                                    outcome,
+                                                    &executor.kill_switch,
+                                                    &alert,
+                                                )
+                                                .await
+                                        }
+                                        Err(e) => warn!("get_bundle_outcome error: {e:?}"),
+                                    }
+                                }
+                                tokio::spawn(report_inclusion(
+                                    inclusion_executor.clone(),
+                                    provider.clone(),
+                                    bundle_stats.clone(),
+                                    alert.clone(),
+                                    bundle,
+                                ));
+                            }
+                        }
+                        Err(e) => warn!("tracker.update error: {e:?}"),
+                    }
+
+                    if env.dashboard {
+                        let balances_due = {
+                            let mut control_state = control_state.write().unwrap();
+                            for bundle in tracker.bundles.values() {
+                                control_state.dashboard.record_bundle(bundle);
+                            }
+                            control_state.dashboard.update_candidates(
+                                new_block.block_number,
+                                &pending_txs,
+                                &promising_sandwiches,
+                            );
+                            control_state.dashboard.balances_due(new_block.block_number)
+                        };
+                        if balances_due {
+                            match get_context_balances(
+                                &provider,
+                                &executor.contexts,
+                                new_block.block_number,
+                            )
+                            .await
+                            {
+                                Ok(balances) => control_state
+                                    .write()
+                                    .unwrap()
+                                    .dashboard
+                                    .set_balances(new_block.block_number, balances),
+                                Err(e) => warn!("get_context_balances error: {e:?}"),
+                            }
+                        }
+                    }
+
+                    if drawdown_breaker.is_enabled() && !env.debug {
+                        match get_equity(&provider, &executor.contexts, new_block.block_number)
+                            .await
+                        {
+                            Ok(equity) => drawdown_breaker.record(equity, &alert).await,
+                            Err(e) => warn!("get_equity error: {e:?}"),
+                        }
+                    }
+
+                    telemetry.maybe_report(tracker.bundles_sent, tracker.bundles_included);
+                    load_shedder.maybe_report();
+                    candidate_gc.maybe_report(&promising_sandwiches);
+                    reputation
+                        .maybe_check(&executor, new_block.block_number, &tracker, &alert)
+                        .await;
+                    failures.maybe_report(&alert).await;
+
+                    if let Some(experiment) = experiment.as_mut() {
+                        experiment.maybe_report(&tracker, &alert).await;
+                    }
+                }
+                Event::StreamRestarted(kind) => {
+                    warn!("{:?} stream restarted, resyncing", kind);
+                    match kind {
+                        StreamKind::Blocks => {
+                            // blocks may have been missed while reconnecting, catch up with the latest one
+                            match provider.get_block(BlockNumber::Latest).await {
+                                Ok(Some(block)) => match NewBlock::from_block(&block) {
+                                    Some(block) if block.block_number > new_block.block_number => {
+                                        queued_events.push_back(Event::Block(block));
+                                    }
+                                    _ => {}
+                                },
+                                Ok(None) => {}
+                                Err(e) => warn!("get_block error: {e:?}"),
+                            }
+                        }
+                        StreamKind::PendingTxs => {
+                            // traces of txs from the old connection are stale
+                            batch_traced.clear();
+                        }
+                    }
                 }
                 Event::PendingTx(mut pending_tx) => {
+                    // MEV-Share hints aren't full txs, we can only backrun them
+                    if let Some(hint) = &pending_tx.hint {
+                        if drawdown_breaker.is_tripped() || executor.kill_switch.is_halted() {
+                            continue;
+                        }
+                        match backrun_hint(
+                            &provider,
+                            &executor,
+                            &new_block,
+                            hint,
+                            &pools_map,
+                            &pools_by_tokens,
+                            &victim_locks,
+                            env.bundle_target_blocks,
+                            env.debug || env.dry_run,
+                        )
+                        .await
+                        {
+                            Err(e) => warn!("backrun_hint error: {e:?}"),
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    block_space.record_pending_tx(&pending_tx.tx, new_block.next_base_fee);
+
+                    let backlog = event_sender.len() + queued_events.len();
+                    if !load_shedder.should_process(&pending_tx.tx, backlog) {
+                        continue;
+                    }
+
+                    // one copy per event, the profile may be switched and the token lists reloaded at any time
+                    let (profile, token_lists) = {
+                        let control_state = control_state.read().unwrap();
+                        (
+                            control_state.profile.clone(),
+                            control_state.token_lists.clone(),
+                        )
+                    };
+
                     let tx_hash = pending_tx.tx.hash;
                     let already_received = pending_txs.contains_key(&tx_hash);
 
+                    // trace the pending txs that arrived together with concurrent debug_traceCallMany requests
+                    if capabilities.debug_trace_call_many
+                        && !already_received
+                        && !batch_traced.contains_key(&tx_hash)
+                    {
+                        let mut batch = vec![pending_tx.clone()];
+                        while batch.len() < MAX_TRACE_BATCH_SIZE {
+                            match event_receiver.try_recv() {
+                                Ok(Event::PendingTx(queued_tx)) => {
+                                    if !pending_txs.contains_key(&queued_tx.tx.hash)
+                                        && queued_tx.hint.is_none()
+                                        && !load_shedder.is_shed(&queued_tx.tx, backlog)
+                                    {
+                                        batch.push(queued_tx.clone());
+                                    }
+                                    queued_events.push_back(Event::PendingTx(queued_tx));
+                                }
+                                Ok(other) => {
+                                    // don't batch across a new block
+                                    queued_events.push_back(other);
+                                    break;
+                                }
+                                Err(_) => break,
+                            }
+                        }
+                        if batch.len() > 1 {
+                            match extract_swap_info_many(
+                                &provider,
+                                &new_block,
+                                &batch,
+                                &pools_map,
+                                &curve_pools_map,
+                                &victim_budget,
+                                &token_lists,
+                            )
+                            .await
+                            {
+                                Ok(traced) => batch_traced.extend(traced),
+                                Err(e) => warn!("extract_swap_info_many error: {e:?}"),
+                            }
+                        }
+                    }
+
                     let mut should_add = false;
 
                     if !already_received {
@@ -161,26 +635,216 @@ pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Se
                     }
 
                     let swap_info = if should_add {
-                        match extract_swap_info(&provider, &new_block, &pending_tx, &pools_map)
-                            .await
-                        {
-                            Ok(swap_info) => swap_info,
-                            Err(e) => {
-                                warn!("extract_swap_info error: {e:?}");
-                                Vec::new()
+                        match batch_traced.remove(&tx_hash) {
+                            Some(swap_info) => Ok(swap_info),
+                            // no debug_traceCall: read the calldata and eth_call it instead
+                            None if !capabilities.debug_trace_call => {
+                                extract_swap_info_by_call(
+                                    &provider,
+                                    &new_block,
+                                    &pending_tx,
+                                    &pools_map,
+                                    &pools_by_tokens,
+                                    &victim_budget,
+                                    &token_lists,
+                                )
+                                .await
+                            }
+                            None => {
+                                extract_swap_info(
+                                    &provider,
+                                    &new_block,
+                                    &pending_tx,
+                                    &pools_map,
+                                    &curve_pools_map,
+                                    &victim_budget,
+                                    &token_lists,
+                                )
+                                .await
                             }
                         }
                     } else {
-                        Vec::new()
+                        Ok(Vec::new())
                     };
+                    let mut swap_info = match swap_info {
+                        Ok(swap_info) => swap_info,
+                        Err(e) => {
+                            failures.record_error(&e);
+                            warn!("extract_swap_info error: {e:?}");
+                            Vec::new()
+                        }
+                    };
+                    swap_info.retain(|info| profile.allows(info, &tokens_map));
+
+                    // probe the actual fee of V2 pools the first time they're traded,
+                    // so the amount out math and calldata match what the pair accepts
+                    let unchecked_pairs: Vec<H160> = swap_info
+                        .iter()
+                        .map(|info| info.target_pair)
+                        .filter(|pair| pools_map.contains_key(pair) && !fee_checked.contains(pair))
+                        .collect();
+                    if !unchecked_pairs.is_empty() {
+                        let mut simulator =
+                            EvmSimulator::new(provider.clone(), None, new_block.block_number);
+                        detect_v2_pool_fees(
+                            &mut simulator,
+                            &mut pools_map,
+                            &unchecked_pairs,
+                            &mut pool_store,
+                        );
+                        fee_checked.extend(unchecked_pairs);
+                    }
+                    for info in swap_info.iter_mut() {
+                        if let Some(pool) = pools_map.get(&info.target_pair) {
+                            info.fee = pool.fee;
+                        }
+                    }
+
+                    // competing frontruns come in after their victim: this tx may be one,
+                    // against a victim we already have sandwiches for on the pools it swaps
+                    if competitor_policy != CompetitorPolicy::Ignore && !swap_info.is_empty() {
+                        let contested_victims = find_contested_victims(
+                            &pending_tx.tx,
+                            &swap_info,
+                            &promising_sandwiches,
+                            &pending_txs,
+                            &load_shedder.routers,
+                            &bot_registry,
+                        );
+                        for (victim_hash, pair) in &contested_victims {
+                            let victim_tx = match pending_txs.get(victim_hash) {
+                                Some(victim) => victim.pending_tx.tx.clone(),
+                                None => continue,
+                            };
+                            bot_registry.suspect(
+                                &pending_tx.tx,
+                                &victim_tx,
+                                new_block.block_number,
+                            );
+                            // outbid: the sandwiches stay, main_dish raises the bribe on them
+                            if competitor_policy == CompetitorPolicy::Outbid {
+                                continue;
+                            }
+                            if let Some(sandwiches) = promising_sandwiches.get_mut(victim_hash) {
+                                let mut control_state = control_state.write().unwrap();
+                                for sandwich in sandwiches
+                                    .iter()
+                                    .filter(|s| s.swap_info.target_pair == *pair)
+                                {
+                                    control_state.record_skip(
+                                        sandwich.swap_info.target_pair,
+                                        sandwich.swap_info.target_token,
+                                        SkipReason::CompetingFrontrun,
+                                    );
+                                }
+                                sandwiches.retain(|s| s.swap_info.target_pair != *pair);
+                            }
+                            if competitor_policy == CompetitorPolicy::Backrun
+                                && !drawdown_breaker.is_tripped()
+                                && !executor.kill_switch.is_halted()
+                            {
+                                match backrun_frontrunner(
+                                    &provider,
+                                    &executor,
+                                    &new_block,
+                                    &pending_tx.tx,
+                                    &victim_tx,
+                                    *pair,
+                                    &pools_map,
+                                    &pools_by_tokens,
+                                    &victim_locks,
+                                    env.bundle_target_blocks,
+                                    env.debug || env.dry_run,
+                                )
+                                .await
+                                {
+                                    Err(e) => warn!("backrun_frontrunner error: {e:?}"),
+                                    _ => {}
+                                }
+                            }
+                        }
+                        promising_sandwiches.retain(|_, sandwiches| !sandwiches.is_empty());
+                    }
+
+                    if competitor_policy != CompetitorPolicy::Ignore && !swap_info.is_empty() {
+                        let competitors = find_competing_frontruns(
+                            &pending_tx.tx,
+                            &swap_info,
+                            &pending_txs,
+                            &load_shedder.routers,
+                            &bot_registry,
+                        );
+                        for competitor in &competitors {
+                            bot_registry.suspect(
+                                &competitor.tx,
+                                &pending_tx.tx,
+                                new_block.block_number,
+                            );
+                        }
+                        // outbid: the contested pools stay, main_dish raises the bribe on them
+                        let contested: HashSet<H160> =
+                            if competitor_policy == CompetitorPolicy::Outbid {
+                                HashSet::new()
+                            } else {
+                                competitors.iter().map(|c| c.pair).collect()
+                            };
+                        if !contested.is_empty() {
+                            let mut control_state = control_state.write().unwrap();
+                            for info in swap_info
+                                .iter()
+                                .filter(|i| contested.contains(&i.target_pair))
+                            {
+                                control_state.record_skip(
+                                    info.target_pair,
+                                    info.target_token,
+                                    SkipReason::CompetingFrontrun,
+                                );
+                            }
+                        }
+                        swap_info.retain(|info| !contested.contains(&info.target_pair));
+
+                        if competitor_policy == CompetitorPolicy::Backrun
+                            && !drawdown_breaker.is_tripped()
+                            && !executor.kill_switch.is_halted()
+                        {
+                            for competitor in &competitors {
+                                match backrun_frontrunner(
+                                    &provider,
+                                    &executor,
+                                    &new_block,
+                                    &competitor.tx,
+                                    &pending_tx.tx,
+                                    competitor.pair,
+                                    &pools_map,
+                                    &pools_by_tokens,
+                                    &victim_locks,
+                                    env.bundle_target_blocks,
+                                    env.debug || env.dry_run,
+                                )
+                                .await
+                                {
+                                    Err(e) => warn!("backrun_frontrunner error: {e:?}"),
+                                    _ => {}
+                                }
+                            }
+                        }
+                    }
 
                     if swap_info.len() > 0 {
                         pending_tx.added_block = Some(new_block.block_number);
                         let pending_tx_info = PendingTxInfo {
                             pending_tx: pending_tx.clone(),
                             touched_pairs: swap_info.clone(),
+                            traced_ms: now_ms(),
                         };
                         pending_txs.insert(tx_hash, pending_tx_info.clone());
+                        {
+                            let mut control_state = control_state.write().unwrap();
+                            for info in &swap_info {
+                                control_state
+                                    .record_victim_swap(info.target_pair, info.target_token);
+                            }
+                        }
                         // info!(
                         //     "🔴 V{:?} TX ADDED: {:?} / Pending txs: {:?}",
                         //     pending_tx_info.touched_pairs.get(0).unwrap().version,
@@ -188,6 +852,10 @@ pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Se
                         //     pending_txs.len()
                         // );
 
+                        let promising_before = promising_sandwiches
+                            .get(&tx_hash)
+                            .map(|s| s.len())
+                            .unwrap_or(0);
                         match appetizer(
                             &provider,
                             &new_block,
@@ -195,36 +863,199 @@ pub async fn run_sandwich_strategy(provider: Arc<Provider<Ws>>, event_sender: Se
                             victim_gas_price,
                             &pending_txs,
                             &mut promising_sandwiches,
+                            &mut failures,
+                            &token_lists,
                         )
                         .await
                         {
+                            Ok(skipped) => {
+                                let mut control_state = control_state.write().unwrap();
+                                for (info, reason) in skipped {
+                                    control_state.record_skip(
+                                        info.target_pair,
+                                        info.target_token,
+                                        reason,
+                                    );
+                                }
+                            }
                             Err(e) => warn!("appetizer error: {e:?}"),
-                            _ => {}
                         }
 
-                        if promising_sandwiches.len() > 0 {
+                        let promising_after = promising_sandwiches
+                            .get(&tx_hash)
+                            .map(|s| s.len())
+                            .unwrap_or(0);
+                        telemetry.record_opportunities(
+                            promising_after.saturating_sub(promising_before) as u64,
+                        );
+
+                        // nothing to sandwich: the pools the victim moves may still be worth an arbitrage
+                        if env.backrun_arbitrage
+                            && promising_after == 0
+                            && !drawdown_breaker.is_tripped()
+                            && !executor.kill_switch.is_halted()
+                        {
+                            match backrun_victim(
+                                &provider,
+                                &executor,
+                                &new_block,
+                                &pending_tx.tx,
+                                &swap_info,
+                                &pools_map,
+                                &pools_by_tokens,
+                                &victim_locks,
+                                env.bundle_target_blocks,
+                                env.debug || env.dry_run,
+                            )
+                            .await
+                            {
+                                Err(e) => warn!("backrun_victim error: {e:?}"),
+                                _ => {}
+                            }
+                        }
+
+                        if let Some(sandwiches) = promising_sandwiches.get(&tx_hash) {
+                            let mut control_state = control_state.write().unwrap();
+                            for sandwich in sandwiches.iter().skip(promising_before) {
+                                let optimized = sandwich.optimized_sandwich.as_ref().unwrap();
+                                control_state.record_sandwich(SandwichRecord {
+                                    tx_hash,
+                                    block_number: new_block.block_number,
+                                    pair: sandwich.swap_info.target_pair,
+                                    main_currency: sandwich.swap_info.main_currency,
+                                    target_token: sandwich.swap_info.target_token,
+                                    amount_in: optimized.amount_in,
+                                    max_revenue: optimized.max_revenue,
+                                });
+                            }
+                        }
+
+                        if promising_sandwiches.len() > 0
+                            && !drawdown_breaker.is_tripped()
+                            && !executor.kill_switch.is_halted()
+                        {
                             match main_dish(
                                 &provider,
                                 &alert,
                                 &executor,
                                 &new_block,
-                                owner,
-                                bot_address,
-                                U256::from(9900), // 99%
+                                &profile,
                                 &promising_sandwiches,
                                 &mut simulated_bundle_ids,
                                 &pending_txs,
+                                &mut tracker,
+                                experiment.as_mut(),
+                                block_space.bundle_gas_cap(new_block.gas_limit),
+                                &victim_locks,
+                                &mut failures,
+                                &mut replacements,
+                                &mut bribe_policy,
+                                &bot_registry,
                             )
                             .await
                             {
-                                Err(e) => warn!("main_dish error: {e:?}"),
+                                Err(e) => {
+                                    failures.record_error(&e);
+                                    warn!("main_dish error: {e:?}");
+                                }
                                 _ => {}
                             }
                         }
+
+                        if env.dashboard {
+                            let mut control_state = control_state.write().unwrap();
+                            for bundle in tracker.bundles.values() {
+                                control_state.dashboard.record_bundle(bundle);
+                            }
+                            control_state.dashboard.update_candidates(
+                                new_block.block_number,
+                                &pending_txs,
+                                &promising_sandwiches,
+                            );
+                        }
                     }
                 }
             },
-            _ => {}
+            Err(RecvError::Lagged(dropped)) => load_shedder.record_lagged(dropped),
+            Err(RecvError::Closed) => break,
         }
     }
 }
+
+/*
+Whether a resolved bundle landed (our frontrun in the target block, the relay's stats), recorded and alerted.
+That's a get_block, a relay call and a Telegram message per bundle, so it runs in its own task
+instead of holding up the block handler.
+*/
+async fn report_inclusion(
+    executor: Arc<Executor>,
+    provider: Arc<Provider<RpcClient>>,
+    bundle_stats: Arc<Mutex<BundleStatsStore>>,
+    alert: Arc<Alert>,
+    mut bundle: TrackedBundle,
+) {
+    // held back for the slot's submission window, its builders' responses came in after it was tracked
+    for (i, variant) in bundle.variants.iter().enumerate() {
+        let sent = match executor.scheduled.take(&variant.replacement_uuid) {
+            Some(sent) => sent,
+            None => continue,
+        };
+        // the first variant's, as for a bundle sent right away
+        if i == 0 {
+            if bundle.bundle_hash.is_none() {
+                bundle.bundle_hash = sent
+                    .responses
+                    .get("flashbots")
+                    .map(|r| r.bundle_hash)
+                    .filter(|hash| !hash.is_zero());
+            }
+            bundle.timeline.broadcast.extend(sent.acknowledged_at);
+        }
+    }
+    let inclusion = match executor
+        .check_inclusion(
+            bundle.bundle_hash,
+            bundle.frontrun_tx_hash,
+            bundle.target_block,
+        )
+        .await
+    {
+        Ok(inclusion) => inclusion,
+        Err(e) => {
+            warn!("Executor.check_inclusion error: {e:?}");
+            return;
+        }
+    };
+    // the block it landed in, or the one it missed
+    match provider.get_block(bundle.target_block).await {
+        Ok(Some(block)) => {
+            bundle.timeline.block_timestamp = block.timestamp.as_u64() * 1000;
+        }
+        Ok(None) => {}
+        Err(e) => warn!("get_block error: {e:?}"),
+    }
+    match bundle_stats.lock().unwrap().record(&bundle, &inclusion) {
+        Err(e) => warn!("BundleStatsStore.record error: {e:?}"),
+        _ => {}
+    }
+    let message = describe_inclusion(&bundle, &inclusion);
+    info!("{}", message);
+    info!(
+        "[{}] Latency: {}",
+        bundle.bundle_id,
+        bundle.timeline.describe()
+    );
+    match alert.send(&message).await {
+        Err(e) => warn!("Telegram error: {e:?}"),
+        _ => {}
+    }
+    if inclusion.landed {
+        alert.event(AlertEvent::BundleIncluded {
+            bundle_id: bundle.bundle_id.clone(),
+            context: bundle.context.clone(),
+            target_block: bundle.target_block,
+            included_block: bundle.included_block.map(|(number, _)| number),
+            expected_profit: bundle.expected_profit,
+        });
+    }
+}
@@ -0,0 +1,174 @@
+use ethers::types::{CallFrame, CallLogFrame, NameOrAddress, Transaction, H160, H256};
+use std::{collections::HashSet, str::FromStr};
+
+use crate::common::constants::Env;
+
+/*
+Victim txs that settle through limit-order protocols aren't plain router swaps:
+- CoW Protocol: a solver calls GPv2Settlement.settle, which swaps against AMMs on behalf of
+  many users at uniform clearing prices. The solver's own slippage bound makes the settlement
+  revert (or the solver eat the loss) if the pool moves, so there is nothing to backrun.
+- 1inch limit orders: fillOrder* moves funds maker <-> taker at a fixed, signed price.
+  Any V2 Swap events come from the taker's interaction hook, so the swap direction
+  we'd infer from the logs has nothing to do with the order's price impact.
+Both are recognized here and skipped before simulation.
+*/
+pub const COW_SETTLEMENT: &str = "0x9008D19f58AAbD9eD0D60971565AA8510560ab41";
+pub const ONEINCH_ROUTER_V4: &str = "0x1111111254fb6c44bAC0beD2854e76F90643097d";
+pub const ONEINCH_ROUTER_V5: &str = "0x1111111254EEB25477B68fb85Ed929f73A960582";
+
+// Trade(address,address,address,uint256,uint256,uint256,bytes) emitted by GPv2Settlement
+pub const COW_TRADE_EVENT: &str =
+    "0xa07a543ab8a018198e99ca0184c93fe9050a79400a0a723441f84de1d972cc17";
+// OrderFilled(address,bytes32,uint256) emitted by 1inch Limit Order Protocol v3
+pub const ONEINCH_ORDER_FILLED_EVENT: &str =
+    "0xb9ed0243fdf00f0545c63a0af8850c090d86bb46682baec4bf3c496814fe4f02";
+
+pub const COW_SETTLE_SELECTOR: [u8; 4] = [0x13, 0xd7, 0x9a, 0x0b];
+pub const ONEINCH_LIMIT_ORDER_SELECTORS: [[u8; 4]; 5] = [
+    [0x62, 0xe2, 0x38, 0xbb], // fillOrder
+    [0xe5, 0xd7, 0xbd, 0xe6], // fillOrderTo
+    [0x3e, 0xca, 0x9c, 0x0a], // fillOrderRFQ
+    [0x5a, 0x09, 0x98, 0x43], // fillOrderRFQTo
+    [0x95, 0x70, 0xee, 0xee], // fillOrderRFQCompact
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VictimProtocol {
+    Swap, // router or direct pair swaps
+    CowSettlement,
+    OneInchLimitOrder,
+}
+
+impl VictimProtocol {
+    pub fn is_sandwichable(&self) -> bool {
+        match self {
+            VictimProtocol::Swap => true,
+            VictimProtocol::CowSettlement => false,
+            VictimProtocol::OneInchLimitOrder => false,
+        }
+    }
+}
+
+// Cheap check on the tx itself, runs before debug_traceCall
+pub fn classify_victim_tx(tx: &Transaction) -> VictimProtocol {
+    let to = match tx.to {
+        Some(to) => to,
+        None => return VictimProtocol::Swap,
+    };
+    if tx.input.len() < 4 {
+        return VictimProtocol::Swap;
+    }
+    let selector: [u8; 4] = tx.input[0..4].try_into().unwrap();
+
+    if to == H160::from_str(COW_SETTLEMENT).unwrap() && selector == COW_SETTLE_SELECTOR {
+        return VictimProtocol::CowSettlement;
+    }
+
+    let is_oneinch_router = to == H160::from_str(ONEINCH_ROUTER_V4).unwrap()
+        || to == H160::from_str(ONEINCH_ROUTER_V5).unwrap();
+    if is_oneinch_router && ONEINCH_LIMIT_ORDER_SELECTORS.contains(&selector) {
+        return VictimProtocol::OneInchLimitOrder;
+    }
+
+    VictimProtocol::Swap
+}
+
+// Catches settlements/fills wrapped by other contracts (solver drivers, taker bots)
+pub fn classify_victim_logs(logs: &Vec<CallLogFrame>) -> VictimProtocol {
+    let cow_settlement = H160::from_str(COW_SETTLEMENT).unwrap();
+    let cow_trade = H256::from_str(COW_TRADE_EVENT).unwrap();
+    let oneinch_filled = H256::from_str(ONEINCH_ORDER_FILLED_EVENT).unwrap();
+
+    for log in logs {
+        let topic0 = match log.topics.as_ref().and_then(|topics| topics.get(0)) {
+            Some(topic0) => *topic0,
+            None => continue,
+        };
+        if log.address == Some(cow_settlement) && topic0 == cow_trade {
+            return VictimProtocol::CowSettlement;
+        }
+        if topic0 == oneinch_filled {
+            return VictimProtocol::OneInchLimitOrder;
+        }
+    }
+
+    VictimProtocol::Swap
+}
+
+/*
+Resource limits per victim (MAX_VICTIM_STATE_FETCHES, MAX_VICTIM_TRACE_DEPTH, 0 = unlimited).
+Victims with huge access lists or deep call trees (arb bots, aggregators hopping through many pools)
+make our fork fetch thousands of accounts and slots from the node, eating into the slot deadline
+of every other candidate. They're skipped instead:
+- before tracing: access list entries (addresses + storage keys) already over the fetch budget
+- after tracing: call depth over the depth budget, or distinct contracts called
+  plus access list entries over the fetch budget (every contract is an account + code fetch)
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct VictimBudget {
+    pub max_state_fetches: usize,
+    pub max_trace_depth: usize,
+}
+
+fn access_list_entries(tx: &Transaction) -> usize {
+    match &tx.access_list {
+        Some(access_list) => access_list
+            .0
+            .iter()
+            .map(|item| 1 + item.storage_keys.len())
+            .sum(),
+        None => 0,
+    }
+}
+
+fn trace_depth(frame: &CallFrame) -> usize {
+    1 + frame
+        .calls
+        .as_ref()
+        .map(|calls| calls.iter().map(trace_depth).max().unwrap_or(0))
+        .unwrap_or(0)
+}
+
+fn called_contracts(frame: &CallFrame, contracts: &mut HashSet<H160>) {
+    if let Some(NameOrAddress::Address(to)) = &frame.to {
+        contracts.insert(*to);
+    }
+    if let Some(calls) = &frame.calls {
+        for call in calls {
+            called_contracts(call, contracts);
+        }
+    }
+}
+
+impl VictimBudget {
+    pub fn new(env: &Env) -> Self {
+        Self {
+            max_state_fetches: env.max_victim_state_fetches,
+            max_trace_depth: env.max_victim_trace_depth,
+        }
+    }
+
+    // Cheap check on the tx itself, runs before debug_traceCall
+    pub fn exceeded_by_tx(&self, tx: &Transaction) -> Option<String> {
+        let entries = access_list_entries(tx);
+        if self.max_state_fetches > 0 && entries > self.max_state_fetches {
+            return Some(format!("{} access list entries", entries));
+        }
+        None
+    }
+
+    pub fn exceeded_by_trace(&self, tx: &Transaction, frame: &CallFrame) -> Option<String> {
+        let depth = trace_depth(frame);
+        if self.max_trace_depth > 0 && depth > self.max_trace_depth {
+            return Some(format!("call depth of {}", depth));
+        }
+        let mut contracts = HashSet::new();
+        called_contracts(frame, &mut contracts);
+        let fetches = contracts.len() + access_list_entries(tx);
+        if self.max_state_fetches > 0 && fetches > self.max_state_fetches {
+            return Some(format!("~{} state fetches", fetches));
+        }
+        None
+    }
+}